@@ -0,0 +1,189 @@
+use std::{
+    error::Error,
+    path::PathBuf,
+    sync::{Arc, Mutex as StdMutex, MutexGuard},
+};
+
+use sync_backend::{sync::SharedLimit, Config, SyncPairs};
+use tauri::{AppHandle, Manager, State};
+use tokio::sync::{watch, Semaphore};
+
+/// Environment variable overriding the config path.
+const CONFIG_ENV: &str = "FILE_SYNCER_CONFIG";
+
+/// Get the config path, `config.yaml` in the app config directory unless overridden by [`CONFIG_ENV`].
+pub fn config_file(app: &AppHandle) -> Result<PathBuf, String> {
+    if let Some(path) = std::env::var_os(CONFIG_ENV) {
+        return Ok(path.into());
+    }
+    app.path()
+        .app_config_dir()
+        .map(|dir| dir.join("config.yaml"))
+        .map_err(|e| format!("Failed to get config dir: {}", e))
+}
+
+fn error_chain(e: &dyn Error) -> String {
+    match e.source() {
+        Some(source) => format!("{}: {}", e, source),
+        None => e.to_string(),
+    }
+}
+
+/// Resolve and validate a copy of `config`, the way the volumes sync with it.
+fn resolve(config: &Config) -> Result<Config, String> {
+    let mut config = config.clone();
+    config
+        .resolve_paths()
+        .and_then(|()| config.validate())
+        .map_err(|e| format!("Invalid config: {}", e))?;
+    Ok(config)
+}
+
+/// The config file as the user wrote it, and the resolved config the volumes sync with.
+pub struct ConfigStore {
+    path: PathBuf,
+    config: StdMutex<Config>,
+    resolved: watch::Sender<Arc<Config>>,
+    limit: SharedLimit,
+}
+
+impl ConfigStore {
+    /// Load the config at `path`.
+    ///
+    /// A missing or invalid config syncs nothing, but an invalid one is kept so it can be fixed.
+    pub fn load(path: PathBuf) -> Self {
+        let config = if path.exists() {
+            Config::read(&path, None).unwrap_or_else(|e| {
+                log::error!("Failed to load config: {}", error_chain(&e));
+                Config::default()
+            })
+        } else {
+            log::warn!("No config file at {}", path.display());
+            Config::default()
+        };
+        let resolved = resolve(&config).unwrap_or_else(|e| {
+            log::error!("{}, nothing will be synced", e);
+            Config::default()
+        });
+        log::info!(
+            "Loaded {} pairs from {}",
+            resolved.pairs.len(),
+            path.display()
+        );
+
+        let limit = SharedLimit::default();
+        limit.set(resolved.global_concurrency);
+
+        Self {
+            path,
+            config: StdMutex::new(config),
+            resolved: watch::Sender::new(Arc::new(resolved)),
+            limit,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Config> {
+        match self.config.lock() {
+            Ok(g) => g,
+            Err(p) => p.into_inner(),
+        }
+    }
+
+    /// Get the config as written in the file.
+    pub fn get(&self) -> Config {
+        self.lock().clone()
+    }
+
+    /// Get the semaphore shared by all pairs, if the config sets a global concurrency.
+    pub fn semaphore(&self) -> Option<Arc<Semaphore>> {
+        self.limit.semaphore()
+    }
+
+    /// Watch the resolved config.
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Config>> {
+        self.resolved.subscribe()
+    }
+
+    /// Change the config with `f`, then validate, save and apply it to the mounted volumes.
+    ///
+    /// Nothing changes if any step fails.
+    pub fn update(&self, f: impl FnOnce(&mut Config) -> Result<(), String>) -> Result<(), String> {
+        let mut current = self.lock();
+        let mut config = current.clone();
+        f(&mut config)?;
+        let resolved = resolve(&config)?;
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        config.save(&self.path, None).map_err(|e| error_chain(&e))?;
+
+        *current = config;
+        if self.limit.set(resolved.global_concurrency) {
+            log::info!("Global concurrency changed, applying it to the next syncs");
+        }
+        self.resolved.send_if_modified(|old| {
+            let changed = !old.diff(&resolved).is_empty();
+            if changed {
+                log::info!("Config changed, applying it to the mounted volumes");
+            }
+            *old = Arc::new(resolved);
+            changed
+        });
+        Ok(())
+    }
+}
+
+#[tauri::command]
+pub fn config_path(store: State<Arc<ConfigStore>>) -> String {
+    store.path.display().to_string()
+}
+
+#[tauri::command]
+pub fn get_config(store: State<Arc<ConfigStore>>) -> Config {
+    store.get()
+}
+
+#[tauri::command]
+pub fn save_config(store: State<Arc<ConfigStore>>, config: Config) -> Result<(), String> {
+    store.update(|current| {
+        *current = config;
+        Ok(())
+    })
+}
+
+#[tauri::command]
+pub fn add_pair(store: State<Arc<ConfigStore>>, pair: SyncPairs) -> Result<(), String> {
+    store.update(|config| {
+        config.pairs.push(pair);
+        Ok(())
+    })
+}
+
+#[tauri::command]
+pub fn update_pair(
+    store: State<Arc<ConfigStore>>,
+    index: usize,
+    pair: SyncPairs,
+) -> Result<(), String> {
+    store.update(|config| {
+        let current = config
+            .pairs
+            .get_mut(index)
+            .ok_or_else(|| format!("No pair at index {}", index))?;
+        *current = pair;
+        Ok(())
+    })
+}
+
+#[tauri::command]
+pub fn remove_pair(store: State<Arc<ConfigStore>>, index: usize) -> Result<(), String> {
+    store.update(|config| {
+        if index >= config.pairs.len() {
+            return Err(format!("No pair at index {}", index));
+        }
+        config.pairs.remove(index);
+        Ok(())
+    })
+}
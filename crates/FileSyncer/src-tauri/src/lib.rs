@@ -1,17 +1,36 @@
 use std::{
     error::Error,
+    path::PathBuf,
     sync::{
-        atomic::{AtomicBool, AtomicU64, Ordering},
-        Arc, Mutex as StdMutex,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex as StdMutex, OnceLock,
     },
+    time::{Duration, Instant},
 };
 
-use tauri::{Emitter, Manager, State};
+use config::ConfigStore;
+use sync_backend::{
+    sync::{
+        default_concurrency, GlobalProgress, PauseHandle, Phase, ProgressSnapshot, SyncFS,
+        SyncSummary,
+    },
+    Config, SyncPairs,
+};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tauri_plugin_dialog::DialogExt;
+use tauri_plugin_notification::NotificationExt;
 use tokio::{sync::Mutex, task::JoinSet};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
 use volume_tracker::{
-    platform_init, Device, FileSystem, NotificationSource, PlatformNotifier, SpawnerDisposition,
+    platform_init, AbortHandleHolder, Device, DriveType, FileSystem, NotificationSource, OnTaskEnd,
+    PlatformNotifier, SpaceInfo, SpawnerDisposition, TaskEnd,
 };
 
+mod config;
+mod tray;
+
 #[tauri::command]
 fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
@@ -41,6 +60,16 @@ async fn wait_tasks<'r>(state: State<'r, TaskJS>) -> Result<(), ()> {
     Ok(())
 }
 
+/// Ask the user for a directory, `None` if the dialog is dismissed.
+#[tauri::command]
+async fn pick_folder(app: AppHandle) -> Option<String> {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    app.dialog().file().pick_folder(move |path| {
+        let _ = tx.send(path);
+    });
+    rx.await.ok().flatten().map(|path| path.to_string())
+}
+
 #[tauri::command]
 fn send_message(
     tx: State<flume::Sender<(u64, Message)>>,
@@ -53,6 +82,17 @@ fn send_message(
     TaskID(AtomicU64::new(this_id))
 }
 
+/// Safely remove the drive holding the volume named `volume_name`, the result is sent as the
+/// `task_result` of the returned task ID.
+#[tauri::command]
+fn eject_volume(
+    tx: State<flume::Sender<(u64, Message)>>,
+    id: State<TaskID>,
+    volume_name: String,
+) -> TaskID {
+    send_message(tx, id, Message::Eject(volume_name))
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct TaskID(AtomicU64);
 
@@ -91,6 +131,11 @@ pub struct InitSpawn<E: Error + Send + Clone>(
 pub enum Message {
     InitSpawn,
     ListMounts,
+    Status,
+    Pause,
+    Resume,
+    Eject(String),
+    Quit,
 }
 
 #[derive(Clone, serde::Serialize)]
@@ -105,16 +150,472 @@ pub struct MessageResultPayload<T: Clone + serde::Serialize> {
     result: MessageResult<T>,
 }
 
+/// The sync tasks the notifier is running.
+#[derive(Clone, serde::Serialize)]
+pub struct NotifierStatus {
+    active_tasks: usize,
+    tracked_volumes: Vec<String>,
+}
+
+/// A mounted volume, the reply to `ListMounts`.
+///
+/// Whatever the platform cannot tell is `None`, and `error` tells why a volume cannot be synced.
+#[derive(Clone, serde::Serialize)]
+pub struct VolumeInfo {
+    volume: String,
+    device: String,
+    mount_paths: Vec<String>,
+    label: Option<String>,
+    serial: Option<String>,
+    volume_id: Option<String>,
+    fs_type: Option<String>,
+    drive_type: Option<DriveType>,
+    space: Option<SpaceInfo>,
+    error: Option<String>,
+    locked: bool,
+}
+
+impl VolumeInfo {
+    fn new(fs: &impl FileSystem, device: &impl Device, mount_paths: &[PathBuf]) -> Self {
+        Self {
+            volume: fs.name().to_string(),
+            device: device.name().to_string(),
+            mount_paths: mount_paths
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect(),
+            label: fs.label(),
+            serial: fs.serial(),
+            volume_id: fs.persistent_id(),
+            fs_type: fs.fs_type(),
+            drive_type: fs.drive_type(),
+            // Measured through a mount path where the volume itself cannot be queried.
+            space: fs.space().or_else(|| {
+                mount_paths
+                    .iter()
+                    .find_map(|p| volume_tracker::space_of(p).ok())
+            }),
+            error: device.error().map(str::to_string),
+            locked: fs.locked(),
+        }
+    }
+}
+
 struct InternalState {
     initialized: AtomicBool,
 }
 
+/// Send the result of message `id` to the frontend.
+fn reply<T: Clone + serde::Serialize>(app: &AppHandle, id: u64, result: Result<T, String>) {
+    app.emit(
+        "task_result",
+        MessageResultPayload {
+            id,
+            result: match result {
+                Ok(v) => MessageResult::Ok(v),
+                Err(e) => MessageResult::Err(e),
+            },
+        },
+    )
+    .expect("Failed to emit task result");
+}
+
+/// Minimum time between two `sync_progress` events of a task.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, serde::Serialize)]
+pub struct SyncProgressPayload {
+    task_id: u64,
+    #[serde(flatten)]
+    progress: ProgressSnapshot,
+    bytes_per_sec: Option<f64>,
+}
+
+impl SyncProgressPayload {
+    fn new(task_id: u64, gp: &GlobalProgress) -> Self {
+        SyncProgressPayload {
+            task_id,
+            progress: gp.snapshot(),
+            bytes_per_sec: gp.throughput().map(|r| r.bytes_per_sec),
+        }
+    }
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct SyncDonePayload {
+    task_id: u64,
+}
+
+/// Emits `sync_done` for a task when dropped, so the frontend also hears of aborted syncs.
+struct SyncDoneGuard<'a> {
+    app: &'a AppHandle,
+    task_id: u64,
+}
+
+impl Drop for SyncDoneGuard<'_> {
+    fn drop(&mut self) {
+        let payload = SyncDonePayload {
+            task_id: self.task_id,
+        };
+        if let Err(e) = self.app.emit("sync_done", payload) {
+            log::error!("Failed to emit sync done: {}", e);
+        }
+    }
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct SyncErrorPayload {
+    task_id: u64,
+    phase: Phase,
+    path: Option<String>,
+    message: String,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct VolumeRemovedPayload {
+    volume: String,
+    device: String,
+}
+
+/// How long quitting waits for the cancelled syncs to remove their partial copies before aborting
+/// them.
+const CANCEL_GRACE: Duration = Duration::from_secs(10);
+
+/// What the sync tasks need from the app, available once it is set up.
+struct SyncCtx {
+    app: AppHandle,
+    config: Arc<ConfigStore>,
+    next_task: AtomicU64,
+    /// Whether new drives are ignored, also until the notifier is first started.
+    paused: AtomicBool,
+    /// Holds the copies of the running syncs while paused, they resume where they were.
+    pause: PauseHandle,
+    /// Number of mounted volumes being watched.
+    volumes: AtomicUsize,
+    /// Stops all syncs on quit.
+    cancel: CancellationToken,
+    /// Number of syncs running, to wait for them on quit.
+    syncs: tokio::sync::watch::Sender<usize>,
+}
+
+impl SyncCtx {
+    fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::SeqCst);
+        if paused {
+            self.pause.pause();
+        } else {
+            self.pause.resume();
+        }
+        self.refresh_tray();
+    }
+
+    /// Show the current state in the tray tooltip.
+    fn refresh_tray(&self) {
+        let tooltip = if self.paused.load(Ordering::SeqCst) {
+            "Paused".to_string()
+        } else {
+            match self.volumes.load(Ordering::SeqCst) {
+                0 => "Waiting for drives".to_string(),
+                1 => "Syncing 1 drive".to_string(),
+                n => format!("Syncing {} drives", n),
+            }
+        };
+        if let Some(tray) = self.app.tray_by_id(tray::TRAY_ID) {
+            if let Err(e) = tray.set_tooltip(Some(tooltip)) {
+                log::error!("Failed to set tray tooltip: {}", e);
+            }
+        }
+    }
+
+    /// Cancel the running syncs and wait up to [`CANCEL_GRACE`] for them to clean up.
+    async fn cancel_syncs(&self) {
+        self.cancel.cancel();
+        let mut syncs = self.syncs.subscribe();
+        let stopped = tokio::time::timeout(CANCEL_GRACE, syncs.wait_for(|n| *n == 0))
+            .await
+            .is_ok();
+        if !stopped {
+            log::warn!(
+                "{} syncs still running {:?} after cancelling them, aborting them",
+                *self.syncs.borrow(),
+                CANCEL_GRACE
+            );
+        }
+    }
+}
+
+/// Counts a running sync in [`SyncCtx::syncs`] while alive.
+struct SyncGuard<'a>(&'a SyncCtx);
+
+impl<'a> SyncGuard<'a> {
+    fn new(ctx: &'a SyncCtx) -> Self {
+        ctx.syncs.send_modify(|n| *n += 1);
+        Self(ctx)
+    }
+}
+
+impl Drop for SyncGuard<'_> {
+    fn drop(&mut self) {
+        self.0.syncs.send_modify(|n| *n -= 1);
+    }
+}
+
+/// Counts a watched volume in [`SyncCtx::volumes`] while alive.
+struct VolumeGuard(Arc<SyncCtx>);
+
+impl VolumeGuard {
+    fn new(ctx: Arc<SyncCtx>) -> Self {
+        ctx.volumes.fetch_add(1, Ordering::SeqCst);
+        ctx.refresh_tray();
+        Self(ctx)
+    }
+}
+
+impl Drop for VolumeGuard {
+    fn drop(&mut self) {
+        self.0.volumes.fetch_sub(1, Ordering::SeqCst);
+        self.0.refresh_tray();
+    }
+}
+
+async fn sync_pair(ctx: &SyncCtx, pair: &SyncPairs) -> SyncSummary {
+    let task_id = ctx.next_task.fetch_add(1, Ordering::SeqCst);
+    let _running = SyncGuard::new(ctx);
+    let _done = SyncDoneGuard {
+        app: &ctx.app,
+        task_id,
+    };
+    if let Err((i, path, e)) = pair.check_paths() {
+        log::error!(
+            "Error syncing {}, path {}: {}: {}",
+            pair,
+            i + 1,
+            path.display(),
+            e
+        );
+        let payload = SyncErrorPayload {
+            task_id,
+            phase: Phase::Discovery,
+            path: Some(path.display().to_string()),
+            message: format!("Path {}: {}", i + 1, e),
+        };
+        if let Err(e) = ctx.app.emit("sync_error", payload) {
+            log::error!("Failed to emit sync error: {}", e);
+        }
+        return SyncSummary {
+            failed_paths: vec![(path, e)],
+            ..SyncSummary::default()
+        };
+    }
+    let last_emit = StdMutex::new(None::<Instant>);
+
+    let summary = SyncFS::with_shared_semaphore(
+        &pair.src.paths,
+        &pair.dest.path,
+        pair.concurrency.unwrap_or_else(default_concurrency),
+        pair.options.clone(),
+        ctx.config.semaphore(),
+    )
+    .with_pause(ctx.pause.clone())
+    .sync(
+        |gp, ms| {
+            let mut last_emit = match last_emit.lock() {
+                Ok(g) => g,
+                Err(p) => p.into_inner(),
+            };
+            // Milestones always go through so the frontend sees the final numbers.
+            if ms.is_none() && last_emit.is_some_and(|t| t.elapsed() < PROGRESS_INTERVAL) {
+                return;
+            }
+            *last_emit = Some(Instant::now());
+
+            if let Err(e) = ctx
+                .app
+                .emit("sync_progress", SyncProgressPayload::new(task_id, gp))
+            {
+                log::error!("Failed to emit sync progress: {}", e);
+            }
+        },
+        &|e| {
+            log::error!("Error syncing {}: {}", pair, e);
+            let payload = SyncErrorPayload {
+                task_id,
+                phase: e.phase,
+                path: e.path.as_ref().map(|p| p.display().to_string()),
+                message: e.error.to_string(),
+            };
+            if let Err(e) = ctx.app.emit("sync_error", payload) {
+                log::error!("Failed to emit sync error: {}", e);
+            }
+        },
+        &ctx.cancel,
+    )
+    .await;
+    log::info!(
+        "Synced {}: {} copied, {} skipped, {} failed",
+        pair,
+        summary.files_copied,
+        summary.files_skipped,
+        summary.files_failed
+    );
+    summary
+}
+
+/// Format `n` with thousands separators, like `1,204`.
+fn thousands(n: u64) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Show a desktop notification of how the sync of the volume `name` ended, from the summary the
+/// task left in `summary`, unless the config disables them.
+fn notify_on_end(
+    ctx: Arc<SyncCtx>,
+    name: String,
+    summary: Arc<StdMutex<Option<SyncSummary>>>,
+) -> OnTaskEnd {
+    Box::new(move |end| {
+        if ctx.config.subscribe().borrow().disable_notifications {
+            return;
+        }
+        let summary = match summary.lock() {
+            Ok(mut g) => g.take(),
+            Err(p) => p.into_inner().take(),
+        };
+        let body = match (end, summary) {
+            (TaskEnd::Completed, Some(summary)) => match summary.aborted {
+                None => format!(
+                    "Synced {}: {} files, {} failed",
+                    name,
+                    thousands(summary.files_copied),
+                    thousands(summary.files_failed)
+                ),
+                Some(reason) => format!("Sync of {} was interrupted: {}", name, reason),
+            },
+            // Aborted, like when the volume is removed, or the task panicked.
+            _ => format!("Sync of {} was interrupted", name),
+        };
+        notify(&ctx, body);
+    })
+}
+
+/// Show a desktop notification with `body`.
+fn notify(ctx: &SyncCtx, body: String) {
+    if let Err(e) = ctx
+        .app
+        .notification()
+        .builder()
+        .title("File Syncer")
+        .body(body)
+        .show()
+    {
+        log::error!("Failed to show notification: {}", e);
+    }
+}
+
+/// Safely remove the drive holding `volume`, blocking the worker thread meanwhile.
+fn eject(volume: &impl FileSystem) -> Result<(), String> {
+    tokio::task::block_in_place(|| volume.eject()).map_err(|e| e.to_string())
+}
+
+/// Keep the sync tasks of a mounted volume in line with the config until it is removed.
+///
+/// Aborting this task aborts the sync tasks with it.
+async fn watch_volume(
+    ctx: Arc<SyncCtx>,
+    volume: impl FileSystem,
+    device: impl Device,
+    mount_paths: Vec<PathBuf>,
+) {
+    let _guard = VolumeGuard::new(Arc::clone(&ctx));
+    let name = volume.label().unwrap_or_else(|| match mount_paths.first() {
+        Some(path) => path.display().to_string(),
+        None => volume.name().to_string(),
+    });
+    let mut config_rx = ctx.config.subscribe();
+    let tasks = AbortHandleHolder::default();
+    let mut js = JoinSet::new();
+    let mut current = Config::default();
+    // Set once a pair asking for it is done, the volume goes when the other syncs are done too.
+    let mut eject_pending = false;
+    loop {
+        let config = Arc::clone(&config_rx.borrow_and_update());
+        let pairs = config.for_volume(&volume, &device, &mount_paths);
+        let diff = current.diff(&pairs);
+        for pair in diff.removed {
+            tasks.remove_abort(pair);
+        }
+        for pair in diff.added {
+            let summary = Arc::new(StdMutex::new(None));
+            let ah = js.spawn({
+                let ctx = Arc::clone(&ctx);
+                let pair = pair.clone();
+                let summary = Arc::clone(&summary);
+                async move {
+                    let done = sync_pair(&ctx, &pair).await;
+                    let eject = pair.eject_when_done && done.aborted.is_none();
+                    match summary.lock() {
+                        Ok(mut g) => *g = Some(done),
+                        Err(p) => *p.into_inner() = Some(done),
+                    }
+                    eject
+                }
+                .in_current_span()
+            });
+            let on_end = notify_on_end(Arc::clone(&ctx), name.clone(), summary);
+            tasks.insert(pair.clone(), ah, Some(on_end));
+        }
+        current = pairs;
+
+        let changed = loop {
+            tokio::select! {
+                Some(res) = js.join_next() => {
+                    match res {
+                        Ok(eject) => eject_pending |= eject,
+                        Err(e) if !e.is_cancelled() => log::error!("Task failed: {:?}", e),
+                        Err(_) => {}
+                    }
+                    // Tells the finished tasks they completed.
+                    tasks.gc();
+                    if eject_pending && js.is_empty() {
+                        eject_pending = false;
+                        match eject(&volume) {
+                            Ok(()) => log::info!("Ejected {}", name),
+                            Err(e) => {
+                                log::error!("Failed to eject {}: {}", name, e);
+                                if !ctx.config.subscribe().borrow().disable_notifications {
+                                    notify(&ctx, format!("Could not eject {}: {}", name, e));
+                                }
+                            }
+                        }
+                    }
+                }
+                res = config_rx.changed() => break res.is_ok(),
+            }
+        };
+        if !changed {
+            break;
+        }
+    }
+    while js.join_next().await.is_some() {}
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "info");
     }
-    env_logger::init();
+    // Log records from the dependencies still using `log` are turned into events.
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
 
     platform_init().expect("Failed to initialize platform");
 
@@ -128,29 +629,74 @@ pub fn run() {
     let rt3 = rt.clone();
     let js = Arc::new(Mutex::new(JoinSet::new()));
     let js2 = js.clone();
+    let sync_ctx = Arc::new(OnceLock::<Arc<SyncCtx>>::new());
+    let sync_ctx2 = sync_ctx.clone();
+    let sync_ctx3 = sync_ctx.clone();
 
-    let mut s = PlatformNotifier::new(move |v, d, p| match p {
-        None => {
+    let mut s = PlatformNotifier::new(move |v, d, p| {
+        if p.is_empty() {
             log::info!("Device not mounted (yet): {}, {}", v.name(), d.name());
 
-            SpawnerDisposition::Skip
+            return SpawnerDisposition::Skip;
+        }
+        let mount_paths = p
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        log::info!(
+            "New device: volume: {}, device: {}, mounted at: {}",
+            v.name(),
+            d.name(),
+            mount_paths
+        );
+
+        let Some(ctx) = sync_ctx.get().cloned() else {
+            log::warn!("App not set up yet, skipping {}", mount_paths);
+            return SpawnerDisposition::Skip;
+        };
+        // Offered again on the next change, in case the config starts asking for them.
+        if v.drive_type() == Some(DriveType::Remote)
+            && !ctx.config.subscribe().borrow().asks_for(DriveType::Remote)
+        {
+            log::info!("Skipping remote drive {}, no pair asks for it", mount_paths);
+            return SpawnerDisposition::Skip;
         }
-        Some(p) => {
+        // Offered again once unlocked, the volume changes then.
+        if v.locked() {
             log::info!(
-                "New device: volume: {}, device: {}, mounted at: {}",
-                v.name(),
-                d.name(),
-                p.display()
+                "Skipping {}, it is locked until unlocked by the user",
+                mount_paths
             );
+            return SpawnerDisposition::Skip;
+        }
 
-            let ah = js
-                .blocking_lock()
-                .spawn_on(async move {}, Arc::clone(&rt3).handle());
+        // Volumes without pairs are watched too, in case the config changes.
+        let ah = js.blocking_lock().spawn_on(
+            watch_volume(ctx, v, d, p).in_current_span(),
+            Arc::clone(&rt3).handle(),
+        );
 
-            SpawnerDisposition::Spawned(ah, None)
-        }
+        SpawnerDisposition::Spawned(ah, None)
     })
     .expect("Failed to create PlatformNotifier");
+    s.on_removal(Box::new(move |e| {
+        log::info!(
+            "Volume {} on {} removed, its sync tasks were stopped",
+            e.volume.name(),
+            e.device.name()
+        );
+        let Some(ctx) = sync_ctx3.get() else {
+            return;
+        };
+        let payload = VolumeRemovedPayload {
+            volume: e.volume.name().to_string(),
+            device: e.device.name().to_string(),
+        };
+        if let Err(e) = ctx.app.emit("volume_removed", payload) {
+            log::error!("Failed to emit volume removal: {}", e);
+        }
+    }));
 
     let state = InternalState {
         initialized: AtomicBool::new(false),
@@ -158,11 +704,34 @@ pub fn run() {
 
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
-        .invoke_handler(tauri::generate_handler![greet, wait_tasks, send_message])
+        .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_notification::init())
+        .invoke_handler(tauri::generate_handler![
+            greet,
+            wait_tasks,
+            send_message,
+            eject_volume,
+            pick_folder,
+            config::config_path,
+            config::get_config,
+            config::save_config,
+            config::add_pair,
+            config::update_pair,
+            config::remove_pair
+        ])
+        .on_window_event(|window, event| {
+            // Closing the window keeps syncing in the tray, quitting is done from there.
+            if let tauri::WindowEvent::CloseRequested { api, .. } = event {
+                api.prevent_close();
+                if let Err(e) = window.hide() {
+                    log::error!("Failed to hide window: {}", e);
+                }
+            }
+        })
         .setup(move |app| {
             let (tx, rx) = flume::unbounded::<(u64, Message)>();
 
-            if !app.manage(tx) {
+            if !app.manage(tx.clone()) {
                 return Err("Failed to manage tx".into());
             }
 
@@ -176,33 +745,64 @@ pub fn run() {
 
             let app = app.handle().to_owned();
 
+            tray::build(&app, tx)?;
+
+            let config = Arc::new(ConfigStore::load(config::config_file(&app)?));
+            if !app.manage(Arc::clone(&config)) {
+                return Err("Failed to manage config".into());
+            }
+            if sync_ctx2
+                .set(Arc::new(SyncCtx {
+                    app: app.clone(),
+                    config,
+                    next_task: AtomicU64::new(0),
+                    paused: AtomicBool::new(true),
+                    pause: PauseHandle::default(),
+                    volumes: AtomicUsize::new(0),
+                    cancel: CancellationToken::new(),
+                    syncs: tokio::sync::watch::Sender::new(0),
+                }))
+                .is_err()
+            {
+                return Err("Failed to set sync context".into());
+            }
+
+            let ctx = sync_ctx2.get().cloned().ok_or("Sync context not set")?;
             rt2.spawn(async move {
                 while let Ok((id, msg)) = rx.recv_async().await {
                     match msg {
                         Message::InitSpawn => {
-                            let success = state.initialized.compare_exchange(
+                            let success = match state.initialized.compare_exchange(
                                 false,
                                 true,
                                 Ordering::SeqCst,
                                 Ordering::SeqCst,
-                            );
+                            ) {
+                                Ok(_) => match s.list_spawn() {
+                                    Ok(()) => s.start().await,
+                                    Err(e) => Err(e),
+                                }
+                                .map_err(Some),
+                                Err(_) => Err(None),
+                            };
+                            if success.is_ok() {
+                                ctx.set_paused(false);
+                            }
 
                             app.emit(
                                 "task_result",
                                 MessageResultPayload {
                                     id,
                                     result: match success {
-                                        Err(_) => {
+                                        Err(None) => {
                                             MessageResult::Err("Already initialized".to_string())
                                         }
-                                        Ok(_) => match s.list_spawn().and_then(|_| s.start()) {
-                                            Ok(_) => MessageResult::Ok(()),
-                                            Err(e) => {
-                                                log::error!("Failed to start notifier: {:?}", e);
-
-                                                MessageResult::Err(format!("{:?}", e))
-                                            }
-                                        },
+                                        Ok(()) => MessageResult::Ok(()),
+                                        Err(Some(e)) => {
+                                            log::error!("Failed to start notifier: {:?}", e);
+
+                                            MessageResult::Err(format!("{:?}", e))
+                                        }
                                     },
                                 },
                             )
@@ -214,14 +814,8 @@ pub fn run() {
                                 .map_err(|e| format!("Failed to list mounts: {:?}", e))
                                 .map(|mounts| {
                                     mounts
-                                        .into_iter()
-                                        .map(|(fs, dev, path)| {
-                                            (
-                                                fs.name().to_string(),
-                                                dev.name().to_string(),
-                                                path.map(|p| p.display().to_string()),
-                                            )
-                                        })
+                                        .iter()
+                                        .map(|(fs, dev, paths)| VolumeInfo::new(fs, dev, paths))
                                         .collect::<Vec<_>>()
                                 });
 
@@ -237,6 +831,61 @@ pub fn run() {
                             )
                             .expect("Failed to emit task result");
                         }
+                        Message::Status => {
+                            let status = NotifierStatus {
+                                active_tasks: s.active_tasks(),
+                                tracked_volumes: s
+                                    .tracked_volumes()
+                                    .iter()
+                                    .map(|v| v.name().to_string())
+                                    .collect(),
+                            };
+                            reply(&app, id, Ok(status));
+                        }
+                        Message::Pause | Message::Resume => {
+                            let pause = matches!(msg, Message::Pause);
+                            let result = if !state.initialized.load(Ordering::SeqCst) {
+                                Err("Not initialized".to_string())
+                            } else if ctx.paused.load(Ordering::SeqCst) == pause {
+                                // Already in the requested state, the notifier is left alone.
+                                Ok(())
+                            } else if pause {
+                                s.pause().await.map_err(|e| format!("{:?}", e))
+                            } else {
+                                s.start().await.map_err(|e| format!("{:?}", e))
+                            };
+                            match &result {
+                                Ok(()) => ctx.set_paused(pause),
+                                Err(e) => log::error!("Failed to pause or resume: {}", e),
+                            }
+                            reply(&app, id, result);
+                        }
+                        Message::Eject(name) => {
+                            let result = match s.list() {
+                                Ok(mounts) => {
+                                    match mounts.iter().find(|(fs, _, _)| fs.name() == name) {
+                                        Some((fs, _, _)) => eject(fs).map_err(|e| {
+                                            format!("Failed to eject {}: {}", name, e)
+                                        }),
+                                        None => Err(format!("Volume {} is not mounted", name)),
+                                    }
+                                }
+                                Err(e) => Err(format!("Failed to list mounts: {:?}", e)),
+                            };
+                            reply(&app, id, result);
+                        }
+                        Message::Quit => {
+                            // Cancelled syncs remove their partial copies on their way out, the
+                            // reset aborts the ones taking too long before exiting.
+                            if state.initialized.load(Ordering::SeqCst) {
+                                ctx.cancel_syncs().await;
+                                if let Err(e) = s.reset().await {
+                                    log::error!("Failed to stop notifier: {:?}", e);
+                                }
+                            }
+                            app.exit(0);
+                            break;
+                        }
                     }
                 }
             });
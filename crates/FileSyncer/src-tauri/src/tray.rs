@@ -0,0 +1,59 @@
+use std::sync::atomic::Ordering;
+
+use tauri::{
+    menu::{Menu, MenuItem, PredefinedMenuItem},
+    tray::TrayIconBuilder,
+    AppHandle, Manager,
+};
+
+use crate::{Message, TaskID};
+
+pub const TRAY_ID: &str = "main";
+
+/// Show and focus the main window.
+pub fn show_main_window(app: &AppHandle) {
+    if let Some(window) = app.get_webview_window("main") {
+        if let Err(e) = window.show().and_then(|()| window.set_focus()) {
+            log::error!("Failed to show main window: {}", e);
+        }
+    }
+}
+
+/// Build the tray icon, pausing and resuming goes through `tx` to reach the notifier.
+pub fn build(app: &AppHandle, tx: flume::Sender<(u64, Message)>) -> tauri::Result<()> {
+    let menu = Menu::with_items(
+        app,
+        &[
+            &MenuItem::with_id(app, "pause", "Pause syncing", true, None::<&str>)?,
+            &MenuItem::with_id(app, "resume", "Resume", true, None::<&str>)?,
+            &MenuItem::with_id(app, "open", "Open", true, None::<&str>)?,
+            &PredefinedMenuItem::separator(app)?,
+            &MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?,
+        ],
+    )?;
+
+    let mut tray = TrayIconBuilder::with_id(TRAY_ID)
+        .title("FileSyncer")
+        .tooltip("Paused")
+        .menu(&menu)
+        .on_menu_event(move |app, event| {
+            let msg = match event.id().as_ref() {
+                "pause" => Message::Pause,
+                "resume" => Message::Resume,
+                "quit" => Message::Quit,
+                "open" => return show_main_window(app),
+                _ => return,
+            };
+
+            let id = app.state::<TaskID>().0.fetch_add(1, Ordering::SeqCst);
+            if tx.send((id, msg)).is_err() {
+                log::error!("Failed to send tray message, the message loop is gone");
+            }
+        });
+    if let Some(icon) = app.default_window_icon() {
+        tray = tray.icon(icon.clone());
+    }
+    tray.build(app)?;
+
+    Ok(())
+}
@@ -2,6 +2,8 @@ import { useState, useContext } from "react";
 import "./App.css";
 import { Box, Button, Paper, TextareaAutosize, Typography } from "@mui/material";
 import { TaskDispatcherContext } from "./context/TaskDispatcher";
+import SyncProgress from "./components/SyncProgress";
+import PairForm from "./components/PairForm";
 
 function App() {
   const dispatcher = useContext(TaskDispatcherContext);
@@ -34,6 +36,10 @@ function App() {
                   })
                 } variant="contained">ListMounts</Button>
               </Box>
+              <Typography variant="h3">Progress</Typography>
+              <SyncProgress />
+              <Typography variant="h3">Add a pair</Typography>
+              <PairForm />
               <Typography variant="h3">Messages</Typography>
               <TextareaAutosize value={messages.join("\n")} readOnly />
             </Box>
@@ -0,0 +1,97 @@
+import { useState } from "react";
+import { invoke } from "@tauri-apps/api/core";
+import { Alert, Box, Button, MenuItem, TextField } from "@mui/material";
+
+const MATCH_FIELDS = ["volume", "device", "label", "serial", "volume_id"] as const;
+
+type MatchField = (typeof MATCH_FIELDS)[number];
+
+type PairFormProps = {
+  // The pair being edited and its index in the config, a new pair is added without them.
+  pair?: SyncPair;
+  index?: number;
+  onSaved?: () => void;
+};
+
+// A multiline field holds one path per line, picking a folder adds a line.
+function PathField({ label, value, multiline, onChange }: {
+  label: string;
+  value: string;
+  multiline?: boolean;
+  onChange: (value: string) => void;
+}) {
+  const picked = (path: string) => onChange(multiline && value ? `${value}\n${path}` : path);
+
+  return (
+    <Box sx={{ display: "flex", gap: 1 }}>
+      <TextField label={label} value={value} multiline={multiline}
+        onChange={(e) => onChange(e.target.value)} fullWidth />
+      <Button onClick={() => invoke<string | null>("pick_folder")
+        .then((path) => path && picked(path))
+      } variant="outlined">Browse</Button>
+    </Box>
+  );
+}
+
+function PairForm({ pair, index, onSaved }: PairFormProps) {
+  const [src, setSrc] = useState(pair?.src.path.join("\n") ?? "");
+  const [dest, setDest] = useState(pair?.dest.path ?? "");
+  const [direction, setDirection] = useState<SyncDirection>(pair?.direction ?? "from_volume");
+  const [match, setMatch] = useState<DeviceMatch>(
+    (pair?.direction === "to_volume" ? pair.dest.match : pair?.src.match) ?? {}
+  );
+  const [concurrency, setConcurrency] = useState(pair?.concurrency?.toString() ?? "");
+  const [error, setError] = useState<string | null>(null);
+
+  const setMatchField = (field: MatchField, value: string) =>
+    setMatch({ ...match, [field]: value || null });
+
+  const submit = () => {
+    const volumeMatch = MATCH_FIELDS.some((field) => match[field]) ? match : null;
+    const updated: SyncPair = {
+      ...pair,
+      src: {
+        match: direction === "from_volume" ? volumeMatch : null,
+        path: src.split("\n").map((path) => path.trim()).filter((path) => path),
+      },
+      dest: { match: direction === "to_volume" ? volumeMatch : null, path: dest },
+      direction,
+      concurrency: concurrency ? Number(concurrency) : null,
+    };
+    const saved = index === undefined
+      ? invoke("add_pair", { pair: updated })
+      : invoke("update_pair", { index, pair: updated });
+    saved
+      .then(() => {
+        setError(null);
+        onSaved?.();
+      })
+      .catch((e) => setError(String(e)));
+  };
+
+  return (
+    <Box sx={{ display: "flex", flexDirection: "column", gap: 2 }}>
+      {error && <Alert severity="error">{error}</Alert>}
+      <TextField select label="Direction" value={direction}
+        onChange={(e) => setDirection(e.target.value as SyncDirection)}>
+        <MenuItem value="from_volume">From the volume</MenuItem>
+        <MenuItem value="to_volume">To the volume</MenuItem>
+      </TextField>
+      <PathField label="Source, one path per line" value={src} multiline onChange={setSrc} />
+      <PathField label="Destination" value={dest} onChange={setDest} />
+      <Box sx={{ display: "flex", gap: 1 }}>
+        {MATCH_FIELDS.map((field) => (
+          <TextField key={field} label={`Match ${field}`} value={match[field] ?? ""}
+            onChange={(e) => setMatchField(field, e.target.value)} />
+        ))}
+      </Box>
+      <TextField label="Concurrency" type="number" value={concurrency}
+        onChange={(e) => setConcurrency(e.target.value)} helperText="Empty for the default" />
+      <Box>
+        <Button onClick={submit} variant="contained">{index === undefined ? "Add pair" : "Save pair"}</Button>
+      </Box>
+    </Box>
+  );
+}
+
+export default PairForm;
@@ -0,0 +1,72 @@
+import { useEffect, useState } from "react";
+import { listen } from "@tauri-apps/api/event";
+import { Box, LinearProgress, Typography } from "@mui/material";
+
+const UNITS = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+function formatBytes(bytes: number): string {
+  let unit = 0;
+  while (bytes >= 1024 && unit < UNITS.length - 1) {
+    bytes /= 1024;
+    unit++;
+  }
+  return `${bytes.toFixed(unit ? 1 : 0)} ${UNITS[unit]}`;
+}
+
+function TaskProgress({ progress }: { progress: SyncProgressPayload }) {
+  const { files, bytes_per_sec } = progress;
+  const finished = files.done + files.skipped + files.failed;
+  const total = progress.estimated_files ?? files.total;
+
+  return (
+    <Box>
+      <Box sx={{ display: "flex", justifyContent: "space-between" }}>
+        <Typography variant="body2">Task {progress.task_id}: {finished}/{total} files</Typography>
+        <Typography variant="body2">
+          {bytes_per_sec === null ? "" : `${formatBytes(bytes_per_sec)}/s`}
+        </Typography>
+      </Box>
+      <LinearProgress
+        variant="determinate"
+        value={total ? (finished / total) * 100 : 0}
+        color={files.failed ? "warning" : "primary"}
+      />
+    </Box>
+  );
+}
+
+// One progress bar for each running sync, keyed by task id.
+function SyncProgress() {
+  const [tasks, setTasks] = useState<Record<number, SyncProgressPayload>>({});
+
+  useEffect(() => {
+    const unlisten = Promise.all([
+      listen<SyncProgressPayload>("sync_progress", (event) => {
+        setTasks((tasks) => ({ ...tasks, [event.payload.task_id]: event.payload }));
+      }),
+      listen<SyncDonePayload>("sync_done", (event) => {
+        setTasks((tasks) => {
+          const rest = { ...tasks };
+          delete rest[event.payload.task_id];
+          return rest;
+        });
+      }),
+    ]);
+    return () => {
+      unlisten.then((fns) => fns.forEach((fn) => fn()));
+    };
+  }, []);
+
+  const running = Object.values(tasks);
+  return (
+    <Box sx={{ display: "flex", flexDirection: "column", gap: 1 }}>
+      {running.length ? (
+        running.map((progress) => <TaskProgress key={progress.task_id} progress={progress} />)
+      ) : (
+        <Typography variant="body2">Nothing is syncing</Typography>
+      )}
+    </Box>
+  );
+}
+
+export default SyncProgress;
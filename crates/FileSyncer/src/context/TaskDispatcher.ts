@@ -12,7 +12,8 @@ export class TaskDispatcher {
 
         await listen("task_result", (event) => {
             const { id, result } = event.payload as TaskResultPayload<any, any>;
-            this.queue[id](result);
+            // Messages sent from the tray have no one waiting for them.
+            this.queue[id]?.(result);
             delete this.queue[id];
         });
 
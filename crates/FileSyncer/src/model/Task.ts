@@ -1,4 +1,6 @@
-type Message = "InitSpawn" | "ListMounts";
+type Message = "InitSpawn" | "ListMounts" | "Status" | "Pause" | "Resume" | "Quit" | { Eject: string };
+
+type DriveType = "unknown" | "removable" | "fixed" | "remote" | "cd_rom" | "ram_disk";
 
 type TaskResultPayload<T, E> = {
     id: number;
@@ -11,5 +13,86 @@ type TaskResult<T, E> = {
     Err: E;
 }
 
-type TaskResultOf<M extends Message> = M extends "InitSpawn" ? TaskResult<null, string> :
-    M extends "ListMounts" ? TaskResult<[string, string, string | null][], string> : never;
\ No newline at end of file
+type TaskResultOf<M extends Message> = M extends "InitSpawn" | "Pause" | "Resume" | { Eject: string } ? TaskResult<null, string> :
+    M extends "ListMounts" ? TaskResult<VolumeInfo[], string> :
+    M extends "Status" ? TaskResult<NotifierStatus, string> : never;
+
+type SpaceInfo = {
+    total: number;
+    free: number;
+    available: number;
+}
+
+type VolumeInfo = {
+    volume: string;
+    device: string;
+    mount_paths: string[];
+    label: string | null;
+    serial: string | null;
+    volume_id: string | null;
+    fs_type: string | null;
+    drive_type: DriveType | null;
+    space: SpaceInfo | null;
+    // Why the volume cannot be synced, like its device not resolving.
+    error: string | null;
+    // Encrypted and not unlocked yet, the user has to unlock it before it is synced.
+    locked: boolean;
+}
+
+type NotifierStatus = {
+    active_tasks: number;
+    tracked_volumes: string[];
+}
+
+type ProgressCounts = {
+    total: number;
+    in_progress: number;
+    done: number;
+    skipped: number;
+    failed: number;
+}
+
+type SyncProgressPayload = {
+    task_id: number;
+    files: ProgressCounts;
+    bytes: ProgressCounts;
+    // Set during discovery when the pair has a pre-scan.
+    estimated_files?: number;
+    bytes_per_sec: number | null;
+}
+
+type SyncDonePayload = {
+    task_id: number;
+}
+
+type SyncErrorPayload = {
+    task_id: number;
+    phase: "discovery" | "space_check" | "copy";
+    path: string | null;
+    message: string;
+}
+
+type VolumeRemovedPayload = {
+    volume: string;
+    device: string;
+}
+
+type DeviceMatch = {
+    volume?: string | null;
+    device?: string | null;
+    label?: string | null;
+    serial?: string | null;
+    volume_id?: string | null;
+    [field: string]: unknown;
+}
+
+type SyncDirection = "from_volume" | "to_volume";
+
+// The sync options are flattened into the pair, they are kept as they are when editing.
+type SyncPair = {
+    src: { match: DeviceMatch | null; path: string[] };
+    dest: { match: DeviceMatch | null; path: string };
+    direction: SyncDirection;
+    concurrency: number | null;
+    [option: string]: unknown;
+}
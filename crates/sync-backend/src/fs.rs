@@ -0,0 +1,667 @@
+use std::{
+    ffi::OsString,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::sync::CancellationToken;
+use volume_tracker::SpaceInfo;
+
+#[cfg(test)]
+pub(crate) mod memory;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// The kind of a file system entry.
+pub enum FileType {
+    /// A regular file.
+    File,
+    /// A directory.
+    Dir,
+    /// A symbolic link, only reported by [`FsBackend::symlink_metadata`].
+    Symlink,
+    /// A named pipe, Unix only.
+    Fifo,
+    /// A Unix domain socket.
+    Socket,
+    /// A block device node, like a disk, Unix only.
+    BlockDevice,
+    /// A character device node, like a terminal, Unix only.
+    CharDevice,
+    /// Anything else.
+    Other,
+}
+
+impl std::fmt::Display for FileType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::File => "file",
+            Self::Dir => "directory",
+            Self::Symlink => "symbolic link",
+            Self::Fifo => "named pipe",
+            Self::Socket => "socket",
+            Self::BlockDevice => "block device",
+            Self::CharDevice => "character device",
+            Self::Other => "special file",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The attributes of a file system entry the synchronization looks at.
+pub struct Metadata {
+    /// The kind of entry.
+    pub file_type: FileType,
+    /// Size in bytes.
+    pub len: u64,
+    /// Last modification time, if the platform records it.
+    pub modified: Option<SystemTime>,
+    /// Whether the entry is read-only.
+    pub readonly: bool,
+    /// Device and inode numbers, used to recognize a directory reached through different paths.
+    ///
+    /// Backends that cannot tell leave it unset and the path is canonicalized instead.
+    pub id: Option<(u64, u64)>,
+    /// Number of hard links to the entry, 1 if the platform cannot tell.
+    pub links: u64,
+}
+
+impl Metadata {
+    /// Whether the entry is a regular file.
+    pub fn is_file(&self) -> bool {
+        self.file_type == FileType::File
+    }
+
+    /// Whether the entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.file_type == FileType::Dir
+    }
+
+    /// Whether the entry is a symbolic link.
+    pub fn is_symlink(&self) -> bool {
+        self.file_type == FileType::Symlink
+    }
+
+    /// Whether the entry is neither a regular file, a directory nor a symbolic link, like a
+    /// named pipe or a device.
+    pub fn is_special(&self) -> bool {
+        !matches!(
+            self.file_type,
+            FileType::File | FileType::Dir | FileType::Symlink
+        )
+    }
+
+    /// Get the modification time, failing like [`std::fs::Metadata::modified`] if there is none.
+    pub fn modified(&self) -> io::Result<SystemTime> {
+        self.modified.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "modification time is not available",
+            )
+        })
+    }
+}
+
+impl From<std::fs::Metadata> for Metadata {
+    fn from(meta: std::fs::Metadata) -> Self {
+        let file_type = if meta.is_symlink() {
+            FileType::Symlink
+        } else if meta.is_dir() {
+            FileType::Dir
+        } else if meta.is_file() {
+            FileType::File
+        } else {
+            special_file_type(&meta.file_type())
+        };
+
+        #[cfg(unix)]
+        let (id, links) = {
+            use std::os::unix::fs::MetadataExt;
+
+            (Some((meta.dev(), meta.ino())), meta.nlink())
+        };
+        #[cfg(not(unix))]
+        let (id, links) = (None, 1);
+
+        Self {
+            file_type,
+            len: meta.len(),
+            modified: meta.modified().ok(),
+            readonly: meta.permissions().readonly(),
+            id,
+            links,
+        }
+    }
+}
+
+/// Tell which special file `file_type` is.
+fn special_file_type(file_type: &std::fs::FileType) -> FileType {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+
+        if file_type.is_fifo() {
+            return FileType::Fifo;
+        } else if file_type.is_socket() {
+            return FileType::Socket;
+        } else if file_type.is_block_device() {
+            return FileType::BlockDevice;
+        } else if file_type.is_char_device() {
+            return FileType::CharDevice;
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = file_type;
+    FileType::Other
+}
+
+/// The file system operations a [`SyncFS`](crate::sync::SyncFS) is built on.
+///
+/// [`TokioFs`] works on the real file system, other implementations let the synchronization
+/// logic be tested without touching the disk.
+pub trait FsBackend: Send + Sync + 'static {
+    /// A file opened for reading.
+    type Reader: AsyncRead + Unpin + Send;
+    /// A file opened for writing.
+    type Writer: AsyncWrite + Unpin + Send;
+
+    /// Get the metadata of `path`, following symbolic links.
+    fn metadata(&self, path: &Path) -> impl Future<Output = io::Result<Metadata>> + Send;
+    /// Get the metadata of `path` without following symbolic links.
+    fn symlink_metadata(&self, path: &Path) -> impl Future<Output = io::Result<Metadata>> + Send;
+    /// List the names of the entries in the directory `path`.
+    fn read_dir(&self, path: &Path) -> impl Future<Output = io::Result<Vec<OsString>>> + Send;
+    /// List the names of the entries in the directory `path` and whether each is a directory,
+    /// not following symbolic links.
+    ///
+    /// Meant to be cheaper than reading the metadata of every entry, the default does just that.
+    fn read_dir_kinds(
+        &self,
+        path: &Path,
+    ) -> impl Future<Output = io::Result<Vec<(OsString, bool)>>> + Send {
+        async move {
+            let mut kinds = Vec::new();
+            for name in self.read_dir(path).await? {
+                let is_dir = self
+                    .symlink_metadata(&path.join(&name))
+                    .await
+                    .is_ok_and(|meta| meta.is_dir());
+                kinds.push((name, is_dir));
+            }
+            Ok(kinds)
+        }
+    }
+
+    /// Create the directory `path` and its missing parents.
+    fn create_dir_all(&self, path: &Path) -> impl Future<Output = io::Result<()>> + Send;
+    /// Open the file `path` for reading.
+    fn open(&self, path: &Path) -> impl Future<Output = io::Result<Self::Reader>> + Send;
+    /// Create or truncate the file `path` for writing.
+    fn create(&self, path: &Path) -> impl Future<Output = io::Result<Self::Writer>> + Send;
+    /// Move `from` to `to`, replacing it.
+    fn rename(&self, from: &Path, to: &Path) -> impl Future<Output = io::Result<()>> + Send;
+    /// Remove the file `path`.
+    fn remove_file(&self, path: &Path) -> impl Future<Output = io::Result<()>> + Send;
+    /// Remove the empty directory `path`.
+    fn remove_dir(&self, path: &Path) -> impl Future<Output = io::Result<()>> + Send;
+    /// Resolve `path` to an absolute path without symbolic links.
+    fn canonicalize(&self, path: &Path) -> impl Future<Output = io::Result<PathBuf>> + Send;
+    /// Get the target of the symbolic link `path`.
+    fn read_link(&self, path: &Path) -> impl Future<Output = io::Result<PathBuf>> + Send;
+    /// Create a symbolic link at `link` pointing to `target`, `dir` tells whether the target is a
+    /// directory for platforms that distinguish them.
+    fn symlink(
+        &self,
+        target: &Path,
+        link: &Path,
+        dir: bool,
+    ) -> impl Future<Output = io::Result<()>> + Send;
+    /// Remove the symbolic link `path`, whatever it points to.
+    fn remove_link(&self, path: &Path) -> impl Future<Output = io::Result<()>> + Send;
+    /// Create a hard link at `link` to the file `original`.
+    fn hard_link(
+        &self,
+        original: &Path,
+        link: &Path,
+    ) -> impl Future<Output = io::Result<()>> + Send;
+
+    /// Create `dest` as a clone of the file `src` sharing its blocks, for
+    /// [`SyncOptions::prefer_reflink`](crate::sync::SyncOptions::prefer_reflink).
+    ///
+    /// Fails if the file system cannot clone, `dest` must not exist.
+    fn reflink(&self, src: &Path, dest: &Path) -> impl Future<Output = io::Result<()>> + Send {
+        let _ = (src, dest);
+        async { Err(io::ErrorKind::Unsupported.into()) }
+    }
+
+    /// Create a device node or named pipe at `dest` like the one at `src`, see
+    /// [`SpecialFilePolicy::Recreate`](crate::sync::SpecialFilePolicy::Recreate).
+    ///
+    /// Fails if the platform cannot, `dest` must not exist.
+    fn make_special(&self, src: &Path, dest: &Path) -> impl Future<Output = io::Result<()>> + Send {
+        let _ = (src, dest);
+        async { Err(io::ErrorKind::Unsupported.into()) }
+    }
+
+    /// Start copying `src` from its position to its end at the position of `dest` without going
+    /// through userspace, up to `chunk` bytes at a time, for copies within one file system.
+    ///
+    /// The returned channel gets how many bytes each chunk copied, zero at the end of `src`, or
+    /// the error that ended the copy. The copy stops early once the channel is dropped or
+    /// `cancel` is cancelled. Fails with [`io::ErrorKind::Unsupported`] if the platform or the
+    /// file system cannot, the file is streamed then.
+    fn copy_range(
+        &self,
+        src: &mut Self::Reader,
+        dest: &mut Self::Writer,
+        chunk: u64,
+        cancel: &CancellationToken,
+    ) -> impl Future<Output = io::Result<flume::Receiver<io::Result<u64>>>> + Send {
+        let _ = (src, dest, chunk, cancel);
+        async { Err(io::ErrorKind::Unsupported.into()) }
+    }
+
+    /// Wait until what was written to `file` is on the disk, see
+    /// [`SyncOptions::fsync`](crate::sync::SyncOptions::fsync).
+    fn sync_file(&self, file: &mut Self::Writer) -> impl Future<Output = io::Result<()>> + Send {
+        let _ = file;
+        async { Ok(()) }
+    }
+
+    /// Wait until the file `path` is on the disk, like [`FsBackend::sync_file`] for a file
+    /// written without a handle here, such as a clone.
+    fn sync_path(&self, path: &Path) -> impl Future<Output = io::Result<()>> + Send {
+        let _ = path;
+        async { Ok(()) }
+    }
+
+    /// Wait until the entries of the directory `path`, like the files renamed into it, are on
+    /// the disk.
+    ///
+    /// Does nothing where the platform cannot, like on Windows.
+    fn sync_dir(&self, path: &Path) -> impl Future<Output = io::Result<()>> + Send {
+        let _ = path;
+        async { Ok(()) }
+    }
+
+    /// Set the modification time of the file `path`, for a copy to keep the one of its source.
+    ///
+    /// Does nothing by default, the copies are then as new as when they were made.
+    fn set_modified(
+        &self,
+        path: &Path,
+        modified: SystemTime,
+    ) -> impl Future<Output = io::Result<()>> + Send {
+        let _ = (path, modified);
+        async { Ok(()) }
+    }
+
+    /// Copy the attributes kept by
+    /// [`SyncOptions::preserve_attributes`](crate::sync::SyncOptions::preserve_attributes) from
+    /// `src` to the finished copy at `partial`, before it replaces `dest`.
+    fn preserve_attributes(
+        &self,
+        src: &Path,
+        partial: &Path,
+        dest: &Path,
+    ) -> impl Future<Output = io::Result<()>> + Send {
+        let _ = (src, partial, dest);
+        async { Ok(()) }
+    }
+
+    /// Copy the metadata kept by
+    /// [`SyncOptions::preserve_windows_metadata`](crate::sync::SyncOptions::preserve_windows_metadata)
+    /// from `src` to the finished copy at `partial`.
+    fn preserve_windows_metadata(
+        &self,
+        src: &Path,
+        partial: &Path,
+    ) -> impl Future<Output = io::Result<()>> + Send {
+        let _ = (src, partial);
+        async { Ok(()) }
+    }
+
+    /// Query the capacity of the file system containing `path`.
+    fn space(&self, path: &Path) -> io::Result<SpaceInfo> {
+        let _ = path;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// The real file system, through [`tokio::fs`].
+pub struct TokioFs;
+
+impl FsBackend for TokioFs {
+    type Reader = tokio::fs::File;
+    type Writer = tokio::fs::File;
+
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        tokio::fs::metadata(path).await.map(Metadata::from)
+    }
+
+    async fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        tokio::fs::symlink_metadata(path).await.map(Metadata::from)
+    }
+
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        let mut rd = tokio::fs::read_dir(path).await?;
+        let mut names = Vec::new();
+        while let Some(entry) = rd.next_entry().await? {
+            names.push(entry.file_name());
+        }
+        Ok(names)
+    }
+
+    async fn read_dir_kinds(&self, path: &Path) -> io::Result<Vec<(OsString, bool)>> {
+        let mut rd = tokio::fs::read_dir(path).await?;
+        let mut kinds = Vec::new();
+        while let Some(entry) = rd.next_entry().await? {
+            // Comes with the listing on most platforms, only a stat where it does not.
+            let is_dir = entry.file_type().await.is_ok_and(|t| t.is_dir());
+            kinds.push((entry.file_name(), is_dir));
+        }
+        Ok(kinds)
+    }
+
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        tokio::fs::create_dir_all(path).await
+    }
+
+    async fn open(&self, path: &Path) -> io::Result<Self::Reader> {
+        tokio::fs::File::open(path).await
+    }
+
+    async fn create(&self, path: &Path) -> io::Result<Self::Writer> {
+        tokio::fs::File::create(path).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        tokio::fs::rename(from, to).await
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        tokio::fs::remove_file(path).await
+    }
+
+    async fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        tokio::fs::remove_dir(path).await
+    }
+
+    async fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        tokio::fs::canonicalize(path).await
+    }
+
+    async fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        tokio::fs::read_link(path).await
+    }
+
+    async fn symlink(&self, target: &Path, link: &Path, dir: bool) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            let _ = dir;
+            tokio::fs::symlink(target, link).await
+        }
+        #[cfg(windows)]
+        {
+            if dir {
+                tokio::fs::symlink_dir(target, link).await
+            } else {
+                tokio::fs::symlink_file(target, link).await
+            }
+        }
+        #[cfg(not(any(unix, windows)))]
+        {
+            let _ = (target, link, dir);
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "symbolic links are not supported on this platform",
+            ))
+        }
+    }
+
+    async fn remove_link(&self, path: &Path) -> io::Result<()> {
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::FileTypeExt;
+
+            if tokio::fs::symlink_metadata(path)
+                .await?
+                .file_type()
+                .is_symlink_dir()
+            {
+                return tokio::fs::remove_dir(path).await;
+            }
+        }
+        tokio::fs::remove_file(path).await
+    }
+
+    async fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+        tokio::fs::hard_link(original, link).await
+    }
+
+    async fn reflink(&self, src: &Path, dest: &Path) -> io::Result<()> {
+        // FICLONE on Linux, clonefile on macOS and FSCTL_DUPLICATE_EXTENTS_TO_FILE on Windows.
+        let (src, dest) = (src.to_path_buf(), dest.to_path_buf());
+        tokio::task::spawn_blocking(move || reflink_copy::reflink(src, dest)).await?
+    }
+
+    async fn make_special(&self, src: &Path, dest: &Path) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            let (src, dest) = (src.to_path_buf(), dest.to_path_buf());
+            tokio::task::spawn_blocking(move || mknod_like(&src, &dest)).await?
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (src, dest);
+            Err(io::ErrorKind::Unsupported.into())
+        }
+    }
+
+    async fn copy_range(
+        &self,
+        src: &mut Self::Reader,
+        dest: &mut Self::Writer,
+        chunk: u64,
+        cancel: &CancellationToken,
+    ) -> io::Result<flume::Receiver<io::Result<u64>>> {
+        #[cfg(target_os = "linux")]
+        {
+            // Duplicated once for the whole copy, so the blocking loop keeps its descriptors
+            // after the receiver is dropped. They share their positions with the originals.
+            let src = src.try_clone().await?.into_std().await;
+            let dest = dest.try_clone().await?.into_std().await;
+            let cancel = cancel.clone();
+            // At most one chunk ahead, so a throttled copy holds the loop back.
+            let (tx, rx) = flume::bounded(1);
+            tokio::task::spawn_blocking(move || {
+                while !cancel.is_cancelled() {
+                    let result = copy_file_range(&src, &dest, chunk);
+                    let more = matches!(result, Ok(n) if n > 0);
+                    if tx.send(result).is_err() || !more {
+                        break;
+                    }
+                }
+            });
+            Ok(rx)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (src, dest, chunk, cancel);
+            Err(io::ErrorKind::Unsupported.into())
+        }
+    }
+
+    async fn sync_file(&self, file: &mut Self::Writer) -> io::Result<()> {
+        file.sync_all().await
+    }
+
+    async fn sync_path(&self, path: &Path) -> io::Result<()> {
+        // Windows only flushes files opened for writing, which does not truncate them.
+        tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(path)
+            .await?
+            .sync_all()
+            .await
+    }
+
+    async fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        // Windows cannot open a directory for writing its buffers out.
+        #[cfg(unix)]
+        tokio::fs::File::open(path).await?.sync_all().await?;
+        #[cfg(not(unix))]
+        let _ = path;
+        Ok(())
+    }
+
+    async fn set_modified(&self, path: &Path, modified: SystemTime) -> io::Result<()> {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open(path)?
+                .set_modified(modified)
+        })
+        .await?
+    }
+
+    async fn preserve_attributes(&self, src: &Path, partial: &Path, dest: &Path) -> io::Result<()> {
+        #[cfg(windows)]
+        {
+            copy_attributes(src, partial)?;
+            // A read-only file cannot be replaced, which it may be from preserving it last time.
+            if let Ok(meta) = tokio::fs::metadata(dest).await {
+                let mut perms = meta.permissions();
+                if perms.readonly() {
+                    #[allow(clippy::permissions_set_readonly_false)]
+                    perms.set_readonly(false);
+                    tokio::fs::set_permissions(dest, perms).await?;
+                }
+            }
+        }
+        #[cfg(not(windows))]
+        let _ = (src, partial, dest);
+
+        Ok(())
+    }
+
+    async fn preserve_windows_metadata(&self, src: &Path, partial: &Path) -> io::Result<()> {
+        #[cfg(windows)]
+        {
+            let (src, partial) = (src.to_path_buf(), partial.to_path_buf());
+            tokio::task::spawn_blocking(move || copy_windows_metadata(&src, &partial)).await?
+        }
+        #[cfg(not(windows))]
+        {
+            let _ = (src, partial);
+            Ok(())
+        }
+    }
+
+    fn space(&self, path: &Path) -> io::Result<SpaceInfo> {
+        volume_tracker::space_of(path)
+    }
+}
+
+/// One `copy_file_range(2)` call of up to `len` bytes at the positions of both files.
+///
+/// The errors of kernels and file systems that cannot do it are turned into
+/// [`io::ErrorKind::Unsupported`].
+#[cfg(target_os = "linux")]
+fn copy_file_range(src: &std::fs::File, dest: &std::fs::File, len: u64) -> io::Result<u64> {
+    crate::sys::copy_file_range(src, dest, len).map_err(|e| {
+        // EXDEV before Linux 5.3, EINVAL or EOPNOTSUPP from file systems without support.
+        match e.raw_os_error() {
+            Some(libc::ENOSYS | libc::EXDEV | libc::EINVAL | libc::EOPNOTSUPP) => {
+                io::Error::new(io::ErrorKind::Unsupported, e)
+            }
+            _ => e,
+        }
+    })
+}
+
+/// Create a node at `dest` with the type, permissions and device number of `src`.
+#[cfg(unix)]
+fn mknod_like(src: &Path, dest: &Path) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let meta = std::fs::symlink_metadata(src)?;
+    crate::sys::mknod(dest, meta.mode(), meta.rdev())
+}
+
+/// Copy the read-only, hidden and system attributes of `src` to `dest`.
+#[cfg(windows)]
+fn copy_attributes(src: &Path, dest: &Path) -> io::Result<()> {
+    use windows::Win32::Storage::FileSystem::{
+        FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_READONLY, FILE_ATTRIBUTE_SYSTEM,
+    };
+
+    const PRESERVED: u32 =
+        FILE_ATTRIBUTE_READONLY.0 | FILE_ATTRIBUTE_HIDDEN.0 | FILE_ATTRIBUTE_SYSTEM.0;
+
+    let src_attrs = crate::sys::file_attributes(src)?;
+    let dest_attrs = crate::sys::file_attributes(dest)?;
+    let attrs = (dest_attrs & !PRESERVED) | (src_attrs & PRESERVED);
+    if attrs != dest_attrs {
+        crate::sys::set_file_attributes(dest, attrs)?;
+    }
+    Ok(())
+}
+
+/// Check whether creating an alternate data stream failed because the file system has none.
+#[cfg(windows)]
+fn streams_unsupported(e: &io::Error) -> bool {
+    // ERROR_NOT_SUPPORTED, ERROR_INVALID_PARAMETER and ERROR_INVALID_NAME, FAT reports the
+    // stream name as invalid.
+    matches!(e.raw_os_error(), Some(50 | 87 | 123)) || e.kind() == io::ErrorKind::Unsupported
+}
+
+/// Copy the alternate data streams and the creation time of `src` to `dest`.
+#[cfg(windows)]
+fn copy_windows_metadata(src: &Path, dest: &Path) -> io::Result<()> {
+    use std::{
+        fs::{File, FileTimes},
+        os::windows::fs::FileTimesExt,
+    };
+
+    let streams = crate::sys::stream_names(src)?;
+
+    for name in streams {
+        // The unnamed stream holds the contents, which are already copied.
+        if name == "::$DATA" {
+            continue;
+        }
+        let mut src_stream = src.as_os_str().to_owned();
+        src_stream.push(&name);
+        let mut dest_stream = dest.as_os_str().to_owned();
+        dest_stream.push(&name);
+        let mut src_file = File::open(src_stream)?;
+        let mut dest_file = match File::create(dest_stream) {
+            Ok(file) => file,
+            // FAT and exFAT have no alternate data streams, the rest still applies.
+            Err(e) if streams_unsupported(&e) => {
+                log::warn!(
+                    "Not copying the alternate data streams of {}, the destination does not support them: {}",
+                    src.display(),
+                    e
+                );
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        io::copy(&mut src_file, &mut dest_file)?;
+    }
+
+    let created = std::fs::metadata(src)?.created()?;
+    File::options()
+        .write(true)
+        .open(dest)?
+        .set_times(FileTimes::new().set_created(created))
+}
@@ -0,0 +1,609 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    io,
+    path::{Component, Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll},
+    time::SystemTime,
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio_util::sync::CancellationToken;
+
+use super::{FileType, FsBackend, Metadata};
+
+/// Give up resolving links after this many hops, like `ELOOP`.
+const MAX_LINK_HOPS: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A kind of operation a [`Fault`] can be injected into.
+pub(crate) enum Op {
+    Metadata,
+    ReadDir,
+    CreateDir,
+    Open,
+    Read,
+    Create,
+    Write,
+    Rename,
+    Remove,
+    Link,
+    Reflink,
+    CopyRange,
+    Sync,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// What happens to an operation matched by [`MemoryFs::inject`].
+pub(crate) enum Fault {
+    /// Fail with an error of this kind.
+    Error(io::ErrorKind),
+    /// Fail with this raw OS error code.
+    Os(i32),
+    /// Never complete.
+    Stall,
+    /// End the file after this many bytes, only for [`Op::Read`] and [`Op::CopyRange`].
+    Short(usize),
+}
+
+struct Injected {
+    op: Op,
+    fault: Fault,
+    matches: Box<dyn Fn(&Path) -> bool + Send + Sync>,
+}
+
+#[derive(Clone)]
+enum Node {
+    Dir,
+    File { data: Vec<u8>, readonly: bool },
+    Symlink(PathBuf),
+}
+
+/// Hard links are entries sharing an id, each with its own copy of the contents.
+#[derive(Clone)]
+struct Entry {
+    id: u64,
+    modified: SystemTime,
+    node: Node,
+}
+
+#[derive(Default)]
+struct State {
+    entries: BTreeMap<PathBuf, Entry>,
+    next_id: u64,
+    faults: Vec<Injected>,
+}
+
+impl State {
+    fn insert(&mut self, path: PathBuf, node: Node) {
+        self.next_id += 1;
+        self.entries.insert(
+            path,
+            Entry {
+                id: self.next_id,
+                modified: SystemTime::now(),
+                node,
+            },
+        );
+    }
+
+    /// Resolve the links in `path`, the last component only if `follow` is set.
+    fn resolve(&self, path: &Path, follow: bool, hops: usize) -> io::Result<PathBuf> {
+        let components = path.components().collect::<Vec<_>>();
+        let mut resolved = PathBuf::new();
+        for (i, component) in components.iter().enumerate() {
+            match component {
+                Component::CurDir => continue,
+                Component::ParentDir => {
+                    resolved.pop();
+                    continue;
+                }
+                _ => resolved.push(component),
+            }
+            let last = i + 1 == components.len();
+            if let Some(Entry {
+                node: Node::Symlink(target),
+                ..
+            }) = self.entries.get(&resolved)
+            {
+                if last && !follow {
+                    break;
+                }
+                if hops >= MAX_LINK_HOPS {
+                    return Err(io::Error::other("too many levels of symbolic links"));
+                }
+                let target = resolved.parent().unwrap_or(Path::new("")).join(target);
+                resolved = self.resolve(&target, true, hops + 1)?;
+            }
+        }
+        Ok(resolved)
+    }
+
+    fn get(&self, path: &Path) -> io::Result<&Entry> {
+        self.entries
+            .get(path)
+            .ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        // Roots always exist.
+        path.parent().is_none()
+            || matches!(
+                self.entries.get(path),
+                Some(Entry {
+                    node: Node::Dir,
+                    ..
+                })
+            )
+    }
+
+    fn check_parent(&self, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if !self.is_dir(parent) => Err(io::ErrorKind::NotFound.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn fault(&self, op: Op, path: &Path) -> Option<Fault> {
+        self.faults
+            .iter()
+            .find(|f| f.op == op && (f.matches)(path))
+            .map(|f| f.fault)
+    }
+}
+
+#[derive(Clone, Default)]
+/// An in-memory [`FsBackend`] that can be told to fail at specific points.
+pub(crate) struct MemoryFs(Arc<Mutex<State>>);
+
+impl MemoryFs {
+    fn state(&self) -> MutexGuard<'_, State> {
+        match self.0.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Apply the fault injected for `op` on `path`, if any, except [`Fault::Short`].
+    async fn fault(&self, op: Op, path: &Path) -> io::Result<()> {
+        let fault = self.state().fault(op, path);
+        Self::apply(fault).await
+    }
+
+    /// Apply `fault`, except [`Fault::Short`].
+    async fn apply(fault: Option<Fault>) -> io::Result<()> {
+        match fault {
+            Some(Fault::Error(kind)) => Err(kind.into()),
+            Some(Fault::Os(code)) => Err(io::Error::from_raw_os_error(code)),
+            Some(Fault::Stall) => std::future::pending().await,
+            Some(Fault::Short(_)) | None => Ok(()),
+        }
+    }
+
+    /// Create the file `path` with `data`, and its parent directories.
+    pub(crate) fn write(&self, path: impl AsRef<Path>, data: impl Into<Vec<u8>>) {
+        let path = path.as_ref();
+        let mut state = self.state();
+        for dir in path.ancestors().skip(1) {
+            if dir.parent().is_some() && !state.entries.contains_key(dir) {
+                state.insert(dir.to_path_buf(), Node::Dir);
+            }
+        }
+        state.insert(
+            path.to_path_buf(),
+            Node::File {
+                data: data.into(),
+                readonly: false,
+            },
+        );
+    }
+
+    /// Get the contents of the file `path`.
+    pub(crate) fn read(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        match self.state().entries.get(path.as_ref()) {
+            Some(Entry {
+                node: Node::File { data, .. },
+                ..
+            }) => Some(data.clone()),
+            _ => None,
+        }
+    }
+
+    /// List every path that exists.
+    pub(crate) fn paths(&self) -> Vec<PathBuf> {
+        self.state().entries.keys().cloned().collect()
+    }
+
+    /// Make `op` on the paths `matches` accepts behave according to `fault`.
+    ///
+    /// The first matching fault wins.
+    pub(crate) fn inject(
+        &self,
+        op: Op,
+        fault: Fault,
+        matches: impl Fn(&Path) -> bool + Send + Sync + 'static,
+    ) {
+        self.state().faults.push(Injected {
+            op,
+            fault,
+            matches: Box::new(matches),
+        });
+    }
+
+    async fn stat(&self, path: &Path, follow: bool) -> io::Result<Metadata> {
+        self.fault(Op::Metadata, path).await?;
+        let state = self.state();
+        let path = state.resolve(path, follow, 0)?;
+        if path.parent().is_none() {
+            return Ok(Metadata {
+                file_type: FileType::Dir,
+                len: 0,
+                modified: None,
+                readonly: false,
+                id: Some((0, 0)),
+                links: 1,
+            });
+        }
+        let entry = state.get(&path)?;
+        let (file_type, len, readonly) = match &entry.node {
+            Node::Dir => (FileType::Dir, 0, false),
+            Node::File { data, readonly } => (FileType::File, data.len() as u64, *readonly),
+            Node::Symlink(target) => (FileType::Symlink, target.as_os_str().len() as u64, false),
+        };
+        let links = state.entries.values().filter(|e| e.id == entry.id).count();
+        Ok(Metadata {
+            file_type,
+            len,
+            modified: Some(entry.modified),
+            readonly,
+            id: Some((0, entry.id)),
+            links: links as u64,
+        })
+    }
+}
+
+impl FsBackend for MemoryFs {
+    type Reader = MemoryReader;
+    type Writer = MemoryWriter;
+
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.stat(path, true).await
+    }
+
+    async fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.stat(path, false).await
+    }
+
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        self.fault(Op::ReadDir, path).await?;
+        let state = self.state();
+        let path = state.resolve(path, true, 0)?;
+        if !state.is_dir(&path) {
+            state.get(&path)?;
+            return Err(io::ErrorKind::NotADirectory.into());
+        }
+        Ok(state
+            .entries
+            .keys()
+            .filter(|p| p.parent() == Some(&path))
+            .filter_map(|p| p.file_name().map(ToOwned::to_owned))
+            .collect())
+    }
+
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.fault(Op::CreateDir, path).await?;
+        let mut state = self.state();
+        let path = state.resolve(path, true, 0)?;
+        let mut missing = Vec::new();
+        for dir in path.ancestors().filter(|dir| dir.parent().is_some()) {
+            match state.entries.get(dir) {
+                Some(Entry {
+                    node: Node::Dir, ..
+                }) => break,
+                Some(_) => return Err(io::ErrorKind::NotADirectory.into()),
+                None => missing.push(dir.to_path_buf()),
+            }
+        }
+        for dir in missing.into_iter().rev() {
+            state.insert(dir, Node::Dir);
+        }
+        Ok(())
+    }
+
+    async fn open(&self, path: &Path) -> io::Result<Self::Reader> {
+        self.fault(Op::Open, path).await?;
+        let state = self.state();
+        let resolved = state.resolve(path, true, 0)?;
+        let Node::File { data, .. } = &state.get(&resolved)?.node else {
+            return Err(io::ErrorKind::IsADirectory.into());
+        };
+        Ok(MemoryReader {
+            data: data.clone(),
+            pos: 0,
+            fault: state.fault(Op::Read, path),
+        })
+    }
+
+    async fn create(&self, path: &Path) -> io::Result<Self::Writer> {
+        self.fault(Op::Create, path).await?;
+        let mut state = self.state();
+        let resolved = state.resolve(path, true, 0)?;
+        state.check_parent(&resolved)?;
+        match state.entries.get(&resolved).map(|e| &e.node) {
+            Some(Node::Dir) => return Err(io::ErrorKind::IsADirectory.into()),
+            Some(Node::File { readonly: true, .. }) => {
+                return Err(io::ErrorKind::PermissionDenied.into())
+            }
+            _ => {}
+        }
+        state.insert(
+            resolved.clone(),
+            Node::File {
+                data: Vec::new(),
+                readonly: false,
+            },
+        );
+        Ok(MemoryWriter {
+            fs: self.clone(),
+            path: resolved,
+            fault: state.fault(Op::Write, path),
+        })
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.fault(Op::Rename, from).await?;
+        let mut state = self.state();
+        let from = state.resolve(from, false, 0)?;
+        let to = state.resolve(to, false, 0)?;
+        state.get(&from)?;
+        state.check_parent(&to)?;
+        if state.is_dir(&to) {
+            return Err(io::ErrorKind::IsADirectory.into());
+        }
+        let moved = state
+            .entries
+            .keys()
+            .filter(|p| p.starts_with(&from))
+            .cloned()
+            .collect::<Vec<_>>();
+        for old in moved {
+            if let Some(entry) = state.entries.remove(&old) {
+                let new = to.join(old.strip_prefix(&from).unwrap_or(&old));
+                state.entries.insert(new, entry);
+            }
+        }
+        Ok(())
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.fault(Op::Remove, path).await?;
+        let mut state = self.state();
+        let path = state.resolve(path, false, 0)?;
+        if let Node::Dir = state.get(&path)?.node {
+            return Err(io::ErrorKind::IsADirectory.into());
+        }
+        state.entries.remove(&path);
+        Ok(())
+    }
+
+    async fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.fault(Op::Remove, path).await?;
+        let mut state = self.state();
+        let path = state.resolve(path, false, 0)?;
+        if !matches!(state.get(&path)?.node, Node::Dir) {
+            return Err(io::ErrorKind::NotADirectory.into());
+        }
+        if state.entries.keys().any(|p| p.parent() == Some(&path)) {
+            return Err(io::ErrorKind::DirectoryNotEmpty.into());
+        }
+        state.entries.remove(&path);
+        Ok(())
+    }
+
+    async fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.fault(Op::Metadata, path).await?;
+        let state = self.state();
+        let path = state.resolve(path, true, 0)?;
+        if !state.is_dir(&path) {
+            state.get(&path)?;
+        }
+        Ok(path)
+    }
+
+    async fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        self.fault(Op::Metadata, path).await?;
+        let state = self.state();
+        let path = state.resolve(path, false, 0)?;
+        match &state.get(&path)?.node {
+            Node::Symlink(target) => Ok(target.clone()),
+            _ => Err(io::ErrorKind::InvalidInput.into()),
+        }
+    }
+
+    async fn symlink(&self, target: &Path, link: &Path, _dir: bool) -> io::Result<()> {
+        self.fault(Op::Create, link).await?;
+        let mut state = self.state();
+        let link = state.resolve(link, false, 0)?;
+        state.check_parent(&link)?;
+        if state.entries.contains_key(&link) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        state.insert(link, Node::Symlink(target.to_path_buf()));
+        Ok(())
+    }
+
+    async fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+        self.fault(Op::Link, link).await?;
+        let mut state = self.state();
+        let original = state.resolve(original, false, 0)?;
+        let link = state.resolve(link, false, 0)?;
+        state.check_parent(&link)?;
+        if state.entries.contains_key(&link) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        let entry = state.get(&original)?.clone();
+        if !matches!(entry.node, Node::File { .. }) {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        state.entries.insert(link, entry);
+        Ok(())
+    }
+
+    async fn reflink(&self, src: &Path, dest: &Path) -> io::Result<()> {
+        self.fault(Op::Reflink, dest).await?;
+        let mut state = self.state();
+        let src = state.resolve(src, true, 0)?;
+        let dest = state.resolve(dest, false, 0)?;
+        state.check_parent(&dest)?;
+        if state.entries.contains_key(&dest) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        let node = state.get(&src)?.node.clone();
+        if !matches!(node, Node::File { .. }) {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        state.insert(dest, node);
+        Ok(())
+    }
+
+    async fn set_modified(&self, path: &Path, modified: SystemTime) -> io::Result<()> {
+        self.fault(Op::Metadata, path).await?;
+        let mut state = self.state();
+        let path = state.resolve(path, true, 0)?;
+        match state.entries.get_mut(&path) {
+            Some(entry) => {
+                entry.modified = modified;
+                Ok(())
+            }
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    async fn sync_file(&self, file: &mut MemoryWriter) -> io::Result<()> {
+        self.fault(Op::Sync, &file.path).await
+    }
+
+    async fn sync_path(&self, path: &Path) -> io::Result<()> {
+        self.fault(Op::Sync, path).await
+    }
+
+    async fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        self.fault(Op::Sync, path).await
+    }
+
+    async fn copy_range(
+        &self,
+        src: &mut MemoryReader,
+        dest: &mut MemoryWriter,
+        chunk: u64,
+        _cancel: &CancellationToken,
+    ) -> io::Result<flume::Receiver<io::Result<u64>>> {
+        let fault = self.state().fault(Op::CopyRange, &dest.path);
+        Self::apply(fault).await?;
+        let end = match fault {
+            Some(Fault::Short(n)) => n,
+            _ => usize::MAX,
+        };
+        // All at once through both files, so their faults apply as to a streamed copy.
+        let (tx, rx) = flume::unbounded();
+        loop {
+            let left = src.data.len().min(end).saturating_sub(src.pos);
+            let mut buf = vec![0; usize::try_from(chunk).unwrap_or(usize::MAX).min(left)];
+            let result = match src.read(&mut buf).await {
+                Ok(n) => dest.write_all(&buf[..n]).await.map(|()| n as u64),
+                Err(e) => Err(e),
+            };
+            let more = matches!(result, Ok(n) if n > 0);
+            // Never dropped here.
+            let _ = tx.send(result);
+            if !more {
+                break;
+            }
+        }
+        Ok(rx)
+    }
+
+    async fn remove_link(&self, path: &Path) -> io::Result<()> {
+        self.fault(Op::Remove, path).await?;
+        let mut state = self.state();
+        let path = state.resolve(path, false, 0)?;
+        if !matches!(state.get(&path)?.node, Node::Symlink(_)) {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        state.entries.remove(&path);
+        Ok(())
+    }
+}
+
+/// A file of a [`MemoryFs`] opened for reading, holding a snapshot of its contents.
+pub(crate) struct MemoryReader {
+    data: Vec<u8>,
+    pos: usize,
+    fault: Option<Fault>,
+}
+
+impl AsyncRead for MemoryReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let end = match self.fault {
+            Some(Fault::Error(kind)) => return Poll::Ready(Err(kind.into())),
+            Some(Fault::Os(code)) => return Poll::Ready(Err(io::Error::from_raw_os_error(code))),
+            Some(Fault::Stall) => return Poll::Pending,
+            Some(Fault::Short(len)) => len.min(self.data.len()),
+            None => self.data.len(),
+        };
+        let start = self.pos.min(end);
+        let n = buf.remaining().min(end - start);
+        buf.put_slice(&self.data[start..start + n]);
+        self.pos = start + n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// A file of a [`MemoryFs`] opened for writing, writes go straight to the file.
+pub(crate) struct MemoryWriter {
+    fs: MemoryFs,
+    path: PathBuf,
+    fault: Option<Fault>,
+}
+
+impl AsyncWrite for MemoryWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.fault {
+            Some(Fault::Error(kind)) => return Poll::Ready(Err(kind.into())),
+            Some(Fault::Os(code)) => return Poll::Ready(Err(io::Error::from_raw_os_error(code))),
+            Some(Fault::Stall) => return Poll::Pending,
+            Some(Fault::Short(_)) | None => {}
+        }
+        let mut state = self.fs.state();
+        match state.entries.get_mut(&self.path) {
+            Some(Entry {
+                node: Node::File { data, .. },
+                modified,
+                ..
+            }) => {
+                data.extend_from_slice(buf);
+                *modified = SystemTime::now();
+                Poll::Ready(Ok(buf.len()))
+            }
+            _ => Poll::Ready(Err(io::ErrorKind::NotFound.into())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
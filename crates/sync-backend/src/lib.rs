@@ -4,114 +4,888 @@
 //! A library for synchronizing files between two directories.
 
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{
+    borrow::Cow,
+    ffi::OsString,
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
+    time::Duration,
+};
+use volume_tracker::{Device, DriveType, FileSystem};
 
+/// File system backends for the synchronization.
+pub mod fs;
 /// File synchronization module.
 pub mod sync;
+/// The platform calls that need unsafe code.
+#[allow(unsafe_code)]
+mod sys;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Name of the marker file placed in the root of a volume to pin its identity.
+pub const MARKER_FILE_NAME: &str = ".sync-id";
+
+/// Get the root directory of a mount path.
+///
+/// Drive letters are reported without a trailing separator (like 'D:'), which
+/// would make joined paths relative to the current directory of that drive.
+pub fn volume_root(mount_path: &Path) -> PathBuf {
+    let mut root = OsString::from(mount_path);
+    if !root.to_string_lossy().ends_with(std::path::is_separator) {
+        root.push(std::path::MAIN_SEPARATOR_STR);
+    }
+    PathBuf::from(root)
+}
+
+/// Make a path immune to `MAX_PATH` on Windows by making it absolute with a `\\?\` prefix.
+///
+/// UNC paths get the `\\?\UNC\` prefix, paths are returned unchanged on other platforms.
+pub fn long_path(path: &Path) -> std::io::Result<PathBuf> {
+    #[cfg(windows)]
+    {
+        // Verbatim paths skip normalization, so `.` and `..` must be resolved first.
+        let path = std::path::absolute(path)?;
+        Ok(match path.to_str() {
+            Some(s) => PathBuf::from(to_verbatim(s)),
+            None => path,
+        })
+    }
+
+    #[cfg(not(windows))]
+    Ok(path.to_path_buf())
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+fn to_verbatim(path: &str) -> String {
+    if path.starts_with(r"\\?\") || path.starts_with(r"\\.\") {
+        path.to_string()
+    } else if let Some(unc) = path.strip_prefix(r"\\") {
+        format!(r"\\?\UNC\{}", unc)
+    } else {
+        format!(r"\\?\{}", path)
+    }
+}
+
+/// Strip the prefix added by [`long_path`] for display, paths without a plain form are kept.
+pub fn display_path(path: &Path) -> Cow<'_, Path> {
+    match path.to_str().and_then(from_verbatim) {
+        Some(plain) if cfg!(windows) => Cow::Owned(PathBuf::from(plain)),
+        _ => Cow::Borrowed(path),
+    }
+}
+
+fn from_verbatim(path: &str) -> Option<String> {
+    if let Some(unc) = path.strip_prefix(r"\\?\UNC\") {
+        return Some(format!(r"\\{}", unc));
+    }
+    // Volume GUID paths like `\\?\Volume{...}\` have no plain form.
+    let rest = path.strip_prefix(r"\\?\")?;
+    let drive = rest.as_bytes();
+    (drive.len() >= 2 && drive[0].is_ascii_alphabetic() && drive[1] == b':')
+        .then(|| rest.to_string())
+}
+
+/// Expand a leading `~`, `$VAR` and `${VAR}` in a path, and `%VAR%` if `percent_vars` is set.
+///
+/// Variables are resolved with `lookup`, the home directory is taken from `HOME` or `USERPROFILE`.
+pub fn expand_path(
+    path: &Path,
+    lookup: impl Fn(&str) -> Option<String>,
+    percent_vars: bool,
+) -> Result<PathBuf, String> {
+    let Some(s) = path.to_str() else {
+        return Ok(path.to_path_buf());
+    };
+
+    let expanded = match s.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => {
+            let home = lookup("HOME")
+                .or_else(|| lookup("USERPROFILE"))
+                .ok_or_else(|| format!("Cannot expand ~ in {}, home directory is not set", s))?;
+            format!("{}{}", home, rest)
+        }
+        _ => s.to_string(),
+    };
+
+    expand_vars(&expanded, &lookup, percent_vars)
+        .map(PathBuf::from)
+        .map_err(|e| format!("Cannot expand {}: {}", s, e))
+}
+
+fn expand_vars(
+    input: &str,
+    lookup: &impl Fn(&str) -> Option<String>,
+    percent_vars: bool,
+) -> Result<String, String> {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(i) = rest.find(|c| c == '$' || (percent_vars && c == '%')) {
+        out.push_str(&rest[..i]);
+        let tail = &rest[i..];
+
+        let var = if let Some(t) = tail.strip_prefix("${") {
+            t.find('}').map(|end| (&t[..end], end + 3))
+        } else if let Some(t) = tail.strip_prefix('$') {
+            let len = t
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(t.len());
+            (len > 0).then(|| (&t[..len], len + 1))
+        } else {
+            let t = &tail[1..];
+            t.find('%')
+                .filter(|end| *end > 0)
+                .map(|end| (&t[..end], end + 2))
+        };
+
+        match var {
+            Some((name, consumed)) => {
+                let value = lookup(name)
+                    .ok_or_else(|| format!("environment variable {} is not set", name))?;
+                out.push_str(&value);
+                rest = &tail[consumed..];
+            }
+            // Not a variable reference, keep the character as is.
+            None => {
+                out.push_str(&tail[..1]);
+                rest = &tail[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+/// Read the identifier stored in the marker file of a volume, if any.
+pub fn read_marker_id(mount_path: &Path) -> Option<String> {
+    let path = volume_root(mount_path).join(MARKER_FILE_NAME);
+    match std::fs::read_to_string(&path) {
+        Ok(id) => Some(id.trim().to_string()),
+        Err(e) => {
+            log::debug!("No marker file at {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 /// Configuration for the synchronization.
 pub struct Config {
     /// Pairs of directories to synchronize.
     pub pairs: Vec<SyncPairs>,
+    /// Number of concurrent file operations across all pairs, unlimited if not set.
+    ///
+    /// The concurrency of each pair still applies within this limit, see [`sync::SharedLimit`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub global_concurrency: Option<usize>,
+    /// Don't show a desktop notification when a sync finishes or is interrupted, only used by
+    /// the app.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub disable_notifications: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Serialization format of a configuration file.
+pub enum ConfigFormat {
+    /// YAML, the default.
+    Yaml,
+    /// JSON.
+    Json,
+}
+
+impl ConfigFormat {
+    /// Guess the format from the extension of a path, `None` if the extension is not recognized.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?;
+        ext.parse().ok()
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "yaml" | "yml" => Ok(Self::Yaml),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Unsupported config format: {}", s)),
+        }
+    }
+}
+
+impl Display for ConfigFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Yaml => write!(f, "YAML"),
+            Self::Json => write!(f, "JSON"),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+/// Errors that can occur while loading a configuration file.
+pub enum ConfigError {
+    #[error("Failed to read config file {0}")]
+    /// Failed to read the configuration file.
+    Io(PathBuf, #[source] std::io::Error),
+    #[error("Failed to parse config file {path} as {format}{}", note.as_deref().unwrap_or_default())]
+    /// The configuration file is not valid in the chosen format.
+    #[allow(missing_docs)]
+    Parse {
+        path: PathBuf,
+        format: ConfigFormat,
+        note: Option<String>,
+        #[source]
+        err: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("Failed to serialize config as {0}")]
+    /// The configuration could not be serialized in the chosen format.
+    Serialize(
+        ConfigFormat,
+        #[source] Box<dyn std::error::Error + Send + Sync>,
+    ),
+    #[error("Failed to write config file {0}")]
+    /// Failed to write the configuration file.
+    Write(PathBuf, #[source] std::io::Error),
 }
 
 impl Config {
+    /// Load a configuration file, filling in the default concurrency of the pairs that leave it
+    /// out.
+    ///
+    /// The format is guessed from the file extension unless `format` is given,
+    /// falling back to YAML for unknown extensions.
+    pub fn load(path: &Path, format: Option<ConfigFormat>) -> Result<Self, ConfigError> {
+        let mut config = Self::read(path, format)?;
+        for pair in &mut config.pairs {
+            pair.concurrency
+                .get_or_insert_with(sync::default_concurrency);
+        }
+
+        Ok(config)
+    }
+
+    /// Read a configuration file as written, like [`Config::load`] but leaving unset options
+    /// unset so it can be saved back unchanged.
+    pub fn read(path: &Path, format: Option<ConfigFormat>) -> Result<Self, ConfigError> {
+        let content =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+
+        let (format, note) = match format.or_else(|| ConfigFormat::from_path(path)) {
+            Some(format) => (format, None),
+            None => {
+                let ext = path
+                    .extension()
+                    .map_or_else(|| "(none)".into(), |e| e.to_string_lossy());
+                log::warn!("Unrecognized config extension {}, assuming YAML", ext);
+                (
+                    ConfigFormat::Yaml,
+                    Some(format!(" (unrecognized extension {})", ext)),
+                )
+            }
+        };
+
+        let parsed = match format {
+            ConfigFormat::Yaml => serde_yaml::from_str(&content).map_err(Into::into),
+            ConfigFormat::Json => serde_json::from_str(&content).map_err(Into::into),
+        };
+        parsed.map_err(|err| ConfigError::Parse {
+            path: path.to_path_buf(),
+            format,
+            note,
+            err,
+        })
+    }
+
+    /// Write the configuration to a file, replacing it.
+    ///
+    /// The format is chosen like in [`Config::load`]. The file is written next to `path` first so
+    /// a crash cannot leave a truncated config behind.
+    pub fn save(&self, path: &Path, format: Option<ConfigFormat>) -> Result<(), ConfigError> {
+        let format = format
+            .or_else(|| ConfigFormat::from_path(path))
+            .unwrap_or(ConfigFormat::Yaml);
+        let content = match format {
+            ConfigFormat::Yaml => serde_yaml::to_string(self).map_err(Into::into),
+            ConfigFormat::Json => serde_json::to_string_pretty(self).map_err(Into::into),
+        }
+        .map_err(|err| ConfigError::Serialize(format, err))?;
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        std::fs::write(&tmp, content)
+            .and_then(|()| std::fs::rename(&tmp, path))
+            .map_err(|e| {
+                let _ = std::fs::remove_file(&tmp);
+                ConfigError::Write(path.to_path_buf(), e)
+            })
+    }
+
+    /// Expand `~` and environment variables in the source and destination paths.
+    ///
+    /// This should run before [`Config::validate`] so validation sees the real paths.
+    pub fn resolve_paths(&mut self) -> Result<(), String> {
+        let lookup = |name: &str| std::env::var(name).ok();
+        for (i, pair) in self.pairs.iter_mut().enumerate() {
+            for path in &mut pair.src.paths {
+                *path = expand_path(path, lookup, cfg!(windows))
+                    .map_err(|e| format!("Pair {}: Source: {}", i, e))?;
+            }
+            pair.dest.path = expand_path(&pair.dest.path, lookup, cfg!(windows))
+                .map_err(|e| format!("Pair {}: Destination: {}", i, e))?;
+        }
+
+        Ok(())
+    }
+
     /// Validate the configuration.
     pub fn validate(&self) -> Result<(), String> {
+        if self.global_concurrency == Some(0) {
+            return Err("Global concurrency must be greater than 0".to_string());
+        }
         for (i, pair) in self.pairs.iter().enumerate() {
             pair.validate().map_err(|e| format!("Pair {}: {}", i, e))?;
         }
 
         Ok(())
     }
+
+    /// Pairs that should be synced with the volume mounted at `mount_paths`, each resolved against
+    /// the first mount path it matches.
+    pub fn for_volume(
+        &self,
+        volume: &impl FileSystem,
+        device: &impl Device,
+        mount_paths: &[PathBuf],
+    ) -> Self {
+        // Fixed and remote drives are only synced when a pair asks for them explicitly, checked
+        // first since anything else may touch a network share whose server is down.
+        let explicit = volume
+            .drive_type()
+            .filter(|t| matches!(t, DriveType::Fixed | DriveType::Remote));
+        Self {
+            pairs: self
+                .pairs
+                .iter()
+                .filter_map(|pair| {
+                    let r#match = pair.volume_match()?;
+                    if explicit.is_some_and(|t| r#match.drive_type != Some(t)) {
+                        return None;
+                    }
+                    mount_paths
+                        .iter()
+                        .find(|mount_path| r#match.matches(volume, device, mount_path))
+                        .map(|mount_path| pair.on_volume(mount_path))
+                })
+                .collect(),
+            global_concurrency: self.global_concurrency,
+            disable_notifications: self.disable_notifications,
+        }
+    }
+
+    /// Check if a pair asks for volumes on drives of type `drive_type`.
+    ///
+    /// Fixed and remote drives are skipped unless one does, see [`Config::for_volume`].
+    pub fn asks_for(&self, drive_type: DriveType) -> bool {
+        self.pairs
+            .iter()
+            .filter_map(SyncPairs::volume_match)
+            .any(|m| m.drive_type == Some(drive_type))
+    }
+
+    /// Compare the pairs of two configurations.
+    ///
+    /// A pair that changed in any way is reported as removed from `self` and added in `other`.
+    pub fn diff<'a>(&'a self, other: &'a Config) -> ConfigDiff<'a> {
+        ConfigDiff {
+            added: other
+                .pairs
+                .iter()
+                .filter(|pair| !self.pairs.contains(pair))
+                .collect(),
+            removed: self
+                .pairs
+                .iter()
+                .filter(|pair| !other.pairs.contains(pair))
+                .collect(),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Pairs that differ between two configurations, see [`Config::diff`].
+pub struct ConfigDiff<'a> {
+    /// Pairs only present in the new configuration.
+    pub added: Vec<&'a SyncPairs>,
+    /// Pairs only present in the old configuration.
+    pub removed: Vec<&'a SyncPairs>,
+}
+
+impl ConfigDiff<'_> {
+    /// Check if both configurations have the same pairs.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 /// A pair of directories to synchronize.
 pub struct SyncPairs {
     /// Source directory.
     pub src: SyncPairSource,
     /// Destination directory.
     pub dest: SyncPairDest,
-    /// Number of concurrent file operations.
-    pub concurrency: usize,
+    /// Which side is on the matched volume, syncing from the volume by default.
+    #[serde(default)]
+    pub direction: SyncDirection,
+    /// Number of concurrent file operations, [`sync::default_concurrency`] if not set.
+    ///
+    /// Filled in by [`Config::load`] so configs stay portable across machines.
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+    /// Options for the synchronization.
+    #[serde(flatten)]
+    pub options: sync::SyncOptions,
+    /// Re-run the synchronization periodically while the volume stays mounted, like `15m`.
+    #[serde(default, with = "humantime_serde")]
+    pub interval: Option<Duration>,
+    /// Eject the volume once the synchronization onto it is done, only used by the app with
+    /// direction `to_volume`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub eject_when_done: bool,
+}
+
+impl Display for SyncPairs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, path) in self.src.paths.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", path.display())?;
+        }
+        write!(f, " -> {}", self.dest.path.display())
+    }
 }
 
 impl SyncPairs {
     /// Validate the configuration.
     pub fn validate(&self) -> Result<(), String> {
-        if self.concurrency == 0 {
+        if self.concurrency == Some(0) {
             return Err("Concurrency must be greater than 0".to_string());
         }
+        if self.interval.is_some_and(|interval| interval.is_zero()) {
+            return Err("Interval must be greater than 0".to_string());
+        }
+        if self
+            .options
+            .timeout
+            .is_some_and(|timeout| timeout.is_zero())
+        {
+            return Err("Timeout must be greater than 0".to_string());
+        }
+        if self
+            .options
+            .stat_timeout
+            .is_some_and(|timeout| timeout.is_zero())
+        {
+            return Err("Stat timeout must be greater than 0".to_string());
+        }
+        if self.src.paths.is_empty() {
+            return Err("Source: No path specified".to_string());
+        }
+        if self.eject_when_done {
+            if self.direction != SyncDirection::ToVolume {
+                return Err("Eject when done is only used with direction to_volume".to_string());
+            }
+            if self.interval.is_some() {
+                return Err("Eject when done cannot be used with an interval".to_string());
+            }
+        }
 
-        self.src
-            .r#match
+        let (side, r#match) = match self.direction {
+            SyncDirection::FromVolume if self.dest.r#match.is_some() => {
+                return Err("Destination match is only used with direction to_volume".to_string())
+            }
+            SyncDirection::ToVolume if self.src.r#match.is_some() => {
+                return Err("Source match is only used with direction from_volume".to_string())
+            }
+            SyncDirection::FromVolume => ("Source", &self.src.r#match),
+            SyncDirection::ToVolume => ("Destination", &self.dest.r#match),
+        };
+        r#match
+            .as_ref()
+            .ok_or_else(|| format!("{}: No match specified", side))?
             .validate()
-            .map_err(|e| format!("Source: {}", e))
+            .map_err(|e| format!("{}: {}", side, e))
+    }
+
+    /// Check that the side of the pair not on the volume is an existing directory, returning the
+    /// index of the offending path on that side, the path and the reason otherwise.
+    ///
+    /// Meant to run right before a sync rather than when loading the config, since the local side
+    /// may itself be on removable media that is not mounted yet.
+    pub fn check_paths(&self) -> Result<(), (usize, PathBuf, String)> {
+        let (side, paths) = match self.direction {
+            SyncDirection::FromVolume => ("Destination", std::slice::from_ref(&self.dest.path)),
+            SyncDirection::ToVolume => ("Source", self.src.paths.as_slice()),
+        };
+        for (i, path) in paths.iter().enumerate() {
+            match std::fs::metadata(path) {
+                Ok(meta) if meta.is_dir() => {}
+                Ok(_) => return Err((i, path.clone(), format!("{} is not a directory", side))),
+                Err(e) => return Err((i, path.clone(), format!("{}: {}", side, e))),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the match configuration of the side on the volume.
+    pub fn volume_match(&self) -> Option<&DeviceMatchConfig> {
+        match self.direction {
+            SyncDirection::FromVolume => self.src.r#match.as_ref(),
+            SyncDirection::ToVolume => self.dest.r#match.as_ref(),
+        }
+    }
+
+    /// Resolve the pair against the volume mounted at `mount_path`.
+    ///
+    /// The path on the volume side is taken relative to the volume root, absolute paths are kept.
+    pub fn on_volume(&self, mount_path: &Path) -> SyncPairs {
+        let mut pair = self.clone();
+        let root = volume_root(mount_path);
+        match self.direction {
+            SyncDirection::FromVolume => {
+                for path in &mut pair.src.paths {
+                    *path = root.join(&*path);
+                }
+            }
+            SyncDirection::ToVolume => pair.dest.path = root.join(&pair.dest.path),
+        }
+        pair
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 /// Source directory to synchronize.
 pub struct SyncPairSource {
-    /// Device match configuration.
-    pub r#match: DeviceMatchConfig,
-    /// Path to synchronize.
-    pub path: PathBuf,
+    /// Device match configuration, used when syncing from a volume.
+    #[serde(default)]
+    pub r#match: Option<DeviceMatchConfig>,
+    /// Paths to synchronize, relative to the volume root when syncing from a volume.
+    ///
+    /// Written as `path` in the configuration, either a single path or a list of paths merged
+    /// into the destination.
+    #[serde(rename = "path", deserialize_with = "one_or_many")]
+    pub paths: Vec<PathBuf>,
+}
+
+fn one_or_many<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<PathBuf>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(path) => vec![path],
+        OneOrMany::Many(paths) => paths,
+    })
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Which side of a pair is on the matched volume.
+pub enum SyncDirection {
+    /// Copy from the matched volume to the destination.
+    #[default]
+    FromVolume,
+    /// Copy from the source onto the matched volume.
+    ToVolume,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+/// A regular expression in the configuration, compiled on first use.
+pub struct RegexPattern {
+    pattern: String,
+    regex: OnceLock<Result<regex::Regex, regex::Error>>,
+}
+
+impl RegexPattern {
+    /// Create a pattern from its source text, the pattern is not compiled yet.
+    pub fn new(pattern: impl Into<String>) -> Self {
+        Self {
+            pattern: pattern.into(),
+            regex: OnceLock::new(),
+        }
+    }
+
+    /// Get the source text of the pattern.
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Get the compiled regular expression, compiling it on first use.
+    pub fn regex(&self) -> Result<&regex::Regex, &regex::Error> {
+        self.regex
+            .get_or_init(|| regex::Regex::new(&self.pattern))
+            .as_ref()
+    }
+
+    /// Check if the pattern matches anywhere in `haystack`, an invalid pattern matches nothing.
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.regex().is_ok_and(|r| r.is_match(haystack))
+    }
+}
+
+impl PartialEq for RegexPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl Eq for RegexPattern {}
+
+impl std::hash::Hash for RegexPattern {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.pattern.hash(state);
+    }
+}
+
+impl From<String> for RegexPattern {
+    fn from(pattern: String) -> Self {
+        Self::new(pattern)
+    }
+}
+
+impl From<RegexPattern> for String {
+    fn from(pattern: RegexPattern) -> Self {
+        pattern.pattern
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 /// Device match configuration.
+///
+/// A literal name and a regular expression for the same target are mutually exclusive.
+/// Regular expressions match anywhere in the name, use `^` and `$` to anchor them.
 pub struct DeviceMatchConfig {
     /// Volume name.
     pub volume: Option<String>,
+    /// Regular expression for the volume name.
+    #[serde(default)]
+    pub volume_regex: Option<RegexPattern>,
     /// Device name.
     pub device: Option<String>,
+    /// Regular expression for the device name.
+    #[serde(default)]
+    pub device_regex: Option<RegexPattern>,
+    /// Human readable volume label, like 'BACKUP_USB'.
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Regular expression for the volume label, like '^PHOTOS_\d{4}$'.
+    #[serde(default)]
+    pub label_regex: Option<RegexPattern>,
+    /// Volume serial number, like 'A1B2-C3D4', compared ignoring case and dashes.
+    ///
+    /// The serial number is stable across machines and drive letters but changes when the volume
+    /// is reformatted.
+    #[serde(default)]
+    pub serial: Option<String>,
+    /// Identifier of the volume that survives remounts, compared case-insensitively.
+    ///
+    /// On Windows this is the unique ID the Mount Manager reports, which unlike the volume name
+    /// does not change across sessions and survives a reformat in place.
+    #[serde(default)]
+    pub volume_id: Option<String>,
+    /// Identifier stored in the [`MARKER_FILE_NAME`] file in the root of the volume.
+    pub marker_id: Option<String>,
+    /// File system type, like 'exFAT', compared case-insensitively.
+    #[serde(default)]
+    pub fs_type: Option<String>,
+    /// Kind of drive, like `removable` or `fixed`.
+    #[serde(default)]
+    pub drive_type: Option<DriveType>,
+    /// Path the volume is mounted at, like 'C:\mnt\backup', for volumes mounted at several paths.
+    ///
+    /// Paths on the volume side are then resolved against it rather than the first mount path.
+    #[serde(default)]
+    pub mount_path: Option<PathBuf>,
+}
+
+/// Check whether two mount paths are the same, ignoring case on Windows.
+fn same_mount_path(a: &Path, b: &Path) -> bool {
+    let (a, b) = (volume_root(a), volume_root(b));
+    if cfg!(windows) {
+        a.to_string_lossy()
+            .eq_ignore_ascii_case(&b.to_string_lossy())
+    } else {
+        a == b
+    }
 }
 
 impl DeviceMatchConfig {
-    /// Check if the volume and/or device names match.
-    pub fn matches(&self, volume_name: &str, device_name: &str) -> bool {
-        if let Some(ref volume) = self.volume {
-            if volume != volume_name {
+    /// Check if all of the configured matchers match, matchers left unset are ignored.
+    ///
+    /// Volume properties are only queried for the matchers that are set.
+    pub fn matches(
+        &self,
+        volume: &impl FileSystem,
+        device: &impl Device,
+        mount_path: &Path,
+    ) -> bool {
+        if let Some(ref expected) = self.volume {
+            if expected != volume.name() {
                 return false;
             }
         }
-        if let Some(ref device) = self.device {
-            if device != device_name {
+        if let Some(ref pattern) = self.volume_regex {
+            if !pattern.is_match(volume.name()) {
+                return false;
+            }
+        }
+        if let Some(ref expected) = self.device {
+            if expected != device.name() {
+                return false;
+            }
+        }
+        if let Some(ref pattern) = self.device_regex {
+            if !pattern.is_match(device.name()) {
+                return false;
+            }
+        }
+        if self.label.is_some() || self.label_regex.is_some() {
+            let label = volume.label();
+            if let Some(ref expected) = self.label {
+                if label.as_ref() != Some(expected) {
+                    return false;
+                }
+            }
+            if let Some(ref pattern) = self.label_regex {
+                if !label.is_some_and(|l| pattern.is_match(&l)) {
+                    return false;
+                }
+            }
+        }
+        if let Some(ref expected) = self.serial {
+            if volume.serial().as_deref().map(normalize_serial) != Some(normalize_serial(expected))
+            {
+                return false;
+            }
+        }
+        if let Some(ref expected) = self.volume_id {
+            if !volume
+                .persistent_id()
+                .is_some_and(|id| id.eq_ignore_ascii_case(expected))
+            {
+                return false;
+            }
+        }
+        if let Some(ref expected) = self.fs_type {
+            if !volume
+                .fs_type()
+                .is_some_and(|t| t.eq_ignore_ascii_case(expected))
+            {
+                return false;
+            }
+        }
+        if let Some(expected) = self.drive_type {
+            if volume.drive_type() != Some(expected) {
+                return false;
+            }
+        }
+        if let Some(ref expected) = self.mount_path {
+            if !same_mount_path(expected, mount_path) {
+                return false;
+            }
+        }
+        if let Some(ref marker_id) = self.marker_id {
+            if read_marker_id(mount_path).as_ref() != Some(marker_id) {
                 return false;
             }
         }
         true
     }
+
     /// Validate the configuration.
     pub fn validate(&self) -> Result<(), String> {
-        if self.volume.is_none() && self.device.is_none() {
-            return Err("At least one of volume or device must be specified".to_string());
+        if self.volume.is_none()
+            && self.volume_regex.is_none()
+            && self.device.is_none()
+            && self.device_regex.is_none()
+            && self.label.is_none()
+            && self.label_regex.is_none()
+            && self.serial.is_none()
+            && self.volume_id.is_none()
+            && self.marker_id.is_none()
+        {
+            return Err(
+                "At least one of volume, device, label, serial, volume_id or marker_id must be \
+                 specified"
+                    .to_string(),
+            );
+        }
+
+        for (name, literal, pattern) in [
+            ("volume", self.volume.is_some(), &self.volume_regex),
+            ("device", self.device.is_some(), &self.device_regex),
+            ("label", self.label.is_some(), &self.label_regex),
+        ] {
+            let Some(pattern) = pattern else {
+                continue;
+            };
+            if literal {
+                return Err(format!("{name} and {name}_regex are mutually exclusive"));
+            }
+            if let Err(e) = pattern.regex() {
+                return Err(format!(
+                    "Invalid {}_regex {:?}: {}",
+                    name,
+                    pattern.as_str(),
+                    e
+                ));
+            }
         }
 
         Ok(())
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+fn normalize_serial(serial: &str) -> String {
+    serial
+        .chars()
+        .filter(|c| *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 /// Destination directory to synchronize.
 pub struct SyncPairDest {
-    /// Path to synchronize (absolute).
+    /// Device match configuration, used when syncing to a volume.
+    #[serde(default)]
+    pub r#match: Option<DeviceMatchConfig>,
+    /// Path to synchronize, relative to the volume root when syncing to a volume.
     pub path: PathBuf,
 }
 
 #[derive(Debug, thiserror::Error)]
 /// Errors that can occur during synchronization.
 pub enum SyncError {
-    #[error("Failed to stat {0}")]
+    #[error("Failed to stat {}", display_path(.0).display())]
     /// Failed to stat a file.
     StatFailed(PathBuf, #[source] std::io::Error),
     #[error("Operation cancelled")]
     /// Operation was cancelled.
     Cancelled,
-    #[error("Failed to copy {src} to {dest}")]
+    #[error("Failed to copy {} to {}", display_path(src).display(), display_path(dest).display())]
     /// Failed to copy a file.
     #[allow(missing_docs)]
     CopyFailed {
@@ -120,7 +894,20 @@ pub enum SyncError {
         #[source]
         err: tokio::io::Error,
     },
-    #[error("Short copy from {src} to {dest}, copied {copied} bytes, expected {expected}")]
+    #[error("Destination is full, failed to write {}", display_path(dest).display())]
+    /// The destination ran out of space while copying a file, nothing more is copied to it.
+    #[allow(missing_docs)]
+    DiskFull {
+        src: PathBuf,
+        dest: PathBuf,
+        #[source]
+        err: tokio::io::Error,
+    },
+    #[error(
+        "Short copy from {} to {}, copied {copied} bytes, expected {expected}",
+        display_path(src).display(),
+        display_path(dest).display()
+    )]
     /// A copy operation was short, maybe a file was modified during the copy or a file system error
     #[allow(missing_docs)]
     ShortCopy {
@@ -129,7 +916,774 @@ pub enum SyncError {
         copied: u64,
         expected: u64,
     },
+    #[error(
+        "{} may not have enough space, {needed} bytes to copy but only {available} available",
+        display_path(dest).display()
+    )]
+    /// The destination is unlikely to fit the files to copy, the synchronization continues anyway
+    /// since files being replaced free up space.
+    #[allow(missing_docs)]
+    InsufficientSpace {
+        dest: PathBuf,
+        needed: u64,
+        available: u64,
+    },
+    #[error(
+        "{path} exists in both {} and {}",
+        display_path(first).display(),
+        display_path(second).display()
+    )]
+    /// A file exists in more than one source and collisions are errors, only the first is copied.
+    #[allow(missing_docs)]
+    Collision {
+        path: PathBuf,
+        first: PathBuf,
+        second: PathBuf,
+    },
+    #[error(
+        "{} does not match {} after copying",
+        display_path(dest).display(),
+        display_path(src).display()
+    )]
+    /// The copied file read back differently from the source, the copy was removed.
+    #[allow(missing_docs)]
+    VerifyFailed { src: PathBuf, dest: PathBuf },
+    #[error("{} is in use by another process", display_path(src).display())]
+    /// The source file is held by another process, it is skipped rather than failed, see
+    /// [`sync::SyncSummary::locked_paths`].
+    #[allow(missing_docs)]
+    SourceLocked {
+        src: PathBuf,
+        dest: PathBuf,
+        #[source]
+        err: tokio::io::Error,
+    },
+    #[error("{} was removed before it could be copied", display_path(.0).display())]
+    /// The source file disappeared after discovery, it is left out of the totals rather than
+    /// failed, see [`sync::SyncSummary::files_gone`].
+    SourceGone(PathBuf),
+    #[error("{} is a {1}, it cannot be copied", display_path(.0).display())]
+    /// A source entry is neither a file, a directory nor a symbolic link and special files are
+    /// errors, see [`sync::SyncOptions::special_files`].
+    SpecialFile(PathBuf, fs::FileType),
+    #[error("Failed to read the ignore rules in {}", display_path(.0).display())]
+    /// A `.syncignore` file could not be read or has an invalid pattern, the directory is walked
+    /// with the rules of its parents, see [`sync::SyncOptions::respect_syncignore`].
+    IgnoreFile(PathBuf, #[source] ignore::Error),
+    #[error("Skipped {}, it links back to a parent directory", display_path(.0).display())]
+    /// A followed symbolic link leads to a directory that is already being walked.
+    SymlinkLoop(PathBuf),
     #[error("An unknown error occurred in a task, this is likely a bug: {0}")]
     /// A panic likely occurred in a task.
     JoinError(#[from] tokio::task::JoinError),
 }
+
+impl SyncError {
+    /// Get the path the error relates to, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::StatFailed(path, _)
+            | Self::IgnoreFile(path, _)
+            | Self::SymlinkLoop(path)
+            | Self::SourceGone(path)
+            | Self::SpecialFile(path, _) => Some(path),
+            Self::CopyFailed { src, .. }
+            | Self::DiskFull { src, .. }
+            | Self::ShortCopy { src, .. }
+            | Self::SourceLocked { src, .. }
+            | Self::VerifyFailed { src, .. } => Some(src),
+            Self::InsufficientSpace { dest, .. } => Some(dest),
+            Self::Collision { second, .. } => Some(second),
+            Self::Cancelled | Self::JoinError(_) => None,
+        }
+    }
+
+    /// Get the kind of the I/O error behind the error, if it comes from one.
+    pub fn io_kind(&self) -> Option<std::io::ErrorKind> {
+        match self {
+            Self::StatFailed(_, err)
+            | Self::CopyFailed { err, .. }
+            | Self::DiskFull { err, .. }
+            | Self::SourceLocked { err, .. } => Some(err.kind()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct TestVolume {
+        label: Option<String>,
+        serial: Option<String>,
+        volume_id: Option<String>,
+        fs_type: Option<String>,
+        drive_type: Option<DriveType>,
+    }
+
+    impl std::fmt::Display for TestVolume {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "volume")
+        }
+    }
+
+    impl FileSystem for TestVolume {
+        fn name(&self) -> &str {
+            "volume"
+        }
+
+        fn label(&self) -> Option<String> {
+            self.label.clone()
+        }
+
+        fn serial(&self) -> Option<String> {
+            self.serial.clone()
+        }
+
+        fn persistent_id(&self) -> Option<String> {
+            self.volume_id.clone()
+        }
+
+        fn fs_type(&self) -> Option<String> {
+            self.fs_type.clone()
+        }
+
+        fn drive_type(&self) -> Option<DriveType> {
+            self.drive_type
+        }
+    }
+
+    fn config() -> DeviceMatchConfig {
+        DeviceMatchConfig {
+            volume: None,
+            volume_regex: None,
+            device: None,
+            device_regex: None,
+            label: None,
+            label_regex: None,
+            serial: None,
+            volume_id: None,
+            marker_id: None,
+            fs_type: None,
+            drive_type: None,
+            mount_path: None,
+        }
+    }
+
+    #[test]
+    fn test_config_load_format() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let pairs = r#"{"pairs": [{"src": {"match": {"volume": "v", "device": null, "marker_id": null}, "path": "/src"}, "dest": {"path": "/dest"}, "concurrency": 4}]}"#;
+
+        let json = tmp_dir.path().join("config.json");
+        std::fs::write(&json, pairs).unwrap();
+        assert_eq!(Config::load(&json, None).unwrap().pairs.len(), 1);
+
+        // An omitted concurrency is resolved on load, an explicit zero is kept for validation.
+        std::fs::write(&json, pairs.replace(r#", "concurrency": 4"#, "")).unwrap();
+        let loaded = Config::load(&json, None).unwrap();
+        assert_eq!(
+            loaded.pairs[0].concurrency,
+            Some(sync::default_concurrency())
+        );
+        // Reading it keeps the concurrency automatic, so saving it back does not pin it.
+        assert_eq!(
+            Config::read(&json, None).unwrap().pairs[0].concurrency,
+            None
+        );
+        std::fs::write(
+            &json,
+            pairs.replace(r#""concurrency": 4"#, r#""concurrency": 0"#),
+        )
+        .unwrap();
+        let zero = Config::load(&json, None).unwrap();
+        assert_eq!(zero.pairs[0].concurrency, Some(0));
+        assert!(zero.pairs[0]
+            .validate()
+            .unwrap_err()
+            .contains("Concurrency must be greater than 0"));
+
+        // JSON is a subset of YAML, but YAML is not JSON.
+        let yaml = tmp_dir.path().join("config.yml");
+        std::fs::write(&yaml, "pairs: []\n").unwrap();
+        assert!(Config::load(&yaml, None).unwrap().pairs.is_empty());
+        std::fs::write(&yaml, "pairs: []\nglobal_concurrency: 0\n").unwrap();
+        let global = Config::load(&yaml, None).unwrap();
+        assert_eq!(global.global_concurrency, Some(0));
+        assert!(global
+            .validate()
+            .unwrap_err()
+            .contains("Global concurrency must be greater than 0"));
+        assert!(matches!(
+            Config::load(&yaml, Some(ConfigFormat::Json)),
+            Err(ConfigError::Parse {
+                format: ConfigFormat::Json,
+                ..
+            })
+        ));
+
+        // Saved configs load back the same.
+        for name in ["saved.yaml", "saved.json"] {
+            let saved = tmp_dir.path().join(name);
+            loaded.save(&saved, None).unwrap();
+            assert_eq!(Config::load(&saved, None).unwrap(), loaded);
+        }
+
+        let unknown = tmp_dir.path().join("config.toml");
+        std::fs::write(&unknown, "pairs = []\n").unwrap();
+        let err = Config::load(&unknown, None).unwrap_err();
+        assert!(err.to_string().contains("unrecognized extension toml"));
+    }
+
+    #[test]
+    fn test_expand_path() {
+        let lookup = |name: &str| match name {
+            "HOME" => Some("/home/user".to_string()),
+            "USERPROFILE" => Some(r"C:\Users\user".to_string()),
+            "DRIVE" => Some("backup".to_string()),
+            _ => None,
+        };
+        let expand = |p: &str, percent| expand_path(Path::new(p), lookup, percent);
+
+        assert_eq!(
+            expand("~/Backups", false).unwrap(),
+            PathBuf::from("/home/user/Backups")
+        );
+        assert_eq!(expand("~", false).unwrap(), PathBuf::from("/home/user"));
+        assert_eq!(expand("~other", false).unwrap(), PathBuf::from("~other"));
+        assert_eq!(
+            expand("/mnt/$DRIVE/${DRIVE}_old", false).unwrap(),
+            PathBuf::from("/mnt/backup/backup_old")
+        );
+        assert_eq!(
+            expand(r"%USERPROFILE%\Docs", true).unwrap(),
+            PathBuf::from(r"C:\Users\user\Docs")
+        );
+        assert_eq!(
+            expand("100%/$/%USERPROFILE%", false).unwrap(),
+            PathBuf::from("100%/$/%USERPROFILE%")
+        );
+
+        let err = expand("/mnt/$MISSING", false).unwrap_err();
+        assert!(err.contains("MISSING"));
+        assert!(expand("%MISSING%", true).is_err());
+    }
+
+    #[test]
+    fn test_marker_id_match() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+
+        let config = DeviceMatchConfig {
+            marker_id: Some("backup-usb".to_string()),
+            ..config()
+        };
+        let volume = TestVolume::default();
+
+        assert!(!config.matches(&volume, &(), tmp_dir.path()));
+
+        std::fs::write(tmp_dir.path().join(MARKER_FILE_NAME), "backup-usb\n").unwrap();
+        assert!(config.matches(&volume, &(), tmp_dir.path()));
+
+        std::fs::write(tmp_dir.path().join(MARKER_FILE_NAME), "other").unwrap();
+        assert!(!config.matches(&volume, &(), tmp_dir.path()));
+    }
+
+    #[test]
+    fn test_label_and_fs_type_match() {
+        let config = DeviceMatchConfig {
+            label: Some("BACKUP_USB".to_string()),
+            fs_type: Some("exFAT".to_string()),
+            ..config()
+        };
+        let volume = |label: Option<&str>, fs_type: Option<&str>| TestVolume {
+            label: label.map(str::to_string),
+            fs_type: fs_type.map(str::to_string),
+            ..Default::default()
+        };
+
+        let root = Path::new("/");
+        assert!(config.matches(&volume(Some("BACKUP_USB"), Some("exFAT")), &(), root));
+        assert!(!config.matches(&volume(Some("OTHER"), Some("exFAT")), &(), root));
+        assert!(!config.matches(&volume(Some("BACKUP_USB"), Some("NTFS")), &(), root));
+        assert!(!config.matches(&volume(None, Some("exFAT")), &(), root));
+    }
+
+    #[test]
+    fn test_serial_match() {
+        let config = DeviceMatchConfig {
+            serial: Some("a1b2-c3d4".to_string()),
+            ..config()
+        };
+        let volume = |serial: Option<&str>| TestVolume {
+            serial: serial.map(str::to_string),
+            ..Default::default()
+        };
+
+        let root = Path::new("/");
+        assert!(config.matches(&volume(Some("A1B2-C3D4")), &(), root));
+        assert!(config.matches(&volume(Some("A1B2C3D4")), &(), root));
+        assert!(!config.matches(&volume(Some("A1B2-C3D5")), &(), root));
+        assert!(!config.matches(&volume(None), &(), root));
+    }
+
+    #[test]
+    fn test_volume_id_match() {
+        let config = DeviceMatchConfig {
+            volume_id: Some("444D494F3A49443A".to_string()),
+            ..config()
+        };
+        let volume = |volume_id: Option<&str>| TestVolume {
+            volume_id: volume_id.map(str::to_string),
+            ..Default::default()
+        };
+
+        let root = Path::new("/");
+        assert!(config.validate().is_ok());
+        assert!(config.matches(&volume(Some("444d494f3a49443a")), &(), root));
+        assert!(!config.matches(&volume(Some("444d494f3a494400")), &(), root));
+        assert!(!config.matches(&volume(None), &(), root));
+    }
+
+    #[test]
+    fn test_fs_type_match() {
+        let config = DeviceMatchConfig {
+            volume: Some("volume".to_string()),
+            fs_type: Some("exFAT".to_string()),
+            ..config()
+        };
+        let volume = |fs_type: Option<&str>| TestVolume {
+            fs_type: fs_type.map(str::to_string),
+            ..Default::default()
+        };
+
+        let root = Path::new("/");
+        assert!(config.matches(&volume(Some("exfat")), &(), root));
+        assert!(!config.matches(&volume(Some("NTFS")), &(), root));
+        assert!(!config.matches(&volume(None), &(), root));
+    }
+
+    #[test]
+    fn test_regex_match() {
+        let config = DeviceMatchConfig {
+            label_regex: Some(RegexPattern::new(r"^PHOTOS_\d{4}$")),
+            device_regex: Some(RegexPattern::new("USB")),
+            ..config()
+        };
+        assert!(config.validate().is_ok());
+
+        let volume = |label: &str| TestVolume {
+            label: Some(label.to_string()),
+            ..Default::default()
+        };
+        #[derive(Debug)]
+        struct UsbDevice;
+        impl Device for UsbDevice {
+            fn name(&self) -> &str {
+                "USBSTOR#Disk"
+            }
+        }
+
+        let root = Path::new("/");
+        assert!(config.matches(&volume("PHOTOS_2023"), &UsbDevice, root));
+        assert!(config.matches(&volume("PHOTOS_2024"), &UsbDevice, root));
+        assert!(!config.matches(&volume("PHOTOS_2024_OLD"), &UsbDevice, root));
+        assert!(!config.matches(&volume("PHOTOS_2024"), &(), root));
+    }
+
+    #[test]
+    fn test_regex_validate() {
+        let invalid = DeviceMatchConfig {
+            volume_regex: Some(RegexPattern::new("PHOTOS_(")),
+            ..config()
+        };
+        let err = invalid.validate().unwrap_err();
+        assert!(err.contains("PHOTOS_("), "{}", err);
+
+        let both = DeviceMatchConfig {
+            volume: Some("volume".to_string()),
+            volume_regex: Some(RegexPattern::new("vol")),
+            ..config()
+        };
+        assert!(both.validate().is_err());
+
+        let parsed: DeviceMatchConfig = serde_yaml::from_str(
+            "volume: null\ndevice: null\nmarker_id: null\nlabel_regex: '^PHOTOS_'",
+        )
+        .unwrap();
+        assert_eq!(parsed.label_regex.unwrap().as_str(), "^PHOTOS_");
+    }
+
+    #[test]
+    fn test_drive_type_match() {
+        let config = DeviceMatchConfig {
+            drive_type: Some(DriveType::Removable),
+            ..config()
+        };
+        let volume = |drive_type: Option<DriveType>| TestVolume {
+            drive_type,
+            ..Default::default()
+        };
+
+        let root = Path::new("/");
+        assert!(config.matches(&volume(Some(DriveType::Removable)), &(), root));
+        assert!(!config.matches(&volume(Some(DriveType::Fixed)), &(), root));
+        assert!(!config.matches(&volume(None), &(), root));
+
+        let config: DeviceMatchConfig = serde_yaml::from_str(
+            "volume: null\ndevice: null\nmarker_id: null\ndrive_type: removable",
+        )
+        .unwrap();
+        assert_eq!(config.drive_type, Some(DriveType::Removable));
+    }
+
+    #[test]
+    fn test_interval() {
+        let pair = r#"{"src": {"match": {"volume": "v", "device": null, "marker_id": null}, "path": "/src"}, "dest": {"path": "/dest"}, "concurrency": 4}"#;
+        let parsed: SyncPairs = serde_yaml::from_str(pair).unwrap();
+        assert_eq!(parsed.interval, None);
+
+        let periodic = pair.replace(
+            r#""concurrency": 4"#,
+            r#""concurrency": 4, "interval": "15m""#,
+        );
+        let parsed: SyncPairs = serde_yaml::from_str(&periodic).unwrap();
+        assert_eq!(parsed.interval, Some(Duration::from_secs(15 * 60)));
+        assert!(parsed.validate().is_ok());
+
+        let zero = pair.replace(
+            r#""concurrency": 4"#,
+            r#""concurrency": 4, "interval": "0s""#,
+        );
+        let parsed: SyncPairs = serde_yaml::from_str(&zero).unwrap();
+        assert!(parsed.validate().is_err());
+
+        let invalid = pair.replace(
+            r#""concurrency": 4"#,
+            r#""concurrency": 4, "interval": "soon""#,
+        );
+        assert!(serde_yaml::from_str::<SyncPairs>(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_timeout() {
+        let pair = r#"{"src": {"match": {"volume": "v", "device": null, "marker_id": null}, "path": "/src"}, "dest": {"path": "/dest"}, "timeout": "30m"}"#;
+        let parsed: SyncPairs = serde_yaml::from_str(pair).unwrap();
+        assert_eq!(parsed.options.timeout, Some(Duration::from_secs(30 * 60)));
+        assert!(parsed.validate().is_ok());
+
+        let zero: SyncPairs = serde_yaml::from_str(&pair.replace("30m", "0s")).unwrap();
+        assert!(zero.validate().is_err());
+    }
+
+    #[test]
+    fn test_config_diff() {
+        let pair = |src: &str, concurrency: usize| SyncPairs {
+            src: SyncPairSource {
+                r#match: Some(DeviceMatchConfig {
+                    volume: Some("v".to_string()),
+                    ..config()
+                }),
+                paths: vec![PathBuf::from(src)],
+            },
+            dest: SyncPairDest {
+                r#match: None,
+                path: PathBuf::from("/dest"),
+            },
+            direction: SyncDirection::FromVolume,
+            concurrency: Some(concurrency),
+            options: sync::SyncOptions::default(),
+            interval: None,
+            eject_when_done: false,
+        };
+        let old = Config {
+            pairs: vec![pair("/a", 4), pair("/b", 4)],
+            ..Config::default()
+        };
+        assert!(old.diff(&old.clone()).is_empty());
+
+        let new = Config {
+            pairs: vec![pair("/b", 8), pair("/a", 4), pair("/c", 4)],
+            ..Config::default()
+        };
+        let diff = old.diff(&new);
+        assert_eq!(diff.added, vec![&pair("/b", 8), &pair("/c", 4)]);
+        assert_eq!(diff.removed, vec![&pair("/b", 4)]);
+
+        // Regular expressions compare by their source text.
+        let regex = |pattern: &str| DeviceMatchConfig {
+            volume_regex: Some(RegexPattern::new(pattern)),
+            ..config()
+        };
+        let compiled = regex("^v");
+        assert!(compiled.volume_regex.as_ref().unwrap().is_match("v"));
+        assert_eq!(regex("^v"), compiled);
+        assert_ne!(regex("^w"), compiled);
+    }
+
+    #[test]
+    fn test_direction() {
+        let from: SyncPairs = serde_yaml::from_str(
+            "src: {match: {volume: v}, path: DCIM}\ndest: {path: /backup}\nconcurrency: 1",
+        )
+        .unwrap();
+        assert_eq!(from.direction, SyncDirection::FromVolume);
+        assert!(from.validate().is_ok());
+        assert_eq!(from.volume_match().unwrap().volume.as_deref(), Some("v"));
+        let resolved = from.on_volume(Path::new("/media/usb"));
+        assert_eq!(resolved.src.paths, [Path::new("/media/usb/DCIM")]);
+        assert_eq!(resolved.dest.path, Path::new("/backup"));
+
+        let to: SyncPairs = serde_yaml::from_str(
+            "src: {path: /home/me/Documents}\ndest: {match: {label: BACKUP}, path: Documents}\ndirection: to_volume\nconcurrency: 1",
+        )
+        .unwrap();
+        assert!(to.validate().is_ok());
+        assert_eq!(to.volume_match().unwrap().label.as_deref(), Some("BACKUP"));
+        let resolved = to.on_volume(Path::new("/media/usb"));
+        assert_eq!(resolved.src.paths, [Path::new("/home/me/Documents")]);
+        assert_eq!(resolved.dest.path, Path::new("/media/usb/Documents"));
+
+        // The match belongs on the volume side.
+        let mut wrong_side = to.clone();
+        wrong_side.direction = SyncDirection::FromVolume;
+        assert!(wrong_side.validate().is_err());
+        let mut both = to;
+        both.src.r#match = from.src.r#match;
+        assert!(both.validate().is_err());
+    }
+
+    #[test]
+    fn test_check_paths() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let file = tmp_dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        let missing = tmp_dir.path().join("missing");
+
+        let pair = |direction: &str, local: &Path| -> SyncPairs {
+            let (src, dest) = match direction {
+                "to_volume" => (
+                    format!("{{path: {}}}", local.display()),
+                    "{match: {volume: v}, path: backup}".to_string(),
+                ),
+                _ => (
+                    "{match: {volume: v}, path: DCIM}".to_string(),
+                    format!("{{path: {}}}", local.display()),
+                ),
+            };
+            serde_yaml::from_str(&format!(
+                "src: {}\ndest: {}\ndirection: {}\nconcurrency: 1",
+                src, dest, direction
+            ))
+            .unwrap()
+        };
+
+        assert!(pair("from_volume", tmp_dir.path()).check_paths().is_ok());
+        assert!(pair("to_volume", tmp_dir.path()).check_paths().is_ok());
+
+        // A missing local path only fails when syncing, it may be on a drive mounted later.
+        let missing_pair = pair("from_volume", &missing);
+        assert!(missing_pair.validate().is_ok());
+        let (i, path, err) = missing_pair.check_paths().unwrap_err();
+        assert_eq!((i, path), (0, missing));
+        assert!(err.starts_with("Destination: "), "{}", err);
+        let (i, path, err) = pair("to_volume", &file).check_paths().unwrap_err();
+        assert_eq!((i, path), (0, file.clone()));
+        assert_eq!(err, "Source is not a directory");
+
+        // Only the second of two sources is bad.
+        let merged: SyncPairs = serde_yaml::from_str(&format!(
+            "src: {{path: [{}, {}]}}\ndest: {{match: {{volume: v}}, path: backup}}\n\
+             direction: to_volume\nconcurrency: 1",
+            tmp_dir.path().display(),
+            file.display()
+        ))
+        .unwrap();
+        let (i, path, err) = merged.check_paths().unwrap_err();
+        assert_eq!((i, path), (1, file));
+        assert_eq!(err, "Source is not a directory");
+    }
+
+    #[test]
+    fn test_multiple_sources() {
+        let single: SyncPairs = serde_yaml::from_str(
+            "src: {match: {volume: v}, path: DCIM}\ndest: {path: /backup}\nconcurrency: 1",
+        )
+        .unwrap();
+        assert_eq!(single.src.paths, [Path::new("DCIM")]);
+
+        let merged: SyncPairs = serde_yaml::from_str(
+            "src: {match: {volume: v}, path: [DCIM, Pictures]}\ndest: {path: /backup}\nconcurrency: 1\ncollision: error",
+        )
+        .unwrap();
+        assert_eq!(merged.src.paths, [Path::new("DCIM"), Path::new("Pictures")]);
+        assert_eq!(merged.options.collision, sync::CollisionPolicy::Error);
+        assert_eq!(merged.to_string(), "DCIM, Pictures -> /backup");
+        assert!(merged.validate().is_ok());
+
+        let resolved = merged.on_volume(Path::new("/media/usb"));
+        assert_eq!(
+            resolved.src.paths,
+            [
+                Path::new("/media/usb/DCIM"),
+                Path::new("/media/usb/Pictures")
+            ]
+        );
+
+        let empty: SyncPairs = serde_yaml::from_str(
+            "src: {match: {volume: v}, path: []}\ndest: {path: /backup}\nconcurrency: 1",
+        )
+        .unwrap();
+        assert!(empty.validate().is_err());
+    }
+
+    #[test]
+    fn test_verbatim_paths() {
+        assert_eq!(to_verbatim(r"C:\Users\me"), r"\\?\C:\Users\me");
+        assert_eq!(
+            to_verbatim(r"\\server\share\dir"),
+            r"\\?\UNC\server\share\dir"
+        );
+        assert_eq!(to_verbatim(r"\\?\C:\Users\me"), r"\\?\C:\Users\me");
+        assert_eq!(
+            to_verbatim(r"\\?\Volume{b1b3}\dir"),
+            r"\\?\Volume{b1b3}\dir"
+        );
+
+        for plain in [r"C:\Users\me", r"\\server\share\dir"] {
+            assert_eq!(from_verbatim(&to_verbatim(plain)).as_deref(), Some(plain));
+        }
+        assert_eq!(from_verbatim(r"\\?\Volume{b1b3}\dir"), None);
+        assert_eq!(from_verbatim(r"C:\Users\me"), None);
+    }
+
+    #[test]
+    fn test_for_volume_mount_paths() {
+        let pair = |mount_path: Option<&str>| SyncPairs {
+            src: SyncPairSource {
+                r#match: Some(DeviceMatchConfig {
+                    volume: Some("volume".to_string()),
+                    mount_path: mount_path.map(PathBuf::from),
+                    ..config()
+                }),
+                paths: vec![PathBuf::from("DCIM")],
+            },
+            dest: SyncPairDest {
+                r#match: None,
+                path: PathBuf::from("/backup"),
+            },
+            direction: SyncDirection::FromVolume,
+            concurrency: Some(1),
+            options: sync::SyncOptions::default(),
+            interval: None,
+            eject_when_done: false,
+        };
+        let config = Config {
+            pairs: vec![
+                pair(None),
+                pair(Some("/mnt/backup/")),
+                pair(Some("/mnt/other")),
+            ],
+            ..Config::default()
+        };
+        let mount_paths = [PathBuf::from("/media/usb"), PathBuf::from("/mnt/backup")];
+
+        let sources = config
+            .for_volume(&TestVolume::default(), &(), &mount_paths)
+            .pairs
+            .into_iter()
+            .map(|pair| pair.src.paths[0].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sources,
+            [
+                volume_root(Path::new("/media/usb")).join("DCIM"),
+                volume_root(Path::new("/mnt/backup")).join("DCIM"),
+            ]
+        );
+        assert!(config
+            .for_volume(&TestVolume::default(), &(), &[])
+            .pairs
+            .is_empty());
+    }
+
+    #[test]
+    fn test_for_volume_drive_types() {
+        let pair = |drive_type: Option<DriveType>| SyncPairs {
+            src: SyncPairSource {
+                r#match: Some(DeviceMatchConfig {
+                    drive_type,
+                    ..config()
+                }),
+                paths: vec![PathBuf::from("DCIM")],
+            },
+            dest: SyncPairDest {
+                r#match: None,
+                path: PathBuf::from("/backup"),
+            },
+            direction: SyncDirection::FromVolume,
+            concurrency: Some(1),
+            options: sync::SyncOptions::default(),
+            interval: None,
+            eject_when_done: false,
+        };
+        let config = Config {
+            pairs: vec![pair(None), pair(Some(DriveType::Remote))],
+            ..Config::default()
+        };
+        let matched = |drive_type: Option<DriveType>| {
+            let volume = TestVolume {
+                drive_type,
+                ..TestVolume::default()
+            };
+            config
+                .for_volume(&volume, &(), &[PathBuf::from("/mnt")])
+                .pairs
+                .len()
+        };
+
+        assert_eq!(matched(None), 1);
+        assert_eq!(matched(Some(DriveType::Removable)), 1);
+        assert_eq!(matched(Some(DriveType::Fixed)), 0);
+        assert_eq!(matched(Some(DriveType::Remote)), 1);
+        assert!(config.asks_for(DriveType::Remote));
+        assert!(!config.asks_for(DriveType::Fixed));
+    }
+
+    #[test]
+    fn test_sync_error_io_kind() {
+        let err = SyncError::CopyFailed {
+            src: PathBuf::from("/src/a"),
+            dest: PathBuf::from("/dest/a"),
+            err: std::io::ErrorKind::PermissionDenied.into(),
+        };
+        assert_eq!(err.io_kind(), Some(std::io::ErrorKind::PermissionDenied));
+        assert_eq!(SyncError::Cancelled.io_kind(), None);
+    }
+
+    #[test]
+    fn test_eject_when_done() {
+        let pair = r#"{"src": {"path": "/src"}, "dest": {"match": {"volume": "v", "device": null, "marker_id": null}, "path": "backup"}, "direction": "to_volume", "eject_when_done": true}"#;
+        let parsed: SyncPairs = serde_yaml::from_str(pair).unwrap();
+        assert!(parsed.eject_when_done);
+        assert!(parsed.validate().is_ok());
+
+        let periodic = pair.replace(
+            r#""eject_when_done""#,
+            r#""interval": "1h", "eject_when_done""#,
+        );
+        let parsed: SyncPairs = serde_yaml::from_str(&periodic).unwrap();
+        assert!(parsed.validate().is_err());
+
+        let from_volume = r#"{"src": {"match": {"volume": "v", "device": null, "marker_id": null}, "path": "/src"}, "dest": {"path": "/dest"}, "eject_when_done": true}"#;
+        let parsed: SyncPairs = serde_yaml::from_str(from_volume).unwrap();
+        assert!(parsed.validate().is_err());
+
+        // Left out of configs that do not use it.
+        let parsed: SyncPairs = serde_yaml::from_str(&pair.replace("true", "false")).unwrap();
+        assert!(!serde_json::to_string(&parsed)
+            .unwrap()
+            .contains("eject_when_done"));
+    }
+}
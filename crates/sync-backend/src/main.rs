@@ -1,43 +1,629 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::{
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex as StdMutex,
     },
+    time::Duration,
 };
 
-use indicatif::{MultiProgress, ProgressBar};
+use indicatif::{HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
 use sync_backend::{
-    sync::{ProgressMilestone, SyncFS},
-    Config,
+    read_marker_id,
+    sync::{default_concurrency, ProgressMilestone, SharedLimit, SyncFS, SyncOptions, SyncSummary},
+    Config, ConfigFormat, SyncDirection, SyncPairDest, SyncPairSource, SyncPairs,
 };
-use tokio::{sync::Mutex, task::JoinSet};
+use tokio::{
+    sync::{mpsc, watch, Mutex, Semaphore},
+    task::JoinSet,
+    time::{Instant, MissedTickBehavior},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
 use volume_tracker::{
-    platform_init, Device, FileSystem, NotificationSource, PlatformNotifier, SpawnerDisposition,
+    platform_init, AbortHandleHolder, Device, DriveType, FileSystem, NotificationSource,
+    PlatformNotifier, SpawnerDisposition, TaskEnd,
 };
 
+/// Time to wait for an editor to finish writing the config file before reloading it.
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Time cancelled syncs get to remove their partial copies before they are aborted.
+const CANCEL_GRACE: Duration = Duration::from_secs(10);
+
 #[derive(Debug, Parser)]
 struct Cli {
     #[clap(short, long, default_value = "config.yaml")]
     config: PathBuf,
+    /// Config file format (yaml or json), guessed from the file extension by default.
+    #[clap(long)]
+    format: Option<ConfigFormat>,
+    /// Exit successfully even if some files failed to sync.
+    #[clap(long, global = true)]
+    ignore_errors: bool,
+    /// List the mounted volumes as `match` sections for the config and exit.
+    #[clap(long)]
+    list: bool,
+    /// Print the volume list as JSON instead of YAML.
+    #[clap(long, requires = "list")]
+    json: bool,
+    /// Time to let running syncs finish on ctrl-c or SIGTERM before aborting them, like `30s`.
+    #[clap(long, default_value = "30s", value_parser = humantime_serde::re::humantime::parse_duration)]
+    shutdown_timeout: Duration,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Sync directories once without a config file, then exit.
+    Sync {
+        /// Source directory, repeat it to merge several sources into the destination.
+        #[clap(long, required = true)]
+        src: Vec<PathBuf>,
+        /// Destination directory.
+        #[clap(long)]
+        dest: PathBuf,
+        /// Number of concurrent file operations.
+        #[clap(long)]
+        concurrency: Option<usize>,
+        /// Read every copied file back and compare it to the source.
+        #[clap(long)]
+        verify: bool,
+        /// Wait until every copied file is on the disk, slower with many small files.
+        #[clap(long)]
+        fsync: bool,
+        /// Report what would be copied without changing anything, `RUST_LOG=info` lists the files.
+        #[clap(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Debug, Serialize)]
+/// A mounted volume, see `--list`.
+struct VolumeInfo {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    mount_paths: Vec<PathBuf>,
+    r#match: MatchInfo,
+    /// Why the volume cannot be synced, like its device not resolving.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// The volume is encrypted and must be unlocked before it can be synced.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    locked: bool,
+}
+
+#[derive(Debug, Serialize)]
+/// The properties of a volume a [`DeviceMatchConfig`](sync_backend::DeviceMatchConfig) can match.
+struct MatchInfo {
+    volume: String,
+    device: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    serial: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    marker_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fs_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    drive_type: Option<DriveType>,
+}
+
+/// Print the mounted volumes as reported by the tracker.
+fn list_volumes(json: bool) -> Result<(), String> {
+    let s = PlatformNotifier::new(|_, _, _| SpawnerDisposition::Ignore)
+        .map_err(|e| format!("Failed to create PlatformNotifier: {}", e))?;
+    let volumes = s
+        .list()
+        .map_err(|e| format!("Failed to list volumes: {}", e))?
+        .into_iter()
+        .map(|(volume, device, mount_paths)| VolumeInfo {
+            r#match: MatchInfo {
+                volume: volume.name().to_string(),
+                device: device.name().to_string(),
+                label: volume.label(),
+                serial: volume.serial(),
+                marker_id: mount_paths.iter().find_map(|p| read_marker_id(p)),
+                fs_type: volume.fs_type(),
+                drive_type: volume.drive_type(),
+            },
+            error: device.error().map(str::to_string),
+            locked: volume.locked(),
+            mount_paths,
+        })
+        .collect::<Vec<_>>();
+
+    let out = if json {
+        serde_json::to_string_pretty(&volumes).map_err(|e| e.to_string())?
+    } else {
+        serde_yaml::to_string(&volumes).map_err(|e| e.to_string())?
+    };
+    println!("{}", out.trim_end());
+    Ok(())
+}
+
+/// Load, resolve and validate the config file.
+fn load_config(path: &Path, format: Option<ConfigFormat>) -> Result<Config, String> {
+    let mut config =
+        Config::load(path, format).map_err(|e| match std::error::Error::source(&e) {
+            Some(source) => format!("{}: {}", e, source),
+            None => e.to_string(),
+        })?;
+    config
+        .resolve_paths()
+        .and_then(|()| config.validate())
+        .map_err(|e| format!("Invalid config: {}", e))?;
+    Ok(config)
+}
+
+/// Watch the config file and publish every valid revision of it to `config_tx`.
+///
+/// The parent directory is watched since editors often replace the file instead of writing to it.
+/// The global concurrency goes to `limit` instead, and applies to the syncs started afterwards.
+fn watch_config(
+    path: &Path,
+    format: Option<ConfigFormat>,
+    config_tx: watch::Sender<Arc<Config>>,
+    limit: Arc<SharedLimit>,
+    handle: &tokio::runtime::Handle,
+) -> notify::Result<RecommendedWatcher> {
+    let path = std::path::absolute(path)?;
+    let dir = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+    let (reload_tx, mut reload_rx) = mpsc::unbounded_channel();
+
+    let file_name = path.file_name().map(ToOwned::to_owned);
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) => {
+                if !event.kind.is_access()
+                    && event
+                        .paths
+                        .iter()
+                        .any(|p| p.file_name() == file_name.as_deref())
+                {
+                    let _ = reload_tx.send(());
+                }
+            }
+            Err(e) => log::warn!("Config watcher error: {}", e),
+        })?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+    handle.spawn(async move {
+        while reload_rx.recv().await.is_some() {
+            // Editors tend to write a file in several steps.
+            tokio::time::sleep(RELOAD_DEBOUNCE).await;
+            while reload_rx.try_recv().is_ok() {}
+
+            let config = match load_config(&path, format) {
+                Ok(config) => config,
+                Err(e) => {
+                    log::error!("Failed to reload config, keeping the old one: {}", e);
+                    continue;
+                }
+            };
+            if limit.set(config.global_concurrency) {
+                log::info!(
+                    "Global concurrency changed to {}",
+                    config
+                        .global_concurrency
+                        .map_or_else(|| "unlimited".to_string(), |n| n.to_string())
+                );
+            }
+            let (added, removed) = {
+                let current = config_tx.borrow();
+                let diff = current.diff(&config);
+                (diff.added.len(), diff.removed.len())
+            };
+            if added == 0 && removed == 0 {
+                continue;
+            }
+            log::info!(
+                "Reloaded config: {} pairs added, {} pairs removed",
+                added,
+                removed
+            );
+            config_tx.send_replace(Arc::new(config));
+        }
+    });
+
+    Ok(watcher)
+}
+
+async fn sync_pair(
+    pair: &SyncPairs,
+    pg: &ProgressBar,
+    mp: &MultiProgress,
+    cancel: &CancellationToken,
+    shared: Option<Arc<Semaphore>>,
+) -> SyncSummary {
+    pg.set_message(format!("(Discovery in progress) {}", pair));
+    let summary = SyncFS::with_shared_semaphore(
+        &pair.src.paths,
+        &pair.dest.path,
+        pair.concurrency.unwrap_or_else(default_concurrency),
+        pair.options.clone(),
+        shared,
+    )
+    .sync(
+        |gp, ms| {
+            if let Some(ProgressMilestone::DiscoveryComplete) = ms {
+                pg.set_message(pair.to_string());
+            }
+            let snapshot = gp.snapshot();
+            let files = snapshot.files;
+            pg.set_length(snapshot.estimated_files.unwrap_or(files.total));
+            pg.set_position(files.done);
+            if let Some(rate) = gp.throughput() {
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let speed = HumanBytes(rate.bytes_per_sec as u64);
+                pg.set_prefix(match rate.eta {
+                    Some(eta) => format!("({}/s, {} left)", speed, HumanDuration(eta)),
+                    None => format!("({}/s)", speed),
+                });
+            }
+        },
+        &|e| {
+            if let Err(e) = mp.println(format!("Error syncing {}: {}", pair, e)) {
+                log::error!("Failed to print sync error: {}", e);
+            }
+        },
+        cancel,
+    )
+    .await;
+    log::info!(
+        "Synced {}: {} copied, {} skipped, {} failed, {} in {}",
+        pair,
+        summary.files_copied,
+        summary.files_skipped,
+        summary.files_failed,
+        HumanBytes(summary.bytes_copied),
+        HumanDuration(summary.duration)
+    );
+    if let Some(reason) = &summary.aborted {
+        log::error!("Stopped syncing {} early: {}", pair, reason);
+    }
+    if !summary.conflicts.is_empty() {
+        log::warn!(
+            "Left {} files of {} alone, their destination was modified after them",
+            summary.conflicts.len(),
+            pair
+        );
+    }
+    summary
+}
+
+#[derive(Debug, Default)]
+/// Files synced by all pairs since startup.
+struct Totals {
+    copied: AtomicU64,
+    skipped: AtomicU64,
+    failed: AtomicU64,
+    bytes: AtomicU64,
+    /// Whether any sync had a failure or stopped early, even without a file counted in `failed`.
+    had_failures: AtomicBool,
+}
+
+impl Totals {
+    fn add(&self, summary: &SyncSummary) {
+        self.copied
+            .fetch_add(summary.files_copied, Ordering::Relaxed);
+        self.skipped
+            .fetch_add(summary.files_skipped, Ordering::Relaxed);
+        self.failed
+            .fetch_add(summary.files_failed, Ordering::Relaxed);
+        self.bytes
+            .fetch_add(summary.bytes_copied, Ordering::Relaxed);
+        if summary.has_failures() {
+            self.had_failures.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+fn pair_progress_bar(pair: &SyncPairs) -> ProgressBar {
+    let pg = ProgressBar::new(0).with_style(
+        ProgressStyle::default_bar()
+            .template("{msg} - [{bar:40.cyan/blue}] {pos}/{len} files {prefix}")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    pg.set_message(format!("(Waiting) {}", pair));
+    pg
+}
+
+/// Sync `pair` once, cancelling on ctrl-c, and get the exit code.
+fn sync_once(pair: &SyncPairs, ignore_errors: bool) -> i32 {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let mp = MultiProgress::new();
+    let pg = mp.add(pair_progress_bar(pair));
+    let cancel = CancellationToken::new();
+
+    rt.spawn({
+        let cancel = cancel.clone();
+        async move {
+            let signal = shutdown_signal().await;
+            log::warn!("Received {}, cancelling", signal);
+            cancel.cancel();
+        }
+    });
+    let summary = rt.block_on(sync_pair(pair, &pg, &mp, &cancel, None));
+    pg.finish_and_clear();
+
+    i32::from(summary.has_failures() && !ignore_errors)
+}
+
+#[derive(Clone)]
+/// State shared by the sync tasks of a volume.
+struct PairCtx {
+    mp: MultiProgress,
+    /// The pairs of a volume share its bandwidth, so only one of them syncs at a time.
+    running: Arc<Mutex<()>>,
+    cancel: CancellationToken,
+    shutdown: CancellationToken,
+    totals: Arc<Totals>,
+    /// Concurrency limit shared by all volumes.
+    limit: Arc<SharedLimit>,
+    /// The pairs syncing right now, of all volumes.
+    syncing: Arc<Syncing>,
+}
+
+#[derive(Debug, Default)]
+/// The pairs syncing right now, to tell which ones a shutdown cuts short.
+struct Syncing(StdMutex<Vec<String>>);
+
+impl Syncing {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<String>> {
+        match self.0.lock() {
+            Ok(g) => g,
+            Err(p) => p.into_inner(),
+        }
+    }
+
+    /// Count `pair` as syncing until the guard is dropped.
+    fn start(&self, pair: &SyncPairs) -> SyncingGuard<'_> {
+        let name = pair.to_string();
+        self.lock().push(name.clone());
+        SyncingGuard(self, name)
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.lock().clone()
+    }
+}
+
+struct SyncingGuard<'a>(&'a Syncing, String);
+
+impl Drop for SyncingGuard<'_> {
+    fn drop(&mut self) {
+        let mut syncing = self.0.lock();
+        if let Some(i) = syncing.iter().position(|name| *name == self.1) {
+            syncing.swap_remove(i);
+        }
+    }
+}
+
+impl PairCtx {
+    /// Sync `pair` once unless shutting down, waiting for the other pairs of the volume.
+    async fn sync(&self, pair: &SyncPairs, pg: &ProgressBar) {
+        let _running = self.running.lock().await;
+        if self.shutdown.is_cancelled() {
+            return;
+        }
+        let _syncing = self.syncing.start(pair);
+        // Unlike the volume side, a missing local side is not created for a configured pair.
+        if let Err((i, path, e)) = pair.check_paths() {
+            let message = format!(
+                "Error syncing {}, path {}: {}: {}",
+                pair,
+                i + 1,
+                path.display(),
+                e
+            );
+            if let Err(e) = self.mp.println(message) {
+                log::error!("Failed to print sync error: {}", e);
+            }
+            self.totals.add(&SyncSummary {
+                failed_paths: vec![(path, e)],
+                ..SyncSummary::default()
+            });
+            return;
+        }
+        self.totals
+            .add(&sync_pair(pair, pg, &self.mp, &self.cancel, self.limit.semaphore()).await);
+    }
+
+    /// Sync `pair` once, then every `interval` until shutdown if it has one.
+    async fn run(&self, pair: &SyncPairs, pg: &ProgressBar) {
+        self.sync(pair, pg).await;
+        let Some(period) = pair.interval else {
+            return;
+        };
+
+        let mut ticker = tokio::time::interval_at(Instant::now() + period, period);
+        // Ticks missed while the previous sync was still running are skipped.
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            pg.set_message(format!("(Next sync in {}) {}", HumanDuration(period), pair));
+            tokio::select! {
+                _ = ticker.tick() => {}
+                () = self.shutdown.cancelled() => return,
+            }
+            self.sync(pair, pg).await;
+        }
+    }
+
+    /// Spawn a task syncing `pair` into `js` and register it with `tasks`.
+    fn spawn(&self, pair: SyncPairs, js: &mut JoinSet<()>, tasks: &AbortHandleHolder<SyncPairs>) {
+        let pg = self.mp.add(pair_progress_bar(&pair));
+
+        let ctx = self.clone();
+        let (pg2, mp, name) = (pg.clone(), self.mp.clone(), pair.to_string());
+        let ah = js.spawn({
+            let pair = pair.clone();
+            async move { ctx.run(&pair, &pg).await }.in_current_span()
+        });
+        tasks.insert(
+            pair,
+            ah,
+            Some(Box::new(move |end| {
+                let verb = match end {
+                    TaskEnd::Completed => "Synced",
+                    TaskEnd::Aborted => "Aborted",
+                };
+                pg2.finish_with_message(format!("{} {}", verb, name));
+                mp.remove(&pg2);
+            })),
+        );
+    }
+}
+
+/// Keep the sync tasks of a mounted volume in line with the current config until shutdown.
+///
+/// Aborting this task, like when the volume is removed, aborts the sync tasks with it.
+async fn watch_volume(
+    volume: impl FileSystem,
+    device: impl Device,
+    mount_paths: Vec<PathBuf>,
+    mut config_rx: watch::Receiver<Arc<Config>>,
+    ctx: PairCtx,
+) {
+    let tasks = AbortHandleHolder::default();
+    let mut js = JoinSet::new();
+    let mut current = Config::default();
+    loop {
+        let config = Arc::clone(&config_rx.borrow_and_update());
+        let pairs = config.for_volume(&volume, &device, &mount_paths);
+        if pairs.pairs.is_empty() {
+            log::info!(
+                "No pairs for volume: {}, device: {}",
+                volume.name(),
+                device.name()
+            );
+        }
+        let diff = current.diff(&pairs);
+        for pair in diff.removed {
+            tasks.remove_abort(pair);
+        }
+        for pair in diff.added {
+            ctx.spawn(pair.clone(), &mut js, &tasks);
+        }
+        current = pairs;
+
+        let changed = loop {
+            tokio::select! {
+                Some(res) = js.join_next() => {
+                    if let Err(e) = res {
+                        if !e.is_cancelled() {
+                            log::error!("Task failed: {:?}", e);
+                        }
+                    }
+                    tasks.gc();
+                }
+                res = config_rx.changed() => break res.is_ok(),
+                () = ctx.shutdown.cancelled() => break false,
+            }
+        };
+        if !changed {
+            break;
+        }
+    }
+    while js.join_next().await.is_some() {}
+}
+
+/// Wait for ctrl-c, or SIGTERM on Unix like when stopped by a service manager, returning its name.
+async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => tokio::select! {
+                res = tokio::signal::ctrl_c() => {
+                    res.expect("Failed to wait for ctrl-c");
+                    return "ctrl-c";
+                }
+                _ = term.recv() => return "SIGTERM",
+            },
+            Err(e) => log::warn!("Failed to listen for SIGTERM: {}", e),
+        }
+    }
+
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to wait for ctrl-c");
+    "ctrl-c"
 }
 
 fn main() {
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "info");
     }
-    env_logger::init();
+    // Log records from the dependencies still using `log` are turned into events.
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
 
     let args = Cli::parse();
 
-    let config: Config = serde_yaml::from_reader(std::fs::File::open(args.config).unwrap())
-        .expect("Failed to read config file");
+    if let Some(Command::Sync {
+        src,
+        dest,
+        concurrency,
+        verify,
+        fsync,
+        dry_run,
+    }) = args.command
+    {
+        if concurrency == Some(0) {
+            log::error!("Concurrency must be greater than 0");
+            std::process::exit(2);
+        }
+        let pair = SyncPairs {
+            src: SyncPairSource {
+                r#match: None,
+                paths: src,
+            },
+            dest: SyncPairDest {
+                r#match: None,
+                path: dest,
+            },
+            direction: SyncDirection::default(),
+            concurrency,
+            options: SyncOptions {
+                verify,
+                fsync,
+                dry_run,
+                ..SyncOptions::default()
+            },
+            interval: None,
+            eject_when_done: false,
+        };
+        std::process::exit(sync_once(&pair, args.ignore_errors));
+    }
 
-    if let Err(e) = config.validate() {
-        log::error!("Invalid config: {}", e);
-        std::process::exit(1);
+    if args.list {
+        platform_init().expect("Failed to initialize platform");
+        if let Err(e) = list_volumes(args.json) {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+        return;
     }
+
+    let config = match load_config(&args.config, args.format) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(2);
+        }
+    };
     if config.pairs.is_empty() {
         log::warn!("No sync pairs set up, demonstrating only");
     }
@@ -48,102 +634,99 @@ fn main() {
         .enable_all()
         .build()
         .unwrap();
-    let handle = rt.handle();
-    let js = Mutex::new(JoinSet::new());
+    let handle = rt.handle().clone();
+    // Shared with the notifier callback, which outlives this scope on its watcher thread.
+    let js = Arc::new(Mutex::new(JoinSet::new()));
 
     let mp = MultiProgress::new();
+    let cancel = CancellationToken::new();
+    let shutdown = CancellationToken::new();
+    let totals = Arc::new(Totals::default());
+    let limit = Arc::new(SharedLimit::default());
+    limit.set(config.global_concurrency);
+    let syncing = Arc::new(Syncing::default());
+    let (config_tx, config_rx) = watch::channel(Arc::new(config));
+
+    let _config_watcher = match watch_config(
+        &args.config,
+        args.format,
+        config_tx,
+        Arc::clone(&limit),
+        &handle,
+    ) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            log::warn!("Failed to watch config file, changes need a restart: {}", e);
+            None
+        }
+    };
 
-    let mut s = PlatformNotifier::new(|v, d, p| match p {
-        None => {
+    // Volumes without pairs are watched too, in case the config changes.
+    let volume_ctx = PairCtx {
+        mp: mp.clone(),
+        running: Arc::default(),
+        cancel: cancel.clone(),
+        shutdown: shutdown.clone(),
+        totals: Arc::clone(&totals),
+        limit: Arc::clone(&limit),
+        syncing: Arc::clone(&syncing),
+    };
+    let spawn_js = Arc::clone(&js);
+    let mut s = PlatformNotifier::new(move |v, d, p| {
+        if p.is_empty() {
             log::info!("Device not mounted (yet): {}, {}", v.name(), d.name());
             return SpawnerDisposition::Skip;
         }
-        Some(p) => {
+        // Offered again on the next change, in case the config starts asking for them.
+        if v.drive_type() == Some(DriveType::Remote)
+            && !config_rx.borrow().asks_for(DriveType::Remote)
+        {
+            log::info!("Skipping remote drive {}, no pair asks for it", v.name());
+            return SpawnerDisposition::Skip;
+        }
+        // Offered again once unlocked, the volume changes then.
+        if v.locked() {
             log::info!(
-                "New device: volume: {}, device: {}, mounted at: {}",
-                v.name(),
-                d.name(),
-                p.display()
-            );
-            let pairs = config
-                .pairs
-                .iter()
-                .filter(|pair| pair.src.r#match.matches(v.name(), d.name()))
-                .cloned()
-                .collect::<Vec<_>>();
-            if pairs.is_empty() {
-                log::info!("No pairs for volume: {}, device: {}", v.name(), d.name());
-                return SpawnerDisposition::Ignore;
-            }
-
-            let v_name = v.name().to_string();
-            let mp = mp.clone();
-            let mp2 = mp.clone();
-            let pg = ProgressBar::new(0);
-            let pg2 = pg.clone();
-            let done = Arc::new(AtomicBool::new(false));
-            let done2 = Arc::clone(&done);
-            let ah = js.blocking_lock().spawn_on(
-                async move {
-                    pg.set_style(
-                        indicatif::ProgressStyle::default_bar()
-                            .template("{msg} - [{bar:40.cyan/blue}] {pos}/{len} files")
-                            .unwrap()
-                            .progress_chars("=> "),
-                    );
-                    mp.add(pg.clone());
-                    for pair in pairs {
-                        pg.set_message(format!(
-                            "(Discovery in progress) {}",
-                            pair.src.path.display()
-                        ));
-                        SyncFS::new(&pair.src.path, &pair.dest.path, pair.concurrency)
-                            .sync(
-                                |gp, ms| {
-                                    if let Some(ProgressMilestone::DiscoveryComplete) = ms {
-                                        pg.set_message(pair.src.path.display().to_string());
-                                    }
-                                    pg.set_length(gp.files.total.load(Ordering::Relaxed));
-                                    pg.set_position(gp.files.done.load(Ordering::Relaxed));
-                                },
-                                &|e| {
-                                    if let Err(e) = mp.println(format!(
-                                        "Error syncing {}: {}",
-                                        pair.src.path.display(),
-                                        e
-                                    )) {
-                                        log::error!("Failed to print sync error: {}", e);
-                                    }
-                                },
-                            )
-                            .await
-                    }
-                    pg.finish_with_message(format!("Synced {}", v.name()));
-                    mp.remove(&pg);
-                    done.store(true, Ordering::SeqCst);
-                },
-                handle,
+                "Skipping {}, it is locked until unlocked by the user",
+                v.name()
             );
-            SpawnerDisposition::Spawned(
-                ah,
-                Some(Box::new(move || {
-                    if done2.load(Ordering::SeqCst) {
-                        return;
-                    }
-                    pg2.finish_with_message(format!("Aborted {}", v_name));
-                    mp2.remove(&pg2);
-                })),
-            )
+            return SpawnerDisposition::Skip;
         }
+        log::info!(
+            "New device: volume: {}, device: {}, mounted at: {}",
+            v.name(),
+            d.name(),
+            p.iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let ctx = PairCtx {
+            running: Arc::default(),
+            cancel: volume_ctx.cancel.child_token(),
+            ..volume_ctx.clone()
+        };
+        let ah = spawn_js.blocking_lock().spawn_on(
+            watch_volume(v, d, p, config_rx.clone(), ctx).in_current_span(),
+            &handle,
+        );
+        SpawnerDisposition::Spawned(ah, None)
     })
     .expect("Failed to create PlatformNotifier");
+    s.on_removal(Box::new(|e| {
+        log::warn!(
+            "Volume {} on {} removed, its sync tasks were stopped",
+            e.volume.name(),
+            e.device.name()
+        );
+    }));
 
     s.list_spawn().unwrap();
-    s.start().unwrap();
+    rt.block_on(s.start()).unwrap();
 
     log::info!("Successfully set up watcher!");
 
-    let wait_tasks = async {
+    let wait_tasks = || async {
         loop {
             let res = js.lock().await.join_next().await;
             match res {
@@ -164,22 +747,62 @@ fn main() {
 
     rt.block_on(async {
         log::info!("Press ctrl-c to exit");
-        tokio::signal::ctrl_c()
-            .await
-            .expect("Failed to wait for ctrl-c");
-        log::info!("Received ctrl-c, shutting down, press ctrl-c again to abort");
-        s.pause().unwrap();
-        tokio::select! {
-            _ = wait_tasks => {
-                log::info!("All tasks completed, shutting down");
+        let signal = shutdown_signal().await;
+        log::info!(
+            "Received {}, shutting down, press ctrl-c again to abort",
+            signal
+        );
+        s.pause().await.unwrap();
+        shutdown.cancel();
+        let drained = tokio::select! {
+            res = tokio::time::timeout(args.shutdown_timeout, wait_tasks()) => match res {
+                Ok(()) => {
+                    log::info!("All tasks completed, shutting down");
+                    true
+                }
+                Err(_) => {
+                    log::warn!(
+                        "Syncs still running after {}, aborting: {}",
+                        HumanDuration(args.shutdown_timeout),
+                        syncing.names().join(", ")
+                    );
+                    false
+                }
+            },
+            signal = shutdown_signal() => {
+                log::warn!("Received {} again, aborting", signal);
+                false
             }
-            _ = tokio::signal::ctrl_c() => {
-                log::warn!("Received ctrl-c again, aborting");
+        };
+        if !drained {
+            cancel.cancel();
+            // Cancelled syncs remove their partial copies on their way out, the reset below
+            // would abort them before they can.
+            if tokio::time::timeout(CANCEL_GRACE, wait_tasks())
+                .await
+                .is_err()
+            {
+                log::warn!(
+                    "Syncs still running {} after cancelling them: {}",
+                    HumanDuration(CANCEL_GRACE),
+                    syncing.names().join(", ")
+                );
             }
         }
     });
 
     log::info!("Cleaning up");
     mp.clear().unwrap();
-    s.reset().unwrap();
+    rt.block_on(s.reset()).unwrap();
+
+    log::info!(
+        "Done: {} copied, {} skipped, {} failed, {}",
+        totals.copied.load(Ordering::Relaxed),
+        totals.skipped.load(Ordering::Relaxed),
+        totals.failed.load(Ordering::Relaxed),
+        HumanBytes(totals.bytes.load(Ordering::Relaxed))
+    );
+    if totals.had_failures.load(Ordering::Relaxed) && !args.ignore_errors {
+        std::process::exit(1);
+    }
 }
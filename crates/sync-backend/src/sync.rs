@@ -1,18 +1,638 @@
 use flume::RecvError;
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+use serde::{Deserialize, Serialize};
 use std::{
+    borrow::Cow,
+    collections::{hash_map::Entry, HashMap, HashSet},
+    ffi::OsStr,
     future::Future,
     hash::Hash,
-    path::PathBuf,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
     pin::Pin,
     sync::{
-        atomic::{AtomicU64, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
     },
     task::Poll,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWrite, BufReader},
+    sync::{Notify, Semaphore},
+    task::{AbortHandle, JoinSet},
+    time::Sleep,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::{
+    display_path,
+    fs::{FileType, FsBackend, Metadata, TokioFs},
+    long_path, SyncError,
 };
-use tokio::{fs::File, io::AsyncWrite, sync::Semaphore, task::JoinSet};
+use compare::cmp_file;
+pub use compare::{Comparator, CompareFuture, FlagComparator};
+use journal::Journal;
+pub use journal::JOURNAL_FILE_NAME;
+use manifest::Manifest;
+pub use manifest::MANIFEST_FILE_NAME;
+
+mod compare;
+mod journal;
+mod manifest;
+
+bitflags::bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    /// Which attributes are compared to decide whether a destination file is out of date.
+    ///
+    /// A file is copied if any of the enabled attributes differ.
+    pub struct ComparisonFlags: u8 {
+        /// The file sizes differ.
+        const SIZE = 1 << 0;
+        /// The destination is older than the source.
+        const MTIME = 1 << 1;
+        /// The file contents hash to different values.
+        const HASH = 1 << 2;
+        /// The read-only attributes differ.
+        const ATTRIBUTES = 1 << 3;
+    }
+}
+
+impl Default for ComparisonFlags {
+    fn default() -> Self {
+        Self::SIZE | Self::MTIME
+    }
+}
+
+/// Default for [`SyncOptions::mtime_tolerance`], the timestamp resolution of FAT file systems.
+pub const DEFAULT_MTIME_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// Name of the files holding the ignore rules of a source directory, see
+/// [`SyncOptions::respect_syncignore`].
+pub const SYNCIGNORE: &str = ".syncignore";
+
+/// Number of files [`CopyOrder`] sorts at most, about a hundred megabytes of paths.
+pub const MAX_ORDERED_FILES: usize = 1 << 20;
+
+/// Default for [`SyncOptions::buffer_size`].
+pub const DEFAULT_BUFFER_SIZE: usize = 1 << 20;
+
+/// How often the journal is saved while syncing, see [`SyncOptions::resume`].
+const JOURNAL_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Default for [`SyncOptions::channel_capacity`].
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 2048;
+
+/// Default for [`SyncOptions::file_progress_interval`].
+pub const DEFAULT_FILE_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+/// Options for a synchronization.
+pub struct SyncOptions {
+    /// Which attributes are compared to decide whether a destination file is up to date.
+    pub comparison: ComparisonFlags,
+    /// Maximum aggregate write rate in bytes per second, shared by all concurrent copies.
+    ///
+    /// Zero or unset means unlimited.
+    pub max_bytes_per_sec: Option<u64>,
+    /// When an existing destination file is replaced.
+    pub overwrite: OverwritePolicy,
+    /// Never replace a destination file modified after its source, like one edited on the
+    /// backup drive, whatever [`SyncOptions::overwrite`] says.
+    ///
+    /// Such files are left alone and reported in [`SyncSummary::conflicts`] to be resolved by
+    /// hand. Modification times within [`SyncOptions::mtime_tolerance`] are not a conflict, and
+    /// copies keep the modification time of their source so they are not taken for edited files.
+    pub no_clobber_newer: bool,
+    /// What to do when a file exists in more than one source.
+    pub collision: CollisionPolicy,
+    /// Read every copied file back and compare its hash to the source before keeping it.
+    pub verify: bool,
+    /// The hash function used by [`ComparisonFlags::HASH`], [`SyncOptions::verify`] and the
+    /// manifest.
+    pub hash_algorithm: HashAlgorithm,
+    /// Keep the hashes of the destination files in [`MANIFEST_FILE_NAME`] in the destination
+    /// root, rewritten after every run.
+    ///
+    /// With [`ComparisonFlags::HASH`], files whose size and modification time did not change
+    /// since the previous run are not hashed again. Without it, only the copied files are hashed
+    /// and the files left alone keep their previous entry. A manifest that cannot be read or was
+    /// written with another [`HashAlgorithm`] is ignored and replaced.
+    pub manifest: bool,
+    /// Keep a journal of the files brought up to date in [`JOURNAL_FILE_NAME`] in the
+    /// destination root while syncing, so a run that is interrupted, like by unplugging the
+    /// drive, is resumed by the next one.
+    ///
+    /// Files the journal lists are left alone without being compared again, as long as their
+    /// source size and modification time did not change. The journal is saved every few seconds
+    /// and when the run is cancelled, and removed once a run completes. A journal written for
+    /// other sources or options is ignored.
+    pub resume: bool,
+    /// Copy the read-only, hidden and system attributes to the destination, only on Windows.
+    pub preserve_attributes: bool,
+    /// Copy the alternate data streams, like `Zone.Identifier`, and the creation time to the
+    /// destination, only on Windows.
+    pub preserve_windows_metadata: bool,
+    /// Recreate source files hard linked to each other as hard links to one copy, where the
+    /// platform tells which files are linked.
+    ///
+    /// A link that cannot be created is copied in full instead.
+    pub preserve_hard_links: bool,
+    /// Clone files instead of copying them where the destination file system supports it, like
+    /// ReFS, APFS, Btrfs or XFS, which is near-instant and shares the blocks until either file
+    /// is modified.
+    ///
+    /// Only tried when the source is on the same file system, anything else is copied as usual.
+    pub prefer_reflink: bool,
+    /// Remove directories created by the synchronization that ended up empty because their source
+    /// entries were filtered out or failed.
+    ///
+    /// Directories that already existed on the destination, and copies of empty source
+    /// directories, are never removed.
+    pub prune_empty_dirs: bool,
+    /// Remove each source file once it is copied, and verified with [`SyncOptions::verify`],
+    /// then the source directories left empty, like when offloading a memory card.
+    ///
+    /// Files that fail or are left alone because the destination is up to date stay on the
+    /// source, and so do the source roots.
+    pub move_files: bool,
+    /// How symbolic links found below the source roots are handled.
+    pub symlinks: SymlinkPolicy,
+    /// How named pipes, sockets and device nodes found below the source roots are handled.
+    pub special_files: SpecialFilePolicy,
+    /// The Unicode normalization form destination names are written in.
+    pub normalize_names: NameNormalization,
+    /// Skip the source entries matched by [`SYNCIGNORE`] files, written like `.gitignore`.
+    ///
+    /// The rules of a directory apply to everything below it. Like with git, the file in the
+    /// deepest directory wins, so a `!pattern` there brings back what a parent excluded, and
+    /// nothing is brought back from an excluded directory since it is never entered.
+    /// The rule files themselves are copied unless they exclude themselves.
+    pub respect_syncignore: bool,
+    /// How many directory levels below the source roots are entered, zero only copies the
+    /// files directly in the roots.
+    ///
+    /// Directories past the limit are skipped with everything in them and not created on the
+    /// destination. Ignore rules are checked first, so an excluded directory is never counted.
+    pub max_depth: Option<usize>,
+    /// The order files are copied in.
+    pub copy_order: CopyOrder,
+    /// Count the source files before discovery, so there is an estimated total to show a
+    /// percentage against while the sources are walked, see
+    /// [`ProgressSnapshot::estimated_files`].
+    ///
+    /// The count only lists directories without reading the metadata of the files, but it still
+    /// walks the sources twice. Ignore rules are not applied, so it can be too high.
+    pub pre_scan: bool,
+    /// Whether the synchronization goes on after a file fails.
+    pub failure_mode: FailureMode,
+    /// Try the source files held by another process once more after the other files are copied.
+    pub retry_locked: bool,
+    /// Size in bytes of the buffer each copy reads the source into, zero uses
+    /// [`DEFAULT_BUFFER_SIZE`].
+    ///
+    /// Larger buffers mean fewer and larger writes, which helps with fast drives. Copies the
+    /// kernel does within one file system go by this much at a time too.
+    pub buffer_size: usize,
+    /// How many discovered files wait to be scheduled for copying before discovery pauses, zero
+    /// uses [`DEFAULT_CHANNEL_CAPACITY`].
+    ///
+    /// A larger capacity absorbs bursts of discovery while scheduling lags behind. It does not
+    /// bound memory: each scheduled file becomes a copy task waiting for its turn, so every file
+    /// found stays in memory until it is copied either way.
+    pub channel_capacity: usize,
+    /// Wait until each copy is on the disk before it replaces the destination file, and until
+    /// the destination directories that received files are at the end.
+    ///
+    /// Guards backups against a power loss or an unplugged drive, at a cost that is large for
+    /// many small files. With [`SyncOptions::move_files`], the directory of each copy is flushed
+    /// before its source is removed.
+    pub fsync: bool,
+    /// Modification times this close are considered equal, since file systems store them with
+    /// different resolutions.
+    #[serde(with = "humantime_serde")]
+    pub mtime_tolerance: Duration,
+    /// Stop the synchronization if it takes longer than this, like `30m`, see
+    /// [`SyncSummary::aborted`].
+    ///
+    /// Guards against a stalled drive holding the pair forever.
+    #[serde(with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<Duration>,
+    /// Report a source entry as failed if reading its metadata or listing it takes longer than
+    /// this, like `10s`.
+    ///
+    /// Keeps a network share whose server is down from holding discovery until the system
+    /// gives up on it.
+    #[serde(with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+    pub stat_timeout: Option<Duration>,
+    /// How often the progress of a file being copied is reported at most, it is also reported
+    /// when the copy starts and ends.
+    ///
+    /// Fast copies would otherwise report far more often than anything can display.
+    #[serde(with = "humantime_serde")]
+    pub file_progress_interval: Duration,
+    /// Only report what would be synced, leaving the destination and the sources untouched.
+    ///
+    /// The files that would be copied are logged and counted as copied in the summary.
+    pub dry_run: bool,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self {
+            comparison: ComparisonFlags::default(),
+            max_bytes_per_sec: None,
+            overwrite: OverwritePolicy::default(),
+            no_clobber_newer: false,
+            collision: CollisionPolicy::default(),
+            verify: false,
+            hash_algorithm: HashAlgorithm::default(),
+            manifest: false,
+            resume: false,
+            preserve_attributes: false,
+            preserve_windows_metadata: false,
+            preserve_hard_links: false,
+            prefer_reflink: false,
+            prune_empty_dirs: false,
+            move_files: false,
+            symlinks: SymlinkPolicy::default(),
+            special_files: SpecialFilePolicy::default(),
+            normalize_names: NameNormalization::default(),
+            respect_syncignore: false,
+            max_depth: None,
+            copy_order: CopyOrder::Discovery,
+            pre_scan: false,
+            failure_mode: FailureMode::default(),
+            retry_locked: true,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            fsync: false,
+            mtime_tolerance: DEFAULT_MTIME_TOLERANCE,
+            timeout: None,
+            stat_timeout: None,
+            file_progress_interval: DEFAULT_FILE_PROGRESS_INTERVAL,
+            dry_run: false,
+        }
+    }
+}
+
+impl SyncOptions {
+    /// Get the copy buffer size, see [`SyncOptions::buffer_size`].
+    fn buffer_size(&self) -> usize {
+        match self.buffer_size {
+            0 => DEFAULT_BUFFER_SIZE,
+            n => n,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// When an existing destination file is replaced by the source file.
+pub enum OverwritePolicy {
+    /// Replace it when the attributes selected by [`SyncOptions::comparison`] differ.
+    #[default]
+    Compare,
+    /// Always replace it.
+    Always,
+    /// Never replace it, existing files are counted as skipped.
+    NoClobber,
+    /// Replace it only when the source is newer, regardless of size.
+    Update,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// How to handle a file that exists in more than one source of a synchronization.
+pub enum CollisionPolicy {
+    /// The file from the source listed last is copied.
+    #[default]
+    LastWins,
+    /// The file from the source listed first is copied and the others are reported as errors.
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// What a synchronization does after an error.
+pub enum FailureMode {
+    /// Report the error and synchronize the other files.
+    #[default]
+    Continue,
+    /// Stop at the first error, like a cancellation, see [`SyncSummary::aborted`].
+    FailFast,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// The hash function file contents are compared and verified with.
+pub enum HashAlgorithm {
+    /// BLAKE3, fast and cryptographically secure.
+    #[default]
+    Blake3,
+    /// XXH3 with 128 bit output, the fastest but not meant to resist tampering.
+    Xxh3,
+    /// SHA-256, the slowest but the most widely available to check the manifest against.
+    Sha256,
+}
+
+/// A running hash of file contents.
+enum ContentHasher {
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+    Sha256(sha2::Sha256),
+}
+
+impl ContentHasher {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Blake3 => Self::Blake3(Box::default()),
+            HashAlgorithm::Xxh3 => Self::Xxh3(Box::default()),
+            HashAlgorithm::Sha256 => Self::Sha256(sha2::Sha256::default()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            Self::Xxh3(hasher) => hasher.update(data),
+            Self::Sha256(hasher) => sha2::Digest::update(hasher, data),
+        }
+    }
+
+    /// Get the hash as lowercase hex.
+    fn finalize(self) -> String {
+        match self {
+            Self::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            Self::Xxh3(hasher) => format!("{:032x}", hasher.digest128()),
+            Self::Sha256(hasher) => format!("{:x}", sha2::Digest::finalize(hasher)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// The order files are copied in, on a best-effort basis.
+///
+/// Any order other than [`CopyOrder::Discovery`] holds back the copies until discovery is over
+/// to sort them, keeping every pending file in memory. Past [`MAX_ORDERED_FILES`] the sorted
+/// files are copied and the rest follow in the order they are found, unsorted.
+///
+/// The order is the one the copies start in. With a concurrency above one, several copies run
+/// at once and may finish in any order.
+pub enum CopyOrder {
+    /// As the files are found, which starts copying right away.
+    #[default]
+    Discovery,
+    /// By destination path.
+    Alphabetical,
+    /// Smallest files first, so the file count rises quickly.
+    SmallestFirst,
+    /// Largest files first, so the copies most likely to fail run early.
+    LargestFirst,
+}
+
+impl CopyOrder {
+    /// Sort `jobs`, ties between sizes go by destination path.
+    fn sort(self, jobs: &mut [CopyJob]) {
+        match self {
+            Self::Discovery => {}
+            Self::Alphabetical => jobs.sort_by(|a, b| a.dest.cmp(&b.dest)),
+            Self::SmallestFirst => {
+                jobs.sort_by(|a, b| (a.meta.len, &a.dest).cmp(&(b.meta.len, &b.dest)));
+            }
+            Self::LargestFirst => {
+                jobs.sort_by(|a, b| (b.meta.len, &a.dest).cmp(&(a.meta.len, &b.dest)));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// How to handle a symbolic link in a source directory.
+///
+/// The source roots themselves are always followed.
+pub enum SymlinkPolicy {
+    /// Synchronize what the link points to, directories linking back to one of their parents are
+    /// reported and skipped.
+    #[default]
+    Follow,
+    /// Ignore the link.
+    Skip,
+    /// Recreate the link on the destination with the same target.
+    Copy,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// How to handle a source entry that is neither a file, a directory nor a symbolic link, like a
+/// named pipe, a socket or a device node.
+///
+/// Reading one could block forever or never end, so its content is never copied. Windows has
+/// none of them.
+pub enum SpecialFilePolicy {
+    /// Leave it out, see [`SyncSummary::special_files`].
+    #[default]
+    Skip,
+    /// Report it as an error.
+    Error,
+    /// Create a named pipe or device node like it on the destination, sockets are left out.
+    ///
+    /// Creating device nodes usually takes root.
+    Recreate,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// How names are normalized on the destination.
+///
+/// Some file systems hand back accented names in another normalization form than they were
+/// written in, so an unchanged file looks missing and gets copied again next to the old one.
+/// Normalizing writes every name in one form, and an existing entry whose name only differs in
+/// normalization is treated as the same file.
+///
+/// This costs a directory listing for every destination name that does not exist yet, and files
+/// whose names differ only in normalization on the source end up as one file.
+pub enum NameNormalization {
+    /// Keep names as they are on the source.
+    #[default]
+    Off,
+    /// Composed form, like most keyboards type and Windows uses.
+    Nfc,
+    /// Decomposed form, like older macOS file systems store.
+    Nfd,
+}
+
+impl NameNormalization {
+    /// Normalize `name`, names that are not valid Unicode are kept as they are.
+    fn apply(self, name: &OsStr) -> Cow<'_, OsStr> {
+        use unicode_normalization::UnicodeNormalization;
+
+        let Some(s) = name.to_str() else {
+            return Cow::Borrowed(name);
+        };
+        let normalized = match self {
+            Self::Off => return Cow::Borrowed(name),
+            Self::Nfc => s.nfc().collect::<String>(),
+            Self::Nfd => s.nfd().collect::<String>(),
+        };
+        if normalized == s {
+            Cow::Borrowed(name)
+        } else {
+            Cow::Owned(normalized.into())
+        }
+    }
+
+    /// Normalize every name in `path`.
+    fn apply_path(self, path: &Path) -> Cow<'_, Path> {
+        if self == Self::Off {
+            return Cow::Borrowed(path);
+        }
+        Cow::Owned(path.iter().map(|name| self.apply(name)).collect())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Identifies a directory independently of the path it was reached through.
+enum DirId {
+    Inode(u64, u64),
+    Path(PathBuf),
+}
+
+async fn dir_id<B: FsBackend>(fs: &B, path: &Path, meta: &Metadata) -> std::io::Result<DirId> {
+    match meta.id {
+        Some((dev, ino)) => Ok(DirId::Inode(dev, ino)),
+        None => fs.canonicalize(path).await.map(DirId::Path),
+    }
+}
+
+/// Check whether `path` is excluded by the ignore rules of its directories, deepest first.
+fn ignored(ignores: &[Arc<Gitignore>], path: &Path, is_dir: bool) -> bool {
+    for rules in ignores.iter().rev() {
+        match rules.matched(path, is_dir) {
+            Match::Ignore(_) => return true,
+            Match::Whitelist(_) => return false,
+            Match::None => {}
+        }
+    }
+    false
+}
+
+/// Read the [`SYNCIGNORE`] file of the source directory `dir`.
+async fn read_ignore<B: FsBackend>(fs: &B, dir: &Path) -> Result<Gitignore, SyncError> {
+    let path = dir.join(SYNCIGNORE);
+    let fail = |e: ignore::Error| SyncError::IgnoreFile(path.clone(), e);
+
+    let mut data = Vec::new();
+    let mut file = fs.open(&path).await.map_err(|e| fail(e.into()))?;
+    file.read_to_end(&mut data)
+        .await
+        .map_err(|e| fail(e.into()))?;
+
+    let mut builder = GitignoreBuilder::new(dir);
+    for line in String::from_utf8_lossy(&data).lines() {
+        builder.add_line(Some(path.clone()), line).map_err(fail)?;
+    }
+    builder.build().map_err(fail)
+}
+
+/// Create a symbolic link at `dest` with the same target as `src`, replacing whatever file or
+/// link is there.
+///
+/// Returns `false` if `dest` already was a link to the same target.
+async fn copy_symlink<B: FsBackend>(fs: &B, src: &Path, dest: &Path) -> std::io::Result<bool> {
+    let target = fs.read_link(src).await?;
+    match fs.symlink_metadata(dest).await {
+        Ok(meta) if meta.is_symlink() => {
+            if fs.read_link(dest).await? == target {
+                return Ok(false);
+            }
+            fs.remove_link(dest).await?;
+        }
+        Ok(meta) if meta.is_dir() => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "a directory is in the way",
+            ));
+        }
+        Ok(_) => fs.remove_file(dest).await?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    // Windows needs to know whether the link points to a directory.
+    let dir = fs.metadata(src).await.is_ok_and(|m| m.is_dir());
+    fs.symlink(&target, dest, dir).await?;
+    Ok(true)
+}
+
+/// Recreate the special file `src` at `dest` unless one of the same type is there, returning
+/// whether it was created.
+async fn recreate_special<B: FsBackend>(
+    fs: &B,
+    src: &Path,
+    dest: &Path,
+    file_type: FileType,
+) -> std::io::Result<bool> {
+    match fs.symlink_metadata(dest).await {
+        Ok(meta) if meta.file_type == file_type => return Ok(false),
+        Ok(meta) if meta.is_dir() => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "a directory is in the way",
+            ));
+        }
+        Ok(_) => fs.remove_file(dest).await?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    fs.make_special(src, dest).await?;
+    Ok(true)
+}
+
+/// A write rate limiter that can be shared between concurrent writers.
+#[derive(Debug)]
+pub struct Throttle {
+    bytes_per_sec: u64,
+    next_free: Mutex<Instant>,
+}
+
+impl Throttle {
+    /// Create a new `Throttle`, returns `None` if the limit is zero.
+    pub fn new(bytes_per_sec: u64) -> Option<Self> {
+        if bytes_per_sec == 0 {
+            return None;
+        }
+
+        Some(Self {
+            bytes_per_sec,
+            next_free: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// Account for `n` written bytes, returning how long the writer should wait before writing again.
+    pub fn consume(&self, n: u64) -> Option<Duration> {
+        #[allow(clippy::cast_precision_loss)]
+        let cost = Duration::from_secs_f64(n as f64 / self.bytes_per_sec as f64);
+        let now = Instant::now();
+        let mut next_free = match self.next_free.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *next_free = (*next_free).max(now) + cost;
 
-use crate::SyncError;
+        Some(*next_free - now).filter(|d| !d.is_zero())
+    }
+}
 
 #[non_exhaustive]
 #[derive(Debug, Default)]
@@ -21,15 +641,245 @@ use crate::SyncError;
 pub struct GlobalProgress {
     pub files: ProgressTIDSF<AtomicU64>,
     pub bytes: ProgressTIDSF<AtomicU64>,
+    /// Number of files counted by [`SyncOptions::pre_scan`], zero without it and after discovery.
+    estimated_files: AtomicU64,
+    rate: Mutex<Option<RateSample>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+/// The values of a [`GlobalProgress`] at one point, see [`GlobalProgress::snapshot`].
+pub struct ProgressSnapshot {
+    /// File counts.
+    pub files: ProgressTIDSF<u64>,
+    /// Byte counts.
+    pub bytes: ProgressTIDSF<u64>,
+    /// The total number of files expected from [`SyncOptions::pre_scan`], raised to the files
+    /// found so far if discovery finds more.
+    ///
+    /// Only set from the end of the pre-scan until [`ProgressMilestone::DiscoveryComplete`],
+    /// when `files.total` is exact.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_files: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+/// The outcome of a synchronization.
+pub struct SyncSummary {
+    /// Number of files copied.
+    pub files_copied: u64,
+    /// Number of files skipped because the destination was up to date, or the source was in use.
+    pub files_skipped: u64,
+    /// Number of files that failed to synchronize.
+    pub files_failed: u64,
+    /// Number of bytes copied.
+    pub bytes_copied: u64,
+    /// Number of files recreated as a hard link to another copy, also counted as copied, see
+    /// [`SyncOptions::preserve_hard_links`].
+    pub files_linked: u64,
+    /// Number of empty directories removed, see [`SyncOptions::prune_empty_dirs`].
+    pub dirs_pruned: u64,
+    /// Number of source files removed after being copied, see [`SyncOptions::move_files`].
+    pub files_moved: u64,
+    /// Paths that failed to synchronize and the reason.
+    pub failed_paths: Vec<(PathBuf, String)>,
+    /// Number of source files removed between discovery and their copy, they are left out of
+    /// the progress totals.
+    pub files_gone: u64,
+    /// Source files skipped because another process held them, and the reason.
+    pub locked_paths: Vec<(PathBuf, String)>,
+    /// Source files left alone because their destination was modified after them, and both
+    /// modification times, see [`SyncOptions::no_clobber_newer`]. Also counted as skipped.
+    pub conflicts: Vec<(PathBuf, String)>,
+    /// Special files left out and their type, see [`SyncOptions::special_files`]. Also counted
+    /// as skipped.
+    pub special_files: Vec<(PathBuf, FileType)>,
+    /// Why the synchronization stopped early: the first error with [`FailureMode::FailFast`], the
+    /// destination filling up, or running past [`SyncOptions::timeout`].
+    ///
+    /// Not set when it is cancelled from outside.
+    pub aborted: Option<String>,
+    /// Time taken by the synchronization.
+    pub duration: Duration,
+}
+
+impl SyncSummary {
+    /// Check if anything failed, including errors not tied to a single file like a missing source,
+    /// and a synchronization stopped early, see [`SyncSummary::aborted`].
+    pub fn has_failures(&self) -> bool {
+        self.files_failed > 0 || !self.failed_paths.is_empty() || self.aborted.is_some()
+    }
+
+    /// Stop the synchronization on the first error with [`FailureMode::FailFast`], and on
+    /// [`SyncError::DiskFull`] whatever the mode since every other copy would fail too.
+    fn check_fail_fast(&mut self, mode: FailureMode, e: &SyncError, cancel: &CancellationToken) {
+        let stop = mode == FailureMode::FailFast || matches!(e, SyncError::DiskFull { .. });
+        if stop && self.aborted.is_none() {
+            self.aborted = Some(e.to_string());
+            cancel.cancel();
+        }
+    }
+
+    fn record_failure(&mut self, e: &SyncError) {
+        tracing::debug!(
+            path = e.path().map(|p| display_path(p).display().to_string()),
+            error.kind = e.io_kind().map(tracing::field::debug),
+            "{}",
+            e
+        );
+        if let Some(path) = e.path() {
+            self.failed_paths
+                .push((display_path(path).into_owned(), e.to_string()));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Transfer rate and estimated time remaining.
+pub struct ProgressRate {
+    /// Smoothed number of bytes written per second.
+    pub bytes_per_sec: f64,
+    /// Estimated time until all remaining bytes are written, if the rate is not zero.
+    pub eta: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct RateSample {
+    at: Instant,
+    bytes: u64,
+    bytes_per_sec: Option<f64>,
+}
+
+impl GlobalProgress {
+    /// Get the current counts.
+    ///
+    /// Each count is loaded on its own, so they can be slightly out of step while files are
+    /// being synchronized.
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        let files = self.files.load();
+        let estimated = self.estimated_files.load(Ordering::Relaxed);
+        ProgressSnapshot {
+            files,
+            bytes: self.bytes.load(),
+            estimated_files: (estimated > 0).then(|| estimated.max(files.total)),
+        }
+    }
+
+    /// Minimum time between two samples of the transfer rate.
+    const RATE_WINDOW: Duration = Duration::from_millis(250);
+    /// Time constant of the exponential moving average of the transfer rate.
+    const RATE_SMOOTHING: Duration = Duration::from_secs(3);
+
+    /// Update and get the transfer rate and estimated time remaining.
+    ///
+    /// This is meant to be called from the progress callback, the rate is an exponential moving
+    /// average sampled at most every 250ms. Returns `None` until the first sample window elapsed.
+    pub fn throughput(&self) -> Option<ProgressRate> {
+        let transferred = self.bytes.done.load(Ordering::Relaxed)
+            + self.bytes.in_progress.load(Ordering::Relaxed);
+        let now = Instant::now();
+
+        let mut rate = match self.rate.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        let sample = rate.get_or_insert(RateSample {
+            at: now,
+            bytes: transferred,
+            bytes_per_sec: None,
+        });
+
+        let elapsed = now.duration_since(sample.at);
+        if elapsed >= Self::RATE_WINDOW {
+            #[allow(clippy::cast_precision_loss)]
+            let current = transferred.saturating_sub(sample.bytes) as f64 / elapsed.as_secs_f64();
+            let alpha = 1.0 - (-elapsed.as_secs_f64() / Self::RATE_SMOOTHING.as_secs_f64()).exp();
+            sample.bytes_per_sec = Some(match sample.bytes_per_sec {
+                Some(prev) => prev + alpha * (current - prev),
+                None => current,
+            });
+            sample.at = now;
+            sample.bytes = transferred;
+        }
+
+        let bytes_per_sec = sample.bytes_per_sec?;
+        let remaining = self.bytes.total.load(Ordering::Relaxed).saturating_sub(
+            transferred
+                + self.bytes.skipped.load(Ordering::Relaxed)
+                + self.bytes.failed.load(Ordering::Relaxed),
+        );
+
+        #[allow(clippy::cast_precision_loss)]
+        Some(ProgressRate {
+            bytes_per_sec,
+            eta: (bytes_per_sec > 0.0)
+                .then(|| Duration::from_secs_f64(remaining as f64 / bytes_per_sec)),
+        })
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 /// Progress milestones.
 pub enum ProgressMilestone {
+    /// The pre-scan is complete, an estimated total number of files is known, see
+    /// [`SyncOptions::pre_scan`].
+    PreScanComplete,
     /// Discovery phase is complete, the total number of files and bytes is known.
     DiscoveryComplete,
     /// Copy phase is complete.
     CopyComplete,
+    /// The synchronization was cancelled before it could complete.
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// The phase of a synchronization an error happened in.
+pub enum Phase {
+    /// Walking the sources and comparing them to the destination.
+    Discovery,
+    /// Checking that the destination has room for the files to copy.
+    SpaceCheck,
+    /// Copying a file.
+    Copy,
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Discovery => write!(f, "discovery"),
+            Self::SpaceCheck => write!(f, "space check"),
+            Self::Copy => write!(f, "copy"),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// An error reported to the `error_fn` callback of [`SyncFS::sync`].
+pub struct SyncErrorContext<'a> {
+    /// The phase the error happened in.
+    pub phase: Phase,
+    /// The source file or directory the error is about, if there is one.
+    ///
+    /// Unlike [`SyncError::path`] this is also known when a copy task failed as a whole.
+    pub path: Option<PathBuf>,
+    /// The error.
+    pub error: &'a SyncError,
+}
+
+impl std::fmt::Display for SyncErrorContext<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (during {})", self.error, self.phase)
+    }
+}
+
+/// Aborts a task when dropped.
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
 }
 
 #[derive(Debug, Default, Clone, Copy)]
@@ -50,7 +900,11 @@ pub struct TrackingAsyncWrite<'a, W: AsyncWrite, K: Unpin, F: Fn(&K, &FileProgre
     failed: bool,
     finalized: bool,
     written: u64,
-    last_progress_reported: u64,
+    report_interval: Duration,
+    last_reported: Instant,
+    throttle: Option<&'a Throttle>,
+    delay: Option<Pin<Box<Sleep>>>,
+    hasher: Option<ContentHasher>,
     inner: Pin<&'a mut W>,
 }
 
@@ -78,11 +932,52 @@ impl<'a, W: AsyncWrite, K: Unpin, F: Fn(&K, &FileProgress)> TrackingAsyncWrite<'
             failed: false,
             finalized: false,
             written: 0,
-            last_progress_reported: 0,
+            report_interval: DEFAULT_FILE_PROGRESS_INTERVAL,
+            last_reported: Instant::now(),
+            throttle: None,
+            delay: None,
+            hasher: None,
             fp,
         }
     }
 
+    /// Hash the written bytes with `algorithm`, see [`TrackingAsyncWrite::take_hash`].
+    #[must_use]
+    pub fn hash(mut self, algorithm: Option<HashAlgorithm>) -> Self {
+        self.hasher = algorithm.map(ContentHasher::new);
+        self
+    }
+
+    /// Get the hash of the bytes written so far as lowercase hex, if hashing.
+    pub fn take_hash(&mut self) -> Option<String> {
+        self.hasher.take().map(ContentHasher::finalize)
+    }
+
+    /// Call the progress callback at most once every `interval` while writing, see
+    /// [`SyncOptions::file_progress_interval`].
+    #[must_use]
+    pub fn report_interval(mut self, interval: Duration) -> Self {
+        self.report_interval = interval;
+        self
+    }
+
+    /// Limit the write rate with a (possibly shared) [`Throttle`].
+    #[must_use]
+    pub fn throttle(mut self, throttle: Option<&'a Throttle>) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    fn poll_delay(&mut self, cx: &mut std::task::Context) -> Poll<()> {
+        if let Some(delay) = self.delay.as_mut() {
+            if delay.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.delay = None;
+        }
+        Poll::Ready(())
+    }
+
     fn register_fail(&mut self) {
         if !self.failed {
             self.gp.bytes.failed.fetch_add(self.size, Ordering::Relaxed);
@@ -92,23 +987,34 @@ impl<'a, W: AsyncWrite, K: Unpin, F: Fn(&K, &FileProgress)> TrackingAsyncWrite<'
         }
     }
 
+    /// The wrapped writer, for writes this does not see like [`FsBackend::copy_range`].
+    fn get_mut(&mut self) -> &mut W
+    where
+        W: Unpin,
+    {
+        self.inner.as_mut().get_mut()
+    }
+
     fn increment_bytes(&mut self, n: u64) {
         if !self.failed {
             self.written += n;
-            if self.written - self.last_progress_reported >= 64 << 10 {
-                (self.progress_callback)(&self.job_id, &self.fp);
-                self.last_progress_reported = self.written;
-            }
             self.fp.done += n;
             self.gp.bytes.in_progress.fetch_add(n, Ordering::Relaxed);
+            let now = Instant::now();
+            if now.duration_since(self.last_reported) >= self.report_interval {
+                (self.progress_callback)(&self.job_id, &self.fp);
+                self.last_reported = now;
+            }
         }
     }
 
     fn finalize(&mut self) {
         (self.progress_callback)(&self.job_id, &self.fp);
         if !self.failed && !self.finalized {
+            self.finalized = true;
             if self.written != self.size {
                 self.register_fail();
+                return;
             }
             self.gp
                 .bytes
@@ -120,17 +1026,6 @@ impl<'a, W: AsyncWrite, K: Unpin, F: Fn(&K, &FileProgress)> TrackingAsyncWrite<'
                 .fetch_sub(self.size, Ordering::Relaxed);
             self.gp.files.in_progress.fetch_sub(1, Ordering::Relaxed);
             self.gp.files.done.fetch_add(1, Ordering::Relaxed);
-            self.finalized = true;
-        }
-    }
-
-    fn revert_progress(&mut self) {
-        if !self.failed && self.finalized {
-            self.gp
-                .bytes
-                .done
-                .fetch_sub(self.written, Ordering::Relaxed);
-            self.gp.files.done.fetch_sub(1, Ordering::Relaxed);
         }
     }
 }
@@ -143,6 +1038,10 @@ impl<'a, W: AsyncWrite, K: Unpin, F: Fn(&K, &FileProgress)> AsyncWrite
         cx: &mut std::task::Context,
         buf: &[u8],
     ) -> std::task::Poll<std::io::Result<usize>> {
+        if self.poll_delay(cx).is_pending() {
+            return Poll::Pending;
+        }
+
         match self.inner.as_mut().poll_write(cx, buf) {
             Poll::Ready(r) => match r {
                 Err(e) => {
@@ -151,6 +1050,12 @@ impl<'a, W: AsyncWrite, K: Unpin, F: Fn(&K, &FileProgress)> AsyncWrite
                 }
                 Ok(n) => {
                     self.increment_bytes(n as u64);
+                    if let Some(hasher) = self.hasher.as_mut() {
+                        hasher.update(&buf[..n]);
+                    }
+                    if let Some(delay) = self.throttle.and_then(|t| t.consume(n as u64)) {
+                        self.delay = Some(Box::pin(tokio::time::sleep(delay)));
+                    }
                     Poll::Ready(Ok(n))
                 }
             },
@@ -162,6 +1067,10 @@ impl<'a, W: AsyncWrite, K: Unpin, F: Fn(&K, &FileProgress)> AsyncWrite
         mut self: Pin<&mut Self>,
         cx: &mut std::task::Context,
     ) -> std::task::Poll<std::io::Result<()>> {
+        if self.poll_delay(cx).is_pending() {
+            return Poll::Pending;
+        }
+
         match self.inner.as_mut().poll_flush(cx) {
             Poll::Ready(r) => match r {
                 Err(e) => {
@@ -202,7 +1111,7 @@ impl<'a, W: AsyncWrite, K: Unpin, F: Fn(&K, &FileProgress)> Drop
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
 /// A structure for tracking progress where the total, in progress, done, skipped, and failed counts are tracked.
 #[allow(missing_docs)]
 pub struct ProgressTIDSF<T: Default> {
@@ -213,56 +1122,767 @@ pub struct ProgressTIDSF<T: Default> {
     pub failed: T,
 }
 
-/// A structure for synchronizing two directories.
-pub struct SyncFS<'a> {
-    src_root: &'a PathBuf,
-    dest_root: &'a PathBuf,
-    ctx: Arc<SyncFSCtx>,
+impl ProgressTIDSF<AtomicU64> {
+    /// Load every count.
+    pub fn load(&self) -> ProgressTIDSF<u64> {
+        ProgressTIDSF {
+            total: self.total.load(Ordering::Relaxed),
+            in_progress: self.in_progress.load(Ordering::Relaxed),
+            done: self.done.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A structure for synchronizing one or more source directories into a destination directory.
+///
+/// The roots are kept as [`long_path`]s so deep trees work on Windows.
+///
+/// File system access goes through `B`, the real file system unless built
+/// [`with_backend`](SyncFS::with_backend).
+pub struct SyncFS<B: FsBackend = TokioFs> {
+    src_roots: Vec<PathBuf>,
+    dest_root: PathBuf,
+    ctx: Arc<SyncFSCtx<B>>,
+    comparator: Box<dyn Comparator<B>>,
+    /// Relative paths of the files found so far and the source they were found in, only
+    /// tracked with multiple sources.
+    claimed: Mutex<HashMap<PathBuf, PathBuf>>,
+    /// Destination directories created by the current run, only tracked when pruning.
+    created_dirs: Mutex<Vec<PathBuf>>,
+    /// Source files whose destination is newer, see [`SyncOptions::no_clobber_newer`].
+    conflicts: Mutex<Vec<(PathBuf, String)>>,
+    /// Special files left out, see [`SpecialFilePolicy::Skip`].
+    special_files: Mutex<Vec<(PathBuf, FileType)>>,
+    /// Source directories entered by the current run below the roots, only tracked when moving
+    /// files.
+    entered_dirs: Mutex<Vec<PathBuf>>,
+    /// Destination of the first source file found for each hard linked source, only tracked
+    /// when preserving hard links.
+    links: Mutex<HashMap<(u64, u64), PathBuf>>,
+    /// Further hard links, recreated once the first copies are done.
+    deferred_links: Mutex<Vec<DeferredLink>>,
+}
+
+/// A file discovery found to copy.
+struct CopyJob {
+    src: PathBuf,
+    dest: PathBuf,
+    meta: Metadata,
+}
+
+/// A source file hard linked to one found before it, see [`SyncOptions::preserve_hard_links`].
+struct DeferredLink {
+    src: PathBuf,
+    dest: PathBuf,
+    /// Where the first link was copied to.
+    first: PathBuf,
+    meta: Metadata,
 }
 
-struct SyncFSCtx {
-    progress: GlobalProgress,
+struct SyncFSCtx<B> {
+    fs: B,
+    progress: Arc<GlobalProgress>,
     semaphore: Semaphore,
+    /// Limit shared with other instances, taken after `semaphore`.
+    shared: Option<Arc<Semaphore>>,
+    throttle: Option<Throttle>,
+    manifest: Arc<Manifest>,
+    journal: Journal,
+    pause: PauseHandle,
+    /// Source files removed by the current run, see [`SyncOptions::move_files`].
+    moved: AtomicU64,
+    /// Destination directories files were copied into by the current run, only tracked with
+    /// [`SyncOptions::fsync`].
+    written_dirs: Mutex<HashSet<PathBuf>>,
+    options: SyncOptions,
 }
 
-impl<'a> SyncFS<'a> {
-    /// Create a new `SyncFS` instance.
-    pub fn new(src_root: &'a PathBuf, dest_root: &'a PathBuf, max_concurrent: usize) -> Self {
-        log::info!(
-            "Creating SyncFS instance from {} to {}, concurrency: {}",
-            src_root.display(),
-            dest_root.display(),
-            max_concurrent
-        );
+impl<B> SyncFSCtx<B> {
+    fn new(fs: B, max_concurrent: usize, options: SyncOptions) -> Self {
         Self {
-            ctx: Arc::new(SyncFSCtx {
-                progress: GlobalProgress::default(),
-                semaphore: Semaphore::new(max_concurrent),
-            }),
-            src_root,
-            dest_root,
+            fs,
+            progress: Arc::default(),
+            semaphore: Semaphore::new(max_concurrent),
+            shared: None,
+            throttle: options.max_bytes_per_sec.and_then(Throttle::new),
+            manifest: Arc::default(),
+            journal: Journal::default(),
+            pause: PauseHandle::default(),
+            moved: AtomicU64::new(0),
+            written_dirs: Mutex::new(HashSet::new()),
+            options,
         }
     }
-    fn walk(
-        &'a self,
-        rel: PathBuf,
-        tx: &'a flume::Sender<Result<(PathBuf, PathBuf), SyncError>>,
-    ) -> Pin<Box<impl Future<Output = ()> + 'a>> {
-        Box::pin(async move {
-            let src = self.src_root.join(&rel);
-            let dest = self.dest_root.join(&rel);
+}
 
-            let src_meta = match tokio::fs::metadata(&src).await {
-                Ok(m) => m,
-                Err(e) => {
-                    tx.send_async(Err(SyncError::StatFailed(src.clone(), e)))
-                        .await
-                        .expect("Result receiver dropped");
-                    return;
-                }
+#[derive(Debug, Default)]
+struct PauseState {
+    paused: AtomicBool,
+    resumed: Notify,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Pauses the copies of the [`SyncFS`] instances it is given to, see [`SyncFS::with_pause`].
+///
+/// While paused, files wait before taking a concurrency permit, and give it back if paused while
+/// waiting for it, so copies already started finish and discovery goes on. Pausing is not
+/// stopping: the waiting files, the open source directories and [`SyncOptions::timeout`] all keep
+/// running until it is resumed or cancelled.
+pub struct PauseHandle(Arc<PauseState>);
+
+impl PauseHandle {
+    /// Hold the copies that did not start yet.
+    pub fn pause(&self) {
+        self.0.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Let the held copies go on.
+    pub fn resume(&self) {
+        self.0.paused.store(false, Ordering::SeqCst);
+        self.0.resumed.notify_waiters();
+    }
+
+    /// Check whether the copies are held.
+    pub fn is_paused(&self) -> bool {
+        self.0.paused.load(Ordering::SeqCst)
+    }
+
+    /// Wait until not paused.
+    async fn wait(&self) {
+        loop {
+            // Registered before checking, so a resume in between is not missed.
+            let resumed = self.0.resumed.notified();
+            tokio::pin!(resumed);
+            resumed.as_mut().enable();
+            if !self.is_paused() {
+                return;
+            }
+            resumed.await;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Controls a synchronization started with [`SyncFS::start`] and tells how far it got.
+///
+/// Clones control the same synchronization.
+pub struct SyncHandle {
+    cancel: CancellationToken,
+    pause: PauseHandle,
+    progress: Arc<GlobalProgress>,
+}
+
+impl SyncHandle {
+    /// Stop the synchronization, like cancelling the token given to [`SyncFS::sync`].
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Check whether the synchronization was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Hold the copies that did not start yet, see [`PauseHandle`].
+    ///
+    /// This pauses every instance sharing the handle given to [`SyncFS::with_pause`], if any.
+    pub fn pause(&self) {
+        self.pause.pause();
+    }
+
+    /// Let the held copies go on.
+    pub fn resume(&self) {
+        self.pause.resume();
+    }
+
+    /// Check whether the copies are held.
+    pub fn is_paused(&self) -> bool {
+        self.pause.is_paused()
+    }
+
+    /// Get the current counts, see [`GlobalProgress::snapshot`].
+    pub fn progress_snapshot(&self) -> ProgressSnapshot {
+        self.progress.snapshot()
+    }
+
+    /// Get the live progress, like for [`GlobalProgress::throughput`].
+    pub fn progress(&self) -> &GlobalProgress {
+        &self.progress
+    }
+}
+
+#[derive(Debug, Default)]
+/// A concurrency limit shared by several [`SyncFS`] instances, see
+/// [`SyncFS::with_shared_semaphore`].
+///
+/// Changing the limit replaces the semaphore, synchronizations already running keep the old one.
+pub struct SharedLimit(Mutex<Option<(usize, Arc<Semaphore>)>>);
+
+impl SharedLimit {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<(usize, Arc<Semaphore>)>> {
+        match self.0.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Set the limit, `None` removes it. Returns whether the limit changed.
+    pub fn set(&self, limit: Option<usize>) -> bool {
+        let mut current = self.lock();
+        let changed = current.as_ref().map(|(n, _)| *n) != limit;
+        if changed {
+            *current = limit.map(|n| (n, Arc::new(Semaphore::new(n))));
+        }
+        changed
+    }
+
+    /// Get the semaphore enforcing the current limit, if any.
+    pub fn semaphore(&self) -> Option<Arc<Semaphore>> {
+        self.lock()
+            .as_ref()
+            .map(|(_, semaphore)| Arc::clone(semaphore))
+    }
+}
+
+/// Number of concurrent file operations to use when none is configured.
+pub fn default_concurrency() -> usize {
+    std::thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+impl SyncFS {
+    /// Create a new `SyncFS` instance.
+    ///
+    /// The roots are taken by value or copied, so the instance can be moved into a spawned task
+    /// on its own. A `max_concurrent` of zero uses [`default_concurrency`].
+    pub fn new(
+        src_root: impl Into<PathBuf>,
+        dest_root: impl Into<PathBuf>,
+        max_concurrent: usize,
+    ) -> Self {
+        Self::with_options(src_root, dest_root, max_concurrent, SyncOptions::default())
+    }
+
+    /// Create a new `SyncFS` instance with the given options.
+    pub fn with_options(
+        src_root: impl Into<PathBuf>,
+        dest_root: impl Into<PathBuf>,
+        max_concurrent: usize,
+        options: SyncOptions,
+    ) -> Self {
+        Self::with_sources([src_root], dest_root, max_concurrent, options)
+    }
+
+    /// Create a new `SyncFS` instance merging several source directories into the destination.
+    ///
+    /// Sources are walked one after the other and share the progress, files present in more than
+    /// one source are handled according to [`SyncOptions::collision`].
+    pub fn with_sources(
+        src_roots: impl IntoIterator<Item = impl Into<PathBuf>>,
+        dest_root: impl Into<PathBuf>,
+        max_concurrent: usize,
+        options: SyncOptions,
+    ) -> Self {
+        Self::with_shared_semaphore(src_roots, dest_root, max_concurrent, options, None)
+    }
+
+    /// Create a new `SyncFS` instance like [`SyncFS::with_sources`], whose copies also take a
+    /// permit from `shared` if given.
+    ///
+    /// Sharing one semaphore between instances bounds the number of files open at once across
+    /// all of them, `max_concurrent` still caps this instance.
+    pub fn with_shared_semaphore(
+        src_roots: impl IntoIterator<Item = impl Into<PathBuf>>,
+        dest_root: impl Into<PathBuf>,
+        max_concurrent: usize,
+        options: SyncOptions,
+        shared: Option<Arc<Semaphore>>,
+    ) -> Self {
+        let long = |path: PathBuf| match long_path(&path) {
+            Ok(long) => long,
+            Err(e) => {
+                log::warn!("Failed to resolve {}: {}", path.display(), e);
+                path
+            }
+        };
+        SyncFS::build(
+            TokioFs,
+            src_roots.into_iter().map(|p| long(p.into())),
+            long(dest_root.into()),
+            max_concurrent,
+            options,
+            shared,
+        )
+    }
+}
+
+impl<B: FsBackend> SyncFS<B> {
+    /// Create a new `SyncFS` instance working on the file system `fs`.
+    ///
+    /// Unlike the other constructors, the paths are used as they are.
+    pub fn with_backend(
+        fs: B,
+        src_roots: impl IntoIterator<Item = impl Into<PathBuf>>,
+        dest_root: impl Into<PathBuf>,
+        max_concurrent: usize,
+        options: SyncOptions,
+    ) -> Self {
+        Self::build(fs, src_roots, dest_root, max_concurrent, options, None)
+    }
+
+    fn build(
+        fs: B,
+        src_roots: impl IntoIterator<Item = impl Into<PathBuf>>,
+        dest_root: impl Into<PathBuf>,
+        max_concurrent: usize,
+        options: SyncOptions,
+        shared: Option<Arc<Semaphore>>,
+    ) -> Self {
+        let src_roots = src_roots.into_iter().map(Into::into).collect::<Vec<_>>();
+        let dest_root = dest_root.into();
+        let max_concurrent = match max_concurrent {
+            0 => default_concurrency(),
+            n => n,
+        };
+        log::info!(
+            "Creating SyncFS instance from {} to {}, concurrency: {}",
+            src_roots
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            dest_root.display(),
+            max_concurrent
+        );
+        let ctx = SyncFSCtx {
+            shared,
+            ..SyncFSCtx::new(fs, max_concurrent, options)
+        };
+        Self {
+            comparator: Box::new(FlagComparator::from_options(&ctx.options, &ctx.manifest)),
+            ctx: Arc::new(ctx),
+            src_roots,
+            dest_root,
+            claimed: Mutex::new(HashMap::new()),
+            created_dirs: Mutex::new(Vec::new()),
+            conflicts: Mutex::new(Vec::new()),
+            special_files: Mutex::new(Vec::new()),
+            entered_dirs: Mutex::new(Vec::new()),
+            links: Mutex::new(HashMap::new()),
+            deferred_links: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Hold the copies while `pause` is paused, see [`PauseHandle`].
+    ///
+    /// The same handle can be given to several instances to pause them all.
+    #[must_use]
+    pub fn with_pause(mut self, pause: PauseHandle) -> Self {
+        match Arc::get_mut(&mut self.ctx) {
+            Some(ctx) => ctx.pause = pause,
+            // Only shared while syncing, which takes the instance by reference.
+            None => log::warn!("Not setting the pause handle while syncing"),
+        }
+        self
+    }
+
+    /// Start synchronizing in the returned future, controlled by the returned handle.
+    ///
+    /// The future resolves to the [`SyncSummary`] like [`SyncFS::sync`], nothing happens until
+    /// it is polled. Errors are only collected into the summary.
+    pub fn start(
+        self,
+    ) -> (
+        SyncHandle,
+        impl Future<Output = SyncSummary> + Send + 'static,
+    ) {
+        let handle = self.handle(CancellationToken::new());
+        let sync = {
+            let handle = handle.clone();
+            async move { self.run(&handle, |_, _| {}, &|_| {}).await }
+        };
+        (handle, sync)
+    }
+
+    /// Get a handle on a synchronization of this instance stopped by `cancel`.
+    fn handle(&self, cancel: CancellationToken) -> SyncHandle {
+        SyncHandle {
+            cancel,
+            pause: self.ctx.pause.clone(),
+            progress: Arc::clone(&self.ctx.progress),
+        }
+    }
+
+    /// Decide whether destination files are up to date with `comparator` instead of
+    /// [`SyncOptions::comparison`], when [`SyncOptions::overwrite`] is
+    /// [`OverwritePolicy::Compare`].
+    ///
+    /// The manifest, if kept, no longer spares hashing since that is up to `comparator`.
+    #[must_use]
+    pub fn with_comparator(mut self, comparator: impl Comparator<B> + 'static) -> Self {
+        self.comparator = Box::new(comparator);
+        self
+    }
+
+    /// Check with the [`Comparator`] whether `dest` is an up to date copy of `src`.
+    async fn same(&self, src: &Path, dest: &Path) -> std::io::Result<bool> {
+        let fs = &self.ctx.fs;
+        let dest_meta = fs.metadata(dest).await?;
+        let src_meta = fs.metadata(src).await?;
+        let same = self
+            .comparator
+            .same(fs, src, dest, &src_meta, &dest_meta)
+            .await?;
+        // Files left alone keep their manifest entry whatever decided it.
+        if same && self.ctx.options.manifest {
+            self.ctx.manifest.lookup(dest, &src_meta, &dest_meta);
+        }
+        Ok(same)
+    }
+
+    /// Claim `rel` for the source file `src`, returning the source that claimed it first if any.
+    fn claim(&self, rel: &Path, src: &Path) -> Option<PathBuf> {
+        if self.src_roots.len() < 2 {
+            return None;
+        }
+        let mut claimed = match self.claimed.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let rel = self.ctx.options.normalize_names.apply_path(rel);
+        match claimed.entry(rel.into_owned()) {
+            Entry::Occupied(first) => Some(first.get().clone()),
+            Entry::Vacant(entry) => {
+                entry.insert(src.to_path_buf());
+                None
+            }
+        }
+    }
+
+    /// Record that the hard linked source file `meta` goes to `dest`, returning where an earlier
+    /// link to it went if any.
+    fn first_link(&self, meta: &Metadata, dest: &Path) -> Option<PathBuf> {
+        if !self.ctx.options.preserve_hard_links || meta.links < 2 {
+            return None;
+        }
+        let id = meta.id?;
+        let mut links = match self.links.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        match links.entry(id) {
+            Entry::Occupied(first) => Some(first.get().clone()),
+            Entry::Vacant(entry) => {
+                entry.insert(dest.to_path_buf());
+                None
+            }
+        }
+    }
+
+    /// Replace `dest` with a hard link to `first`, if `first` is an up to date copy of `src`.
+    async fn link_copy(&self, src: &Path, dest: &Path, first: &Path) -> std::io::Result<()> {
+        let fs = &self.ctx.fs;
+        if !self.same(src, first).await? {
+            return Err(std::io::Error::other("the first link was not copied"));
+        }
+
+        let partial = partial_path(dest);
+        fs.hard_link(first, &partial).await?;
+        if let Err(e) = fs.rename(&partial, dest).await {
+            remove_partial(fs, &partial).await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Recreate the deferred hard links, copying the files that cannot be linked, returning
+    /// those whose source is in use.
+    async fn link_deferred<EF: Fn(&SyncErrorContext)>(
+        &self,
+        error_fn: &EF,
+        summary: &mut SyncSummary,
+        cancel: &CancellationToken,
+    ) -> Vec<(PathBuf, PathBuf, std::io::Error)> {
+        let deferred = match self.deferred_links.lock() {
+            Ok(mut guard) => std::mem::take(&mut *guard),
+            Err(poisoned) => std::mem::take(&mut *poisoned.into_inner()),
+        };
+        let mut locked = Vec::new();
+        for link in deferred {
+            if cancel.is_cancelled() {
+                break;
+            }
+            match self.link_copy(&link.src, &link.dest, &link.first).await {
+                Ok(()) => {
+                    let progress = &self.ctx.progress;
+                    progress.files.done.fetch_add(1, Ordering::Relaxed);
+                    progress
+                        .bytes
+                        .skipped
+                        .fetch_add(link.meta.len, Ordering::Relaxed);
+                    summary.files_linked += 1;
+                    if self.ctx.options.move_files {
+                        remove_source(&self.ctx, &link.src, &link.dest).await;
+                    }
+                    continue;
+                }
+                Err(e) => log::debug!(
+                    "Failed to link {} to {}, copying it instead: {}",
+                    link.dest.display(),
+                    link.first.display(),
+                    e
+                ),
+            }
+            match copy_file(
+                link.src.clone(),
+                link.dest,
+                link.src.clone(),
+                Some(link.meta),
+                &self.ctx,
+                &|_, _| {},
+                cancel,
+            )
+            .await
+            {
+                Ok(_) | Err(SyncError::Cancelled) => {}
+                Err(SyncError::SourceGone(_)) => summary.files_gone += 1,
+                Err(SyncError::SourceLocked { src, dest, err }) => locked.push((src, dest, err)),
+                Err(e) => {
+                    summary.record_failure(&e);
+                    summary.check_fail_fast(self.ctx.options.failure_mode, &e, cancel);
+                    error_fn(&SyncErrorContext {
+                        phase: Phase::Copy,
+                        path: Some(link.src),
+                        error: &e,
+                    });
+                }
+            }
+        }
+        locked
+    }
+
+    /// Get the destination of the source entry at `rel`, see [`SyncOptions::normalize_names`].
+    async fn dest_path(&self, rel: &Path) -> PathBuf {
+        let form = self.ctx.options.normalize_names;
+        if form == NameNormalization::Off {
+            return self.dest_root.join(rel);
+        }
+
+        let fs = &self.ctx.fs;
+        let mut dest = self.dest_root.clone();
+        for name in rel {
+            let name = form.apply(name);
+            let candidate = dest.join(&name);
+            if fs.symlink_metadata(&candidate).await.is_ok() {
+                dest = candidate;
+                continue;
+            }
+            let existing = fs
+                .read_dir(&dest)
+                .await
+                .ok()
+                .and_then(|names| names.into_iter().find(|n| form.apply(n) == name));
+            dest = match existing {
+                Some(existing) => dest.join(existing),
+                None => candidate,
+            };
+        }
+        dest
+    }
+
+    /// Check whether `dest` was modified after the source file of metadata `src_meta`, getting
+    /// both modification times if so, see [`SyncOptions::no_clobber_newer`].
+    async fn newer_dest(&self, src_meta: &Metadata, dest: &Path) -> Option<String> {
+        let dest_meta = self.ctx.fs.metadata(dest).await.ok()?;
+        let (src_modified, dest_modified) = (src_meta.modified?, dest_meta.modified?);
+        let ahead = dest_modified.duration_since(src_modified).ok()?;
+        if ahead <= self.ctx.options.mtime_tolerance {
+            return None;
+        }
+        let format = humantime_serde::re::humantime::format_rfc3339_seconds;
+        Some(format!(
+            "Destination modified at {}, after the source at {}",
+            format(dest_modified),
+            format(src_modified)
+        ))
+    }
+
+    /// Check whether `dest` should be left alone according to [`SyncOptions::overwrite`].
+    async fn up_to_date(&self, src: &Path, dest: &Path) -> bool {
+        let options = &self.ctx.options;
+        let same = match options.overwrite {
+            OverwritePolicy::Always => return false,
+            OverwritePolicy::NoClobber => return self.ctx.fs.symlink_metadata(dest).await.is_ok(),
+            OverwritePolicy::Update => {
+                cmp_file(
+                    &self.ctx.fs,
+                    dest,
+                    src,
+                    ComparisonFlags::MTIME,
+                    options.mtime_tolerance,
+                    options.hash_algorithm,
+                    options.manifest.then_some(&*self.ctx.manifest),
+                )
+                .await
+            }
+            OverwritePolicy::Compare => self.same(src, dest).await,
+        };
+        same.unwrap_or(false)
+    }
+
+    /// Remove the source directories emptied by moving their files, deepest first.
+    async fn remove_moved_dirs(&self) {
+        let mut dirs = match self.entered_dirs.lock() {
+            Ok(mut guard) => std::mem::take(&mut *guard),
+            Err(poisoned) => std::mem::take(&mut *poisoned.into_inner()),
+        };
+        dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+
+        for dir in dirs {
+            match self.ctx.fs.remove_dir(&dir).await {
+                Ok(()) => log::debug!("Removed {}", display_path(&dir).display()),
+                Err(e) if e.kind() == std::io::ErrorKind::DirectoryNotEmpty => {}
+                Err(e) => log::warn!(
+                    "Failed to remove moved directory {}: {}",
+                    display_path(&dir).display(),
+                    e
+                ),
+            }
+        }
+    }
+
+    /// Remove the directories created by this run that ended up empty, deepest first.
+    async fn prune_created_dirs(&self) -> u64 {
+        let mut dirs = match self.created_dirs.lock() {
+            Ok(mut guard) => std::mem::take(&mut *guard),
+            Err(poisoned) => std::mem::take(&mut *poisoned.into_inner()),
+        };
+        dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+
+        let mut pruned = 0;
+        for dir in dirs {
+            match self.ctx.fs.remove_dir(&dir).await {
+                Ok(()) => pruned += 1,
+                Err(e) if e.kind() == std::io::ErrorKind::DirectoryNotEmpty => {}
+                Err(e) => log::warn!(
+                    "Failed to remove empty directory {}: {}",
+                    display_path(&dir).display(),
+                    e
+                ),
+            }
+        }
+        pruned
+    }
+
+    /// Run a metadata read or listing of the source, giving up after
+    /// [`SyncOptions::stat_timeout`].
+    async fn timed<T>(&self, op: impl Future<Output = std::io::Result<T>>) -> std::io::Result<T> {
+        let Some(limit) = self.ctx.options.stat_timeout else {
+            return op.await;
+        };
+        tokio::time::timeout(limit, op).await.unwrap_or_else(|_| {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!(
+                    "No answer after {}",
+                    humantime_serde::re::humantime::format_duration(limit)
+                ),
+            ))
+        })
+    }
+
+    fn walk<'a>(
+        &'a self,
+        src_root: &'a Path,
+        rel: PathBuf,
+        ancestors: &'a [DirId],
+        ignores: &'a [Arc<Gitignore>],
+        tx: &'a flume::Sender<Result<CopyJob, SyncError>>,
+        cancel: &'a CancellationToken,
+    ) -> Pin<Box<impl Future<Output = ()> + 'a>> {
+        Box::pin(async move {
+            if cancel.is_cancelled() {
+                return;
+            }
+
+            let src = src_root.join(&rel);
+            let dest = self.dest_path(&rel).await;
+
+            let fs = &self.ctx.fs;
+            let stat = async {
+                let meta = fs.symlink_metadata(&src).await?;
+                if !meta.is_symlink() {
+                    return Ok(Some(meta));
+                }
+                match self.ctx.options.symlinks {
+                    // Roots are always followed.
+                    _ if rel.as_os_str().is_empty() => fs.metadata(&src).await.map(Some),
+                    SymlinkPolicy::Follow => fs.metadata(&src).await.map(Some),
+                    SymlinkPolicy::Skip | SymlinkPolicy::Copy => Ok(None),
+                }
+            };
+            let stat = self.timed(stat).await;
+            if let Ok(meta) = &stat {
+                let is_dir = meta.as_ref().is_some_and(Metadata::is_dir);
+                if ignored(ignores, &src, is_dir) {
+                    log::debug!("Ignoring {}", display_path(&src).display());
+                    return;
+                }
+            }
+            let src_meta = match stat {
+                Ok(Some(m)) => m,
+                Ok(None) => {
+                    if self.ctx.options.symlinks == SymlinkPolicy::Copy
+                        && self.claim(&rel, &src).is_none()
+                    {
+                        let files = &self.ctx.progress.files;
+                        files.total.fetch_add(1, Ordering::Relaxed);
+                        let copied = if self.ctx.options.dry_run {
+                            log::info!("Would copy {}", display_path(&src).display());
+                            Ok(true)
+                        } else {
+                            copy_symlink(fs, &src, &dest).await
+                        };
+                        match copied {
+                            Ok(true) => files.done.fetch_add(1, Ordering::Relaxed),
+                            Ok(false) => files.skipped.fetch_add(1, Ordering::Relaxed),
+                            Err(err) => {
+                                files.failed.fetch_add(1, Ordering::Relaxed);
+                                tx.send_async(Err(SyncError::CopyFailed { src, dest, err }))
+                                    .await
+                                    .expect("Result receiver dropped");
+                                return;
+                            }
+                        };
+                    }
+                    return;
+                }
+                Err(e) => {
+                    tx.send_async(Err(SyncError::StatFailed(src.clone(), e)))
+                        .await
+                        .expect("Result receiver dropped");
+                    return;
+                }
             };
 
             if src_meta.is_file() {
+                if let Some(first) = self.claim(&rel, &src) {
+                    if self.ctx.options.collision == CollisionPolicy::Error {
+                        let files = &self.ctx.progress.files;
+                        files.total.fetch_add(1, Ordering::Relaxed);
+                        files.failed.fetch_add(1, Ordering::Relaxed);
+                        tx.send_async(Err(SyncError::Collision {
+                            path: rel,
+                            first,
+                            second: src,
+                        }))
+                        .await
+                        .expect("Result receiver dropped");
+                    }
+                    return;
+                }
+
                 self.ctx
                     .progress
                     .files
@@ -272,13 +1892,40 @@ impl<'a> SyncFS<'a> {
                     .progress
                     .bytes
                     .total
-                    .fetch_add(src_meta.len(), Ordering::Relaxed);
+                    .fetch_add(src_meta.len, Ordering::Relaxed);
 
-                if !cmp_file(dest.clone(), src.clone()).await.unwrap_or(false) {
-                    if let Err(e) = tx.send_async(Ok((src.clone(), dest.clone()))).await {
-                        log::error!("Failed to send copy job: {}", e);
+                let resume = self.ctx.options.resume;
+                let up_to_date = if resume && self.ctx.journal.done(&dest, &src_meta) {
+                    true
+                } else if self.up_to_date(&src, &dest).await {
+                    if resume {
+                        self.ctx.journal.record(&dest, &src_meta);
                     }
+                    true
                 } else {
+                    false
+                };
+                // Only a file about to be replaced can be a conflict.
+                if !up_to_date && self.ctx.options.no_clobber_newer {
+                    if let Some(times) = self.newer_dest(&src_meta, &dest).await {
+                        log::info!("Not replacing {}: {}", display_path(&dest).display(), times);
+                        let progress = &self.ctx.progress;
+                        progress.files.skipped.fetch_add(1, Ordering::Relaxed);
+                        progress
+                            .bytes
+                            .skipped
+                            .fetch_add(src_meta.len, Ordering::Relaxed);
+                        let conflict = (display_path(&src).into_owned(), times);
+                        match self.conflicts.lock() {
+                            Ok(mut guard) => guard.push(conflict),
+                            Err(poisoned) => poisoned.into_inner().push(conflict),
+                        }
+                        return;
+                    }
+                }
+
+                let first = self.first_link(&src_meta, &dest);
+                if up_to_date {
                     self.ctx
                         .progress
                         .files
@@ -288,317 +1935,4115 @@ impl<'a> SyncFS<'a> {
                         .progress
                         .bytes
                         .skipped
-                        .fetch_add(src_meta.len(), Ordering::Relaxed);
+                        .fetch_add(src_meta.len, Ordering::Relaxed);
+                } else if self.ctx.options.dry_run {
+                    log::info!("Would copy {}", display_path(&src).display());
+                    let progress = &self.ctx.progress;
+                    progress.files.done.fetch_add(1, Ordering::Relaxed);
+                    progress
+                        .bytes
+                        .done
+                        .fetch_add(src_meta.len, Ordering::Relaxed);
+                } else if let Some(first) = first {
+                    let link = DeferredLink {
+                        src,
+                        dest,
+                        first,
+                        meta: src_meta,
+                    };
+                    match self.deferred_links.lock() {
+                        Ok(mut guard) => guard.push(link),
+                        Err(poisoned) => poisoned.into_inner().push(link),
+                    }
+                } else {
+                    let job = CopyJob {
+                        src,
+                        dest,
+                        meta: src_meta,
+                    };
+                    if let Err(e) = tx.send_async(Ok(job)).await {
+                        log::error!("Failed to send copy job: {}", e);
+                    }
                 }
             } else if src_meta.is_dir() {
-                match tokio::fs::create_dir_all(&dest).await {
-                    Ok(_) => {}
+                let depth = rel.components().count();
+                if self.ctx.options.max_depth.is_some_and(|max| depth > max) {
+                    log::debug!("Not entering {}, past the maximum depth", src.display());
+                    return;
+                }
+                let ancestors = match dir_id(fs, &src, &src_meta).await {
+                    Ok(id) if ancestors.contains(&id) => {
+                        tx.send_async(Err(SyncError::SymlinkLoop(src)))
+                            .await
+                            .expect("Result receiver dropped");
+                        return;
+                    }
+                    Ok(id) => ancestors.iter().cloned().chain([id]).collect::<Vec<_>>(),
                     Err(e) => {
-                        tx.send_async(Err(SyncError::CopyFailed {
-                            src: src.clone(),
-                            dest,
-                            err: e,
-                        }))
+                        tx.send_async(Err(SyncError::StatFailed(src.clone(), e)))
+                            .await
+                            .expect("Result receiver dropped");
+                        return;
+                    }
+                };
+                // A dry run creates nothing, so it has nothing to prune or remove afterwards.
+                let dry_run = self.ctx.options.dry_run;
+                let created = !dry_run
+                    && self.ctx.options.prune_empty_dirs
+                    && fs.symlink_metadata(&dest).await.is_err();
+                let made = if dry_run {
+                    Ok(())
+                } else {
+                    fs.create_dir_all(&dest).await
+                };
+                if let Err(e) = made {
+                    tx.send_async(Err(write_failed(src.clone(), dest, e)))
                         .await
                         .expect("Result receiver dropped");
-                        return;
+                    return;
+                }
+                // Only directories with source entries that may not make it are pruned, a copy
+                // of an empty source directory is kept.
+                let record_created = || match self.created_dirs.lock() {
+                    Ok(mut guard) => guard.push(dest.clone()),
+                    Err(poisoned) => poisoned.into_inner().push(dest.clone()),
+                };
+                if self.ctx.options.move_files && !dry_run && !rel.as_os_str().is_empty() {
+                    match self.entered_dirs.lock() {
+                        Ok(mut guard) => guard.push(src.clone()),
+                        Err(poisoned) => poisoned.into_inner().push(src.clone()),
                     }
                 }
-                let mut rd = match tokio::fs::read_dir(&src).await {
-                    Ok(rd) => rd,
+                let names = match self.timed(fs.read_dir(&src)).await {
+                    Ok(names) => names,
                     Err(e) => {
+                        if created {
+                            record_created();
+                        }
                         tx.send_async(Err(SyncError::StatFailed(src.clone(), e)))
                             .await
                             .expect("Result receiver dropped");
                         return;
                     }
                 };
-                loop {
-                    match rd.next_entry().await {
-                        Err(e) => {
-                            tx.send_async(Err(SyncError::StatFailed(src.clone(), e)))
-                                .await
-                                .expect("Result receiver dropped");
-                            return;
-                        }
-                        Ok(None) => break,
-                        Ok(Some(entry)) => {
-                            self.walk(rel.join(entry.file_name()), tx).await;
-                        }
+                if created && !names.is_empty() {
+                    record_created();
+                }
+                let mut ignores = ignores.to_vec();
+                if self.ctx.options.respect_syncignore && names.iter().any(|n| n == SYNCIGNORE) {
+                    match read_ignore(fs, &src).await {
+                        Ok(rules) => ignores.push(Arc::new(rules)),
+                        Err(e) => tx
+                            .send_async(Err(e))
+                            .await
+                            .expect("Result receiver dropped"),
+                    }
+                }
+                for name in names {
+                    if cancel.is_cancelled() {
+                        return;
+                    }
+                    self.walk(src_root, rel.join(name), &ancestors, &ignores, tx, cancel)
+                        .await;
+                }
+            } else if src_meta.is_special() {
+                self.special_file(&rel, src, dest, src_meta.file_type, tx)
+                    .await;
+            }
+        })
+    }
+
+    /// Handle the special file at `src` as told by [`SyncOptions::special_files`].
+    async fn special_file(
+        &self,
+        rel: &Path,
+        src: PathBuf,
+        dest: PathBuf,
+        file_type: FileType,
+        tx: &flume::Sender<Result<CopyJob, SyncError>>,
+    ) {
+        if self.claim(rel, &src).is_some() {
+            return;
+        }
+        let files = &self.ctx.progress.files;
+        files.total.fetch_add(1, Ordering::Relaxed);
+        let recreatable = matches!(
+            file_type,
+            FileType::Fifo | FileType::BlockDevice | FileType::CharDevice
+        );
+        let error = match self.ctx.options.special_files {
+            SpecialFilePolicy::Error => SyncError::SpecialFile(src, file_type),
+            SpecialFilePolicy::Recreate if recreatable && self.ctx.options.dry_run => {
+                log::info!("Would recreate {}", display_path(&src).display());
+                files.done.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            SpecialFilePolicy::Recreate if recreatable => {
+                match recreate_special(&self.ctx.fs, &src, &dest, file_type).await {
+                    Ok(true) => {
+                        files.done.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                    Ok(false) => {
+                        files.skipped.fetch_add(1, Ordering::Relaxed);
+                        return;
                     }
+                    Err(err) => SyncError::CopyFailed { src, dest, err },
                 }
             }
+            SpecialFilePolicy::Skip | SpecialFilePolicy::Recreate => {
+                log::info!(
+                    "Skipped {}, it is a {}",
+                    display_path(&src).display(),
+                    file_type
+                );
+                files.skipped.fetch_add(1, Ordering::Relaxed);
+                let special = (display_path(&src).into_owned(), file_type);
+                match self.special_files.lock() {
+                    Ok(mut guard) => guard.push(special),
+                    Err(poisoned) => poisoned.into_inner().push(special),
+                }
+                return;
+            }
+        };
+        files.failed.fetch_add(1, Ordering::Relaxed);
+        tx.send_async(Err(error))
+            .await
+            .expect("Result receiver dropped");
+    }
+
+    /// Count the files below the source roots for [`SyncOptions::pre_scan`], listing
+    /// directories without reading the metadata of their entries.
+    ///
+    /// Directories that cannot be listed are left out, discovery reports them.
+    async fn pre_scan(&self, cancel: &CancellationToken) -> u64 {
+        let fs = &self.ctx.fs;
+        let max_depth = self.ctx.options.max_depth;
+        let mut count = 0;
+        for root in &self.src_roots {
+            let mut dirs = vec![(root.clone(), 0)];
+            while let Some((dir, depth)) = dirs.pop() {
+                if cancel.is_cancelled() {
+                    return count;
+                }
+                let entries = match self.timed(fs.read_dir_kinds(&dir)).await {
+                    Ok(entries) => entries,
+                    Err(e) => {
+                        log::debug!(
+                            "Pre-scan could not list {}: {}",
+                            display_path(&dir).display(),
+                            e
+                        );
+                        continue;
+                    }
+                };
+                for (name, is_dir) in entries {
+                    if !is_dir {
+                        count += 1;
+                    } else if max_depth.is_none_or(|max| depth < max) {
+                        dirs.push((dir.join(name), depth + 1));
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    /// Wait until the directories files were copied into are on the disk, see
+    /// [`SyncOptions::fsync`].
+    async fn sync_written_dirs(&self) {
+        let dirs = match self.ctx.written_dirs.lock() {
+            Ok(mut guard) => std::mem::take(&mut *guard),
+            Err(poisoned) => std::mem::take(&mut *poisoned.into_inner()),
+        };
+        for dir in dirs {
+            if let Err(e) = self.ctx.fs.sync_dir(&dir).await {
+                log::warn!(
+                    "Failed to flush {} to the disk: {}",
+                    display_path(&dir).display(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// Copy the source files that were in use once more, one at a time, returning those that
+    /// still are.
+    async fn retry_locked<EF: Fn(&SyncErrorContext)>(
+        &self,
+        locked: Vec<(PathBuf, PathBuf, std::io::Error)>,
+        error_fn: &EF,
+        summary: &mut SyncSummary,
+        cancel: &CancellationToken,
+    ) -> Vec<(PathBuf, PathBuf, std::io::Error)> {
+        let mut still_locked = Vec::new();
+        for (src, dest, err) in locked {
+            if cancel.is_cancelled() {
+                still_locked.push((src, dest, err));
+                continue;
+            }
+            // The file may have changed while it was in use, so it is measured again.
+            match copy_file(
+                src.clone(),
+                dest.clone(),
+                src.clone(),
+                None,
+                &self.ctx,
+                &|_, _| {},
+                cancel,
+            )
+            .await
+            {
+                Ok(_) | Err(SyncError::Cancelled) => {}
+                Err(SyncError::SourceGone(_)) => summary.files_gone += 1,
+                Err(SyncError::SourceLocked { src, dest, err }) => {
+                    still_locked.push((src, dest, err));
+                }
+                Err(e) => {
+                    summary.record_failure(&e);
+                    summary.check_fail_fast(self.ctx.options.failure_mode, &e, cancel);
+                    error_fn(&SyncErrorContext {
+                        phase: Phase::Copy,
+                        path: Some(src),
+                        error: &e,
+                    });
+                }
+            }
+        }
+        still_locked
+    }
+
+    /// Synchronize the two directories, the Future will resolve to a [`SyncSummary`] when the synchronization is complete.
+    ///
+    /// Progress will be periodically reported to the `progress_fn` callback.
+    /// Errors will be reported to the `error_fn` callback, along with the phase and the source
+    /// path they relate to.
+    ///
+    /// Cancelling `cancel` stops discovery, makes in-flight copies remove their partially
+    /// written destination files, and reports [`ProgressMilestone::Cancelled`] at the end.
+    /// With [`FailureMode::FailFast`] the first error does the same, and so do a full destination
+    /// and running past [`SyncOptions::timeout`].
+    ///
+    /// Everything logged while syncing is in a `sync` span carrying the source and destination
+    /// roots, each copy in a nested `copy` span carrying the source path.
+    pub async fn sync<
+        F: Fn(&GlobalProgress, Option<ProgressMilestone>),
+        EF: Fn(&SyncErrorContext),
+    >(
+        &self,
+        progress_fn: F,
+        error_fn: &EF,
+        cancel: &CancellationToken,
+    ) -> SyncSummary {
+        self.run(&self.handle(cancel.clone()), progress_fn, error_fn)
+            .await
+    }
+
+    /// Synchronize the two directories under the control of `handle`, for both [`SyncFS::sync`]
+    /// and [`SyncFS::start`].
+    #[tracing::instrument(
+        name = "sync",
+        skip_all,
+        fields(src = ?self.src_roots, dest = %self.dest_root.display())
+    )]
+    async fn run<F: Fn(&GlobalProgress, Option<ProgressMilestone>), EF: Fn(&SyncErrorContext)>(
+        &self,
+        handle: &SyncHandle,
+        progress_fn: F,
+        error_fn: &EF,
+    ) -> SyncSummary {
+        let start = Instant::now();
+        let mut summary = SyncSummary::default();
+        let failure_mode = self.ctx.options.failure_mode;
+        // Cancelled with the handle, by the first error when failing fast, or by the timeout.
+        let cancel = &handle.cancel.child_token();
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let _timer = self.ctx.options.timeout.map(|limit| {
+            let (cancel, timed_out) = (cancel.clone(), Arc::clone(&timed_out));
+            AbortOnDrop(
+                tokio::spawn(async move {
+                    tokio::time::sleep(limit).await;
+                    timed_out.store(true, Ordering::Relaxed);
+                    cancel.cancel();
+                })
+                .abort_handle(),
+            )
+        });
+        match self.claimed.lock() {
+            Ok(mut guard) => guard.clear(),
+            Err(poisoned) => poisoned.into_inner().clear(),
+        }
+        match self.created_dirs.lock() {
+            Ok(mut guard) => guard.clear(),
+            Err(poisoned) => poisoned.into_inner().clear(),
+        }
+        match self.conflicts.lock() {
+            Ok(mut guard) => guard.clear(),
+            Err(poisoned) => poisoned.into_inner().clear(),
+        }
+        match self.special_files.lock() {
+            Ok(mut guard) => guard.clear(),
+            Err(poisoned) => poisoned.into_inner().clear(),
+        }
+        match self.entered_dirs.lock() {
+            Ok(mut guard) => guard.clear(),
+            Err(poisoned) => poisoned.into_inner().clear(),
+        }
+        self.ctx.moved.store(0, Ordering::Relaxed);
+        match self.links.lock() {
+            Ok(mut guard) => guard.clear(),
+            Err(poisoned) => poisoned.into_inner().clear(),
+        }
+        let options = &self.ctx.options;
+        if options.manifest {
+            self.ctx
+                .manifest
+                .load(&self.ctx.fs, &self.dest_root, options.hash_algorithm)
+                .await;
+        }
+
+        if options.pre_scan {
+            let estimate = self.pre_scan(cancel).await;
+            self.ctx
+                .progress
+                .estimated_files
+                .store(estimate, Ordering::Relaxed);
+            if !cancel.is_cancelled() {
+                progress_fn(&self.ctx.progress, Some(ProgressMilestone::PreScanComplete));
+            }
+        }
+
+        if options.resume {
+            self.ctx
+                .journal
+                .load(&self.ctx.fs, &self.dest_root, &self.src_roots, options)
+                .await;
+        }
+        // Saved as it goes, in case the drive is gone before the end. Stopped between saves so
+        // it never writes after the final save or removal.
+        let stop_saving = CancellationToken::new();
+        let journal_saver = (options.resume && !options.dry_run).then(|| {
+            let ctx = Arc::clone(&self.ctx);
+            let stop = stop_saving.clone();
+            let saver = tokio::spawn(async move {
+                loop {
+                    tokio::select! {
+                        () = stop.cancelled() => break,
+                        () = tokio::time::sleep(JOURNAL_SAVE_INTERVAL) => {}
+                    }
+                    if let Err(e) = ctx.journal.save(&ctx.fs).await {
+                        log::debug!("Failed to save the journal: {}", e);
+                    }
+                }
+            });
+            let abort = AbortOnDrop(saver.abort_handle());
+            (saver, abort)
+        });
+
+        // The destination may not exist yet, measure the closest existing ancestor instead.
+        let dest_space = self
+            .dest_root
+            .ancestors()
+            .find_map(|p| self.ctx.fs.space(p).ok());
+
+        // Copies are spawned as soon as they are received rather than awaited, so stalled copies
+        // never keep the channel full.
+        let (tx, rx) = flume::bounded(match self.ctx.options.channel_capacity {
+            0 => DEFAULT_CHANNEL_CAPACITY,
+            n => n,
+        });
+
+        let mut js = JoinSet::new();
+        let copy_order = self.ctx.options.copy_order;
+        let spawn_copy = |js: &mut JoinSet<_>, CopyJob { src, dest, meta }| {
+            let ctx_clone = self.ctx.clone();
+            let cancel = cancel.clone();
+            let span = tracing::debug_span!("copy", src = %display_path(&src).display());
+            js.spawn(
+                async move {
+                    // The copy runs in its own task so a panic in it still leaves the path to report.
+                    let copy = tokio::spawn({
+                        let src = src.clone();
+                        let cancel = cancel.clone();
+                        async move {
+                            copy_file(
+                                src.clone(),
+                                dest,
+                                src,
+                                Some(meta),
+                                &ctx_clone,
+                                &|k: &PathBuf, prog| {
+                                    log::trace!(
+                                        "{}: {}/{}",
+                                        display_path(k).display(),
+                                        prog.done,
+                                        prog.total
+                                    );
+                                },
+                                &cancel,
+                            )
+                            .await
+                        }
+                        .in_current_span()
+                    });
+                    let _abort = AbortOnDrop(copy.abort_handle());
+                    let result = copy.await;
+                    if let Ok(Ok(bytes)) = result {
+                        tracing::debug!(bytes, "Copied");
+                    }
+                    // Stop the other copies right away rather than when the error is collected, which
+                    // happens after discovery.
+                    if failure_mode == FailureMode::FailFast
+                        && !matches!(
+                            result,
+                            Ok(Ok(_)
+                                | Err(SyncError::Cancelled
+                                    | SyncError::SourceLocked { .. }
+                                    | SyncError::SourceGone(_)))
+                        )
+                    {
+                        cancel.cancel();
+                    }
+                    (src, result)
+                }
+                .instrument(span),
+            );
+        };
+
+        tokio::join!(
+            async move {
+                // The first source to reach a file claims it, so the last one goes first for
+                // it to win.
+                let mut roots = self.src_roots.iter().collect::<Vec<_>>();
+                if self.ctx.options.collision == CollisionPolicy::LastWins {
+                    roots.reverse();
+                }
+                for root in roots {
+                    self.walk(root, PathBuf::new(), &[], &[], &tx, cancel).await;
+                }
+            },
+            async {
+                let mut streaming = copy_order == CopyOrder::Discovery;
+                let mut pending = Vec::new();
+                // With an estimated total, report each percent of it found.
+                let progress = &self.ctx.progress;
+                let estimate = progress.estimated_files.load(Ordering::Relaxed);
+                let one_pct = std::cmp::max(1, estimate / 100);
+                let mut last_found = 0;
+                loop {
+                    let received = rx.recv_async().await;
+                    if estimate > 0 {
+                        let found = progress.files.total.load(Ordering::Relaxed);
+                        if found - last_found >= one_pct {
+                            progress_fn(progress, None);
+                            last_found = found;
+                        }
+                    }
+                    match received {
+                        Ok(Ok(_)) if cancel.is_cancelled() => {}
+                        Ok(Ok(job)) if streaming => spawn_copy(&mut js, job),
+                        Ok(Ok(job)) => {
+                            pending.push(job);
+                            if pending.len() >= MAX_ORDERED_FILES {
+                                log::warn!(
+                                    "More than {} files to copy, copying the rest in the order \
+                                     they are found",
+                                    MAX_ORDERED_FILES
+                                );
+                                copy_order.sort(&mut pending);
+                                for job in pending.drain(..) {
+                                    spawn_copy(&mut js, job);
+                                }
+                                streaming = true;
+                            }
+                        }
+                        Ok(Err(e)) => {
+                            error_fn(&SyncErrorContext {
+                                phase: Phase::Discovery,
+                                path: e.path().map(Path::to_path_buf),
+                                error: &e,
+                            });
+                            // File counters are kept by `walk`, which knows whether the entry
+                            // was a file.
+                            summary.record_failure(&e);
+                            summary.check_fail_fast(failure_mode, &e, cancel);
+                            continue;
+                        }
+                        Err(RecvError::Disconnected) => {
+                            if !cancel.is_cancelled() {
+                                copy_order.sort(&mut pending);
+                                for job in pending {
+                                    spawn_copy(&mut js, job);
+                                }
+                            }
+                            return;
+                        }
+                    }
+                }
+            }
+        );
+
+        self.ctx
+            .progress
+            .estimated_files
+            .store(0, Ordering::Relaxed);
+        progress_fn(
+            &self.ctx.progress,
+            Some(ProgressMilestone::DiscoveryComplete),
+        );
+
+        if let Some(space) = dest_space {
+            let bytes = &self.ctx.progress.bytes;
+            let needed = bytes
+                .total
+                .load(Ordering::Relaxed)
+                .saturating_sub(bytes.skipped.load(Ordering::Relaxed));
+            if needed > space.available {
+                error_fn(&SyncErrorContext {
+                    phase: Phase::SpaceCheck,
+                    path: None,
+                    error: &SyncError::InsufficientSpace {
+                        dest: self.dest_root.clone(),
+                        needed,
+                        available: space.available,
+                    },
+                });
+            }
+        }
+
+        let total = js.len();
+        let one_pct = std::cmp::max(1, total / 100);
+        let mut last_reported = 0;
+        let mut completed = 0;
+        let mut locked = Vec::new();
+
+        while let Some(result) = js.join_next().await {
+            completed += 1;
+            if completed - last_reported >= one_pct {
+                progress_fn(&self.ctx.progress, None);
+                last_reported = completed;
+            }
+
+            let (path, result) = match result {
+                Ok((path, result)) => (Some(path), result),
+                Err(e) => (None, Err(e)),
+            };
+            let error = match result {
+                Ok(Ok(_) | Err(SyncError::Cancelled)) => continue,
+                Ok(Err(SyncError::SourceGone(_))) => {
+                    summary.files_gone += 1;
+                    continue;
+                }
+                Ok(Err(SyncError::SourceLocked { src, dest, err })) => {
+                    locked.push((src, dest, err));
+                    continue;
+                }
+                Ok(Err(e)) => {
+                    summary.record_failure(&e);
+                    e
+                }
+                Err(e) if e.is_cancelled() => SyncError::Cancelled,
+                Err(e) => SyncError::JoinError(e),
+            };
+            if !matches!(error, SyncError::Cancelled) {
+                summary.check_fail_fast(failure_mode, &error, cancel);
+            }
+            error_fn(&SyncErrorContext {
+                phase: Phase::Copy,
+                path,
+                error: &error,
+            });
+        }
+
+        // The first links are all copied by now.
+        locked.extend(self.link_deferred(error_fn, &mut summary, cancel).await);
+
+        if self.ctx.options.retry_locked && !locked.is_empty() && !cancel.is_cancelled() {
+            log::info!("Retrying {} files that were in use", locked.len());
+            locked = self
+                .retry_locked(locked, error_fn, &mut summary, cancel)
+                .await;
+        }
+        for (src, _, err) in locked {
+            log::warn!(
+                "Skipped {}, it is in use: {}",
+                display_path(&src).display(),
+                err
+            );
+            let len = self.ctx.fs.metadata(&src).await.map_or(0, |meta| meta.len);
+            let progress = &self.ctx.progress;
+            progress.files.skipped.fetch_add(1, Ordering::Relaxed);
+            progress.bytes.total.fetch_add(len, Ordering::Relaxed);
+            progress.bytes.skipped.fetch_add(len, Ordering::Relaxed);
+            summary
+                .locked_paths
+                .push((display_path(&src).into_owned(), err.to_string()));
+        }
+
+        if self.ctx.options.fsync {
+            self.sync_written_dirs().await;
+        }
+
+        if let Some(limit) = self.ctx.options.timeout {
+            if timed_out.load(Ordering::Relaxed) && summary.aborted.is_none() {
+                let reason = format!(
+                    "Timed out after {}",
+                    humantime_serde::re::humantime::format_duration(limit)
+                );
+                log::warn!("{} syncing into {}", reason, self.dest_root.display());
+                summary.aborted = Some(reason);
+            }
+        }
+
+        if cancel.is_cancelled() {
+            progress_fn(&self.ctx.progress, Some(ProgressMilestone::Cancelled));
+        } else {
+            progress_fn(&self.ctx.progress, Some(ProgressMilestone::CopyComplete));
+            if self.ctx.options.prune_empty_dirs {
+                summary.dirs_pruned = self.prune_created_dirs().await;
+            }
+            if self.ctx.options.move_files {
+                self.remove_moved_dirs().await;
+            }
+        }
+        if let Some((saver, _abort)) = journal_saver {
+            stop_saving.cancel();
+            if let Err(e) = saver.await {
+                log::error!("Journal saver failed: {}", e);
+            }
+            let (what, result) = if cancel.is_cancelled() {
+                ("save", self.ctx.journal.save(&self.ctx.fs).await)
+            } else {
+                ("remove", self.ctx.journal.remove(&self.ctx.fs).await)
+            };
+            if let Err(e) = result {
+                log::warn!(
+                    "Failed to {} the journal in {}: {}",
+                    what,
+                    display_path(&self.dest_root).display(),
+                    e
+                );
+            }
+        }
+        // A cancelled run did not look at every file, keep the previous manifest whole.
+        if options.manifest && !options.dry_run && !cancel.is_cancelled() {
+            if let Err(e) = self
+                .ctx
+                .manifest
+                .save(&self.ctx.fs, options.hash_algorithm)
+                .await
+            {
+                log::warn!(
+                    "Failed to write the manifest into {}: {}",
+                    display_path(&self.dest_root).display(),
+                    e
+                );
+            }
+        }
+
+        let progress = &self.ctx.progress;
+        summary.files_copied = progress.files.done.load(Ordering::Relaxed);
+        summary.files_skipped = progress.files.skipped.load(Ordering::Relaxed);
+        summary.files_failed = progress.files.failed.load(Ordering::Relaxed);
+        summary.bytes_copied = progress.bytes.done.load(Ordering::Relaxed);
+        summary.files_moved = self.ctx.moved.load(Ordering::Relaxed);
+        summary.conflicts = match self.conflicts.lock() {
+            Ok(mut guard) => std::mem::take(&mut *guard),
+            Err(poisoned) => std::mem::take(&mut *poisoned.into_inner()),
+        };
+        summary.special_files = match self.special_files.lock() {
+            Ok(mut guard) => std::mem::take(&mut *guard),
+            Err(poisoned) => std::mem::take(&mut *poisoned.into_inner()),
+        };
+        summary.duration = start.elapsed();
+        tracing::debug!(
+            files_copied = summary.files_copied,
+            files_skipped = summary.files_skipped,
+            files_failed = summary.files_failed,
+            bytes_copied = summary.bytes_copied,
+            duration = ?summary.duration,
+            aborted = summary.aborted,
+            "Sync finished"
+        );
+
+        summary
+    }
+}
+
+/// Check whether writing failed because the destination is full.
+fn is_disk_full(e: &std::io::Error) -> bool {
+    // ERROR_HANDLE_DISK_FULL and ERROR_DISK_FULL.
+    #[cfg(windows)]
+    if matches!(e.raw_os_error(), Some(39 | 112)) {
+        return true;
+    }
+    e.kind() == std::io::ErrorKind::StorageFull
+}
+
+/// Build the error for a failed write to `dest`, [`SyncError::DiskFull`] if it ran out of space.
+fn write_failed(src: PathBuf, dest: PathBuf, err: std::io::Error) -> SyncError {
+    if is_disk_full(&err) {
+        SyncError::DiskFull { src, dest, err }
+    } else {
+        SyncError::CopyFailed { src, dest, err }
+    }
+}
+
+/// Cancel the other copies if `e` is [`SyncError::DiskFull`].
+///
+/// This runs while the copy still holds its permit, so the copies waiting for it never start.
+fn stop_if_full(e: SyncError, cancel: &CancellationToken) -> SyncError {
+    if matches!(e, SyncError::DiskFull { .. }) {
+        cancel.cancel();
+    }
+    e
+}
+
+/// Check whether opening a source file failed because another process holds it.
+///
+/// A denied permission is not a lock, the file would never become readable by waiting.
+fn is_locked(e: &std::io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION.
+    #[cfg(windows)]
+    if matches!(e.raw_os_error(), Some(32 | 33)) {
+        return true;
+    }
+    // EAGAIN and EWOULDBLOCK, from mandatory locks.
+    e.kind() == std::io::ErrorKind::WouldBlock
+}
+
+/// File descriptors a copy holds, for the source and the destination.
+const FDS_PER_COPY: u32 = 2;
+
+/// File descriptors [`fd_budget`] leaves to the rest of the process, at least.
+const FD_HEADROOM: usize = 64;
+
+/// How many times opening a file is tried again while the process is out of file descriptors,
+/// waiting twice as long each time from [`FD_BACKOFF`], two and a half seconds in all.
+const FD_RETRIES: u32 = 8;
+const FD_BACKOFF: Duration = Duration::from_millis(10);
+
+/// The file descriptors copies may hold at once across all instances, `None` where the process
+/// has no practical limit.
+///
+/// Copies take [`FDS_PER_COPY`] from opening their files until they are verified, apart from
+/// their concurrency permit. Discovery and the rest of the process get what is left.
+fn fd_budget() -> Option<&'static Semaphore> {
+    static BUDGET: OnceLock<Option<Semaphore>> = OnceLock::new();
+    BUDGET
+        .get_or_init(|| {
+            crate::sys::open_files_limit().map(|limit| Semaphore::new(budget_for(limit)))
+        })
+        .as_ref()
+}
+
+/// The part of the open files `limit` given to copies, a quarter of it or [`FD_HEADROOM`] left
+/// to the rest, but always enough for one copy.
+fn budget_for(limit: u64) -> usize {
+    let limit = usize::try_from(limit)
+        .unwrap_or(usize::MAX)
+        .min(Semaphore::MAX_PERMITS);
+    limit
+        .saturating_sub(FD_HEADROOM.max(limit / 4))
+        .max(FDS_PER_COPY as usize)
+}
+
+/// Check whether opening a file failed because the process or the system is out of file
+/// descriptors.
+fn is_out_of_fds(e: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    if matches!(e.raw_os_error(), Some(libc::EMFILE | libc::ENFILE)) {
+        return true;
+    }
+    // ERROR_TOO_MANY_OPEN_FILES.
+    #[cfg(windows)]
+    if e.raw_os_error() == Some(4) {
+        return true;
+    }
+    false
+}
+
+/// Run `open`, backing off while out of file descriptors since other copies will close theirs.
+async fn open_backing_off<T, Fut>(mut open: impl FnMut() -> Fut) -> std::io::Result<T>
+where
+    Fut: Future<Output = std::io::Result<T>>,
+{
+    let mut delay = FD_BACKOFF;
+    for _ in 0..FD_RETRIES {
+        match open().await {
+            Err(e) if is_out_of_fds(&e) => {
+                log::debug!("Out of file descriptors, trying again in {:?}", delay);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+    open().await
+}
+
+async fn hash_file<B: FsBackend>(
+    fs: &B,
+    path: &Path,
+    algorithm: HashAlgorithm,
+) -> Result<String, tokio::io::Error> {
+    let mut file = fs.open(path).await?;
+    let mut hasher = ContentHasher::new(algorithm);
+    let mut buf = vec![0u8; 64 << 10];
+
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(hasher.finalize())
+}
+
+async fn copy_file<B: FsBackend, K: Hash + PartialEq + Unpin, F: Fn(&K, &FileProgress)>(
+    job_id: K,
+    dest: PathBuf,
+    src: PathBuf,
+    src_meta: Option<Metadata>,
+    ctx: &SyncFSCtx<B>,
+    file_progress_callback: &F,
+    cancel: &CancellationToken,
+) -> Result<u64, SyncError> {
+    if cancel.is_cancelled() {
+        return Err(SyncError::Cancelled);
+    }
+
+    let progress = &ctx.progress;
+
+    let (permit, shared_permit) = loop {
+        tokio::select! {
+            () = ctx.pause.wait() => {}
+            () = cancel.cancelled() => return Err(SyncError::Cancelled),
+        }
+        let permit = tokio::select! {
+            p = ctx.semaphore.acquire() => match p {
+                Ok(p) => p,
+                Err(_) => {
+                    progress.files.failed.fetch_add(1, Ordering::Relaxed);
+                    return Err(SyncError::Cancelled);
+                }
+            },
+            () = cancel.cancelled() => return Err(SyncError::Cancelled),
+        };
+        let shared_permit = match &ctx.shared {
+            Some(shared) => tokio::select! {
+                p = shared.acquire() => match p {
+                    Ok(p) => Some(p),
+                    Err(_) => {
+                        progress.files.failed.fetch_add(1, Ordering::Relaxed);
+                        return Err(SyncError::Cancelled);
+                    }
+                },
+                () = cancel.cancelled() => return Err(SyncError::Cancelled),
+            },
+            None => None,
+        };
+        // Paused while waiting for the permits, which go back until it is resumed.
+        if !ctx.pause.is_paused() {
+            break (permit, shared_permit);
+        }
+    };
+    if cancel.is_cancelled() {
+        return Err(SyncError::Cancelled);
+    }
+
+    let fd_permit = match fd_budget() {
+        Some(budget) => tokio::select! {
+            p = budget.acquire_many(FDS_PER_COPY) => p.ok(),
+            () = cancel.cancelled() => return Err(SyncError::Cancelled),
+        },
+        None => None,
+    };
+
+    let mut src_file = match open_backing_off(|| ctx.fs.open(&src)).await {
+        Ok(f) => f,
+        // Counted once the retry, if any, is over, when it is measured again.
+        Err(e) if is_locked(&e) => {
+            if let Some(meta) = &src_meta {
+                progress.bytes.total.fetch_sub(meta.len, Ordering::Relaxed);
+            }
+            return Err(SyncError::SourceLocked { src, dest, err: e });
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            log::debug!(
+                "{} was removed since discovery, not copying it",
+                display_path(&src).display()
+            );
+            progress.files.total.fetch_sub(1, Ordering::Relaxed);
+            if let Some(meta) = &src_meta {
+                progress.bytes.total.fetch_sub(meta.len, Ordering::Relaxed);
+            }
+            return Err(SyncError::SourceGone(src));
+        }
+        Err(e) => {
+            progress.files.failed.fetch_add(1, Ordering::Relaxed);
+            return Err(SyncError::CopyFailed {
+                src: src.clone(),
+                dest,
+                err: e,
+            });
+        }
+    };
+
+    // Discovery already measured the file, a change since then shows up as a short copy.
+    let src_meta = match src_meta {
+        Some(meta) => meta,
+        None => {
+            let meta = ctx.fs.metadata(&src).await.map_err(|e| {
+                progress.files.failed.fetch_add(1, Ordering::Relaxed);
+                SyncError::StatFailed(src.clone(), e)
+            })?;
+            progress.bytes.total.fetch_add(meta.len, Ordering::Relaxed);
+            meta
+        }
+    };
+
+    let partial = partial_path(&dest);
+
+    // Both a clone and a copy in the kernel need it, unless the manifest needs the hash of what
+    // is streamed.
+    let same_dev = (ctx.options.prefer_reflink || !ctx.options.manifest)
+        && same_device(&ctx.fs, &src_meta, &dest).await;
+    let cloned =
+        ctx.options.prefer_reflink && same_dev && try_reflink(&ctx.fs, &src, &partial).await;
+
+    let (result, hash) = if cloned {
+        // Nothing to stream, the whole file is done at once.
+        let mut sink = tokio::io::sink();
+        let mut dest_write = TrackingAsyncWrite::new(
+            job_id,
+            src_meta.len,
+            progress,
+            file_progress_callback,
+            Pin::new(&mut sink),
+        );
+        // The clone is of the file as it is now, which may have changed since discovery.
+        let result = match ctx.fs.metadata(&partial).await {
+            Ok(meta) if meta.len != src_meta.len => Err(SyncError::ShortCopy {
+                src: src.clone(),
+                dest: dest.clone(),
+                copied: meta.len,
+                expected: src_meta.len,
+            }),
+            // There is no handle on the clone to flush, so it is done by its path.
+            Ok(meta) if ctx.options.fsync => ctx
+                .fs
+                .sync_path(&partial)
+                .await
+                .map(|()| meta.len)
+                .map_err(|e| write_failed(src.clone(), dest.clone(), e)),
+            Ok(meta) => Ok(meta.len),
+            Err(e) => Err(write_failed(src.clone(), dest.clone(), e)),
+        };
+        match result {
+            Ok(len) => dest_write.increment_bytes(len),
+            Err(_) => dest_write.register_fail(),
+        }
+        (result, None)
+    } else {
+        let mut dst_file = match open_backing_off(|| ctx.fs.create(&partial)).await {
+            Ok(f) => f,
+            Err(e) => {
+                progress.files.failed.fetch_add(1, Ordering::Relaxed);
+                return Err(stop_if_full(write_failed(src, dest, e), cancel));
+            }
+        };
+
+        let mut dest_write = TrackingAsyncWrite::new(
+            job_id,
+            src_meta.len,
+            progress,
+            file_progress_callback,
+            Pin::new(&mut dst_file),
+        )
+        .report_interval(ctx.options.file_progress_interval)
+        .throttle(ctx.throttle.as_ref())
+        .hash(ctx.options.manifest.then_some(ctx.options.hash_algorithm));
+
+        // Within one file system the kernel can copy without bouncing the data through here.
+        let in_kernel = if !ctx.options.manifest && same_dev {
+            copy_in_kernel(
+                &ctx.fs,
+                &mut src_file,
+                &mut dest_write,
+                src_meta.len,
+                ctx.options.buffer_size(),
+                ctx.throttle.as_ref(),
+                cancel,
+            )
+            .await
+        } else {
+            None
+        };
+
+        let result = match in_kernel {
+            Some(result) => result,
+            None => {
+                let mut reader = BufReader::with_capacity(ctx.options.buffer_size(), src_file);
+                // This already handles flushing the file so we don't need to do it again.
+                tokio::select! {
+                    r = tokio::io::copy_buf(&mut reader, &mut dest_write) => Some(r),
+                    () = cancel.cancelled() => None,
+                }
+            }
+        };
+
+        let result = match result {
+            None => Err(SyncError::Cancelled),
+            Some(Ok(written)) if written != src_meta.len => {
+                dest_write.register_fail();
+                Err(SyncError::ShortCopy {
+                    src: src.clone(),
+                    dest: dest.clone(),
+                    copied: written,
+                    expected: src_meta.len,
+                })
+            }
+            Some(Ok(written)) => Ok(written),
+            Some(Err(e)) => {
+                dest_write.register_fail();
+                Err(write_failed(src.clone(), dest.clone(), e))
+            }
+        };
+        let result = match result {
+            Ok(written) if ctx.options.fsync => {
+                match ctx.fs.sync_file(dest_write.get_mut()).await {
+                    Ok(()) => Ok(written),
+                    Err(e) => {
+                        dest_write.register_fail();
+                        Err(write_failed(src.clone(), dest.clone(), e))
+                    }
+                }
+            }
+            result => result,
+        };
+
+        let hash = dest_write.take_hash();
+        // The file must be closed before it can be renamed or removed on Windows.
+        drop(dest_write);
+        drop(dst_file);
+        (result, hash)
+    };
+
+    let written = match result {
+        Ok(written) => written,
+        Err(e) => {
+            remove_partial(&ctx.fs, &partial).await;
+            return Err(stop_if_full(e, cancel));
+        }
+    };
+
+    // Reading the copy back is I/O too, so it runs under the same permit.
+    let verified = if ctx.options.verify {
+        verify_copy(&ctx.fs, &src, &partial, ctx.options.hash_algorithm)
+            .await
+            .map_err(|e| match e {
+                None => SyncError::VerifyFailed {
+                    src: src.clone(),
+                    dest: dest.clone(),
+                },
+                Some(err) => SyncError::CopyFailed {
+                    src: src.clone(),
+                    dest: dest.clone(),
+                    err,
+                },
+            })
+    } else {
+        Ok(())
+    };
+    drop(fd_permit);
+    drop(shared_permit);
+    drop(permit);
+
+    let result = match verified {
+        Ok(()) => replace_dest(&ctx.fs, &src, &src_meta, &partial, &dest, &ctx.options)
+            .await
+            .map_err(|err| write_failed(src.clone(), dest.clone(), err)),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        progress.files.done.fetch_sub(1, Ordering::Relaxed);
+        progress.bytes.done.fetch_sub(written, Ordering::Relaxed);
+        progress.files.failed.fetch_add(1, Ordering::Relaxed);
+        remove_partial(&ctx.fs, &partial).await;
+        return Err(e);
+    }
+
+    if let Some(hash) = hash {
+        match ctx.fs.metadata(&dest).await {
+            Ok(dest_meta) => ctx.manifest.record(&dest, hash, &src_meta, &dest_meta),
+            Err(e) => log::debug!(
+                "Not adding {} to the manifest: {}",
+                display_path(&dest).display(),
+                e
+            ),
+        }
+    }
+
+    if ctx.options.resume {
+        ctx.journal.record(&dest, &src_meta);
+    }
+
+    if ctx.options.fsync {
+        if let Some(parent) = dest.parent() {
+            match ctx.written_dirs.lock() {
+                Ok(mut guard) => guard.insert(parent.to_path_buf()),
+                Err(poisoned) => poisoned.into_inner().insert(parent.to_path_buf()),
+            };
+        }
+    }
+
+    // Only now is the copy in place, and verified if asked.
+    if ctx.options.move_files {
+        remove_source(ctx, &src, &dest).await;
+    }
+
+    Ok(written)
+}
+
+/// Remove the source file `src` of a finished copy at `dest`, see [`SyncOptions::move_files`].
+///
+/// With [`SyncOptions::fsync`], the directory of the copy is flushed first and the source is
+/// kept if that fails. The copy succeeded, so failing to remove the source is only logged.
+async fn remove_source<B: FsBackend>(ctx: &SyncFSCtx<B>, src: &Path, dest: &Path) {
+    if let Some(parent) = dest.parent().filter(|_| ctx.options.fsync) {
+        if let Err(e) = ctx.fs.sync_dir(parent).await {
+            log::warn!(
+                "Not removing {}, its copy could not be flushed to the disk: {}",
+                display_path(src).display(),
+                e
+            );
+            return;
+        }
+    }
+    match ctx.fs.remove_file(src).await {
+        Ok(()) => {
+            ctx.moved.fetch_add(1, Ordering::Relaxed);
+        }
+        Err(e) => log::warn!(
+            "Failed to remove {} after copying it: {}",
+            display_path(src).display(),
+            e
+        ),
+    }
+}
+
+/// Move the finished copy at `partial` into place at `dest`.
+///
+/// `partial` is next to `dest` so this is a rename, unless something like a junction puts them on
+/// different file systems. Then it is copied to a new partial copy created next to `dest`, which
+/// is renamed over it instead.
+async fn replace_dest<B: FsBackend>(
+    fs: &B,
+    src: &Path,
+    src_meta: &Metadata,
+    partial: &Path,
+    dest: &Path,
+    options: &SyncOptions,
+) -> tokio::io::Result<()> {
+    // Before the attributes, which may make it read-only.
+    keep_modified(fs, src_meta, partial).await;
+    if options.preserve_windows_metadata {
+        fs.preserve_windows_metadata(src, partial).await?;
+    }
+    if options.preserve_attributes {
+        fs.preserve_attributes(src, partial, dest).await?;
+    }
+    match fs.rename(partial, dest).await {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            log::warn!(
+                "{} is on another file system than its partial copy, copying it into place",
+                dest.display()
+            );
+            let moved = partial_path(dest);
+            let result = async {
+                let mut reader =
+                    BufReader::with_capacity(options.buffer_size(), fs.open(partial).await?);
+                let mut writer = fs.create(&moved).await?;
+                tokio::io::copy_buf(&mut reader, &mut writer).await?;
+                if options.fsync {
+                    fs.sync_file(&mut writer).await?;
+                }
+                drop(writer);
+                keep_modified(fs, src_meta, &moved).await;
+                fs.rename(&moved, dest).await
+            }
+            .await;
+            if result.is_err() {
+                remove_partial(fs, &moved).await;
+            }
+            remove_partial(fs, partial).await;
+            result
+        }
+        result => result,
+    }
+}
+
+/// Give the copy at `path` the modification time of its source, so it is not taken for a file
+/// edited at the destination the next time, see [`SyncOptions::no_clobber_newer`].
+async fn keep_modified<B: FsBackend>(fs: &B, src_meta: &Metadata, path: &Path) {
+    let Some(modified) = src_meta.modified else {
+        return;
+    };
+    if let Err(e) = fs.set_modified(path, modified).await {
+        log::warn!(
+            "Failed to keep the modification time of {}: {}",
+            display_path(path).display(),
+            e
+        );
+    }
+}
+
+/// Check that `copy` has the same contents as `src`, failing with `None` on a mismatch.
+async fn verify_copy<B: FsBackend>(
+    fs: &B,
+    src: &Path,
+    copy: &Path,
+    algorithm: HashAlgorithm,
+) -> Result<(), Option<tokio::io::Error>> {
+    let (src_hash, copy_hash) = tokio::try_join!(
+        hash_file(fs, src, algorithm),
+        hash_file(fs, copy, algorithm)
+    )
+    .map_err(Some)?;
+    if src_hash == copy_hash {
+        Ok(())
+    } else {
+        Err(None)
+    }
+}
+
+/// Get a temporary sibling path for `dest`, so the final rename stays on the same file system.
+fn partial_path(dest: &Path) -> PathBuf {
+    let name = dest
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    dest.with_file_name(format!(".{}.partial-{:08x}", name, fastrand::u32(..)))
+}
+
+/// Whether `dest` would be on the file system of the source described by `src_meta`, since
+/// neither a clone nor a copy in the kernel can cross file systems.
+///
+/// Backends that do not give device numbers are assumed to be, the clone fails otherwise.
+async fn same_device<B: FsBackend>(fs: &B, src_meta: &Metadata, dest: &Path) -> bool {
+    let (Some((src_dev, _)), Some(dir)) = (src_meta.id, dest.parent()) else {
+        return true;
+    };
+    match fs.metadata(dir).await {
+        Ok(meta) => meta.id.is_none_or(|(dest_dev, _)| dest_dev == src_dev),
+        Err(_) => true,
+    }
+}
+
+/// Clone `src` to `partial`, telling whether it worked so the file is streamed otherwise.
+async fn try_reflink<B: FsBackend>(fs: &B, src: &Path, partial: &Path) -> bool {
+    match fs.reflink(src, partial).await {
+        Ok(()) => true,
+        Err(e) => {
+            log::debug!(
+                "Cannot clone {}, copying it instead: {}",
+                display_path(src).display(),
+                e
+            );
+            false
+        }
+    }
+}
+
+/// Copy `src` of `len` bytes to `dest` with [`FsBackend::copy_range`] by `chunk` bytes at a
+/// time, reporting progress through `dest` and noticing cancellation between chunks.
+///
+/// Gives `None` if the kernel refused before anything was copied so the file is streamed instead,
+/// otherwise what streaming it would: the bytes copied or the error, `None` inside if cancelled.
+/// Like [`std::io::copy`], a copy ending at once although `src` is not empty also falls back,
+/// some file systems report that instead of an error.
+async fn copy_in_kernel<B: FsBackend, K: Unpin, F: Fn(&K, &FileProgress)>(
+    fs: &B,
+    src: &mut B::Reader,
+    dest: &mut TrackingAsyncWrite<'_, B::Writer, K, F>,
+    len: u64,
+    chunk: usize,
+    throttle: Option<&Throttle>,
+    cancel: &CancellationToken,
+) -> Option<Option<std::io::Result<u64>>> {
+    let chunks = tokio::select! {
+        r = fs.copy_range(src, dest.get_mut(), chunk as u64, cancel) => r,
+        () = cancel.cancelled() => return Some(None),
+    };
+    let chunks = match chunks {
+        Ok(chunks) => chunks,
+        Err(e) if falls_back(&e) => {
+            log::debug!("Cannot copy in the kernel, streaming instead: {}", e);
+            return None;
+        }
+        Err(e) => return Some(Some(Err(e))),
+    };
+    let mut copied = 0;
+    loop {
+        let n = tokio::select! {
+            r = chunks.recv_async() => r,
+            () = cancel.cancelled() => return Some(None),
+        };
+        match n {
+            // Also the end if the copy went away without saying so.
+            Ok(Ok(0)) | Err(_) if copied == 0 && len > 0 => {
+                log::debug!(
+                    "The kernel copied nothing of {} bytes, streaming instead",
+                    len
+                );
+                return None;
+            }
+            Ok(Ok(0)) | Err(_) => return Some(Some(Ok(copied))),
+            Ok(Ok(n)) => {
+                copied += n;
+                dest.increment_bytes(n);
+                if let Some(delay) = throttle.and_then(|t| t.consume(n)) {
+                    tokio::select! {
+                        () = tokio::time::sleep(delay) => {}
+                        () = cancel.cancelled() => return Some(None),
+                    }
+                }
+            }
+            Ok(Err(e)) if copied == 0 && falls_back(&e) => {
+                log::debug!("Cannot copy in the kernel, streaming instead: {}", e);
+                return None;
+            }
+            Ok(Err(e)) => return Some(Some(Err(e))),
+        }
+    }
+}
+
+/// Whether `e` from [`FsBackend::copy_range`] means the kernel would not copy these files rather
+/// than that copying them failed, so streaming them may still work.
+fn falls_back(e: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    if e.raw_os_error() == Some(libc::EBADF) {
+        return true;
+    }
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::Unsupported
+            | std::io::ErrorKind::InvalidInput
+            | std::io::ErrorKind::CrossesDevices
+            | std::io::ErrorKind::PermissionDenied
+    )
+}
+
+async fn remove_partial<B: FsBackend>(fs: &B, partial: &Path) {
+    if let Err(e) = fs.remove_file(partial).await {
+        log::warn!(
+            "Failed to remove partially copied file {}: {}",
+            partial.display(),
+            e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::memory::{Fault, MemoryFs, Op};
+    use tokio::{fs::File, io::AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_copy_file() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        let dest = tmp_dir.path().join("dest");
+
+        let mut src_file = File::create(&src).await.unwrap();
+        src_file.write_all(b"hello world").await.unwrap();
+
+        copy_file(
+            "test",
+            dest.clone(),
+            src.clone(),
+            None,
+            &SyncFSCtx::new(TokioFs, 1, SyncOptions::default()),
+            &|_, _| {},
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        let mut dest_file = File::open(&dest).await.unwrap();
+        let mut buf = Vec::new();
+        dest_file.read_to_end(&mut buf).await.unwrap();
+
+        assert_eq!(buf, b"hello world");
+    }
+
+    #[tokio::test]
+    async fn test_sync() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        let dest = tmp_dir.path().join("dest");
+
+        let src_subdir = src.join("subdir");
+        let dest_subdir = dest.join("subdir");
+        tokio::fs::create_dir_all(&src_subdir).await.unwrap();
+
+        let src_file = src.join("file");
+        let dest_file = dest.join("file");
+        tokio::fs::write(&src_file, b"hello world").await.unwrap();
+
+        let src_subfile = src_subdir.join("subfile");
+        let dest_subfile = dest_subdir.join("subfile");
+        tokio::fs::write(&src_subfile, b"goodbye world")
+            .await
+            .unwrap();
+
+        let sync = SyncFS::new(&src, &dest, 1);
+
+        let done = AtomicU64::new(0);
+
+        let summary = sync
+            .sync(
+                |gp, _| {
+                    done.store(gp.files.done.load(Ordering::Relaxed), Ordering::Relaxed);
+                },
+                &|e| {
+                    panic!("Error occurred: {:?}", e);
+                },
+                &CancellationToken::new(),
+            )
+            .await;
+
+        assert_eq!(done.into_inner(), 2);
+        assert_eq!(summary.files_copied, 2);
+        assert_eq!(summary.files_failed, 0);
+        assert_eq!(summary.bytes_copied, 24);
+
+        let mut dest_file = File::open(&dest_file).await.unwrap();
+
+        let mut buf = Vec::new();
+        dest_file.read_to_end(&mut buf).await.unwrap();
+
+        assert_eq!(buf, b"hello world");
+
+        let mut dest_subfile = File::open(&dest_subfile).await.unwrap();
+
+        let mut buf = Vec::new();
+        dest_subfile.read_to_end(&mut buf).await.unwrap();
+
+        assert_eq!(buf, b"goodbye world");
+    }
+
+    #[tokio::test]
+    async fn test_sync_spawned() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        let dest = tmp_dir.path().join("dest");
+        tokio::fs::create_dir_all(&src).await.unwrap();
+        tokio::fs::write(src.join("file"), b"hello world")
+            .await
+            .unwrap();
+
+        // The roots are moved in, nothing outside the task has to outlive it.
+        let sync_fs = SyncFS::new(src, dest.clone(), 1);
+        let summary = tokio::spawn(async move {
+            sync_fs
+                .sync(
+                    |_, _| {},
+                    &|e| panic!("Error occurred: {:?}", e),
+                    &CancellationToken::new(),
+                )
+                .await
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(summary.files_copied, 1);
+        assert!(dest.join("file").exists());
+    }
+
+    #[tokio::test]
+    async fn test_sync_multiple_sources() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let srcs = [tmp_dir.path().join("dcim"), tmp_dir.path().join("pictures")];
+        for (src, content) in srcs.iter().zip(["first", "second"]) {
+            tokio::fs::create_dir_all(src.join("sub")).await.unwrap();
+            tokio::fs::write(src.join("shared"), content).await.unwrap();
+            tokio::fs::write(src.join("sub").join(content), content)
+                .await
+                .unwrap();
+        }
+
+        let dest = tmp_dir.path().join("last_wins");
+        let summary = SyncFS::with_sources(&srcs, &dest, 2, SyncOptions::default())
+            .sync(
+                |_, _| {},
+                &|e| {
+                    panic!("Error occurred: {:?}", e);
+                },
+                &CancellationToken::new(),
+            )
+            .await;
+        assert_eq!(summary.files_copied, 3);
+        assert_eq!(std::fs::read(dest.join("shared")).unwrap(), b"second");
+        assert!(dest.join("sub").join("first").exists());
+        assert!(dest.join("sub").join("second").exists());
+
+        let dest = tmp_dir.path().join("error");
+        let options = SyncOptions {
+            collision: CollisionPolicy::Error,
+            ..Default::default()
+        };
+        let collisions = Mutex::new(Vec::new());
+        let summary = SyncFS::with_sources(&srcs, &dest, 2, options)
+            .sync(
+                |_, _| {},
+                &|e| {
+                    if let SyncError::Collision { path, .. } = e.error {
+                        collisions.lock().unwrap().push(path.clone());
+                    }
+                },
+                &CancellationToken::new(),
+            )
+            .await;
+        assert_eq!(summary.files_copied, 3);
+        assert_eq!(collisions.into_inner().unwrap(), [PathBuf::from("shared")]);
+        assert_eq!(summary.failed_paths[0].0, srcs[1].join("shared"));
+        assert_eq!(std::fs::read(dest.join("shared")).unwrap(), b"first");
+    }
+
+    #[tokio::test]
+    async fn test_sync_progress_throttled() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        let dest = tmp_dir.path().join("dest");
+
+        tokio::fs::create_dir_all(&src).await.unwrap();
+        for i in 0..500 {
+            tokio::fs::write(src.join(i.to_string()), b"hello world")
+                .await
+                .unwrap();
+        }
+
+        let reports = AtomicU64::new(0);
+        let summary = SyncFS::new(&src, &dest, 8)
+            .sync(
+                |_, ms| {
+                    if ms.is_none() {
+                        reports.fetch_add(1, Ordering::Relaxed);
+                    }
+                },
+                &|e| {
+                    panic!("Error occurred: {:?}", e);
+                },
+                &CancellationToken::new(),
+            )
+            .await;
+
+        assert_eq!(summary.files_copied, 500);
+        // One report per percent of the copies.
+        let reports = reports.into_inner();
+        assert!((95..=100).contains(&reports), "{} reports", reports);
+    }
+
+    #[tokio::test]
+    async fn test_sync_directory_error_totals() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        let dest = tmp_dir.path().join("dest");
+
+        tokio::fs::create_dir_all(src.join("sub")).await.unwrap();
+        tokio::fs::write(src.join("file"), b"hello world")
+            .await
+            .unwrap();
+        tokio::fs::write(src.join("sub").join("subfile"), b"goodbye world")
+            .await
+            .unwrap();
+        // A file in the way of the destination directory fails the whole directory.
+        tokio::fs::create_dir_all(&dest).await.unwrap();
+        tokio::fs::write(dest.join("sub"), b"").await.unwrap();
+
+        let errors = AtomicU64::new(0);
+        let totals = Mutex::new((0, 0));
+        let summary = SyncFS::new(&src, &dest, 1)
+            .sync(
+                |gp, ms| {
+                    if let Some(ProgressMilestone::CopyComplete) = ms {
+                        let files = &gp.files;
+                        *totals.lock().unwrap() = (
+                            files.total.load(Ordering::Relaxed),
+                            files.done.load(Ordering::Relaxed)
+                                + files.skipped.load(Ordering::Relaxed)
+                                + files.failed.load(Ordering::Relaxed),
+                        );
+                    }
+                },
+                &|_| {
+                    errors.fetch_add(1, Ordering::Relaxed);
+                },
+                &CancellationToken::new(),
+            )
+            .await;
+
+        assert_eq!(errors.into_inner(), 1);
+        assert_eq!(summary.failed_paths.len(), 1);
+        assert_eq!(summary.failed_paths[0].0, src.join("sub"));
+        assert_eq!(summary.files_copied, 1);
+        assert_eq!(summary.files_failed, 0);
+        // The progress bar reaches its end.
+        assert_eq!(totals.into_inner().unwrap(), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_sync_long_path() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        let dest = tmp_dir.path().join("dest");
+
+        // Longer than `MAX_PATH` (260) with each component well within the 255 limit.
+        let deep = (0..6)
+            .map(|i| format!("{}{}", i, "d".repeat(59)))
+            .collect::<PathBuf>();
+        assert!(dest.join(&deep).join("file").as_os_str().len() > 260);
+        tokio::fs::create_dir_all(src.join(&deep)).await.unwrap();
+        tokio::fs::write(src.join(&deep).join("file"), b"hello world")
+            .await
+            .unwrap();
+
+        let summary = SyncFS::new(&src, &dest, 1)
+            .sync(
+                |_, _| {},
+                &|e| {
+                    panic!("Error occurred: {:?}", e);
+                },
+                &CancellationToken::new(),
+            )
+            .await;
+
+        assert_eq!(summary.files_copied, 1);
+        let copied = long_path(&dest.join(&deep).join("file")).unwrap();
+        assert_eq!(std::fs::read(copied).unwrap(), b"hello world");
+    }
+
+    #[tokio::test]
+    async fn test_sync_verify() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        let dest = tmp_dir.path().join("dest");
+
+        tokio::fs::create_dir_all(&src).await.unwrap();
+        tokio::fs::write(src.join("file"), b"hello world")
+            .await
+            .unwrap();
+
+        let options = SyncOptions {
+            verify: true,
+            ..Default::default()
+        };
+        let summary = SyncFS::with_options(&src, &dest, 1, options)
+            .sync(
+                |_, _| {},
+                &|e| {
+                    panic!("Error occurred: {:?}", e);
+                },
+                &CancellationToken::new(),
+            )
+            .await;
+        assert_eq!(summary.files_copied, 1);
+        assert_eq!(std::fs::read(dest.join("file")).unwrap(), b"hello world");
+
+        let corrupt = tmp_dir.path().join("corrupt");
+        tokio::fs::write(&corrupt, b"hello w0rld").await.unwrap();
+        for algorithm in [
+            HashAlgorithm::Blake3,
+            HashAlgorithm::Xxh3,
+            HashAlgorithm::Sha256,
+        ] {
+            assert!(
+                verify_copy(&TokioFs, &src.join("file"), &dest.join("file"), algorithm)
+                    .await
+                    .is_ok()
+            );
+            assert!(matches!(
+                verify_copy(&TokioFs, &src.join("file"), &corrupt, algorithm).await,
+                Err(None)
+            ));
+        }
+        assert!(matches!(
+            verify_copy(
+                &TokioFs,
+                &src.join("file"),
+                &tmp_dir.path().join("missing"),
+                HashAlgorithm::default()
+            )
+            .await,
+            Err(Some(_))
+        ));
+    }
+
+    #[cfg(windows)]
+    #[tokio::test]
+    async fn test_sync_preserve_attributes() {
+        use std::os::windows::fs::MetadataExt;
+
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        let dest = tmp_dir.path().join("dest");
+
+        tokio::fs::create_dir_all(&src).await.unwrap();
+        tokio::fs::write(src.join("file"), b"hello world")
+            .await
+            .unwrap();
+        let status = std::process::Command::new("attrib")
+            .arg("+h")
+            .arg(src.join("file"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let options = SyncOptions {
+            preserve_attributes: true,
+            ..Default::default()
+        };
+        let summary = SyncFS::with_options(&src, &dest, 1, options)
+            .sync(
+                |_, _| {},
+                &|e| {
+                    panic!("Error occurred: {:?}", e);
+                },
+                &CancellationToken::new(),
+            )
+            .await;
+
+        assert_eq!(summary.files_copied, 1);
+        let attrs = std::fs::metadata(dest.join("file"))
+            .unwrap()
+            .file_attributes();
+        assert_ne!(attrs & FILE_ATTRIBUTE_HIDDEN, 0);
+    }
+
+    #[cfg(windows)]
+    #[tokio::test]
+    async fn test_sync_preserve_windows_metadata() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        let dest = tmp_dir.path().join("dest");
+
+        tokio::fs::create_dir_all(&src).await.unwrap();
+        tokio::fs::write(src.join("file"), b"hello world")
+            .await
+            .unwrap();
+        tokio::fs::write(src.join("file:tag"), b"tagged")
+            .await
+            .unwrap();
+        let created = std::fs::metadata(src.join("file"))
+            .unwrap()
+            .created()
+            .unwrap();
+
+        let options = SyncOptions {
+            preserve_windows_metadata: true,
+            ..Default::default()
+        };
+        let summary = SyncFS::with_options(&src, &dest, 1, options)
+            .sync(
+                |_, _| {},
+                &|e| {
+                    panic!("Error occurred: {:?}", e);
+                },
+                &CancellationToken::new(),
+            )
+            .await;
+
+        assert_eq!(summary.files_copied, 1);
+        assert_eq!(
+            tokio::fs::read(dest.join("file:tag")).await.unwrap(),
+            b"tagged"
+        );
+        assert_eq!(
+            std::fs::metadata(dest.join("file"))
+                .unwrap()
+                .created()
+                .unwrap(),
+            created
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sync_prune_empty_dirs() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        let dest = tmp_dir.path().join("dest");
+
+        tokio::fs::create_dir_all(src.join("empty").join("nested"))
+            .await
+            .unwrap();
+        tokio::fs::create_dir_all(src.join("full")).await.unwrap();
+        tokio::fs::write(src.join("full").join("file"), b"hello world")
+            .await
+            .unwrap();
+        // Everything below `filtered` is ignored, so its copy ends up empty.
+        tokio::fs::create_dir_all(src.join("filtered").join("nested"))
+            .await
+            .unwrap();
+        tokio::fs::write(src.join("filtered").join("nested").join("a.tmp"), b"tmp")
+            .await
+            .unwrap();
+        tokio::fs::write(src.join(SYNCIGNORE), b"*.tmp\n")
+            .await
+            .unwrap();
+        // Directories the user made on the destination are left alone.
+        tokio::fs::create_dir_all(dest.join("mine")).await.unwrap();
+        tokio::fs::create_dir_all(src.join("mine")).await.unwrap();
+
+        let options = SyncOptions {
+            prune_empty_dirs: true,
+            respect_syncignore: true,
+            ..Default::default()
+        };
+        let summary = SyncFS::with_options(&src, &dest, 1, options)
+            .sync(
+                |_, _| {},
+                &|e| {
+                    panic!("Error occurred: {:?}", e);
+                },
+                &CancellationToken::new(),
+            )
+            .await;
+
+        assert_eq!(summary.files_copied, 2);
+        assert_eq!(summary.dirs_pruned, 2);
+        assert!(!dest.join("filtered").exists());
+        // Empty source directories are mirrored, not pruned.
+        assert!(dest.join("empty").join("nested").exists());
+        assert!(dest.join("full").join("file").exists());
+        assert!(dest.join("mine").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sync_symlink_policy() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+
+        tokio::fs::create_dir_all(src.join("dir")).await.unwrap();
+        tokio::fs::write(src.join("dir").join("file"), b"hello world")
+            .await
+            .unwrap();
+        // Links back to the source root.
+        tokio::fs::symlink("..", src.join("dir").join("loop"))
+            .await
+            .unwrap();
+
+        for symlinks in [
+            SymlinkPolicy::Follow,
+            SymlinkPolicy::Skip,
+            SymlinkPolicy::Copy,
+        ] {
+            let dest = tmp_dir.path().join(format!("{:?}", symlinks));
+            let options = SyncOptions {
+                symlinks,
+                ..Default::default()
+            };
+            let errors = Mutex::new(Vec::new());
+            let summary = SyncFS::with_options(&src, &dest, 1, options)
+                .sync(
+                    |_, _| {},
+                    &|e| errors.lock().unwrap().push(e.to_string()),
+                    &CancellationToken::new(),
+                )
+                .await;
+            let errors = errors.into_inner().unwrap();
+            let link = dest.join("dir").join("loop");
+
+            assert!(dest.join("dir").join("file").exists(), "{:?}", symlinks);
+            match symlinks {
+                SymlinkPolicy::Follow => {
+                    assert_eq!(errors.len(), 1, "{:?}", errors);
+                    assert!(errors[0].contains("links back"), "{:?}", errors);
+                    assert_eq!(summary.files_copied, 1);
+                    assert!(tokio::fs::symlink_metadata(&link).await.is_err());
+                }
+                SymlinkPolicy::Skip => {
+                    assert!(errors.is_empty(), "{:?}", errors);
+                    assert_eq!(summary.files_copied, 1);
+                    assert!(tokio::fs::symlink_metadata(&link).await.is_err());
+                }
+                SymlinkPolicy::Copy => {
+                    assert!(errors.is_empty(), "{:?}", errors);
+                    assert_eq!(summary.files_copied, 2);
+                    assert_eq!(tokio::fs::read_link(&link).await.unwrap(), Path::new(".."));
+                }
+            }
+        }
+
+        // An unchanged link is left alone.
+        let options = SyncOptions {
+            symlinks: SymlinkPolicy::Copy,
+            ..Default::default()
+        };
+        let summary = SyncFS::with_options(&src, tmp_dir.path().join("Copy"), 1, options)
+            .sync(
+                |_, _| {},
+                &|e| panic!("Error occurred: {:?}", e),
+                &CancellationToken::new(),
+            )
+            .await;
+        assert_eq!(summary.files_copied, 0);
+        assert_eq!(summary.files_skipped, 2);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sync_special_files() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        tokio::fs::create_dir_all(&src).await.unwrap();
+        tokio::fs::write(src.join("file"), b"hello world")
+            .await
+            .unwrap();
+        // S_IFIFO, a named pipe.
+        crate::sys::mknod(&src.join("pipe"), 0o010_000 | 0o644, 0).unwrap();
+        let _socket = std::os::unix::net::UnixListener::bind(src.join("socket")).unwrap();
+
+        for special_files in [
+            SpecialFilePolicy::Skip,
+            SpecialFilePolicy::Error,
+            SpecialFilePolicy::Recreate,
+        ] {
+            let dest = tmp_dir.path().join(format!("{:?}", special_files));
+            let options = SyncOptions {
+                special_files,
+                ..Default::default()
+            };
+            let errors = Mutex::new(Vec::new());
+            let mut summary = SyncFS::with_options(&src, &dest, 1, options)
+                .sync(
+                    |_, _| {},
+                    &|e| errors.lock().unwrap().push(e.to_string()),
+                    &CancellationToken::new(),
+                )
+                .await;
+            let errors = errors.into_inner().unwrap();
+            summary.special_files.sort_by(|a, b| a.0.cmp(&b.0));
+            let pipe = tokio::fs::symlink_metadata(dest.join("pipe")).await;
+
+            assert!(dest.join("file").exists(), "{:?}", special_files);
+            assert!(!dest.join("socket").exists());
+            match special_files {
+                SpecialFilePolicy::Skip => {
+                    assert!(errors.is_empty(), "{:?}", errors);
+                    assert_eq!(summary.files_copied, 1);
+                    assert_eq!(summary.files_skipped, 2);
+                    assert_eq!(
+                        summary.special_files,
+                        [
+                            (src.join("pipe"), FileType::Fifo),
+                            (src.join("socket"), FileType::Socket)
+                        ]
+                    );
+                    assert!(pipe.is_err());
+                }
+                SpecialFilePolicy::Error => {
+                    assert_eq!(errors.len(), 2, "{:?}", errors);
+                    assert!(errors[0].contains("named pipe") || errors[1].contains("named pipe"));
+                    assert_eq!(summary.files_failed, 2);
+                    assert!(pipe.is_err());
+                }
+                SpecialFilePolicy::Recreate => {
+                    assert!(errors.is_empty(), "{:?}", errors);
+                    assert_eq!(summary.files_copied, 2);
+                    assert_eq!(
+                        summary.special_files,
+                        [(src.join("socket"), FileType::Socket)]
+                    );
+                    assert_eq!(Metadata::from(pipe.unwrap()).file_type, FileType::Fifo);
+                }
+            }
+        }
+
+        // A pipe already there is left alone.
+        let options = SyncOptions {
+            special_files: SpecialFilePolicy::Recreate,
+            ..Default::default()
+        };
+        let summary = SyncFS::with_options(&src, tmp_dir.path().join("Recreate"), 1, options)
+            .sync(
+                |_, _| {},
+                &|e| panic!("Error occurred: {:?}", e),
+                &CancellationToken::new(),
+            )
+            .await;
+        assert_eq!(summary.files_copied, 0);
+        assert_eq!(summary.files_skipped, 3);
+    }
+
+    #[tokio::test]
+    async fn test_sync_overwrite_policy() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        tokio::fs::create_dir_all(&src).await.unwrap();
+        for name in ["same", "older", "resized", "missing"] {
+            tokio::fs::write(src.join(name), b"hello world")
+                .await
+                .unwrap();
+        }
+        let src_mtime = tokio::fs::metadata(src.join("same"))
+            .await
+            .unwrap()
+            .modified()
+            .unwrap();
+
+        for (policy, expected) in [
+            (
+                OverwritePolicy::Compare,
+                &["older", "resized", "missing"][..],
+            ),
+            (
+                OverwritePolicy::Always,
+                &["same", "older", "resized", "missing"],
+            ),
+            (OverwritePolicy::NoClobber, &["missing"]),
+            (OverwritePolicy::Update, &["older", "missing"]),
+        ] {
+            let dest = tmp_dir.path().join(format!("{:?}", policy));
+            tokio::fs::create_dir_all(&dest).await.unwrap();
+            // Same size as the source unless resized, and newer unless older.
+            for (name, content, newer) in [
+                ("same", &b"HELLO WORLD"[..], true),
+                ("older", b"HELLO WORLD", false),
+                ("resized", b"HELLO", true),
+            ] {
+                let file = std::fs::File::create(dest.join(name)).unwrap();
+                std::io::Write::write_all(&mut &file, content).unwrap();
+                let mtime = if newer {
+                    src_mtime + Duration::from_secs(10)
+                } else {
+                    src_mtime - Duration::from_secs(10)
+                };
+                file.set_modified(mtime).unwrap();
+            }
+
+            let options = SyncOptions {
+                overwrite: policy,
+                ..Default::default()
+            };
+            let summary = SyncFS::with_options(&src, &dest, 1, options)
+                .sync(
+                    |_, _| {},
+                    &|e| {
+                        panic!("Error occurred: {:?}", e);
+                    },
+                    &CancellationToken::new(),
+                )
+                .await;
+
+            assert_eq!(summary.files_copied, expected.len() as u64, "{:?}", policy);
+            assert_eq!(
+                summary.files_skipped,
+                4 - expected.len() as u64,
+                "{:?}",
+                policy
+            );
+            for name in ["same", "older", "resized", "missing"] {
+                let copied = std::fs::read(dest.join(name)).unwrap() == b"hello world";
+                assert_eq!(copied, expected.contains(&name), "{:?} {}", policy, name);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_no_clobber_newer() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        let dest = tmp_dir.path().join("dest");
+        tokio::fs::create_dir_all(&src).await.unwrap();
+        tokio::fs::create_dir_all(&dest).await.unwrap();
+        // Well before the copies are made.
+        let an_hour_ago = std::time::SystemTime::now() - Duration::from_secs(3600);
+        for name in ["edited", "stale", "missing"] {
+            let file = std::fs::File::create(src.join(name)).unwrap();
+            std::io::Write::write_all(&mut &file, b"hello world").unwrap();
+            file.set_modified(an_hour_ago).unwrap();
+        }
+        let src_mtime = tokio::fs::metadata(src.join("edited"))
+            .await
+            .unwrap()
+            .modified()
+            .unwrap();
+        for (name, mtime) in [
+            ("edited", src_mtime + Duration::from_secs(10)),
+            ("stale", src_mtime - Duration::from_secs(10)),
+        ] {
+            let file = std::fs::File::create(dest.join(name)).unwrap();
+            std::io::Write::write_all(&mut &file, b"HELLO WORLD").unwrap();
+            file.set_modified(mtime).unwrap();
+        }
+
+        let options = SyncOptions {
+            overwrite: OverwritePolicy::Always,
+            no_clobber_newer: true,
+            ..Default::default()
+        };
+        let summary = SyncFS::with_options(&src, &dest, 1, options.clone())
+            .sync(|_, _| {}, &|e| panic!("{:?}", e), &CancellationToken::new())
+            .await;
+
+        assert_eq!((summary.files_copied, summary.files_skipped), (2, 1));
+        assert_eq!(summary.conflicts.len(), 1);
+        assert!(summary.conflicts[0].0.ends_with("edited"));
+        assert_eq!(std::fs::read(dest.join("edited")).unwrap(), b"HELLO WORLD");
+        assert_eq!(std::fs::read(dest.join("stale")).unwrap(), b"hello world");
+
+        // The copies keep the time of their source, so they are no conflict the next time.
+        let copies = tmp_dir.path().join("copies");
+        for _ in 0..2 {
+            let summary = SyncFS::with_options(&src, &copies, 1, options.clone())
+                .sync(|_, _| {}, &|e| panic!("{:?}", e), &CancellationToken::new())
+                .await;
+            assert_eq!(summary.files_copied, 3);
+            assert!(summary.conflicts.is_empty(), "{:?}", summary.conflicts);
+        }
+        let copied = tokio::fs::metadata(copies.join("stale")).await.unwrap();
+        assert_eq!(copied.modified().unwrap(), src_mtime);
+    }
+
+    #[tokio::test]
+    async fn test_cmp_file_flags() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        let dest = tmp_dir.path().join("dest");
+
+        tokio::fs::write(&src, b"hello world").await.unwrap();
+        tokio::fs::write(&dest, b"hello there").await.unwrap();
+
+        assert!(cmp_file(
+            &TokioFs,
+            &dest,
+            &src,
+            ComparisonFlags::SIZE,
+            DEFAULT_MTIME_TOLERANCE,
+            HashAlgorithm::default(),
+            None,
+        )
+        .await
+        .unwrap());
+        assert!(!cmp_file(
+            &TokioFs,
+            &dest,
+            &src,
+            ComparisonFlags::SIZE | ComparisonFlags::HASH,
+            DEFAULT_MTIME_TOLERANCE,
+            HashAlgorithm::default(),
+            None,
+        )
+        .await
+        .unwrap());
+
+        tokio::fs::write(&dest, b"hello world").await.unwrap();
+
+        assert!(cmp_file(
+            &TokioFs,
+            &dest,
+            &src,
+            ComparisonFlags::HASH,
+            DEFAULT_MTIME_TOLERANCE,
+            HashAlgorithm::default(),
+            None,
+        )
+        .await
+        .unwrap());
+
+        let mut perms = tokio::fs::metadata(&dest).await.unwrap().permissions();
+        perms.set_readonly(true);
+        tokio::fs::set_permissions(&dest, perms).await.unwrap();
+
+        assert!(cmp_file(
+            &TokioFs,
+            &dest,
+            &src,
+            ComparisonFlags::HASH,
+            DEFAULT_MTIME_TOLERANCE,
+            HashAlgorithm::default(),
+            None,
+        )
+        .await
+        .unwrap());
+        assert!(!cmp_file(
+            &TokioFs,
+            &dest,
+            &src,
+            ComparisonFlags::HASH | ComparisonFlags::ATTRIBUTES,
+            DEFAULT_MTIME_TOLERANCE,
+            HashAlgorithm::default(),
+            None,
+        )
+        .await
+        .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_cmp_file_mtime_tolerance() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        let dest = tmp_dir.path().join("dest");
+
+        tokio::fs::write(&src, b"hello world").await.unwrap();
+        tokio::fs::write(&dest, b"hello world").await.unwrap();
+        let src_mtime = tokio::fs::metadata(&src).await.unwrap().modified().unwrap();
+        let set_dest_mtime = |behind: Duration| {
+            std::fs::File::options()
+                .write(true)
+                .open(&dest)
+                .unwrap()
+                .set_modified(src_mtime - behind)
+                .unwrap();
+        };
+        let up_to_date = |tolerance| {
+            cmp_file(
+                &TokioFs,
+                &dest,
+                &src,
+                ComparisonFlags::MTIME,
+                tolerance,
+                HashAlgorithm::default(),
+                None,
+            )
+        };
+
+        // Like a copy onto FAT, which rounds timestamps to 2 seconds.
+        set_dest_mtime(Duration::from_secs(1));
+        assert!(up_to_date(DEFAULT_MTIME_TOLERANCE).await.unwrap());
+        assert!(!up_to_date(Duration::ZERO).await.unwrap());
+
+        set_dest_mtime(Duration::from_secs(3));
+        assert!(!up_to_date(DEFAULT_MTIME_TOLERANCE).await.unwrap());
+
+        // A newer destination is up to date either way.
+        std::fs::File::options()
+            .write(true)
+            .open(&dest)
+            .unwrap()
+            .set_modified(src_mtime + Duration::from_secs(1))
+            .unwrap();
+        assert!(up_to_date(DEFAULT_MTIME_TOLERANCE).await.unwrap());
+        assert!(up_to_date(Duration::ZERO).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_sync_cancelled() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        let dest = tmp_dir.path().join("dest");
+
+        tokio::fs::create_dir_all(&src).await.unwrap();
+        tokio::fs::write(src.join("file"), b"hello world")
+            .await
+            .unwrap();
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let cancelled = std::sync::atomic::AtomicBool::new(false);
+
+        SyncFS::new(&src, &dest, 1)
+            .sync(
+                |_, ms| {
+                    if let Some(ProgressMilestone::Cancelled) = ms {
+                        cancelled.store(true, Ordering::Relaxed);
+                    }
+                },
+                &|e| {
+                    panic!("Error occurred: {:?}", e);
+                },
+                &cancel,
+            )
+            .await;
+
+        assert!(cancelled.into_inner());
+        assert!(!dest.join("file").exists());
+    }
+
+    #[tokio::test]
+    async fn test_copy_file_throttled() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        let dest = tmp_dir.path().join("dest");
+
+        tokio::fs::write(&src, vec![0u8; 64 << 10]).await.unwrap();
+
+        let ctx = SyncFSCtx::new(
+            TokioFs,
+            1,
+            SyncOptions {
+                max_bytes_per_sec: Some(128 << 10),
+                ..Default::default()
+            },
+        );
+        let start = Instant::now();
+
+        copy_file(
+            "test",
+            dest.clone(),
+            src.clone(),
+            None,
+            &ctx,
+            &|_, _| {},
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(450));
+        assert_eq!(tokio::fs::metadata(&dest).await.unwrap().len(), 64 << 10);
+    }
+
+    /// Copy a `len` bytes file with a `buffer_size` buffer, returning the number of writes and
+    /// the time it took.
+    async fn copy_with_buffer(len: usize, buffer_size: usize) -> (u64, Duration) {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        let dest = tmp_dir.path().join("dest");
+        let data = (0..len).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        tokio::fs::write(&src, &data).await.unwrap();
+
+        let ctx = SyncFSCtx::new(
+            TokioFs,
+            1,
+            SyncOptions {
+                buffer_size,
+                // Every write is reported, to count them.
+                file_progress_interval: Duration::ZERO,
+                ..Default::default()
+            },
+        );
+        let writes = AtomicU64::new(0);
+        let start = Instant::now();
+        copy_file(
+            "test",
+            dest.clone(),
+            src,
+            None,
+            &ctx,
+            &|_, _| {
+                writes.fetch_add(1, Ordering::Relaxed);
+            },
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        let elapsed = start.elapsed();
+
+        assert_eq!(tokio::fs::read(&dest).await.unwrap(), data);
+        (writes.into_inner(), elapsed)
+    }
+
+    #[tokio::test]
+    async fn test_copy_file_buffer_size() {
+        let (small, _) = copy_with_buffer(4 << 20, 8 << 10).await;
+        let (large, _) = copy_with_buffer(4 << 20, 1 << 20).await;
+        assert!(large * 4 <= small, "{} writes vs {}", large, small);
+
+        // Odd sizes still copy everything, zero uses the default.
+        copy_with_buffer(100_000, 7).await;
+        copy_with_buffer(100_000, 0).await;
+    }
+
+    #[tokio::test]
+    async fn test_copy_file_progress_interval() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        tokio::fs::write(&src, vec![0u8; 1 << 20]).await.unwrap();
+
+        for (interval, expected) in [(Duration::ZERO, 128..=usize::MAX), (Duration::MAX, 2..=3)] {
+            let ctx = SyncFSCtx::new(
+                TokioFs,
+                1,
+                SyncOptions {
+                    buffer_size: 8 << 10,
+                    file_progress_interval: interval,
+                    ..Default::default()
+                },
+            );
+            let reports = Mutex::new(Vec::new());
+            copy_file(
+                "test",
+                tmp_dir.path().join("dest"),
+                src.clone(),
+                None,
+                &ctx,
+                &|_, prog| reports.lock().unwrap().push(prog.done),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+
+            // The start and the end are reported whatever the interval.
+            let reports = reports.into_inner().unwrap();
+            assert!(expected.contains(&reports.len()), "{:?}", reports.len());
+            assert_eq!(reports.first(), Some(&0));
+            assert_eq!(reports.last(), Some(&(1 << 20)));
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "benchmark, run with --ignored --nocapture"]
+    async fn bench_copy_file_buffer_size() {
+        let len = 256 << 20;
+        let (_, small) = copy_with_buffer(len, 8 << 10).await;
+        let (_, large) = copy_with_buffer(len, 1 << 20).await;
+        let rate = |elapsed: Duration| (len as f64 / elapsed.as_secs_f64()) as u64 >> 20;
+        println!(
+            "8 KiB: {:?} ({} MiB/s), 1 MiB: {:?} ({} MiB/s)",
+            small,
+            rate(small),
+            large,
+            rate(large)
+        );
+        assert!(large <= small, "1 MiB buffer slower than 8 KiB");
+    }
+
+    #[tokio::test]
+    async fn test_copy_file_no_partial_left() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src = tmp_dir.path().join("src");
+        let dest_dir = tmp_dir.path().join("dest");
+        tokio::fs::create_dir_all(&dest_dir).await.unwrap();
+
+        let has_partial = || {
+            std::fs::read_dir(&dest_dir).unwrap().any(|e| {
+                e.unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .contains(".partial-")
+            })
+        };
+
+        tokio::fs::write(&src, b"hello world").await.unwrap();
+        copy_file(
+            "ok",
+            dest_dir.join("ok"),
+            src.clone(),
+            None,
+            &SyncFSCtx::new(TokioFs, 1, SyncOptions::default()),
+            &|_, _| {},
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        assert!(!has_partial());
+        assert_eq!(
+            tokio::fs::read(dest_dir.join("ok")).await.unwrap(),
+            b"hello world"
+        );
+
+        // Reading a directory as a file fails after the destination has been created.
+        copy_file(
+            "fail",
+            dest_dir.join("fail"),
+            tmp_dir.path().to_path_buf(),
+            None,
+            &SyncFSCtx::new(TokioFs, 1, SyncOptions::default()),
+            &|_, _| {},
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(!has_partial());
+        assert!(!dest_dir.join("fail").exists());
+    }
+
+    /// Get a [`MemoryFs`] holding `files`, by path and contents.
+    fn memory_fs(files: &[(&str, &str)]) -> MemoryFs {
+        let fs = MemoryFs::default();
+        for (path, data) in files {
+            fs.write(path, *data);
+        }
+        fs
+    }
+
+    /// Synchronize `/src` into `/dest` on `fs`, cancelling once discovery is done if `cancel`.
+    async fn sync_memory(fs: &MemoryFs, cancel: bool) -> (SyncSummary, Vec<String>) {
+        let token = CancellationToken::new();
+        let errors = Mutex::new(Vec::new());
+        let summary = SyncFS::with_backend(
+            fs.clone(),
+            &[PathBuf::from("/src")],
+            Path::new("/dest"),
+            1,
+            SyncOptions::default(),
+        )
+        .sync(
+            |_, milestone| {
+                if cancel && matches!(milestone, Some(ProgressMilestone::DiscoveryComplete)) {
+                    token.cancel();
+                }
+            },
+            &|e| errors.lock().unwrap().push(e.to_string()),
+            &token,
+        )
+        .await;
+        (summary, errors.into_inner().unwrap())
+    }
+
+    fn partials(fs: &MemoryFs) -> Vec<PathBuf> {
+        fs.paths()
+            .into_iter()
+            .filter(|p| p.to_string_lossy().contains(".partial-"))
+            .collect()
+    }
+
+    /// Synchronize `/src` into `/dest` on `fs` with the copies held back until discovery is over,
+    /// running `between` before letting them go, and get the summary, the last progress and the
+    /// errors.
+    async fn sync_after_discovery(
+        fs: &MemoryFs,
+        options: SyncOptions,
+        between: impl Future<Output = ()>,
+    ) -> (SyncSummary, ProgressSnapshot, Vec<String>) {
+        let pause = PauseHandle::default();
+        pause.pause();
+        let discovered = AtomicBool::new(false);
+        let sync = SyncFS::with_backend(fs.clone(), ["/src"], "/dest", 1, options)
+            .with_pause(pause.clone());
+
+        let control = async {
+            while !discovered.load(Ordering::Relaxed) {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            between.await;
+            pause.resume();
+        };
+        let progress = Mutex::new(None);
+        let errors = Mutex::new(Vec::new());
+        let error_fn = |e: &SyncErrorContext| errors.lock().unwrap().push(e.to_string());
+        let cancel = CancellationToken::new();
+        let (summary, ()) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(
+                sync.sync(
+                    |gp, ms| {
+                        if matches!(ms, Some(ProgressMilestone::DiscoveryComplete)) {
+                            discovered.store(true, Ordering::Relaxed);
+                        }
+                        *progress.lock().unwrap() = Some(gp.snapshot());
+                    },
+                    &error_fn,
+                    &cancel,
+                ),
+                control
+            )
+        })
+        .await
+        .expect("sync did not resume");
+        let progress = progress.into_inner().unwrap().unwrap();
+        (summary, progress, errors.into_inner().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_sync_fsync() {
+        let sync = |fs: &MemoryFs, options| {
+            SyncFS::with_backend(fs.clone(), ["/src"], "/dest", 1, options)
+                .start()
+                .1
+        };
+        let fsync = |fsync| SyncOptions {
+            fsync,
+            ..SyncOptions::default()
+        };
+
+        // Failing to flush a file fails its copy.
+        for fsync_on in [false, true] {
+            let fs = memory_fs(&[("/src/a", "hello"), ("/src/dir/b", "world")]);
+            fs.inject(Op::Sync, Fault::Error(std::io::ErrorKind::Other), |p| {
+                p.parent() == Some(Path::new("/dest/dir"))
+            });
+            let summary = sync(&fs, fsync(fsync_on)).await;
+
+            assert!(fs.read("/dest/a").is_some());
+            assert_eq!(fs.read("/dest/dir/b").is_some(), !fsync_on);
+            assert_eq!(summary.files_copied, if fsync_on { 1 } else { 2 });
+            assert_eq!(summary.files_failed, u64::from(fsync_on));
+            assert!(partials(&fs).is_empty(), "{:?}", partials(&fs));
+        }
+
+        // Failing to flush a directory only warns.
+        let fs = memory_fs(&[("/src/a", "hello"), ("/src/dir/b", "world")]);
+        fs.inject(Op::Sync, Fault::Error(std::io::ErrorKind::Other), |p| {
+            p == Path::new("/dest/dir") || p == Path::new("/dest")
+        });
+        let summary = sync(&fs, fsync(true)).await;
+        assert_eq!(summary.files_copied, 2);
+        assert_eq!(summary.files_failed, 0);
+        assert!(!summary.has_failures());
+        assert!(fs.read("/dest/dir/b").is_some());
+
+        // A clone has no handle to flush, it is flushed all the same.
+        let fs = memory_fs(&[("/src/a", "hello")]);
+        fs.inject(Op::Sync, Fault::Error(std::io::ErrorKind::Other), |p| {
+            p.parent() == Some(Path::new("/dest"))
+        });
+        let options = SyncOptions {
+            prefer_reflink: true,
+            ..fsync(true)
+        };
+        let summary = sync(&fs, options).await;
+        assert_eq!(summary.files_copied, 0);
+        assert_eq!(summary.files_failed, 1);
+        assert!(fs.read("/dest/a").is_none());
+        assert!(partials(&fs).is_empty(), "{:?}", partials(&fs));
+
+        // A moved file keeps its source until the directory of its copy is flushed.
+        let fs = memory_fs(&[("/src/a", "hello"), ("/src/dir/b", "world")]);
+        fs.inject(Op::Sync, Fault::Error(std::io::ErrorKind::Other), |p| {
+            p == Path::new("/dest/dir")
+        });
+        let options = SyncOptions {
+            move_files: true,
+            ..fsync(true)
+        };
+        let summary = sync(&fs, options).await;
+        assert_eq!(summary.files_copied, 2);
+        assert_eq!(summary.files_failed, 0);
+        assert!(fs.read("/src/a").is_none());
+        assert_eq!(fs.read("/src/dir/b").as_deref(), Some(&b"world"[..]));
+        assert_eq!(fs.read("/dest/dir/b").as_deref(), Some(&b"world"[..]));
+    }
+
+    #[tokio::test]
+    async fn test_sync_memory_backend() {
+        let fs = memory_fs(&[("/src/a", "hello world"), ("/src/dir/b", "hello")]);
+
+        let (summary, _) = sync_memory(&fs, false).await;
+        assert_eq!(summary.files_copied, 2);
+        assert_eq!(fs.read("/dest/a").unwrap(), b"hello world");
+        assert_eq!(fs.read("/dest/dir/b").unwrap(), b"hello");
+
+        let (summary, _) = sync_memory(&fs, false).await;
+        assert_eq!(summary.files_copied, 0);
+        assert_eq!(summary.files_skipped, 2);
+    }
+
+    #[tokio::test]
+    async fn test_sync_short_copy() {
+        let fs = memory_fs(&[("/src/a", "hello world"), ("/src/b", "hello world")]);
+        // The file shrinks between stat and copy.
+        fs.inject(Op::Read, Fault::Short(5), |p| p.ends_with("a"));
+
+        let (summary, _) = sync_memory(&fs, false).await;
+        assert_eq!(summary.files_copied, 1);
+        assert_eq!(summary.files_failed, 1);
+        assert_eq!(summary.failed_paths.len(), 1);
+        assert!(
+            summary.failed_paths[0].1.starts_with("Short copy"),
+            "{:?}",
+            summary.failed_paths
+        );
+        assert!(fs.read("/dest/a").is_none());
+        assert!(partials(&fs).is_empty(), "{:?}", partials(&fs));
+    }
+
+    #[tokio::test]
+    async fn test_sync_permission_denied() {
+        let fs = memory_fs(&[
+            ("/src/ok", "hello world"),
+            ("/src/locked/file", "hello world"),
+            ("/src/unreadable/file", "hello world"),
+        ]);
+        fs.inject(
+            Op::Create,
+            Fault::Error(std::io::ErrorKind::PermissionDenied),
+            |p| p.starts_with("/dest/locked"),
+        );
+        fs.inject(
+            Op::ReadDir,
+            Fault::Error(std::io::ErrorKind::PermissionDenied),
+            |p| p == Path::new("/src/unreadable"),
+        );
+
+        let (summary, errors) = sync_memory(&fs, false).await;
+        assert_eq!(summary.files_copied, 1);
+        assert_eq!(summary.files_failed, 1);
+        assert!(fs.read("/dest/ok").is_some());
+        assert!(fs.read("/dest/locked/file").is_none());
+        // Copy errors are reported too, and also listed in the summary.
+        assert_eq!(
+            errors,
+            [
+                "Failed to stat /src/unreadable (during discovery)",
+                "Failed to copy /src/locked/file to /dest/locked/file (during copy)"
+            ]
+        );
+        assert!(summary
+            .failed_paths
+            .iter()
+            .any(|(path, _)| path == Path::new("/src/locked/file")));
+        assert!(partials(&fs).is_empty(), "{:?}", partials(&fs));
+    }
+
+    #[tokio::test]
+    async fn test_sync_cancelled_mid_copy() {
+        let fs = memory_fs(&[("/src/a", "hello world"), ("/src/b", "hello world")]);
+        fs.inject(Op::Write, Fault::Stall, |p| p.starts_with("/dest"));
+
+        let (summary, _) = sync_memory(&fs, true).await;
+        assert_eq!(summary.files_copied, 0);
+        assert!(fs.read("/dest/a").is_none());
+        assert!(fs.read("/dest/b").is_none());
+        assert!(partials(&fs).is_empty(), "{:?}", partials(&fs));
+    }
+
+    #[tokio::test]
+    async fn test_sync_error_context() {
+        let fs = memory_fs(&[
+            ("/src/locked/file", "hello world"),
+            ("/src/unreadable/file", "hello world"),
+        ]);
+        fs.inject(
+            Op::Create,
+            Fault::Error(std::io::ErrorKind::PermissionDenied),
+            |p| p.starts_with("/dest/locked"),
+        );
+        fs.inject(
+            Op::ReadDir,
+            Fault::Error(std::io::ErrorKind::PermissionDenied),
+            |p| p == Path::new("/src/unreadable"),
+        );
+
+        let errors = Mutex::new(Vec::new());
+        SyncFS::with_backend(fs, ["/src"], "/dest", 1, SyncOptions::default())
+            .sync(
+                |_, _| {},
+                &|e| errors.lock().unwrap().push((e.phase, e.path.clone())),
+                &CancellationToken::new(),
+            )
+            .await;
+        assert_eq!(
+            errors.into_inner().unwrap(),
+            [
+                (Phase::Discovery, Some(PathBuf::from("/src/unreadable"))),
+                (Phase::Copy, Some(PathBuf::from("/src/locked/file"))),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sync_fail_fast() {
+        for failure_mode in [FailureMode::Continue, FailureMode::FailFast] {
+            let fs = memory_fs(&[
+                ("/src/a", "hello world"),
+                ("/src/b", "hello world"),
+                ("/src/c", "hello world"),
+            ]);
+            fs.inject(
+                Op::Create,
+                Fault::Error(std::io::ErrorKind::PermissionDenied),
+                |p| p.to_string_lossy().contains("a.partial-"),
+            );
+            if failure_mode == FailureMode::FailFast {
+                // The other copies only end when the failure cancels them.
+                fs.inject(Op::Write, Fault::Stall, |p| p.starts_with("/dest"));
+            }
+
+            let milestones = Mutex::new(Vec::new());
+            let summary = SyncFS::with_backend(
+                fs.clone(),
+                ["/src"],
+                "/dest",
+                1,
+                SyncOptions {
+                    failure_mode,
+                    ..SyncOptions::default()
+                },
+            )
+            .sync(
+                |_, ms| {
+                    if let Some(ms) = ms {
+                        milestones.lock().unwrap().push(format!("{:?}", ms));
+                    }
+                },
+                &|_| {},
+                &CancellationToken::new(),
+            )
+            .await;
+
+            // Copies interrupted by the failure are counted as failed, but not reported.
+            assert_eq!(summary.failed_paths.len(), 1, "{:?}", summary);
+            assert!(summary.has_failures());
+            assert!(partials(&fs).is_empty(), "{:?}", partials(&fs));
+            let milestones = milestones.into_inner().unwrap();
+            match failure_mode {
+                FailureMode::Continue => {
+                    assert_eq!(summary.files_copied, 2);
+                    assert!(summary.aborted.is_none());
+                    assert_eq!(milestones.last().unwrap(), "CopyComplete");
+                }
+                FailureMode::FailFast => {
+                    assert_eq!(summary.files_copied, 0);
+                    assert!(summary
+                        .aborted
+                        .as_deref()
+                        .is_some_and(|e| e.contains("/src/a")));
+                    assert_eq!(milestones.last().unwrap(), "Cancelled");
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_shared_semaphore() {
+        let fs = MemoryFs::default();
+        for name in ["a", "b", "c"] {
+            fs.write(Path::new("/src1").join(name), "hello world");
+            fs.write(Path::new("/src2").join(name), "hello world");
+        }
+        // Copies hold their permits until cancelled.
+        fs.inject(Op::Write, Fault::Stall, |_| true);
+
+        let limit = SharedLimit::default();
+        assert!(limit.set(Some(2)));
+        let semaphore = limit.semaphore().unwrap();
+        assert!(!limit.set(Some(2)));
+        assert!(Arc::ptr_eq(&semaphore, &limit.semaphore().unwrap()));
+
+        let cancel = CancellationToken::new();
+        let sync = |src: &str, dest: &str| {
+            SyncFS::build(
+                fs.clone(),
+                [src],
+                dest,
+                4,
+                SyncOptions::default(),
+                limit.semaphore(),
+            )
+        };
+        let (first, second) = (sync("/src1", "/dest1"), sync("/src2", "/dest2"));
+        let in_flight = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let in_flight = partials(&fs).len();
+            cancel.cancel();
+            in_flight
+        };
+        let (_, _, in_flight) = tokio::join!(
+            first.sync(|_, _| {}, &|_| {}, &cancel),
+            second.sync(|_, _| {}, &|_| {}, &cancel),
+            in_flight
+        );
+        assert_eq!(in_flight, 2);
+        assert_eq!(semaphore.available_permits(), 2);
+
+        limit.set(Some(3));
+        assert!(!Arc::ptr_eq(&semaphore, &limit.semaphore().unwrap()));
+        limit.set(None);
+        assert!(limit.semaphore().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_progress_snapshot() {
+        let fs = memory_fs(&[
+            ("/src/a", "hello world"),
+            ("/src/b", "hello"),
+            ("/src/c", "hi"),
+            ("/dest/c", "hi"),
+        ]);
+        fs.inject(
+            Op::Create,
+            Fault::Error(std::io::ErrorKind::PermissionDenied),
+            |p| p.to_string_lossy().contains("b.partial-"),
+        );
+
+        let last = Mutex::new(None);
+        SyncFS::with_backend(fs.clone(), ["/src"], "/dest", 1, SyncOptions::default())
+            .sync(
+                |gp, ms| {
+                    if ms.is_some() {
+                        *last.lock().unwrap() = Some(gp.snapshot());
+                    }
+                },
+                &|_| {},
+                &CancellationToken::new(),
+            )
+            .await;
+
+        let count = |done, skipped, failed| ProgressTIDSF {
+            total: 3,
+            in_progress: 0,
+            done,
+            skipped,
+            failed,
+        };
+        assert_eq!(
+            last.into_inner().unwrap().unwrap(),
+            ProgressSnapshot {
+                files: count(1, 1, 1),
+                bytes: ProgressTIDSF {
+                    total: 18,
+                    ..count(11, 2, 0)
+                },
+                estimated_files: None,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sync_locked_source() {
+        for retry_locked in [false, true] {
+            let fs = memory_fs(&[
+                ("/src/ok", "hello world"),
+                ("/src/locked", "hello world"),
+                ("/src/busy", "hello world"),
+            ]);
+            let released = Arc::new(std::sync::atomic::AtomicBool::new(false));
+            fs.inject(
+                Op::Open,
+                Fault::Error(std::io::ErrorKind::WouldBlock),
+                move |p| {
+                    p.ends_with("locked")
+                        || (p.ends_with("busy") && !released.swap(true, Ordering::SeqCst))
+                },
+            );
+
+            let summary = SyncFS::with_backend(
+                fs.clone(),
+                ["/src"],
+                "/dest",
+                2,
+                SyncOptions {
+                    retry_locked,
+                    ..SyncOptions::default()
+                },
+            )
+            .sync(
+                |_, _| {},
+                &|e| panic!("Error occurred: {}", e),
+                &CancellationToken::new(),
+            )
+            .await;
+
+            assert!(!summary.has_failures(), "{:?}", summary);
+            let mut locked = summary
+                .locked_paths
+                .iter()
+                .map(|(path, _)| path.clone())
+                .collect::<Vec<_>>();
+            locked.sort();
+            if retry_locked {
+                assert_eq!(summary.files_copied, 2);
+                assert_eq!(summary.files_skipped, 1);
+                assert_eq!(locked, [PathBuf::from("/src/locked")]);
+                assert_eq!(fs.read("/dest/busy").unwrap(), b"hello world");
+            } else {
+                assert_eq!(summary.files_copied, 1);
+                assert_eq!(summary.files_skipped, 2);
+                assert_eq!(
+                    locked,
+                    [PathBuf::from("/src/busy"), PathBuf::from("/src/locked")]
+                );
+            }
+            assert!(fs.read("/dest/locked").is_none());
+        }
+
+        // Not being allowed to read a file is a failure, retrying it would not help.
+        let fs = MemoryFs::default();
+        fs.write("/src/denied", "hello world");
+        fs.inject(
+            Op::Open,
+            Fault::Error(std::io::ErrorKind::PermissionDenied),
+            |p| p.ends_with("denied"),
+        );
+        let (summary, errors) = sync_memory(&fs, false).await;
+        assert_eq!(summary.files_failed, 1, "{:?}", summary);
+        assert!(summary.locked_paths.is_empty());
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+    }
+
+    #[tokio::test]
+    async fn test_sync_timeout() {
+        let fs = MemoryFs::default();
+        fs.write("/src/a", "hello world");
+        fs.inject(Op::Write, Fault::Stall, |p| p.starts_with("/dest"));
+
+        let milestones = Mutex::new(Vec::new());
+        let summary = SyncFS::with_backend(
+            fs.clone(),
+            ["/src"],
+            "/dest",
+            1,
+            SyncOptions {
+                timeout: Some(Duration::from_millis(50)),
+                ..SyncOptions::default()
+            },
+        )
+        .sync(
+            |_, ms| {
+                if let Some(ms) = ms {
+                    milestones.lock().unwrap().push(format!("{:?}", ms));
+                }
+            },
+            &|_| {},
+            &CancellationToken::new(),
+        )
+        .await;
+
+        assert_eq!(summary.aborted.as_deref(), Some("Timed out after 50ms"));
+        assert!(summary.has_failures(), "{:?}", summary);
+        assert_eq!(summary.files_copied, 0);
+        assert!(partials(&fs).is_empty(), "{:?}", partials(&fs));
+        assert_eq!(
+            milestones.into_inner().unwrap().last().map(String::as_str),
+            Some("Cancelled")
+        );
+
+        // Finishing in time is not a timeout.
+        let fs = MemoryFs::default();
+        fs.write("/src/a", "hello world");
+        let summary = SyncFS::with_backend(
+            fs,
+            ["/src"],
+            "/dest",
+            1,
+            SyncOptions {
+                timeout: Some(Duration::from_secs(60)),
+                ..SyncOptions::default()
+            },
+        )
+        .sync(|_, _| {}, &|_| {}, &CancellationToken::new())
+        .await;
+        assert!(summary.aborted.is_none());
+        assert!(!summary.has_failures());
+        assert_eq!(summary.files_copied, 1);
+    }
+
+    #[tokio::test]
+    async fn test_sync_normalize_names() {
+        let sync = |fs: &MemoryFs, normalize_names| {
+            let sync = SyncFS::with_backend(
+                fs.clone(),
+                ["/src"],
+                "/dest",
+                1,
+                SyncOptions {
+                    normalize_names,
+                    ..SyncOptions::default()
+                },
+            );
+            async move {
+                sync.sync(|_, _| {}, &|e| panic!("{:?}", e), &CancellationToken::new())
+                    .await
+            }
+        };
+        let (nfd, nfc) = ("/src/cafe\u{301}/cafe\u{301}", "/dest/caf\u{e9}/caf\u{e9}");
+
+        let fs = MemoryFs::default();
+        fs.write(nfd, "hello world");
+        let summary = sync(&fs, NameNormalization::Nfc).await;
+        assert_eq!(summary.files_copied, 1);
+        assert_eq!(fs.read(nfc).as_deref(), Some(&b"hello world"[..]));
+
+        // A copy left in another form is the same file, not a missing one.
+        let fs = MemoryFs::default();
+        fs.write(nfd, "hello world");
+        assert_eq!(sync(&fs, NameNormalization::Off).await.files_copied, 1);
+        let summary = sync(&fs, NameNormalization::Nfc).await;
+        assert_eq!((summary.files_copied, summary.files_skipped), (0, 1));
+        assert!(fs.read(nfc).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sync_rename_crosses_devices() {
+        let fs = memory_fs(&[("/src/a", "hello world"), ("/dest/a", "old")]);
+        // Only the first partial copy is on another file system.
+        let first = Mutex::new(None);
+        fs.inject(
+            Op::Rename,
+            Fault::Error(std::io::ErrorKind::CrossesDevices),
+            move |p| {
+                let mut first = first.lock().unwrap();
+                first.get_or_insert_with(|| p.to_path_buf()) == p
+            },
+        );
+
+        let summary = SyncFS::with_backend(
+            fs.clone(),
+            ["/src"],
+            "/dest",
+            1,
+            SyncOptions {
+                overwrite: OverwritePolicy::Always,
+                ..SyncOptions::default()
+            },
+        )
+        .sync(|_, _| {}, &|e| panic!("{:?}", e), &CancellationToken::new())
+        .await;
+
+        assert_eq!(summary.files_copied, 1);
+        assert_eq!(fs.read("/dest/a").as_deref(), Some(&b"hello world"[..]));
+        assert!(partials(&fs).is_empty(), "{:?}", partials(&fs));
+    }
+
+    #[tokio::test]
+    async fn test_sync_preserve_hard_links() {
+        for (preserve_hard_links, link_fails) in [(true, false), (true, true), (false, false)] {
+            let fs = MemoryFs::default();
+            fs.write("/src/a", "hello world");
+            fs.create_dir_all(Path::new("/src/sub")).await.unwrap();
+            fs.hard_link(Path::new("/src/a"), Path::new("/src/sub/b"))
+                .await
+                .unwrap();
+            if link_fails {
+                fs.inject(
+                    Op::Link,
+                    Fault::Error(std::io::ErrorKind::Unsupported),
+                    |_| true,
+                );
+            }
+
+            let summary = SyncFS::with_backend(
+                fs.clone(),
+                ["/src"],
+                "/dest",
+                2,
+                SyncOptions {
+                    preserve_hard_links,
+                    ..SyncOptions::default()
+                },
+            )
+            .sync(|_, _| {}, &|e| panic!("{:?}", e), &CancellationToken::new())
+            .await;
+
+            let linked = preserve_hard_links && !link_fails;
+            assert_eq!(summary.files_copied, 2);
+            assert_eq!(summary.files_linked, u64::from(linked));
+            assert_eq!(summary.bytes_copied, if linked { 11 } else { 22 });
+            assert_eq!(fs.read("/dest/sub/b").as_deref(), Some(&b"hello world"[..]));
+            let id = |p: &str| {
+                let fs = fs.clone();
+                let p = PathBuf::from(p);
+                async move { fs.metadata(&p).await.unwrap().id }
+            };
+            assert_eq!(id("/dest/a").await == id("/dest/sub/b").await, linked);
+            assert!(partials(&fs).is_empty(), "{:?}", partials(&fs));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_respect_syncignore() {
+        for respect_syncignore in [true, false] {
+            let fs = memory_fs(&[
+                ("/src/.syncignore", "*.tmp\nbuild/\n"),
+                ("/src/a.txt", "a"),
+                ("/src/a.tmp", "a"),
+                ("/src/build/out", "out"),
+                ("/src/sub/.syncignore", "!keep.tmp\n"),
+                ("/src/sub/keep.tmp", "keep"),
+                ("/src/sub/x.tmp", "x"),
+                ("/src/bad/.syncignore", "[z-a]\n"),
+                ("/src/bad/b.tmp", "b"),
+            ]);
+
+            let errors = Mutex::new(Vec::new());
+            SyncFS::with_backend(
+                fs.clone(),
+                ["/src"],
+                "/dest",
+                1,
+                SyncOptions {
+                    respect_syncignore,
+                    ..SyncOptions::default()
+                },
+            )
+            .sync(
+                |_, _| {},
+                &|e| errors.lock().unwrap().push(e.error.to_string()),
+                &CancellationToken::new(),
+            )
+            .await;
+
+            let copied = |p: &str| fs.read(Path::new("/dest").join(p)).is_some();
+            assert!(copied("a.txt"));
+            assert!(copied(".syncignore"));
+            assert!(copied("sub/keep.tmp"));
+            assert_eq!(copied("a.tmp"), !respect_syncignore);
+            assert_eq!(copied("build/out"), !respect_syncignore);
+            assert_eq!(copied("sub/x.tmp"), !respect_syncignore);
+            // Rules that fail to parse leave the parent rules in place.
+            assert_eq!(copied("bad/b.tmp"), !respect_syncignore);
+            let errors = errors.into_inner().unwrap();
+            if respect_syncignore {
+                assert_eq!(
+                    errors,
+                    ["Failed to read the ignore rules in /src/bad/.syncignore"]
+                );
+            } else {
+                assert!(errors.is_empty(), "{:?}", errors);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_disk_full() {
+        let fs = MemoryFs::default();
+        for name in ["a", "b", "c"] {
+            fs.write(format!("/src/{}", name), "hello world");
+        }
+        fs.inject(
+            Op::Write,
+            Fault::Error(std::io::ErrorKind::StorageFull),
+            |_| true,
+        );
+
+        let errors = Mutex::new(Vec::new());
+        let summary =
+            SyncFS::with_backend(fs.clone(), ["/src"], "/dest", 1, SyncOptions::default())
+                .sync(
+                    |_, _| {},
+                    &|e| {
+                        if !matches!(e.error, SyncError::Cancelled) {
+                            errors.lock().unwrap().push(e.error.to_string());
+                        }
+                    },
+                    &CancellationToken::new(),
+                )
+                .await;
+
+        let errors = errors.into_inner().unwrap();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(
+            errors[0].starts_with("Destination is full"),
+            "{}",
+            errors[0]
+        );
+        assert_eq!(summary.files_copied, 0);
+        assert_eq!(summary.failed_paths.len(), 1);
+        assert_eq!(summary.aborted.as_deref(), Some(errors[0].as_str()));
+        assert!(partials(&fs).is_empty(), "{:?}", partials(&fs));
+    }
+
+    #[tokio::test]
+    async fn test_sync_max_depth() {
+        let fs = memory_fs(&[
+            ("/src/f0", "0"),
+            ("/src/d1/f1", "1"),
+            ("/src/d1/d2/f2", "2"),
+            ("/src/d1/d2/d3/f3", "3"),
+            ("/src/d1/d2/d3/d4/f4", "4"),
+        ]);
+
+        let summary = SyncFS::with_backend(
+            fs.clone(),
+            ["/src"],
+            "/dest",
+            1,
+            SyncOptions {
+                max_depth: Some(2),
+                ..SyncOptions::default()
+            },
+        )
+        .sync(|_, _| {}, &|e| panic!("{:?}", e), &CancellationToken::new())
+        .await;
+
+        assert_eq!(summary.files_copied, 3);
+        for copied in ["f0", "d1/f1", "d1/d2/f2"] {
+            assert!(
+                fs.read(Path::new("/dest").join(copied)).is_some(),
+                "{}",
+                copied
+            );
+        }
+        assert!(fs
+            .symlink_metadata(Path::new("/dest/d1/d2/d3"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sync_dry_run() {
+        let fs = MemoryFs::default();
+        let sync = |options| {
+            let fs = fs.clone();
+            async move {
+                SyncFS::with_backend(fs, ["/src"], "/dest", 1, options)
+                    .sync(|_, _| {}, &|e| panic!("{:?}", e), &CancellationToken::new())
+                    .await
+            }
+        };
+        fs.write("/src/c", "up to date");
+        assert_eq!(sync(SyncOptions::default()).await.files_copied, 1);
+
+        fs.write("/src/a", "hello");
+        fs.write("/src/d/b", "world!");
+        let before = fs.paths();
+        let summary = sync(SyncOptions {
+            dry_run: true,
+            prune_empty_dirs: true,
+            ..SyncOptions::default()
         })
+        .await;
+
+        assert_eq!(summary.files_copied, 2);
+        assert_eq!(summary.bytes_copied, 11);
+        assert_eq!(summary.files_skipped, 1);
+        assert_eq!(summary.dirs_pruned, 0);
+        assert_eq!(fs.paths(), before);
     }
-    /// Synchronize the two directories, the Future will resolve when the synchronization is complete.
-    ///
-    /// Progress will be periodically reported to the `progress_fn` callback.
-    /// Errors will be reported to the `error_fn` callback.
-    pub async fn sync<F: Fn(&GlobalProgress, Option<ProgressMilestone>), EF: Fn(&SyncError)>(
-        &self,
-        progress_fn: F,
-        error_fn: &EF,
-    ) {
-        let (tx, rx) = flume::bounded(2048);
 
-        let mut js = JoinSet::new();
+    #[tokio::test]
+    async fn test_sync_pre_scan() {
+        let fs = memory_fs(&[
+            ("/src/f0", "0"),
+            ("/src/d1/f1", "1"),
+            ("/src/d1/d2/f2", "2"),
+            ("/src/d1/d2/f3", "3"),
+        ]);
 
-        tokio::join!(async move { self.walk(PathBuf::new(), &tx).await }, async {
-            loop {
-                match rx.recv_async().await {
-                    Ok(Ok((src, dest))) => {
-                        let ctx_clone = self.ctx.clone();
-                        js.spawn(async move {
-                            copy_file(
-                                src.clone(),
-                                dest.clone(),
-                                src.clone(),
-                                Some(&ctx_clone.semaphore),
-                                &ctx_clone.progress,
-                                &|k, prog| {
-                                    println!("File: {:?} - {}/{}", k, prog.done, prog.total);
-                                },
-                            )
-                            .await
-                            .map(|_| (src, dest))
-                        });
-                    }
-                    Ok(Err(e)) => {
-                        println!("Error occurred during discovery: {}", e);
-                        error_fn(&e);
-                        self.ctx
-                            .progress
-                            .files
-                            .total
-                            .fetch_add(1, Ordering::Relaxed);
-                        self.ctx
-                            .progress
-                            .files
-                            .failed
-                            .fetch_add(1, Ordering::Relaxed);
-                        continue;
-                    }
-                    Err(RecvError::Disconnected) => {
-                        return;
+        for (max_depth, estimate) in [(None, 4), (Some(1), 2)] {
+            let milestones = Mutex::new(Vec::new());
+            let summary = SyncFS::with_backend(
+                fs.clone(),
+                ["/src"],
+                "/dest",
+                1,
+                SyncOptions {
+                    pre_scan: true,
+                    max_depth,
+                    ..SyncOptions::default()
+                },
+            )
+            .sync(
+                |gp, ms| {
+                    if let Some(ms) = ms {
+                        let estimated = gp.snapshot().estimated_files;
+                        milestones.lock().unwrap().push((ms, estimated));
                     }
+                },
+                &|e| panic!("{:?}", e),
+                &CancellationToken::new(),
+            )
+            .await;
+
+            assert_eq!(summary.files_copied + summary.files_skipped, estimate);
+            let milestones = milestones.into_inner().unwrap();
+            assert!(
+                matches!(
+                    milestones[..],
+                    [
+                        (ProgressMilestone::PreScanComplete, Some(a)),
+                        (ProgressMilestone::DiscoveryComplete, None),
+                        (ProgressMilestone::CopyComplete, None),
+                    ] if a == estimate
+                ),
+                "{:?}",
+                milestones
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_copy_order() {
+        for (copy_order, expected) in [
+            (CopyOrder::Alphabetical, ["a", "b", "c", "x"]),
+            (CopyOrder::SmallestFirst, ["b", "c", "a", "x"]),
+            (CopyOrder::LargestFirst, ["x", "a", "c", "b"]),
+        ] {
+            let fs = memory_fs(&[
+                ("/src/a", "aaa"),
+                ("/src/b", "b"),
+                ("/src/c", "cc"),
+                ("/src/d/x", "xxxx"),
+            ]);
+            let created = Arc::new(Mutex::new(Vec::new()));
+            fs.inject(Op::Create, Fault::Stall, {
+                let created = created.clone();
+                move |p| {
+                    let name = p.file_name().unwrap().to_string_lossy();
+                    created.lock().unwrap().push(name[1..2].to_string());
+                    false
                 }
-            }
-        });
+            });
 
-        progress_fn(
-            &self.ctx.progress,
-            Some(ProgressMilestone::DiscoveryComplete),
-        );
+            let summary = SyncFS::with_backend(
+                fs.clone(),
+                ["/src"],
+                "/dest",
+                1,
+                SyncOptions {
+                    copy_order,
+                    ..SyncOptions::default()
+                },
+            )
+            .sync(|_, _| {}, &|e| panic!("{:?}", e), &CancellationToken::new())
+            .await;
 
-        let total = js.len();
-        let one_pct = std::cmp::max(1, total / 100);
-        let mut last_reported = 0;
-        let mut completed = 0;
+            assert_eq!(summary.files_copied, 4);
+            assert_eq!(*created.lock().unwrap(), expected, "{:?}", copy_order);
+        }
+    }
 
-        while let Some(result) = js.join_next().await {
-            completed += 1;
-            if completed - last_reported >= one_pct {
-                progress_fn(&self.ctx.progress, None);
-                last_reported = js.len();
-            }
+    #[tokio::test]
+    async fn test_sync_stat_timeout() {
+        let fs = memory_fs(&[("/src/a", "a"), ("/src/dead/b", "b")]);
+        fs.inject(Op::ReadDir, Fault::Stall, |p| p == Path::new("/src/dead"));
 
-            match result {
-                Ok(Ok(_)) => {}
-                Ok(Err(e)) => {
-                    println!("Error occurred during copy: {}", e);
-                    continue;
-                }
-                Err(e) => {
-                    if e.is_cancelled() {
-                        error_fn(&SyncError::Cancelled);
-                    } else {
-                        error_fn(&SyncError::JoinError(e));
+        let errors = Mutex::new(Vec::new());
+        let summary = SyncFS::with_backend(
+            fs.clone(),
+            ["/src"],
+            "/dest",
+            1,
+            SyncOptions {
+                stat_timeout: Some(Duration::from_millis(50)),
+                ..SyncOptions::default()
+            },
+        )
+        .sync(
+            |_, _| {},
+            &|e| errors.lock().unwrap().push(e.path.clone()),
+            &CancellationToken::new(),
+        )
+        .await;
+
+        assert_eq!(summary.files_copied, 1);
+        assert_eq!(
+            errors.into_inner().unwrap(),
+            [Some(PathBuf::from("/src/dead"))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sync_small_channel() {
+        let fs = MemoryFs::default();
+        for i in 0..200 {
+            fs.write(format!("/src/f{:03}", i), "x");
+        }
+        let options = SyncOptions {
+            channel_capacity: 2,
+            ..SyncOptions::default()
+        };
+
+        // Discovery gets through every file while the only copy slot is stuck.
+        fs.inject(Op::Write, Fault::Stall, |p| p.starts_with("/dest"));
+        let cancel = CancellationToken::new();
+        let summary = tokio::time::timeout(
+            Duration::from_secs(10),
+            SyncFS::with_backend(fs.clone(), ["/src"], "/dest", 1, options.clone()).sync(
+                |gp, milestone| {
+                    if matches!(milestone, Some(ProgressMilestone::DiscoveryComplete)) {
+                        assert_eq!(gp.files.total.load(Ordering::Relaxed), 200);
+                        cancel.cancel();
                     }
+                },
+                &|_| {},
+                &cancel,
+            ),
+        )
+        .await
+        .expect("discovery blocked by a stalled copy");
+        assert_eq!(summary.files_copied, 0);
+
+        let fs = MemoryFs::default();
+        for i in 0..200 {
+            fs.write(format!("/src/f{:03}", i), "x");
+        }
+        let summary = tokio::time::timeout(
+            Duration::from_secs(10),
+            SyncFS::with_backend(fs.clone(), ["/src"], "/dest", 1, options).sync(
+                |_, _| {},
+                &|e| panic!("{:?}", e),
+                &CancellationToken::new(),
+            ),
+        )
+        .await
+        .expect("sync did not finish");
+        assert_eq!(summary.files_copied, 200);
+    }
+
+    #[tokio::test]
+    async fn test_sync_stats_source_once() {
+        let fs = MemoryFs::default();
+        fs.write("/src/a", "hello");
+        let stats = Arc::new(AtomicU64::new(0));
+        fs.inject(Op::Metadata, Fault::Stall, {
+            let stats = stats.clone();
+            move |p| {
+                if p == Path::new("/src/a") {
+                    stats.fetch_add(1, Ordering::Relaxed);
                 }
+                false
             }
-        }
+        });
+
+        let summary =
+            SyncFS::with_backend(fs.clone(), ["/src"], "/dest", 1, SyncOptions::default())
+                .sync(|_, _| {}, &|e| panic!("{:?}", e), &CancellationToken::new())
+                .await;
 
-        progress_fn(&self.ctx.progress, Some(ProgressMilestone::CopyComplete));
+        assert_eq!(summary.files_copied, 1);
+        assert_eq!(stats.load(Ordering::Relaxed), 1);
     }
-}
 
-async fn cmp_file(dest: PathBuf, src: PathBuf) -> Result<bool, tokio::io::Error> {
-    let dest_meta = tokio::fs::metadata(&dest).await?;
-    let src_meta = tokio::fs::metadata(&src).await?;
+    #[tokio::test]
+    async fn test_copy_file_changed_since_walk() {
+        let fs = MemoryFs::default();
+        fs.write("/src/a", "hello");
+        let walked = fs.metadata(Path::new("/src/a")).await.unwrap();
+        fs.write("/src/a", "grown since");
+        fs.create_dir_all(Path::new("/dest")).await.unwrap();
 
-    if dest_meta.len() != src_meta.len() {
-        return Ok(false);
-    }
+        let result = copy_file(
+            "a",
+            PathBuf::from("/dest/a"),
+            PathBuf::from("/src/a"),
+            Some(walked),
+            &SyncFSCtx::new(fs.clone(), 1, SyncOptions::default()),
+            &|_, _| {},
+            &CancellationToken::new(),
+        )
+        .await;
 
-    if dest_meta.modified()? < src_meta.modified()? {
-        return Ok(false);
+        assert!(
+            matches!(
+                result,
+                Err(SyncError::ShortCopy {
+                    copied: 11,
+                    expected: 5,
+                    ..
+                })
+            ),
+            "{:?}",
+            result
+        );
+        assert!(fs.read("/dest/a").is_none());
+        assert!(partials(&fs).is_empty(), "{:?}", partials(&fs));
     }
 
-    Ok(true)
-}
-
-async fn copy_file<K: Hash + PartialEq + Unpin, F: Fn(&K, &FileProgress)>(
-    job_id: K,
-    dest: PathBuf,
-    src: PathBuf,
-    semaphore: Option<&Semaphore>,
-    progress: &GlobalProgress,
-    file_progress_callback: &F,
-) -> Result<u64, SyncError> {
-    let permit = match semaphore {
-        Some(s) => match s.acquire().await {
-            Ok(p) => Some(p),
-            Err(_) => {
-                progress.files.failed.fetch_add(1, Ordering::Relaxed);
-                return Err(SyncError::Cancelled);
+    #[tokio::test]
+    async fn test_sync_manifest() {
+        let fs = memory_fs(&[("/src/a", "hello"), ("/src/dir/b", "world")]);
+        let opens = Arc::new(AtomicU64::new(0));
+        fs.inject(Op::Open, Fault::Stall, {
+            let opens = opens.clone();
+            move |p| {
+                if p.starts_with("/src") {
+                    opens.fetch_add(1, Ordering::Relaxed);
+                }
+                false
             }
-        },
-        None => None,
-    };
+        });
+        let options = SyncOptions {
+            comparison: ComparisonFlags::SIZE | ComparisonFlags::HASH,
+            manifest: true,
+            ..SyncOptions::default()
+        };
+        let run = || async {
+            SyncFS::with_backend(fs.clone(), ["/src"], "/dest", 1, options.clone())
+                .sync(|_, _| {}, &|e| panic!("{:?}", e), &CancellationToken::new())
+                .await
+        };
 
-    let mut src_file = match File::open(&src).await {
-        Ok(f) => f,
-        Err(e) => {
-            progress.files.failed.fetch_add(1, Ordering::Relaxed);
-            return Err(SyncError::CopyFailed {
-                src: src.clone(),
-                dest,
-                err: e,
+        assert_eq!(run().await.files_copied, 2);
+        let manifest = fs
+            .read(Path::new("/dest").join(MANIFEST_FILE_NAME))
+            .unwrap();
+        let manifest: serde_json::Value = serde_json::from_slice(&manifest).unwrap();
+        let mut hasher = ContentHasher::new(HashAlgorithm::default());
+        hasher.update(b"hello");
+        assert_eq!(manifest["files"]["a"]["hash"], hasher.finalize());
+        assert!(manifest["files"]["dir/b"].is_object(), "{}", manifest);
+
+        // Unchanged files are vouched for by the manifest instead of being read again.
+        opens.store(0, Ordering::Relaxed);
+        assert_eq!(run().await.files_copied, 0);
+        assert_eq!(opens.load(Ordering::Relaxed), 0);
+
+        // A corrupt manifest falls back to hashing and is rewritten.
+        fs.write(Path::new("/dest").join(MANIFEST_FILE_NAME), "{not json");
+        assert_eq!(run().await.files_copied, 0);
+        assert_eq!(opens.load(Ordering::Relaxed), 2);
+        let manifest = fs
+            .read(Path::new("/dest").join(MANIFEST_FILE_NAME))
+            .unwrap();
+        serde_json::from_slice::<serde_json::Value>(&manifest).unwrap();
+        assert!(partials(&fs).is_empty(), "{:?}", partials(&fs));
+    }
+
+    #[tokio::test]
+    async fn test_sync_reflink() {
+        for supported in [true, false] {
+            let fs = memory_fs(&[("/src/a", "hello")]);
+            if !supported {
+                fs.inject(
+                    Op::Reflink,
+                    Fault::Error(std::io::ErrorKind::Unsupported),
+                    |_| true,
+                );
+            }
+            let writes = Arc::new(AtomicU64::new(0));
+            fs.inject(Op::Write, Fault::Stall, {
+                let writes = writes.clone();
+                move |_| {
+                    writes.fetch_add(1, Ordering::Relaxed);
+                    false
+                }
             });
-        }
-    };
+            let options = SyncOptions {
+                prefer_reflink: true,
+                ..SyncOptions::default()
+            };
 
-    let src_meta = src_file.metadata().await.map_err(|e| {
-        progress.files.failed.fetch_add(1, Ordering::Relaxed);
-        SyncError::StatFailed(src.clone(), e)
-    })?;
+            let summary = SyncFS::with_backend(fs.clone(), ["/src"], "/dest", 1, options)
+                .sync(|_, _| {}, &|e| panic!("{:?}", e), &CancellationToken::new())
+                .await;
 
-    let dst_file = std::pin::pin!(match File::create(&dest).await {
-        Ok(f) => f,
-        Err(e) => {
-            progress.files.failed.fetch_add(1, Ordering::Relaxed);
-            return Err(SyncError::CopyFailed { src, dest, err: e });
+            assert_eq!(summary.files_copied, 1);
+            assert_eq!(summary.bytes_copied, 5);
+            assert_eq!(fs.read("/dest/a").as_deref(), Some(&b"hello"[..]));
+            assert_eq!(writes.load(Ordering::Relaxed) == 0, supported);
+            assert!(partials(&fs).is_empty(), "{:?}", partials(&fs));
         }
-    });
 
-    let mut dest_write = TrackingAsyncWrite::new(
-        job_id,
-        src_meta.len(),
-        progress,
-        file_progress_callback,
-        dst_file,
-    );
+        // A clone of a source that grew since discovery is as short a copy as a streamed one.
+        let fs = MemoryFs::default();
+        fs.write("/src/a", "hello");
+        let options = SyncOptions {
+            prefer_reflink: true,
+            ..SyncOptions::default()
+        };
+        let (summary, progress, errors) =
+            sync_after_discovery(&fs, options, async { fs.write("/src/a", "hello world") }).await;
+        assert_eq!(summary.files_failed, 1, "{:?}", summary);
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(
+            errors[0].contains("copied 11 bytes, expected 5"),
+            "{}",
+            errors[0]
+        );
+        assert_eq!(progress.bytes.done, 0);
+        assert!(fs.read("/dest/a").is_none());
+        assert!(partials(&fs).is_empty(), "{:?}", partials(&fs));
+    }
+
+    #[tokio::test]
+    async fn test_sync_comparator() {
+        let fs = memory_fs(&[
+            ("/src/a.keep", "new"),
+            ("/src/b.log", "new"),
+            ("/dest/a.keep", "older"),
+            ("/dest/b.log", "older"),
+        ]);
 
-    // This already handles flushing the file so we don't need to do it again.
-    let result = tokio::io::copy(&mut src_file, &mut dest_write).await;
+        let keep = |_: &Path, dest: &Path, _: &Metadata, _: &Metadata| {
+            dest.extension().is_some_and(|ext| ext == "keep")
+        };
+        let summary =
+            SyncFS::with_backend(fs.clone(), ["/src"], "/dest", 1, SyncOptions::default())
+                .with_comparator(keep)
+                .sync(|_, _| {}, &|e| panic!("{:?}", e), &CancellationToken::new())
+                .await;
 
-    drop(permit);
+        assert_eq!(summary.files_copied, 1);
+        assert_eq!(fs.read("/dest/a.keep").as_deref(), Some(&b"older"[..]));
+        assert_eq!(fs.read("/dest/b.log").as_deref(), Some(&b"new"[..]));
 
-    match result {
-        Ok(written) => {
-            if written != src_meta.len() {
-                dest_write.revert_progress();
-                progress.files.failed.fetch_add(1, Ordering::Relaxed);
-                progress
-                    .bytes
-                    .failed
-                    .fetch_add(src_meta.len(), Ordering::Relaxed);
-                return Err(SyncError::ShortCopy {
-                    src,
-                    dest,
-                    copied: written,
-                    expected: src_meta.len(),
-                });
-            }
-            Ok(written)
-        }
-        Err(e) => {
-            progress.files.failed.fetch_add(1, Ordering::Relaxed);
-            Err(SyncError::CopyFailed { src, dest, err: e })
-        }
+        // Size only, whatever the modification times.
+        fs.write("/src/b.log", "one");
+        let size_only = FlagComparator::new(
+            ComparisonFlags::SIZE,
+            DEFAULT_MTIME_TOLERANCE,
+            HashAlgorithm::default(),
+        );
+        let summary =
+            SyncFS::with_backend(fs.clone(), ["/src"], "/dest", 1, SyncOptions::default())
+                .with_comparator(size_only)
+                .sync(|_, _| {}, &|e| panic!("{:?}", e), &CancellationToken::new())
+                .await;
+
+        assert_eq!(summary.files_copied, 1);
+        assert_eq!(fs.read("/dest/a.keep").as_deref(), Some(&b"new"[..]));
+        assert_eq!(fs.read("/dest/b.log").as_deref(), Some(&b"new"[..]));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    #[tokio::test]
+    async fn test_sync_resume() {
+        let fs = memory_fs(&[("/src/a", "a"), ("/src/dir/b", "b")]);
+        let journal = Path::new("/dest").join(JOURNAL_FILE_NAME);
+        let options = SyncOptions {
+            resume: true,
+            ..SyncOptions::default()
+        };
+        let compared = Arc::new(AtomicU64::new(0));
+        let sync = |options: SyncOptions, interrupt: bool| {
+            let compared = Arc::clone(&compared);
+            let token = CancellationToken::new();
+            let fs = fs.clone();
+            async move {
+                SyncFS::with_backend(fs, ["/src"], "/dest", 1, options)
+                    .with_comparator(move |_: &Path, _: &Path, _: &Metadata, _: &Metadata| {
+                        compared.fetch_add(1, Ordering::Relaxed);
+                        false
+                    })
+                    .sync(
+                        // Interrupted once everything is done, for the journal to list it all.
+                        |_, ms| {
+                            if interrupt && matches!(ms, Some(ProgressMilestone::CopyComplete)) {
+                                token.cancel();
+                            }
+                        },
+                        &|e| panic!("{:?}", e),
+                        &token,
+                    )
+                    .await
+            }
+        };
+
+        assert_eq!(sync(options.clone(), true).await.files_copied, 2);
+        assert!(fs.read(&journal).is_some());
+
+        // Files the journal lists are not compared, unless the source changed.
+        fs.write("/src/dir/b", "changed");
+        compared.store(0, Ordering::Relaxed);
+        let summary = sync(options.clone(), true).await;
+        assert_eq!((summary.files_skipped, summary.files_copied), (1, 1));
+        assert_eq!(compared.load(Ordering::Relaxed), 1);
+        assert_eq!(fs.read("/dest/dir/b").as_deref(), Some(&b"changed"[..]));
+
+        // A journal written with other options is ignored.
+        compared.store(0, Ordering::Relaxed);
+        let verify = SyncOptions {
+            verify: true,
+            ..options.clone()
+        };
+        sync(verify, true).await;
+        assert_eq!(compared.load(Ordering::Relaxed), 2);
+
+        // A run that completes removes it.
+        sync(options, false).await;
+        assert!(fs.read(&journal).is_none());
+    }
 
     #[tokio::test]
-    async fn test_copy_file() {
-        let tmp_dir = tempfile::tempdir().unwrap();
-        let src = tmp_dir.path().join("src");
-        let dest = tmp_dir.path().join("dest");
+    async fn test_sync_move_files() {
+        let fs = memory_fs(&[
+            ("/src/a", "hello"),
+            ("/src/dir/b", "world"),
+            ("/src/dir/c", "cut short"),
+            ("/src/done/d", "gone"),
+        ]);
+        fs.inject(Op::Read, Fault::Short(3), |p| p == Path::new("/src/dir/c"));
+        let options = SyncOptions {
+            move_files: true,
+            verify: true,
+            ..SyncOptions::default()
+        };
 
-        let mut src_file = File::create(&src).await.unwrap();
-        src_file.write_all(b"hello world").await.unwrap();
+        let errors = Mutex::new(Vec::new());
+        let summary = SyncFS::with_backend(fs.clone(), ["/src"], "/dest", 2, options)
+            .sync(
+                |_, _| {},
+                &|e| errors.lock().unwrap().push(e.path.clone()),
+                &CancellationToken::new(),
+            )
+            .await;
 
-        copy_file(
-            "test",
-            dest.clone(),
-            src.clone(),
-            None,
-            &GlobalProgress::default(),
-            &|_, _| {},
-        )
-        .await
-        .unwrap();
+        assert_eq!(summary.files_copied, 3);
+        assert_eq!(summary.files_failed, 1);
+        assert_eq!(summary.files_moved, 3);
+        assert_eq!(
+            errors.into_inner().unwrap(),
+            [Some(PathBuf::from("/src/dir/c"))]
+        );
+        // Only the failed copy is left, with the directory holding it and the root.
+        let src = fs
+            .paths()
+            .into_iter()
+            .filter(|p| p.starts_with("/src"))
+            .collect::<Vec<_>>();
+        assert_eq!(src, ["/src", "/src/dir", "/src/dir/c"].map(PathBuf::from));
+        assert_eq!(fs.read("/dest/a").as_deref(), Some(&b"hello"[..]));
+        assert_eq!(fs.read("/dest/dir/b").as_deref(), Some(&b"world"[..]));
+        assert_eq!(fs.read("/dest/done/d").as_deref(), Some(&b"gone"[..]));
+        assert!(fs.read("/dest/dir/c").is_none());
+    }
 
-        let mut dest_file = File::open(&dest).await.unwrap();
-        let mut buf = Vec::new();
-        dest_file.read_to_end(&mut buf).await.unwrap();
+    #[tokio::test]
+    async fn test_sync_pause() {
+        let fs = MemoryFs::default();
+        for name in ["a", "b", "c"] {
+            fs.write(Path::new("/src").join(name), name);
+        }
+        let pause = PauseHandle::default();
+        pause.pause();
+        let discovered = AtomicBool::new(false);
+        let cancel = CancellationToken::new();
+        let sync = SyncFS::with_backend(fs.clone(), ["/src"], "/dest", 1, SyncOptions::default())
+            .with_pause(pause.clone());
 
-        assert_eq!(buf, b"hello world");
+        let control = async {
+            while !discovered.load(Ordering::Relaxed) {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            // Discovery is done, but nothing is copied until resumed.
+            assert!(fs.read("/dest/a").is_none());
+            assert!(pause.is_paused());
+            pause.resume();
+        };
+        let (summary, ()) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(
+                sync.sync(
+                    |gp, ms| {
+                        if matches!(ms, Some(ProgressMilestone::DiscoveryComplete)) {
+                            assert_eq!(gp.files.total.load(Ordering::Relaxed), 3);
+                            discovered.store(true, Ordering::Relaxed);
+                        }
+                    },
+                    &|e| panic!("{:?}", e),
+                    &cancel,
+                ),
+                control
+            )
+        })
+        .await
+        .expect("sync did not resume");
+
+        assert_eq!(summary.files_copied, 3);
+        assert_eq!(fs.read("/dest/c").as_deref(), Some(&b"c"[..]));
     }
 
     #[tokio::test]
-    async fn test_sync() {
-        let tmp_dir = tempfile::tempdir().unwrap();
-        let src = tmp_dir.path().join("src");
-        let dest = tmp_dir.path().join("dest");
+    async fn test_sync_pause_while_copying() {
+        let fs = MemoryFs::default();
+        for name in ["a", "b", "c"] {
+            fs.write(Path::new("/src").join(name), [0; 100]);
+        }
+        let pause = PauseHandle::default();
+        // Slow enough for the other copies to be waiting for the permit of the first one.
+        let options = SyncOptions {
+            max_bytes_per_sec: Some(1000),
+            ..SyncOptions::default()
+        };
+        let sync = SyncFS::with_backend(fs.clone(), ["/src"], "/dest", 1, options)
+            .with_pause(pause.clone());
+        let copied = || {
+            ["a", "b", "c"]
+                .into_iter()
+                .filter(|name| fs.read(Path::new("/dest").join(name)).is_some())
+                .count()
+        };
 
-        let src_subdir = src.join("subdir");
-        let dest_subdir = dest.join("subdir");
-        tokio::fs::create_dir_all(&src_subdir).await.unwrap();
+        let control = async {
+            while partials(&fs).is_empty() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            pause.pause();
+            while copied() == 0 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            // The copy already started finished, but no other one starts until resumed.
+            assert_eq!(copied(), 1);
+            assert!(partials(&fs).is_empty());
+            pause.resume();
+        };
+        let cancel = CancellationToken::new();
+        let (summary, ()) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(
+                sync.sync(|_, _| {}, &|e| panic!("{:?}", e), &cancel),
+                control
+            )
+        })
+        .await
+        .expect("sync did not resume");
 
-        let src_file = src.join("file");
-        let dest_file = dest.join("file");
-        tokio::fs::write(&src_file, b"hello world").await.unwrap();
+        assert_eq!(summary.files_copied, 3);
+        assert_eq!(copied(), 3);
+    }
 
-        let src_subfile = src_subdir.join("subfile");
-        let dest_subfile = dest_subdir.join("subfile");
-        tokio::fs::write(&src_subfile, b"goodbye world")
+    #[tokio::test]
+    async fn test_sync_handle() {
+        let fs = MemoryFs::default();
+        for name in ["a", "b", "c"] {
+            fs.write(Path::new("/src").join(name), name);
+        }
+        let (handle, sync) =
+            SyncFS::with_backend(fs.clone(), ["/src"], "/dest", 1, SyncOptions::default()).start();
+        handle.pause();
+        let sync = tokio::spawn(sync);
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while handle.progress_snapshot().files.total < 3 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .expect("discovery did not complete");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(handle.progress_snapshot().files.done, 0);
+        assert!(fs.read("/dest/a").is_none());
+
+        handle.resume();
+        let summary = tokio::time::timeout(Duration::from_secs(5), sync)
             .await
+            .expect("sync did not resume")
             .unwrap();
+        assert_eq!(summary.files_copied, 3);
+        assert_eq!(handle.progress_snapshot().files.done, 3);
 
-        let sync = SyncFS::new(&src, &dest, 1);
+        let (handle, sync) =
+            SyncFS::with_backend(fs.clone(), ["/src"], "/other", 1, SyncOptions::default()).start();
+        handle.cancel();
+        let summary = sync.await;
+        assert!(handle.is_cancelled());
+        assert_eq!(summary.files_copied, 0);
+        assert!(fs.read("/other/a").is_none());
+    }
 
-        let done = AtomicU64::new(0);
+    #[tokio::test]
+    async fn test_sync_source_gone() {
+        let fs = memory_fs(&[("/src/a", "hello"), ("/src/b", "world!")]);
+        let (summary, progress, errors) =
+            sync_after_discovery(&fs, SyncOptions::default(), async {
+                fs.remove_file(Path::new("/src/b")).await.unwrap();
+            })
+            .await;
 
-        sync.sync(
-            |gp, _| {
-                done.store(gp.files.done.load(Ordering::Relaxed), Ordering::Relaxed);
-            },
-            &|e| {
-                panic!("Error occurred: {:?}", e);
-            },
-        )
-        .await;
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(summary.files_copied, 1);
+        assert_eq!(summary.files_failed, 0);
+        assert_eq!(summary.files_gone, 1);
+        assert!(!summary.has_failures());
+        assert_eq!(progress.files.total, 1);
+        assert_eq!(progress.bytes.total, 5);
+        assert_eq!(fs.read("/dest/a").as_deref(), Some(&b"hello"[..]));
+        assert!(fs.read("/dest/b").is_none());
 
-        assert_eq!(done.into_inner(), 2);
+        // In use at first, then gone by the time it is retried.
+        let fs = memory_fs(&[("/src/a", "hello"), ("/src/b", "world!")]);
+        let locked = AtomicBool::new(false);
+        fs.inject(
+            Op::Open,
+            Fault::Error(std::io::ErrorKind::WouldBlock),
+            move |p| p.ends_with("b") && !locked.swap(true, Ordering::Relaxed),
+        );
+        fs.inject(Op::Open, Fault::Error(std::io::ErrorKind::NotFound), |p| {
+            p.ends_with("b")
+        });
+        let options = SyncOptions {
+            retry_locked: true,
+            ..SyncOptions::default()
+        };
+        let (summary, progress, errors) = sync_after_discovery(&fs, options, async {}).await;
 
-        let mut dest_file = File::open(&dest_file).await.unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(summary.files_gone, 1);
+        assert!(summary.locked_paths.is_empty());
+        assert_eq!(progress.files.total, 1);
+        assert_eq!(progress.bytes.total, 5);
+    }
 
-        let mut buf = Vec::new();
-        dest_file.read_to_end(&mut buf).await.unwrap();
+    #[test]
+    fn test_budget_for() {
+        assert_eq!(budget_for(1024), 768);
+        assert_eq!(budget_for(256), 192);
+        assert_eq!(budget_for(100), 36);
+        assert_eq!(budget_for(10), FDS_PER_COPY as usize);
+        let max = Semaphore::MAX_PERMITS;
+        assert_eq!(budget_for(u64::MAX), max - max / 4);
+    }
 
-        assert_eq!(buf, b"hello world");
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_copy_out_of_fds() {
+        let fs = memory_fs(&[("/src/a", "hello"), ("/src/b", "world")]);
+        let opens = Arc::new(AtomicU64::new(0));
+        // The first two opens run out of descriptors, and every open of `b` does.
+        fs.inject(Op::Open, Fault::Os(libc::EMFILE), {
+            let opens = opens.clone();
+            move |p| {
+                let n = opens.fetch_add(1, Ordering::Relaxed);
+                p == Path::new("/src/b") || n < 2
+            }
+        });
 
-        let mut dest_subfile = File::open(&dest_subfile).await.unwrap();
+        let errors = Mutex::new(Vec::new());
+        let summary =
+            SyncFS::with_backend(fs.clone(), ["/src"], "/dest", 1, SyncOptions::default())
+                .sync(
+                    |_, _| {},
+                    &|e| errors.lock().unwrap().push(e.path.clone()),
+                    &CancellationToken::new(),
+                )
+                .await;
 
-        let mut buf = Vec::new();
-        dest_subfile.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(summary.files_copied, 1);
+        assert_eq!(fs.read("/dest/a").as_deref(), Some(&b"hello"[..]));
+        assert_eq!(
+            errors.into_inner().unwrap(),
+            [Some(PathBuf::from("/src/b"))]
+        );
+    }
 
-        assert_eq!(buf, b"goodbye world");
+    #[tokio::test]
+    async fn test_sync_copy_in_kernel() {
+        for supported in [true, false] {
+            let fs = memory_fs(&[("/src/a", "hello"), ("/src/empty", "")]);
+            let calls = Arc::new(AtomicU64::new(0));
+            fs.inject(
+                Op::CopyRange,
+                Fault::Error(std::io::ErrorKind::Unsupported),
+                {
+                    let calls = calls.clone();
+                    move |_| {
+                        calls.fetch_add(1, Ordering::Relaxed);
+                        !supported
+                    }
+                },
+            );
+
+            let summary =
+                SyncFS::with_backend(fs.clone(), ["/src"], "/dest", 1, SyncOptions::default())
+                    .sync(|_, _| {}, &|e| panic!("{:?}", e), &CancellationToken::new())
+                    .await;
+
+            assert_eq!(summary.files_copied, 2);
+            assert_eq!(summary.bytes_copied, 5);
+            assert_eq!(fs.read("/dest/a").as_deref(), Some(&b"hello"[..]));
+            assert_eq!(fs.read("/dest/empty").as_deref(), Some(&b""[..]));
+            // Once for each file, either way.
+            assert_eq!(calls.load(Ordering::Relaxed), 2);
+            assert!(partials(&fs).is_empty(), "{:?}", partials(&fs));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_copy_in_kernel_falls_back() {
+        let faults = [
+            // Refused by the file system.
+            #[cfg(unix)]
+            Fault::Os(libc::EINVAL),
+            Fault::Error(std::io::ErrorKind::PermissionDenied),
+            // Nothing copied although the file is not empty.
+            Fault::Short(0),
+        ];
+        for fault in faults {
+            let fs = memory_fs(&[("/src/a", "hello"), ("/src/empty", "")]);
+            fs.inject(Op::CopyRange, fault, |_| true);
+
+            let summary =
+                SyncFS::with_backend(fs.clone(), ["/src"], "/dest", 1, SyncOptions::default())
+                    .sync(|_, _| {}, &|e| panic!("{:?}", e), &CancellationToken::new())
+                    .await;
+
+            assert_eq!(summary.files_copied, 2, "{:?}", fault);
+            assert_eq!(summary.bytes_copied, 5, "{:?}", fault);
+            assert_eq!(fs.read("/dest/a").as_deref(), Some(&b"hello"[..]));
+            assert_eq!(fs.read("/dest/empty").as_deref(), Some(&b""[..]));
+            assert!(partials(&fs).is_empty(), "{:?}", partials(&fs));
+        }
     }
 }
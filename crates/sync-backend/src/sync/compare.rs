@@ -0,0 +1,186 @@
+use std::{future::Future, path::Path, pin::Pin, sync::Arc, time::Duration};
+
+use super::{hash_file, manifest::Manifest, ComparisonFlags, HashAlgorithm, SyncOptions};
+use crate::fs::{FsBackend, Metadata, TokioFs};
+
+/// The future returned by [`Comparator::same`].
+pub type CompareFuture<'a> = Pin<Box<dyn Future<Output = std::io::Result<bool>> + Send + 'a>>;
+
+/// Decides whether a destination file is up to date with its source, see
+/// [`SyncFS::with_comparator`](super::SyncFS::with_comparator).
+///
+/// Both files are stated beforehand, implementations that need the contents read them through
+/// `fs`. Closures taking the source and destination paths and metadata implement it, for rules
+/// that need nothing more.
+pub trait Comparator<B: FsBackend = TokioFs>: Send + Sync {
+    /// Tell whether `dest` can be left alone, an error counts as out of date.
+    fn same<'a>(
+        &'a self,
+        fs: &'a B,
+        src: &'a Path,
+        dest: &'a Path,
+        src_meta: &'a Metadata,
+        dest_meta: &'a Metadata,
+    ) -> CompareFuture<'a>;
+}
+
+impl<B, F> Comparator<B> for F
+where
+    B: FsBackend,
+    F: Fn(&Path, &Path, &Metadata, &Metadata) -> bool + Send + Sync,
+{
+    fn same<'a>(
+        &'a self,
+        _: &'a B,
+        src: &'a Path,
+        dest: &'a Path,
+        src_meta: &'a Metadata,
+        dest_meta: &'a Metadata,
+    ) -> CompareFuture<'a> {
+        let same = self(src, dest, src_meta, dest_meta);
+        Box::pin(async move { Ok(same) })
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The built-in [`Comparator`], checking the attributes in [`ComparisonFlags`] cheapest first.
+///
+/// This is what [`SyncOptions::comparison`] configures, like size only with
+/// [`ComparisonFlags::SIZE`] or contents with [`ComparisonFlags::HASH`].
+pub struct FlagComparator {
+    flags: ComparisonFlags,
+    mtime_tolerance: Duration,
+    algorithm: HashAlgorithm,
+    manifest: Option<Arc<Manifest>>,
+}
+
+impl FlagComparator {
+    /// Compare `flags`, with modification times within `mtime_tolerance` of each other
+    /// considered equal and contents hashed with `algorithm`.
+    pub fn new(
+        flags: ComparisonFlags,
+        mtime_tolerance: Duration,
+        algorithm: HashAlgorithm,
+    ) -> Self {
+        Self {
+            flags,
+            mtime_tolerance,
+            algorithm,
+            manifest: None,
+        }
+    }
+
+    /// The comparator configured by `options`, looking hashes up in `manifest` if it is kept.
+    pub(super) fn from_options(options: &SyncOptions, manifest: &Arc<Manifest>) -> Self {
+        Self {
+            manifest: options.manifest.then(|| Arc::clone(manifest)),
+            ..Self::new(
+                options.comparison,
+                options.mtime_tolerance,
+                options.hash_algorithm,
+            )
+        }
+    }
+}
+
+impl<B: FsBackend> Comparator<B> for FlagComparator {
+    fn same<'a>(
+        &'a self,
+        fs: &'a B,
+        src: &'a Path,
+        dest: &'a Path,
+        src_meta: &'a Metadata,
+        dest_meta: &'a Metadata,
+    ) -> CompareFuture<'a> {
+        Box::pin(cmp_meta(
+            fs,
+            dest,
+            src,
+            dest_meta,
+            src_meta,
+            self.flags,
+            self.mtime_tolerance,
+            self.algorithm,
+            self.manifest.as_deref(),
+        ))
+    }
+}
+
+/// Check whether the destination is up to date, evaluating the cheapest criteria first.
+///
+/// Modification times within `mtime_tolerance` of each other are considered equal.
+pub(super) async fn cmp_file<B: FsBackend>(
+    fs: &B,
+    dest: &Path,
+    src: &Path,
+    flags: ComparisonFlags,
+    mtime_tolerance: Duration,
+    algorithm: HashAlgorithm,
+    manifest: Option<&Manifest>,
+) -> Result<bool, tokio::io::Error> {
+    let dest_meta = fs.metadata(dest).await?;
+    let src_meta = fs.metadata(src).await?;
+    cmp_meta(
+        fs,
+        dest,
+        src,
+        &dest_meta,
+        &src_meta,
+        flags,
+        mtime_tolerance,
+        algorithm,
+        manifest,
+    )
+    .await
+}
+
+/// [`cmp_file`] with both files already stated.
+#[allow(clippy::too_many_arguments)]
+async fn cmp_meta<B: FsBackend>(
+    fs: &B,
+    dest: &Path,
+    src: &Path,
+    dest_meta: &Metadata,
+    src_meta: &Metadata,
+    flags: ComparisonFlags,
+    mtime_tolerance: Duration,
+    algorithm: HashAlgorithm,
+    manifest: Option<&Manifest>,
+) -> Result<bool, tokio::io::Error> {
+    if flags.contains(ComparisonFlags::SIZE) && dest_meta.len != src_meta.len {
+        return Ok(false);
+    }
+
+    if flags.contains(ComparisonFlags::ATTRIBUTES) && dest_meta.readonly != src_meta.readonly {
+        return Ok(false);
+    }
+
+    if flags.contains(ComparisonFlags::MTIME)
+        && src_meta
+            .modified()?
+            .duration_since(dest_meta.modified()?)
+            .is_ok_and(|newer| newer > mtime_tolerance)
+    {
+        return Ok(false);
+    }
+
+    // The previous manifest vouches for files that did not change since.
+    let known = manifest.and_then(|m| m.lookup(dest, src_meta, dest_meta));
+    if flags.contains(ComparisonFlags::HASH) && known.is_none() {
+        if dest_meta.len != src_meta.len {
+            return Ok(false);
+        }
+        let (dest_hash, src_hash) = tokio::try_join!(
+            hash_file(fs, dest, algorithm),
+            hash_file(fs, src, algorithm)
+        )?;
+        if dest_hash != src_hash {
+            return Ok(false);
+        }
+        if let Some(manifest) = manifest {
+            manifest.record(dest, src_hash, src_meta, dest_meta);
+        }
+    }
+
+    Ok(true)
+}
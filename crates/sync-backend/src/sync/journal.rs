@@ -0,0 +1,221 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::{partial_path, remove_partial, SyncOptions};
+use crate::{
+    display_path,
+    fs::{FsBackend, Metadata},
+};
+
+/// Name of the journal written to the destination root, see [`super::SyncOptions::resume`].
+pub const JOURNAL_FILE_NAME: &str = ".sync-journal.json";
+
+/// Bumped when the format changes, journals of another version are ignored.
+const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// The source file a destination file was brought up to date with.
+struct Entry {
+    size: u64,
+    modified: SystemTime,
+}
+
+impl Entry {
+    fn new(src: &Metadata) -> Option<Self> {
+        Some(Self {
+            size: src.len,
+            modified: src.modified?,
+        })
+    }
+
+    /// Whether the source did not change since the entry was recorded.
+    fn unchanged(&self, src: &Metadata) -> bool {
+        src.len == self.size && src.modified == Some(self.modified)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JournalFile {
+    version: u32,
+    /// The configuration the journal was written for, it is ignored by any other.
+    src: Vec<PathBuf>,
+    options: SyncOptions,
+    files: HashMap<String, Entry>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    root: PathBuf,
+    src: Vec<PathBuf>,
+    options: Option<SyncOptions>,
+    previous: HashMap<String, Entry>,
+    next: HashMap<String, Entry>,
+}
+
+/// The destination files brought up to date by an unfinished run, by path relative to the
+/// destination root.
+///
+/// Paths that are not valid Unicode are left out, they are compared on every run.
+///
+/// Entries of the interrupted run are carried over as they are found again, so the journal
+/// written by this run also covers what the previous one did.
+#[derive(Debug, Default)]
+pub(crate) struct Journal(Mutex<State>);
+
+impl Journal {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        match self.0.lock() {
+            Ok(g) => g,
+            Err(p) => p.into_inner(),
+        }
+    }
+
+    fn key(root: &Path, dest: &Path) -> Option<String> {
+        dest.strip_prefix(root).ok()?.to_str().map(str::to_string)
+    }
+
+    /// Read the journal in `root`, starting over if it is missing, corrupt or was written for
+    /// other sources or options.
+    pub(crate) async fn load<B: FsBackend>(
+        &self,
+        fs: &B,
+        root: &Path,
+        src: &[PathBuf],
+        options: &SyncOptions,
+    ) {
+        let path = root.join(JOURNAL_FILE_NAME);
+        let previous = match read(fs, &path).await {
+            Ok(Some(file)) if file.version != VERSION => {
+                log::warn!(
+                    "Ignoring {}, it has version {} instead of {}",
+                    display_path(&path).display(),
+                    file.version,
+                    VERSION
+                );
+                HashMap::new()
+            }
+            Ok(Some(file)) if file.src != src || file.options != *options => {
+                log::info!(
+                    "Ignoring {}, it was written for another configuration",
+                    display_path(&path).display()
+                );
+                HashMap::new()
+            }
+            Ok(Some(file)) => {
+                log::info!(
+                    "Resuming the interrupted sync into {}, {} files are done",
+                    display_path(root).display(),
+                    file.files.len()
+                );
+                file.files
+            }
+            Ok(None) => HashMap::new(),
+            Err(e) => {
+                log::warn!("Ignoring {}: {}", display_path(&path).display(), e);
+                HashMap::new()
+            }
+        };
+
+        let mut state = self.lock();
+        state.root = root.to_path_buf();
+        state.src = src.to_vec();
+        state.options = Some(options.clone());
+        state.previous = previous;
+        state.next.clear();
+    }
+
+    /// Check whether the interrupted run brought `dest` up to date with the source, which did
+    /// not change since, keeping the entry for this run.
+    pub(crate) fn done(&self, dest: &Path, src_meta: &Metadata) -> bool {
+        let mut state = self.lock();
+        let Some(key) = Self::key(&state.root, dest) else {
+            return false;
+        };
+        let Some(entry) = state
+            .previous
+            .remove(&key)
+            .filter(|entry| entry.unchanged(src_meta))
+        else {
+            return false;
+        };
+        state.next.insert(key, entry);
+        true
+    }
+
+    /// Record that `dest` is up to date with the source file of metadata `src_meta`.
+    pub(crate) fn record(&self, dest: &Path, src_meta: &Metadata) {
+        let mut state = self.lock();
+        let (Some(key), Some(entry)) = (Self::key(&state.root, dest), Entry::new(src_meta)) else {
+            return;
+        };
+        state.next.insert(key, entry);
+    }
+
+    /// Write the entries of this run so far to the destination root, keeping them for the next
+    /// save.
+    pub(crate) async fn save<B: FsBackend>(&self, fs: &B) -> std::io::Result<()> {
+        let (path, json) = {
+            let state = self.lock();
+            let Some(options) = state.options.clone() else {
+                return Ok(());
+            };
+            let file = JournalFile {
+                version: VERSION,
+                src: state.src.clone(),
+                options,
+                files: state.next.clone(),
+            };
+            (
+                state.root.join(JOURNAL_FILE_NAME),
+                serde_json::to_vec(&file)?,
+            )
+        };
+
+        // Written aside and renamed, so an interrupted save leaves the previous journal.
+        let partial = partial_path(&path);
+        let written = async {
+            let mut file = fs.create(&partial).await?;
+            file.write_all(&json).await?;
+            file.shutdown().await?;
+            drop(file);
+            fs.rename(&partial, &path).await
+        }
+        .await;
+        if written.is_err() {
+            remove_partial(fs, &partial).await;
+        }
+        written
+    }
+
+    /// Remove the journal once the run is complete, forgetting its entries.
+    pub(crate) async fn remove<B: FsBackend>(&self, fs: &B) -> std::io::Result<()> {
+        let path = {
+            let mut state = self.lock();
+            state.previous.clear();
+            state.next.clear();
+            state.root.join(JOURNAL_FILE_NAME)
+        };
+        match fs.remove_file(&path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+async fn read<B: FsBackend>(fs: &B, path: &Path) -> std::io::Result<Option<JournalFile>> {
+    let mut file = match fs.open(path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut json = Vec::new();
+    file.read_to_end(&mut json).await?;
+    Ok(Some(serde_json::from_slice(&json)?))
+}
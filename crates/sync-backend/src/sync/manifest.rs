@@ -0,0 +1,204 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::{partial_path, remove_partial, HashAlgorithm};
+use crate::{
+    display_path,
+    fs::{FsBackend, Metadata},
+};
+
+/// Name of the manifest written to the destination root, see [`super::SyncOptions::manifest`].
+pub const MANIFEST_FILE_NAME: &str = ".sync-manifest.json";
+
+/// Bumped when the format changes, manifests of another version are ignored.
+const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// What is known of a copied file.
+struct Entry {
+    hash: String,
+    size: u64,
+    src_modified: SystemTime,
+    dest_modified: SystemTime,
+}
+
+impl Entry {
+    fn new(hash: String, src: &Metadata, dest: &Metadata) -> Option<Self> {
+        Some(Self {
+            hash,
+            size: dest.len,
+            src_modified: src.modified?,
+            dest_modified: dest.modified?,
+        })
+    }
+
+    /// Whether neither file changed since the entry was recorded.
+    fn unchanged(&self, src: &Metadata, dest: &Metadata) -> bool {
+        src.len == self.size
+            && dest.len == self.size
+            && src.modified == Some(self.src_modified)
+            && dest.modified == Some(self.dest_modified)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestFile {
+    version: u32,
+    algorithm: HashAlgorithm,
+    files: HashMap<String, Entry>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    root: PathBuf,
+    previous: HashMap<String, Entry>,
+    next: HashMap<String, Entry>,
+}
+
+/// The hashes of the destination files, by path relative to the destination root.
+///
+/// Paths that are not valid Unicode are left out, they are hashed on every run.
+///
+/// The manifest of the previous run is only read, the entries of this run are collected apart
+/// so files that are gone drop out of it.
+#[derive(Debug, Default)]
+pub(crate) struct Manifest(Mutex<State>);
+
+impl Manifest {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        match self.0.lock() {
+            Ok(g) => g,
+            Err(p) => p.into_inner(),
+        }
+    }
+
+    fn key(root: &Path, dest: &Path) -> Option<String> {
+        dest.strip_prefix(root).ok()?.to_str().map(str::to_string)
+    }
+
+    /// Read the manifest in `root`, starting over if it is missing, corrupt or was written with
+    /// another algorithm.
+    pub(crate) async fn load<B: FsBackend>(&self, fs: &B, root: &Path, algorithm: HashAlgorithm) {
+        let path = root.join(MANIFEST_FILE_NAME);
+        let previous = match read(fs, &path).await {
+            Ok(Some(file)) if file.version != VERSION => {
+                log::warn!(
+                    "Ignoring {}, it has version {} instead of {}",
+                    display_path(&path).display(),
+                    file.version,
+                    VERSION
+                );
+                HashMap::new()
+            }
+            Ok(Some(file)) if file.algorithm != algorithm => {
+                log::info!(
+                    "Ignoring {}, it was written with another hash algorithm",
+                    display_path(&path).display()
+                );
+                HashMap::new()
+            }
+            Ok(Some(file)) => file.files,
+            Ok(None) => HashMap::new(),
+            Err(e) => {
+                log::warn!("Ignoring {}: {}", display_path(&path).display(), e);
+                HashMap::new()
+            }
+        };
+
+        let mut state = self.lock();
+        state.root = root.to_path_buf();
+        state.previous = previous;
+        state.next.clear();
+    }
+
+    /// Get the hash of `dest` from the previous run if neither file changed since, keeping it
+    /// for this run.
+    pub(crate) fn lookup(
+        &self,
+        dest: &Path,
+        src_meta: &Metadata,
+        dest_meta: &Metadata,
+    ) -> Option<String> {
+        let mut state = self.lock();
+        let key = Self::key(&state.root, dest)?;
+        let entry = state
+            .previous
+            .get(&key)
+            .filter(|entry| entry.unchanged(src_meta, dest_meta))?
+            .clone();
+        let hash = entry.hash.clone();
+        state.next.insert(key, entry);
+        Some(hash)
+    }
+
+    /// Record that `dest` has the contents hashing to `hash`.
+    pub(crate) fn record(
+        &self,
+        dest: &Path,
+        hash: String,
+        src_meta: &Metadata,
+        dest_meta: &Metadata,
+    ) {
+        let mut state = self.lock();
+        let (Some(key), Some(entry)) = (
+            Self::key(&state.root, dest),
+            Entry::new(hash, src_meta, dest_meta),
+        ) else {
+            return;
+        };
+        state.next.insert(key, entry);
+    }
+
+    /// Write the entries of this run to the destination root, they are not kept in memory.
+    pub(crate) async fn save<B: FsBackend>(
+        &self,
+        fs: &B,
+        algorithm: HashAlgorithm,
+    ) -> std::io::Result<()> {
+        let (path, json) = {
+            let mut state = self.lock();
+            let file = ManifestFile {
+                version: VERSION,
+                algorithm,
+                files: std::mem::take(&mut state.next),
+            };
+            (
+                state.root.join(MANIFEST_FILE_NAME),
+                serde_json::to_vec(&file)?,
+            )
+        };
+
+        // Written aside and renamed, so an interrupted run leaves the old manifest.
+        let partial = partial_path(&path);
+        let written = async {
+            let mut file = fs.create(&partial).await?;
+            file.write_all(&json).await?;
+            file.shutdown().await?;
+            drop(file);
+            fs.rename(&partial, &path).await
+        }
+        .await;
+        if written.is_err() {
+            remove_partial(fs, &partial).await;
+        }
+        written
+    }
+}
+
+async fn read<B: FsBackend>(fs: &B, path: &Path) -> std::io::Result<Option<ManifestFile>> {
+    let mut file = match fs.open(path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut json = Vec::new();
+    file.read_to_end(&mut json).await?;
+    Ok(Some(serde_json::from_slice(&json)?))
+}
@@ -0,0 +1,156 @@
+//! The platform calls that need unsafe code, each behind a safe function.
+//!
+//! The rest of the crate denies unsafe code, so this is all there is to audit.
+
+use std::{io, path::Path};
+
+/// One `copy_file_range(2)` call of up to `len` bytes at the positions of both files.
+#[cfg(target_os = "linux")]
+pub(crate) fn copy_file_range(
+    src: &std::fs::File,
+    dest: &std::fs::File,
+    len: u64,
+) -> io::Result<u64> {
+    use std::os::fd::AsRawFd;
+
+    let len = usize::try_from(len).unwrap_or(usize::MAX);
+    // SAFETY: both descriptors are open for the whole call, null offsets mean the file positions
+    // are used and advanced.
+    let n = unsafe {
+        libc::copy_file_range(
+            src.as_raw_fd(),
+            std::ptr::null_mut(),
+            dest.as_raw_fd(),
+            std::ptr::null_mut(),
+            len,
+            0,
+        )
+    };
+    u64::try_from(n).map_err(|_| io::Error::last_os_error())
+}
+
+/// Create a node at `path` with the type and permissions in `mode` and the device number `dev`.
+#[cfg(unix)]
+pub(crate) fn mknod(path: &Path, mode: u32, dev: u64) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // The mode and device number come from the kernel, so they fit the platform types.
+    #[allow(clippy::cast_possible_truncation)]
+    let (mode, dev) = (mode as libc::mode_t, dev as libc::dev_t);
+    // SAFETY: the path is null-terminated and outlives the call.
+    if unsafe { libc::mknod(path.as_ptr(), mode, dev) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Get the soft limit on open files of the process, `None` if unlimited.
+#[cfg(unix)]
+pub(crate) fn open_files_limit() -> Option<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is a valid rlimit to write to.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    // Field widths differ between platforms.
+    #[allow(clippy::useless_conversion)]
+    (limit.rlim_cur != libc::RLIM_INFINITY).then(|| u64::from(limit.rlim_cur))
+}
+
+/// Handles are only limited by memory on Windows.
+#[cfg(not(unix))]
+pub(crate) fn open_files_limit() -> Option<u64> {
+    None
+}
+
+/// Get `path` as a null-terminated wide string.
+#[cfg(windows)]
+fn wide(path: &Path) -> Vec<u16> {
+    use std::os::windows::ffi::OsStrExt;
+
+    path.as_os_str().encode_wide().chain(Some(0)).collect()
+}
+
+/// Get the attributes of `path`, without following it if it is a link.
+#[cfg(windows)]
+pub(crate) fn file_attributes(path: &Path) -> io::Result<u32> {
+    use windows::{
+        core::PCWSTR,
+        Win32::Storage::FileSystem::{GetFileAttributesW, INVALID_FILE_ATTRIBUTES},
+    };
+
+    let path = wide(path);
+    // SAFETY: the path is null-terminated and outlives the call.
+    let attrs = unsafe { GetFileAttributesW(PCWSTR(path.as_ptr())) };
+    if attrs == INVALID_FILE_ATTRIBUTES {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(attrs)
+}
+
+/// Replace the attributes of `path` with `attrs`.
+#[cfg(windows)]
+pub(crate) fn set_file_attributes(path: &Path, attrs: u32) -> io::Result<()> {
+    use windows::{
+        core::PCWSTR,
+        Win32::Storage::FileSystem::{SetFileAttributesW, FILE_FLAGS_AND_ATTRIBUTES},
+    };
+
+    let path = wide(path);
+    // SAFETY: the path is null-terminated and outlives the call.
+    unsafe { SetFileAttributesW(PCWSTR(path.as_ptr()), FILE_FLAGS_AND_ATTRIBUTES(attrs)) }?;
+    Ok(())
+}
+
+/// List the names of the data streams of `path`, like `::$DATA` for its contents.
+#[cfg(windows)]
+pub(crate) fn stream_names(path: &Path) -> io::Result<Vec<std::ffi::OsString>> {
+    use std::{ffi::OsString, os::windows::ffi::OsStringExt};
+    use windows::{
+        core::PCWSTR,
+        Win32::{
+            Foundation::ERROR_HANDLE_EOF,
+            Storage::FileSystem::{
+                FindClose, FindFirstStreamW, FindNextStreamW, FindStreamInfoStandard,
+                WIN32_FIND_STREAM_DATA,
+            },
+        },
+    };
+
+    let path = wide(path);
+    let mut data = WIN32_FIND_STREAM_DATA::default();
+    // SAFETY: the path is null-terminated and `data` is the struct for the standard info level.
+    let find = unsafe {
+        FindFirstStreamW(
+            PCWSTR(path.as_ptr()),
+            FindStreamInfoStandard,
+            std::ptr::from_mut(&mut data).cast(),
+            0,
+        )
+    }?;
+
+    let mut streams = Vec::new();
+    let listed = loop {
+        let len = data
+            .cStreamName
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(data.cStreamName.len());
+        streams.push(OsString::from_wide(&data.cStreamName[..len]));
+        // SAFETY: as above, and `find` is open until closed below.
+        match unsafe { FindNextStreamW(find, std::ptr::from_mut(&mut data).cast()) } {
+            Ok(()) => {}
+            Err(e) if e.code() == ERROR_HANDLE_EOF.to_hresult() => break Ok(()),
+            Err(e) => break Err(io::Error::from(e)),
+        }
+    };
+    // SAFETY: `find` was returned by `FindFirstStreamW` and is not used after this.
+    unsafe { FindClose(find) }?;
+    listed.map(|()| streams)
+}
@@ -4,31 +4,172 @@
 #![allow(
     clippy::missing_errors_doc,
     clippy::unreadable_literal,
-    clippy::items_after_statements
+    clippy::items_after_statements,
+    clippy::unnecessary_literal_bound
 )]
 
 //! Operating system specific file system notification sources.
 
 use std::{
     fmt::{Debug, Display},
+    future::Future,
     hash::Hash,
     marker::PhantomData,
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::RwLock,
 };
 
 use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
 use tokio::task::AbortHandle;
 
 #[cfg(windows)]
 /// Windows specific file system notification sources.
 pub mod windows;
 
+#[cfg(target_os = "linux")]
+/// Linux specific file system notification sources.
+pub mod linux;
+
+#[cfg(target_os = "macos")]
+/// macOS specific file system notification sources.
+pub mod macos;
+
+#[cfg(windows)]
 pub(crate) mod mem;
 
+/// A notification source polling for file system changes, for when the platform notifications
+/// are not available.
+pub mod poll;
+
 /// A file system identifier.
 pub trait FileSystem: Debug + Display {
     /// Get the file system name.
     fn name(&self) -> &str;
+
+    /// Get the human readable label of the file system, like `BACKUP_USB`, if it has one.
+    fn label(&self) -> Option<String> {
+        None
+    }
+
+    /// Get an identifier of the file system that survives remounts, if the platform has one.
+    ///
+    /// Unlike [`FileSystem::name`] it stays the same across sessions, see the platform types for
+    /// whether it survives a reformat.
+    fn persistent_id(&self) -> Option<String> {
+        None
+    }
+
+    /// Get the volume serial number formatted like `A1B2-C3D4`, if the platform can tell.
+    ///
+    /// The serial number is assigned when the volume is formatted, so it is stable across
+    /// machines and mount points but changes on reformat.
+    fn serial(&self) -> Option<String> {
+        None
+    }
+
+    /// Get the file system type, like 'NTFS' or 'exFAT', if the platform can tell.
+    fn fs_type(&self) -> Option<String> {
+        None
+    }
+
+    /// Get the capacity of the file system, if the platform can tell.
+    fn space(&self) -> Option<SpaceInfo> {
+        None
+    }
+
+    /// Get the kind of drive backing the file system, if the platform can tell.
+    fn drive_type(&self) -> Option<DriveType> {
+        None
+    }
+
+    /// Check whether the file system is encrypted and still locked, so its files cannot be read.
+    ///
+    /// Only the user can unlock it, this never does it for them.
+    fn locked(&self) -> bool {
+        false
+    }
+
+    /// Safely remove the drive backing the file system, so it can be unplugged.
+    ///
+    /// This blocks until the drive is gone or the removal is refused. Fails with
+    /// [`std::io::ErrorKind::ResourceBusy`] while files are open on it, and with
+    /// [`std::io::ErrorKind::Unsupported`] where the drive or the platform cannot eject.
+    fn eject(&self) -> std::io::Result<()> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            NotImplementedError,
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// The kind of drive backing a file system.
+pub enum DriveType {
+    /// The drive type could not be determined.
+    Unknown,
+    /// Removable media, like a USB flash drive or an SD card.
+    Removable,
+    /// A fixed disk, like an internal SSD.
+    Fixed,
+    /// A network drive.
+    Remote,
+    /// An optical drive.
+    CdRom,
+    /// A RAM disk.
+    RamDisk,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// The capacity of a file system in bytes.
+pub struct SpaceInfo {
+    /// Total size of the file system.
+    pub total: u64,
+    /// Free space on the file system.
+    pub free: u64,
+    /// Free space available to the current user, which may be less than `free` due to quotas or reserved blocks.
+    pub available: u64,
+}
+
+/// Query the capacity of the file system containing `path`.
+pub fn space_of(path: &Path) -> std::io::Result<SpaceInfo> {
+    #[cfg(windows)]
+    {
+        windows::space_of(path)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+        use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+        let path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let mut st = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+        if unsafe { libc::statvfs(path.as_ptr(), st.as_mut_ptr()) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let st = unsafe { st.assume_init() };
+
+        // Field widths differ between platforms.
+        #[allow(clippy::useless_conversion)]
+        let block_size = u64::from(st.f_frsize);
+        #[allow(clippy::useless_conversion)]
+        Ok(SpaceInfo {
+            total: u64::from(st.f_blocks) * block_size,
+            free: u64::from(st.f_bfree) * block_size,
+            available: u64::from(st.f_bavail) * block_size,
+        })
+    }
+
+    #[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+    {
+        let _ = path;
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            NotImplementedError,
+        ))
+    }
 }
 
 #[derive(Debug)]
@@ -51,6 +192,14 @@ impl Display for UnimplementedFileSystem {
 pub trait Device: Debug {
     /// Get the device name.
     fn name(&self) -> &str;
+
+    /// Get why the device of a listed file system could not be resolved, `None` if it was.
+    ///
+    /// Such file systems are still listed, so they can be shown, but with an `unknown` device
+    /// and no mount paths. Notifiers do not offer them to the spawner.
+    fn error(&self) -> Option<&str> {
+        None
+    }
 }
 
 impl Device for () {
@@ -69,10 +218,20 @@ impl Device for UnimplementedDevice {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How a task registered with an [`AbortHandleHolder`] ended.
+pub enum TaskEnd {
+    /// The task finished on its own.
+    Completed,
+    /// The task was aborted, like when its file system was removed.
+    Aborted,
+}
+
+/// A callback told how a registered task ended.
+pub type OnTaskEnd = Box<dyn FnOnce(TaskEnd) + Send + Sync>;
+
 /// A holder for [`AbortHandle`]s, used to cancel tasks whose file systems have been removed.
-pub struct AbortHandleHolder<K: Hash + Eq + Display>(
-    DashMap<K, (AbortHandle, Option<Box<dyn FnOnce() + Send + Sync>>)>,
-);
+pub struct AbortHandleHolder<K: Hash + Eq + Display>(DashMap<K, (AbortHandle, Option<OnTaskEnd>)>);
 
 impl<K: Hash + Eq + Display> Default for AbortHandleHolder<K> {
     fn default() -> Self {
@@ -80,47 +239,84 @@ impl<K: Hash + Eq + Display> Default for AbortHandleHolder<K> {
     }
 }
 
-#[allow(dead_code)]
+/// Abort `handle` unless it already finished, returning how the task ended.
+fn end_task(handle: &AbortHandle) -> TaskEnd {
+    if handle.is_finished() {
+        TaskEnd::Completed
+    } else {
+        handle.abort();
+        TaskEnd::Aborted
+    }
+}
+
 impl<K: Hash + Eq + Display> AbortHandleHolder<K> {
-    pub(crate) fn insert(
-        &self,
-        key: K,
-        handle: AbortHandle,
-        on_remove: Option<Box<dyn FnOnce() + Send + Sync>>,
-    ) {
-        self.0.insert(key, (handle, on_remove));
+    /// Register a task, `on_end` is called when the task is aborted or found finished.
+    pub fn insert(&self, key: K, handle: AbortHandle, on_end: Option<OnTaskEnd>) {
+        self.0.insert(key, (handle, on_end));
     }
 
-    pub(crate) fn gc(&self) {
-        self.0.retain(|_, v| !v.0.is_finished());
+    /// Check whether a task registered for `key` is still running.
+    #[must_use]
+    pub fn is_running(&self, key: &K) -> bool {
+        self.0.get(key).is_some_and(|rec| !rec.0.is_finished())
     }
 
-    pub(crate) fn remove_abort(&self, key: &K) -> Option<K> {
-        if let Some((k, (abort, cleanup))) = self.0.remove(key) {
-            abort.abort();
-            if let Some(cleanup) = cleanup {
-                cleanup();
+    /// Forget tasks that have finished, telling their callbacks they completed.
+    pub fn gc(&self) {
+        let mut completed = Vec::new();
+        self.0.retain(|_, (handle, on_end)| {
+            if !handle.is_finished() {
+                return true;
             }
-            Some(k)
-        } else {
-            None
+            completed.extend(on_end.take());
+            false
+        });
+        // Called outside the map so the callbacks can use the holder.
+        for on_end in completed {
+            on_end(TaskEnd::Completed);
+        }
+    }
+
+    /// Count the registered tasks that are still running.
+    #[must_use]
+    pub fn active_count(&self) -> usize {
+        self.gc();
+        self.0.len()
+    }
+
+    /// Get the keys of the registered tasks, finished or not.
+    #[must_use]
+    pub fn tracked_keys(&self) -> Vec<K>
+    where
+        K: Clone,
+    {
+        self.0.iter().map(|rec| rec.key().clone()).collect()
+    }
+
+    /// Abort the task registered for `key`, returning the key if there was one.
+    pub fn remove_abort(&self, key: &K) -> Option<K> {
+        let (k, (handle, on_end)) = self.0.remove(key)?;
+        let end = end_task(&handle);
+        if let Some(on_end) = on_end {
+            on_end(end);
         }
+        Some(k)
     }
 
     /// Clear all [`AbortHandle`]s and abort the associated tasks.
     pub fn clear_abort(&self) {
-        self.0.iter_mut().for_each(|mut rec| {
-            let (key, (abort, cleanup)) = rec.pair_mut();
-            if !abort.is_finished() {
-                log::info!("Aborting task for volume: {}", key);
-                abort.abort();
-                if let Some(cleanup) = cleanup.take() {
-                    cleanup();
-                }
+        let mut ended = Vec::new();
+        self.0.retain(|key, (handle, on_end)| {
+            if !handle.is_finished() {
+                log::info!("Aborting task for {}", key);
             }
+            let end = end_task(handle);
+            ended.extend(on_end.take().map(|on_end| (on_end, end)));
+            false
         });
-
-        self.0.clear();
+        for (on_end, end) in ended {
+            on_end(end);
+        }
     }
 }
 
@@ -130,25 +326,94 @@ impl<K: Hash + Eq + Display> Drop for AbortHandleHolder<K> {
     }
 }
 
+/// The span a spawner callback runs in, carrying the name of the file system it is called for.
+pub(crate) fn volume_span(fs: &impl FileSystem) -> tracing::Span {
+    tracing::info_span!("volume", name = fs.name())
+}
+
 /// The disposition of a spawner callback.
 pub enum SpawnerDisposition {
-    /// A task has been spawned to handle the file system.
-    Spawned(AbortHandle, Option<Box<dyn FnOnce() + Send + Sync>>),
+    /// A task has been spawned to handle the file system, with a callback told how it ended.
+    Spawned(AbortHandle, Option<OnTaskEnd>),
     /// The file system should be ignored.
     Ignore,
     /// The file system should be skipped but next time a file system change is detected, the callback should be called again.
     Skip,
 }
 
+#[derive(Debug, Clone)]
+/// A file system that was removed after a task was spawned for it.
+pub struct RemovalEvent<FS, D> {
+    /// The removed file system.
+    pub volume: FS,
+    /// The device the file system was on when the task was spawned.
+    pub device: D,
+}
+
+/// A callback told about [`RemovalEvent`]s.
+pub type RemovalCallback<FS, D> = Box<dyn Fn(RemovalEvent<FS, D>) + Send + Sync>;
+
+/// The devices of the file systems tasks were spawned for, and the callback told when they go away.
+pub(crate) struct RemovalHook<FS: Hash + Eq, D> {
+    callback: RwLock<Option<RemovalCallback<FS, D>>>,
+    devices: DashMap<FS, D>,
+}
+
+impl<FS: Hash + Eq, D> Default for RemovalHook<FS, D> {
+    fn default() -> Self {
+        Self {
+            callback: RwLock::new(None),
+            devices: DashMap::new(),
+        }
+    }
+}
+
+impl<FS: Hash + Eq, D> RemovalHook<FS, D> {
+    /// Replace the callback.
+    pub(crate) fn set(&self, callback: RemovalCallback<FS, D>) {
+        let mut guard = match self.callback.write() {
+            Ok(g) => g,
+            Err(p) => p.into_inner(),
+        };
+        *guard = Some(callback);
+    }
+
+    /// Remember the device of a file system a task was spawned for.
+    pub(crate) fn spawned(&self, volume: FS, device: D) {
+        self.devices.insert(volume, device);
+    }
+
+    /// Tell the callback that `volume` is gone, if a task was spawned for it.
+    pub(crate) fn removed(&self, volume: &FS) {
+        let Some((volume, device)) = self.devices.remove(volume) else {
+            return;
+        };
+        let guard = match self.callback.read() {
+            Ok(g) => g,
+            Err(p) => p.into_inner(),
+        };
+        if let Some(callback) = guard.as_ref() {
+            callback(RemovalEvent { volume, device });
+        }
+    }
+
+    /// Forget all file systems, without telling the callback.
+    pub(crate) fn clear(&self) {
+        self.devices.clear();
+    }
+}
+
 /// A source of notifications for file system changes.
 ///
-/// `F` is a callback that takes a file system and a device,
-/// optionally spawning a task to handle the file system.
+/// `F` is a callback that takes a file system, a device and the paths the file system is
+/// mounted at, optionally spawning a task to handle the file system.
+/// A file system can be mounted at several paths, like both `D:` and `C:\mnt\backup`,
+/// and at none if it is not mounted yet.
 /// The returned [`tokio::task::AbortHandle`] will be registered
 /// and can be used to abort the task when the file system is removed.
 pub trait NotificationSource<'a, F>: Sized
 where
-    F: Fn(Self::FileSystem, Self::Device, Option<PathBuf>) -> SpawnerDisposition + Send + Sync + 'a,
+    F: Fn(Self::FileSystem, Self::Device, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync + 'a,
 {
     /// The file system type, usually a volume identifier.
     type FileSystem: FileSystem;
@@ -158,19 +423,30 @@ where
     type Error: std::error::Error;
 
     /// Create a new notification source with the given callback.
+    ///
+    /// The callback runs in a `volume` span, tasks it spawns keep it when instrumented with
+    /// [`tracing::Instrument::in_current_span`].
     fn new(callback: F) -> Result<Self, Self::Error>;
-    /// List all currently present file systems.
+    /// List all currently present file systems and their mount paths.
     #[allow(clippy::type_complexity)]
-    fn list(&self) -> Result<Vec<(Self::FileSystem, Self::Device, Option<PathBuf>)>, Self::Error>;
+    fn list(&self) -> Result<Vec<(Self::FileSystem, Self::Device, Vec<PathBuf>)>, Self::Error>;
     /// List all currently present file systems and spawn tasks for each.
     fn list_spawn(&self) -> Result<(), Self::Error>;
+    /// Set the callback told when a file system a task was spawned for is removed.
+    ///
+    /// It is called after the task is aborted, from the thread watching for changes.
+    fn on_removal(&self, callback: RemovalCallback<Self::FileSystem, Self::Device>);
+    /// Count the spawned tasks that are still running.
+    fn active_tasks(&self) -> usize;
+    /// List the file systems tasks were spawned for.
+    fn tracked_volumes(&self) -> Vec<Self::FileSystem>;
 
     /// Start the notification source and begin spawning tasks for new file systems.
-    fn start(&mut self) -> Result<(), Self::Error>;
+    fn start(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send;
     /// Stop the notification source but do not abort spawned tasks.
-    fn pause(&mut self) -> Result<(), Self::Error>;
+    fn pause(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send;
     /// Stop the notification source and abort spawned tasks.
-    fn reset(&mut self) -> Result<(), Self::Error>;
+    fn reset(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send;
 }
 
 #[derive(Clone)]
@@ -196,7 +472,7 @@ pub struct UnimplementedNotifier<'a, F>(PhantomData<&'a F>);
 
 impl<'a, F> NotificationSource<'a, F> for UnimplementedNotifier<'a, F>
 where
-    F: Fn(UnimplementedFileSystem, UnimplementedDevice, Option<PathBuf>) -> SpawnerDisposition
+    F: Fn(UnimplementedFileSystem, UnimplementedDevice, Vec<PathBuf>) -> SpawnerDisposition
         + Send
         + Sync
         + 'a,
@@ -209,7 +485,7 @@ where
         Ok(Self(PhantomData))
     }
 
-    fn list(&self) -> Result<Vec<(Self::FileSystem, Self::Device, Option<PathBuf>)>, Self::Error> {
+    fn list(&self) -> Result<Vec<(Self::FileSystem, Self::Device, Vec<PathBuf>)>, Self::Error> {
         log::warn!("Platform not supported, no notifications will be received");
         Ok(vec![])
     }
@@ -219,16 +495,26 @@ where
         Ok(())
     }
 
-    fn start(&mut self) -> Result<(), Self::Error> {
+    fn on_removal(&self, _: RemovalCallback<Self::FileSystem, Self::Device>) {}
+
+    fn active_tasks(&self) -> usize {
+        0
+    }
+
+    fn tracked_volumes(&self) -> Vec<Self::FileSystem> {
+        vec![]
+    }
+
+    async fn start(&mut self) -> Result<(), Self::Error> {
         log::warn!("Platform not supported, no notifications will be received");
         Ok(())
     }
 
-    fn pause(&mut self) -> Result<(), Self::Error> {
+    async fn pause(&mut self) -> Result<(), Self::Error> {
         Ok(())
     }
 
-    fn reset(&mut self) -> Result<(), Self::Error> {
+    async fn reset(&mut self) -> Result<(), Self::Error> {
         Ok(())
     }
 }
@@ -237,10 +523,34 @@ where
 /// A platform specific [`NotificationSource`].
 pub type PlatformNotifier<'a, F> = windows::HcmNotifier<'a, F>;
 
-#[cfg(not(windows))]
+#[cfg(target_os = "linux")]
+/// A platform specific [`NotificationSource`].
+pub type PlatformNotifier<'a, F> = linux::MountNotifier<'a, F>;
+
+#[cfg(target_os = "macos")]
+/// A platform specific [`NotificationSource`].
+pub type PlatformNotifier<'a, F> = macos::DiskArbitrationNotifier<'a, F>;
+
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
 /// A platform specific [`NotificationSource`].
 pub type PlatformNotifier<'a, F> = UnimplementedNotifier<'a, F>;
 
+#[cfg(windows)]
+/// A [`poll::PollingNotifier`] listing the platform specific file systems.
+pub type PlatformPollingNotifier<'a, F> = poll::PollingNotifier<'a, F, windows::VolumeList>;
+
+#[cfg(target_os = "linux")]
+/// A [`poll::PollingNotifier`] listing the platform specific file systems.
+pub type PlatformPollingNotifier<'a, F> = poll::PollingNotifier<'a, F, linux::MountTable>;
+
+#[cfg(target_os = "macos")]
+/// A [`poll::PollingNotifier`] listing the platform specific file systems.
+pub type PlatformPollingNotifier<'a, F> = poll::PollingNotifier<'a, F, macos::DiskList>;
+
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+/// A [`poll::PollingNotifier`] listing the platform specific file systems.
+pub type PlatformPollingNotifier<'a, F> = UnimplementedNotifier<'a, F>;
+
 /// Initialize the platform specific components.
 pub fn platform_init() -> Result<(), Box<dyn std::error::Error>> {
     #[cfg(windows)]
@@ -250,3 +560,107 @@ pub fn platform_init() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_space_of() {
+        let tmp_dir = std::env::temp_dir();
+        let space = space_of(&tmp_dir).unwrap();
+
+        assert!(space.total > 0);
+        assert!(space.free <= space.total);
+        assert!(space.available <= space.free);
+
+        assert!(space_of(&tmp_dir.join("does-not-exist-42")).is_err());
+    }
+
+    #[test]
+    fn test_removal_hook() {
+        let hook = RemovalHook::<String, u32>::default();
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen_clone = seen.clone();
+        hook.set(Box::new(move |e| {
+            seen_clone.lock().unwrap().push((e.volume, e.device));
+        }));
+
+        hook.spawned("a".to_string(), 1);
+        hook.spawned("b".to_string(), 2);
+        hook.removed(&"a".to_string());
+        // Only reported once, and never for volumes without a task.
+        hook.removed(&"a".to_string());
+        hook.removed(&"c".to_string());
+        hook.clear();
+        hook.removed(&"b".to_string());
+
+        assert_eq!(*seen.lock().unwrap(), vec![("a".to_string(), 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_abort_handle_holder_counts() {
+        let holder = AbortHandleHolder::<String>::default();
+        let running = tokio::spawn(std::future::pending::<()>());
+        let finished = tokio::spawn(async {});
+        holder.insert("running".to_string(), running.abort_handle(), None);
+        holder.insert("finished".to_string(), finished.abort_handle(), None);
+        finished.await.unwrap();
+
+        let mut keys = holder.tracked_keys();
+        keys.sort();
+        assert_eq!(keys, vec!["finished".to_string(), "running".to_string()]);
+
+        assert_eq!(holder.active_count(), 1);
+        assert_eq!(holder.tracked_keys(), vec!["running".to_string()]);
+        assert!(holder.is_running(&"running".to_string()));
+        assert!(!holder.is_running(&"finished".to_string()));
+
+        holder.clear_abort();
+        assert_eq!(holder.active_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_abort_handle_holder_task_end() {
+        let holder = AbortHandleHolder::<String>::default();
+        let ends = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let on_end = |name: &'static str| -> Option<OnTaskEnd> {
+            let ends = ends.clone();
+            Some(Box::new(move |end| ends.lock().unwrap().push((name, end))))
+        };
+
+        let finished = tokio::spawn(async {});
+        holder.insert(
+            "finished".to_string(),
+            finished.abort_handle(),
+            on_end("finished"),
+        );
+        let aborted = tokio::spawn(std::future::pending::<()>());
+        holder.insert(
+            "aborted".to_string(),
+            aborted.abort_handle(),
+            on_end("aborted"),
+        );
+        let cleared = tokio::spawn(std::future::pending::<()>());
+        holder.insert(
+            "cleared".to_string(),
+            cleared.abort_handle(),
+            on_end("cleared"),
+        );
+
+        finished.await.unwrap();
+        holder.gc();
+        holder.remove_abort(&"aborted".to_string());
+        assert!(aborted.await.unwrap_err().is_cancelled());
+        holder.clear_abort();
+
+        assert_eq!(
+            *ends.lock().unwrap(),
+            vec![
+                ("finished", TaskEnd::Completed),
+                ("aborted", TaskEnd::Aborted),
+                ("cleared", TaskEnd::Aborted),
+            ]
+        );
+    }
+}
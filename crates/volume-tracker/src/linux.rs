@@ -0,0 +1,741 @@
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    fs,
+    hash::Hash,
+    io,
+    marker::PhantomData,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crate::{
+    poll::VolumeSource, space_of, volume_span, AbortHandleHolder, Device, FileSystem,
+    NotificationSource, RemovalCallback, RemovalHook, SpaceInfo, SpawnerDisposition,
+};
+
+const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+const BY_LABEL_DIR: &str = "/dev/disk/by-label";
+const POLL_TIMEOUT_MS: i32 = 500;
+const JOIN_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A mounted block device file system, identified by its mount source like '/dev/sdb1'.
+#[derive(Clone)]
+pub struct VolumeName {
+    source: String,
+    label: Option<String>,
+    fs_type: String,
+    mount_point: PathBuf,
+}
+
+impl Debug for VolumeName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "VolumeName({})", self.source)
+    }
+}
+
+impl Hash for VolumeName {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.source.hash(state);
+    }
+}
+
+impl PartialEq for VolumeName {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for VolumeName {}
+
+impl VolumeName {
+    /// Get the mount source of the volume. Like '/dev/sdb1'.
+    #[must_use]
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+impl Display for VolumeName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl FileSystem for VolumeName {
+    fn name(&self) -> &str {
+        &self.source
+    }
+
+    /// The label as listed in '/dev/disk/by-label'.
+    fn label(&self) -> Option<String> {
+        self.label.clone()
+    }
+
+    fn fs_type(&self) -> Option<String> {
+        Some(self.fs_type.clone())
+    }
+
+    fn space(&self) -> Option<SpaceInfo> {
+        space_of(&self.mount_point)
+            .map_err(|e| log::warn!("Failed to query space of {}: {}", self, e))
+            .ok()
+    }
+}
+
+/// The canonical device node backing a volume, like '/dev/sdb1'.
+#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+pub struct DeviceName(String);
+
+impl Device for DeviceName {
+    fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+/// Errors that can occur in the Linux volume tracker.
+#[allow(missing_docs)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("io error on {0}: {1}")]
+    Io(&'static str, #[source] io::Error),
+}
+
+struct MountEntry {
+    mount_point: PathBuf,
+    fs_type: String,
+    source: String,
+}
+
+/// Decode the octal escapes (like '\040' for a space) used in '/proc/self/mountinfo'.
+fn unescape_octal(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = (bytes[i] == b'\\')
+            .then(|| input.get(i + 1..i + 4))
+            .flatten()
+            .and_then(|o| u8::from_str_radix(o, 8).ok());
+        if let Some(b) = octal {
+            out.push(b);
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Decode the hex escapes (like '\x20' for a space) used in '/dev/disk/by-label'.
+fn unescape_hex(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'\\' && bytes.get(i + 1) == Some(&b'x'))
+            .then(|| input.get(i + 2..i + 4))
+            .flatten()
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        if let Some(b) = hex {
+            out.push(b);
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn parse_mountinfo(content: &str) -> Vec<MountEntry> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let mount_point = fields.nth(4)?;
+            // Optional fields are terminated by a single hyphen.
+            let mut rest = fields.skip_while(|f| *f != "-").skip(1);
+            let fs_type = rest.next()?;
+            let source = rest.next()?;
+
+            Some(MountEntry {
+                mount_point: PathBuf::from(unescape_octal(mount_point)),
+                fs_type: unescape_octal(fs_type),
+                source: unescape_octal(source),
+            })
+        })
+        .collect()
+}
+
+fn read_labels() -> HashMap<PathBuf, String> {
+    let Ok(rd) = fs::read_dir(BY_LABEL_DIR) else {
+        return HashMap::new();
+    };
+
+    rd.filter_map(|entry| {
+        let entry = entry.ok()?;
+        let device = fs::canonicalize(entry.path()).ok()?;
+        Some((device, unescape_hex(&entry.file_name().to_string_lossy())))
+    })
+    .collect()
+}
+
+fn mounted_volumes() -> Result<Vec<(VolumeName, DeviceName, Vec<PathBuf>)>, Error> {
+    let content = fs::read_to_string(MOUNTINFO_PATH).map_err(|e| Error::Io("read mountinfo", e))?;
+    Ok(group_mounts(parse_mountinfo(&content), &read_labels()))
+}
+
+/// Group the block device mounts by device, keeping all of their mount points, like bind mounts.
+fn group_mounts(
+    entries: Vec<MountEntry>,
+    labels: &HashMap<PathBuf, String>,
+) -> Vec<(VolumeName, DeviceName, Vec<PathBuf>)> {
+    let mut volumes: Vec<(VolumeName, DeviceName, Vec<PathBuf>)> = Vec::new();
+
+    for entry in entries {
+        if !entry.source.starts_with("/dev/") {
+            continue;
+        }
+        if let Some((_, _, mount_points)) = volumes
+            .iter_mut()
+            .find(|(v, _, _)| v.source == entry.source)
+        {
+            mount_points.push(entry.mount_point);
+            continue;
+        }
+        let device =
+            fs::canonicalize(&entry.source).unwrap_or_else(|_| PathBuf::from(&entry.source));
+        let volume = VolumeName {
+            label: labels.get(&device).cloned(),
+            source: entry.source,
+            fs_type: entry.fs_type,
+            mount_point: entry.mount_point.clone(),
+        };
+        volumes.push((
+            volume,
+            DeviceName(device.to_string_lossy().into_owned()),
+            vec![entry.mount_point],
+        ));
+    }
+
+    volumes
+}
+
+/// The block device mounts listed in '/proc/self/mountinfo', for a
+/// [`PollingNotifier`](crate::poll::PollingNotifier).
+pub struct MountTable;
+
+impl VolumeSource for MountTable {
+    type FileSystem = VolumeName;
+    type Device = DeviceName;
+    type Error = Error;
+
+    fn open() -> Result<Self, Self::Error> {
+        Ok(Self)
+    }
+
+    fn list(&self) -> Result<Vec<(VolumeName, DeviceName, Vec<PathBuf>)>, Self::Error> {
+        mounted_volumes()
+    }
+}
+
+/// The netlink multicast group the kernel sends uevents to.
+///
+/// udevd re-broadcasts them to group 2 once it has handled them, but in its own binary format, so
+/// the kernel group is joined. Its events can arrive before udev has removed the device node or
+/// the mount is gone, which is fine: a removal here only aborts the tasks on that device early,
+/// the rescan that notices the unmount is also woken up by `POLLPRI` on '/proc/self/mountinfo'.
+const KERNEL_UEVENT_GROUP: u32 = 1;
+
+fn open_uevent_socket() -> io::Result<OwnedFd> {
+    let fd = socket(
+        libc::AF_NETLINK,
+        libc::SOCK_DGRAM | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+        libc::NETLINK_KOBJECT_UEVENT,
+    )?;
+    bind_netlink(&fd, KERNEL_UEVENT_GROUP)?;
+    Ok(fd)
+}
+
+/// Open a socket, owned so it is closed on every path.
+fn socket(domain: libc::c_int, ty: libc::c_int, protocol: libc::c_int) -> io::Result<OwnedFd> {
+    // SAFETY: takes no pointers, the result is checked before use.
+    let fd = unsafe { libc::socket(domain, ty, protocol) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `fd` was just opened and nothing else owns it.
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Bind the netlink socket `fd` to the multicast `groups`, with the port picked by the kernel.
+fn bind_netlink(fd: &OwnedFd, groups: u32) -> io::Result<()> {
+    // SAFETY: `sockaddr_nl` is plain integers, all zero is a valid value.
+    let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    {
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    }
+    addr.nl_groups = groups;
+
+    #[allow(clippy::cast_possible_truncation)]
+    let len = std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+    // SAFETY: `fd` is open, and `addr` is a `sockaddr_nl` of `len` bytes that outlives the call.
+    let ret = unsafe { libc::bind(fd.as_raw_fd(), std::ptr::from_ref(&addr).cast(), len) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Receive one datagram from `fd` into `buf` without blocking.
+///
+/// Gives its full length, which is more than `buf` holds if it was truncated.
+fn recv(fd: BorrowedFd<'_>, buf: &mut [u8]) -> io::Result<usize> {
+    // SAFETY: `fd` is open and `buf` is valid for writes of its length for the whole call.
+    let n = unsafe {
+        libc::recv(
+            fd.as_raw_fd(),
+            buf.as_mut_ptr().cast(),
+            buf.len(),
+            libc::MSG_DONTWAIT | libc::MSG_TRUNC,
+        )
+    };
+    usize::try_from(n).map_err(|_| io::Error::last_os_error())
+}
+
+/// Wait up to `timeout_ms` for the events of `fds`, giving how many have some.
+fn poll(fds: &mut [libc::pollfd], timeout_ms: i32) -> io::Result<usize> {
+    #[allow(clippy::cast_possible_truncation)]
+    let len = fds.len() as libc::nfds_t;
+    // SAFETY: `fds` is valid for reads and writes of `len` entries for the whole call.
+    let n = unsafe { libc::poll(fds.as_mut_ptr(), len, timeout_ms) };
+    usize::try_from(n).map_err(|_| io::Error::last_os_error())
+}
+
+/// Drain pending kernel uevents, returning the device nodes of removed block devices.
+///
+/// Stops once none are left, messages too long for the buffer are skipped.
+fn drain_uevents(fd: impl AsFd) -> Vec<String> {
+    let mut removed = Vec::new();
+    let mut buf = [0u8; 8192];
+
+    loop {
+        let n = match recv(fd.as_fd(), &mut buf) {
+            Ok(0) => break,
+            Ok(n) if n > buf.len() => {
+                log::warn!("Skipping a uevent of {} bytes, longer than expected", n);
+                continue;
+            }
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(e) => {
+                log::warn!("Failed to receive uevents: {}", e);
+                break;
+            }
+        };
+
+        if let Some(device) = parse_block_removal(&buf[..n]) {
+            removed.push(device);
+        }
+    }
+
+    removed
+}
+
+fn parse_block_removal(msg: &[u8]) -> Option<String> {
+    let mut fields = msg.split(|b| *b == 0).map(String::from_utf8_lossy);
+    // Messages re-broadcast by udevd start with "libudev" and are not handled here.
+    if !fields.next()?.contains('@') {
+        return None;
+    }
+
+    let mut action = None;
+    let mut subsystem = None;
+    let mut devname = None;
+    for field in fields {
+        if let Some(v) = field.strip_prefix("ACTION=") {
+            action = Some(v.to_string());
+        } else if let Some(v) = field.strip_prefix("SUBSYSTEM=") {
+            subsystem = Some(v.to_string());
+        } else if let Some(v) = field.strip_prefix("DEVNAME=") {
+            devname = Some(v.to_string());
+        }
+    }
+
+    if action.as_deref() != Some("remove") || subsystem.as_deref() != Some("block") {
+        return None;
+    }
+
+    devname.map(|d| format!("/dev/{d}"))
+}
+
+struct Watcher {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl Watcher {
+    /// Ask the thread to stop and wait for it without blocking the async runtime.
+    ///
+    /// The watcher is only borrowed, so the thread is still joined on drop if the future is
+    /// dropped early.
+    async fn stopped(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        while !self.thread.is_finished() {
+            tokio::time::sleep(JOIN_POLL_INTERVAL).await;
+        }
+    }
+
+    fn join(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if self.thread.join().is_err() {
+            log::error!("Mount watcher thread panicked");
+        }
+    }
+}
+
+struct WatchState<F> {
+    spawner: Arc<F>,
+    aborter: Arc<AbortHandleHolder<VolumeName>>,
+    removal: Arc<RemovalHook<VolumeName, DeviceName>>,
+    known: HashMap<VolumeName, DeviceName>,
+}
+
+impl<F> WatchState<F>
+where
+    F: Fn(VolumeName, DeviceName, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync,
+{
+    fn on_event(&mut self, removed_devices: Vec<String>) {
+        self.aborter.gc();
+
+        // A yanked device may stay in the mount table for a while, abort its tasks right away.
+        for device in removed_devices {
+            for (v, _) in self.known.iter().filter(|(_, d)| d.0 == device) {
+                log::info!("device removal: {:?}", v);
+                self.aborter.remove_abort(v);
+                self.removal.removed(v);
+            }
+        }
+
+        let current = match mounted_volumes() {
+            Ok(current) => current,
+            Err(e) => {
+                log::error!("Failed to list mounted volumes: {}", e);
+                return;
+            }
+        };
+
+        self.known.retain(|v, _| {
+            let mounted = current.iter().any(|(c, _, _)| c == v);
+            if !mounted {
+                log::info!("volume unmounted: {:?}", v);
+                self.aborter.remove_abort(v);
+                self.removal.removed(v);
+            }
+            mounted
+        });
+
+        for (v, d, p) in current {
+            if self.known.contains_key(&v) {
+                continue;
+            }
+            let _span = volume_span(&v).entered();
+            log::info!("new volume: {:?}", v);
+            match (self.spawner)(v.clone(), d.clone(), p) {
+                SpawnerDisposition::Spawned(handle, cleanup) => {
+                    self.aborter.insert(v.clone(), handle, cleanup);
+                    self.removal.spawned(v.clone(), d.clone());
+                    self.known.insert(v, d);
+                }
+                SpawnerDisposition::Ignore => {
+                    self.known.insert(v, d);
+                }
+                SpawnerDisposition::Skip => {}
+            }
+        }
+    }
+}
+
+/// A file system notification source for Linux watching '/proc/self/mountinfo' and kernel uevents.
+///
+/// The callback runs on the watcher thread, which nothing guarantees is joined before a borrow
+/// would end, so it must be `'static`.
+pub struct MountNotifier<
+    'a,
+    F: Fn(VolumeName, DeviceName, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync + 'a,
+> {
+    spawner: Arc<F>,
+    aborter: Arc<AbortHandleHolder<VolumeName>>,
+    removal: Arc<RemovalHook<VolumeName, DeviceName>>,
+    watcher: Option<Watcher>,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl<F: Fn(VolumeName, DeviceName, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync + 'static>
+    NotificationSource<'static, F> for MountNotifier<'static, F>
+{
+    type FileSystem = VolumeName;
+    type Device = DeviceName;
+    type Error = Error;
+
+    fn new(callback: F) -> Result<Self, Self::Error> {
+        Ok(Self {
+            spawner: Arc::new(callback),
+            aborter: Arc::new(AbortHandleHolder::default()),
+            removal: Arc::new(RemovalHook::default()),
+            watcher: None,
+            _marker: PhantomData,
+        })
+    }
+
+    fn list(&self) -> Result<Vec<(Self::FileSystem, Self::Device, Vec<PathBuf>)>, Self::Error> {
+        mounted_volumes()
+    }
+
+    fn list_spawn(&self) -> Result<(), Self::Error> {
+        self.aborter.clear_abort();
+        self.removal.clear();
+        let list = self.list()?;
+        for (v, d, p) in list {
+            let _span = volume_span(&v).entered();
+            if let SpawnerDisposition::Spawned(handle, cleanup) =
+                (self.spawner)(v.clone(), d.clone(), p)
+            {
+                self.aborter.insert(v.clone(), handle, cleanup);
+                self.removal.spawned(v, d);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn on_removal(&self, callback: RemovalCallback<Self::FileSystem, Self::Device>) {
+        self.removal.set(callback);
+    }
+
+    fn active_tasks(&self) -> usize {
+        self.aborter.active_count()
+    }
+
+    fn tracked_volumes(&self) -> Vec<Self::FileSystem> {
+        self.aborter.tracked_keys()
+    }
+
+    async fn start(&mut self) -> Result<(), Self::Error> {
+        if self.watcher.is_some() {
+            return Ok(());
+        }
+
+        let mountinfo =
+            fs::File::open(MOUNTINFO_PATH).map_err(|e| Error::Io("open mountinfo", e))?;
+        let uevent = match open_uevent_socket() {
+            Ok(fd) => Some(fd),
+            Err(e) => {
+                log::warn!(
+                    "Failed to open uevent socket, relying on mountinfo only: {}",
+                    e
+                );
+                None
+            }
+        };
+
+        let mut state = WatchState {
+            spawner: self.spawner.clone(),
+            aborter: self.aborter.clone(),
+            removal: self.removal.clone(),
+            // Volumes present now have been handled by `list_spawn`.
+            known: mounted_volumes()?
+                .into_iter()
+                .map(|(v, d, _)| (v, d))
+                .collect(),
+        };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+
+        let run = move || {
+            let mut fds = [
+                libc::pollfd {
+                    fd: mountinfo.as_raw_fd(),
+                    events: libc::POLLPRI,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: uevent.as_ref().map_or(-1, AsRawFd::as_raw_fd),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+
+            while !stop_clone.load(Ordering::Relaxed) {
+                for fd in &mut fds {
+                    fd.revents = 0;
+                }
+
+                match poll(&mut fds, POLL_TIMEOUT_MS) {
+                    Ok(0) => continue,
+                    Ok(_) => {}
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        log::error!("Failed to poll for mount changes: {}", e);
+                        break;
+                    }
+                }
+
+                let removed = match uevent.as_ref() {
+                    Some(fd) if fds[1].revents & libc::POLLIN != 0 => drain_uevents(fd),
+                    _ => Vec::new(),
+                };
+                state.on_event(removed);
+            }
+        };
+
+        let thread = std::thread::Builder::new()
+            .name("mount-watcher".to_string())
+            .spawn(run)
+            .map_err(|e| Error::Io("spawn watcher thread", e))?;
+
+        self.watcher = Some(Watcher { stop, thread });
+
+        Ok(())
+    }
+
+    async fn pause(&mut self) -> Result<(), Self::Error> {
+        if let Some(watcher) = &self.watcher {
+            watcher.stopped().await;
+        }
+        self.stop_watcher();
+
+        Ok(())
+    }
+
+    async fn reset(&mut self) -> Result<(), Self::Error> {
+        self.pause().await?;
+        self.aborter.clear_abort();
+        self.removal.clear();
+        Ok(())
+    }
+}
+
+impl<'a, F> MountNotifier<'a, F>
+where
+    F: Fn(VolumeName, DeviceName, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync + 'a,
+{
+    fn stop_watcher(&mut self) {
+        if let Some(watcher) = self.watcher.take() {
+            watcher.join();
+        }
+        self.aborter.gc();
+    }
+}
+
+impl<'a, F> Drop for MountNotifier<'a, F>
+where
+    F: Fn(VolumeName, DeviceName, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync + 'a,
+{
+    fn drop(&mut self) {
+        self.stop_watcher();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mountinfo() {
+        let content = "\
+22 1 8:2 / / rw,relatime shared:1 - ext4 /dev/sda2 rw
+36 22 0:32 / /proc rw,nosuid - proc proc rw
+98 22 8:17 / /media/USB\\040DRIVE rw,nosuid shared:40 master:1 - vfat /dev/sdb1 rw,fmask=0022
+";
+        let entries = parse_mountinfo(content);
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].source, "/dev/sda2");
+        assert_eq!(entries[0].mount_point, PathBuf::from("/"));
+        assert_eq!(entries[1].source, "proc");
+        assert_eq!(entries[2].source, "/dev/sdb1");
+        assert_eq!(entries[2].fs_type, "vfat");
+        assert_eq!(entries[2].mount_point, PathBuf::from("/media/USB DRIVE"));
+    }
+
+    #[test]
+    fn test_unescape_label() {
+        assert_eq!(unescape_hex(r"BACKUP\x20USB"), "BACKUP USB");
+        assert_eq!(unescape_hex(r"PLAIN"), "PLAIN");
+        assert_eq!(unescape_hex(r"TRAILING\x2"), r"TRAILING\x2");
+    }
+
+    #[test]
+    fn test_parse_block_removal() {
+        let msg = b"remove@/devices/pci0000:00/block/sdb/sdb1\0ACTION=remove\0SUBSYSTEM=block\0DEVNAME=sdb1\0";
+        assert_eq!(parse_block_removal(msg), Some("/dev/sdb1".to_string()));
+
+        let msg =
+            b"add@/devices/pci0000:00/block/sdb/sdb1\0ACTION=add\0SUBSYSTEM=block\0DEVNAME=sdb1\0";
+        assert_eq!(parse_block_removal(msg), None);
+
+        let msg = b"libudev\0ACTION=remove\0SUBSYSTEM=block\0DEVNAME=sdb1\0";
+        assert_eq!(parse_block_removal(msg), None);
+    }
+
+    #[test]
+    fn test_drain_uevents() {
+        let (tx, rx) = std::os::unix::net::UnixDatagram::pair().unwrap();
+        // Nothing pending.
+        assert!(drain_uevents(&rx).is_empty());
+
+        // Cut off by the buffer, so its fields cannot be trusted.
+        let mut long =
+            b"remove@/devices/block/sdc/sdc1\0ACTION=remove\0SUBSYSTEM=block\0DEVNAME=sdc1\0"
+                .to_vec();
+        long.resize(10_000, b'x');
+        tx.send(&long).unwrap();
+        // Cut off by the sender, without a device name.
+        tx.send(b"remove@/devices/block/sdd/sdd1\0ACTION=remove\0SUBSYSTEM=blo")
+            .unwrap();
+        tx.send(b"remove@/devices/block/sdb/sdb1\0ACTION=remove\0SUBSYSTEM=block\0DEVNAME=sdb1\0")
+            .unwrap();
+
+        assert_eq!(drain_uevents(&rx), ["/dev/sdb1"]);
+        assert!(drain_uevents(&rx).is_empty());
+    }
+
+    #[test]
+    fn test_group_mounts() {
+        let content = "\
+22 1 8:2 / / rw,relatime shared:1 - ext4 /dev/sda2 rw
+98 22 8:17 / /media/usb rw,nosuid - vfat /dev/sdb1 rw
+99 22 8:17 /backup /mnt/backup rw,nosuid - vfat /dev/sdb1 rw
+36 22 0:32 / /proc rw,nosuid - proc proc rw
+";
+        let volumes = group_mounts(parse_mountinfo(content), &HashMap::new());
+
+        let mounts = volumes
+            .iter()
+            .map(|(v, _, p)| (v.source(), p.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            mounts,
+            [
+                ("/dev/sda2", vec![PathBuf::from("/")]),
+                (
+                    "/dev/sdb1",
+                    vec![PathBuf::from("/media/usb"), PathBuf::from("/mnt/backup")]
+                ),
+            ]
+        );
+    }
+}
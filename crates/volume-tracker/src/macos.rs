@@ -0,0 +1,632 @@
+use std::{
+    collections::HashMap,
+    ffi::{c_char, c_void, CStr, CString},
+    fmt::{Debug, Display},
+    hash::Hash,
+    marker::PhantomData,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use core_foundation_sys::{
+    array::CFArrayRef,
+    base::{kCFAllocatorDefault, CFAllocatorRef, CFIndex, CFRelease},
+    dictionary::{CFDictionaryGetValue, CFDictionaryRef},
+    runloop::{
+        kCFRunLoopDefaultMode, kCFRunLoopRunFinished, CFRunLoopGetCurrent, CFRunLoopRef,
+        CFRunLoopRunInMode,
+    },
+    string::{
+        kCFStringEncodingUTF8, CFStringGetCString, CFStringGetLength,
+        CFStringGetMaximumSizeForEncoding, CFStringRef,
+    },
+    url::{CFURLGetFileSystemRepresentation, CFURLRef},
+};
+use tokio::sync::oneshot;
+
+use crate::{
+    poll::VolumeSource, volume_span, AbortHandleHolder, Device, FileSystem, NotificationSource,
+    RemovalCallback, RemovalHook, SpawnerDisposition,
+};
+
+const VOLUMES_DIR: &str = "/Volumes";
+const RUN_LOOP_TIMEOUT_SECS: f64 = 0.5;
+const JOIN_POLL_INTERVAL: Duration = Duration::from_millis(20);
+const MAX_PATH_LEN: usize = 1024;
+
+type DASessionRef = *const c_void;
+type DADiskRef = *const c_void;
+type DADiskAppearedCallback = extern "C" fn(disk: DADiskRef, context: *mut c_void);
+type DADiskDisappearedCallback = extern "C" fn(disk: DADiskRef, context: *mut c_void);
+type DADiskDescriptionChangedCallback =
+    extern "C" fn(disk: DADiskRef, keys: CFArrayRef, context: *mut c_void);
+
+#[link(name = "DiskArbitration", kind = "framework")]
+extern "C" {
+    static kDADiskDescriptionVolumeNameKey: CFStringRef;
+    static kDADiskDescriptionVolumePathKey: CFStringRef;
+    static kDADiskDescriptionWatchVolumePath: CFArrayRef;
+
+    fn DASessionCreate(allocator: CFAllocatorRef) -> DASessionRef;
+    fn DASessionScheduleWithRunLoop(
+        session: DASessionRef,
+        run_loop: CFRunLoopRef,
+        run_loop_mode: CFStringRef,
+    );
+    fn DASessionUnscheduleFromRunLoop(
+        session: DASessionRef,
+        run_loop: CFRunLoopRef,
+        run_loop_mode: CFStringRef,
+    );
+    fn DARegisterDiskAppearedCallback(
+        session: DASessionRef,
+        match_: CFDictionaryRef,
+        callback: DADiskAppearedCallback,
+        context: *mut c_void,
+    );
+    fn DARegisterDiskDisappearedCallback(
+        session: DASessionRef,
+        match_: CFDictionaryRef,
+        callback: DADiskDisappearedCallback,
+        context: *mut c_void,
+    );
+    fn DARegisterDiskDescriptionChangedCallback(
+        session: DASessionRef,
+        match_: CFDictionaryRef,
+        watch: CFArrayRef,
+        callback: DADiskDescriptionChangedCallback,
+        context: *mut c_void,
+    );
+    fn DAUnregisterCallback(session: DASessionRef, callback: *mut c_void, context: *mut c_void);
+    fn DADiskCreateFromBSDName(
+        allocator: CFAllocatorRef,
+        session: DASessionRef,
+        name: *const c_char,
+    ) -> DADiskRef;
+    fn DADiskCopyDescription(disk: DADiskRef) -> CFDictionaryRef;
+    fn DADiskGetBSDName(disk: DADiskRef) -> *const c_char;
+}
+
+/// A mounted volume, identified by the BSD name of its device node and displayed by its volume name.
+#[derive(Clone)]
+pub struct VolumeName {
+    name: String,
+    bsd_name: String,
+}
+
+impl Debug for VolumeName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "VolumeName({}, {})", self.name, self.bsd_name)
+    }
+}
+
+impl Hash for VolumeName {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.bsd_name.hash(state);
+    }
+}
+
+impl PartialEq for VolumeName {
+    fn eq(&self, other: &Self) -> bool {
+        self.bsd_name == other.bsd_name
+    }
+}
+
+impl Eq for VolumeName {}
+
+impl VolumeName {
+    /// Get the BSD name of the device node backing the volume. Like 'disk2s1'.
+    #[must_use]
+    pub fn bsd_name(&self) -> &str {
+        &self.bsd_name
+    }
+}
+
+impl Display for VolumeName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl FileSystem for VolumeName {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn label(&self) -> Option<String> {
+        Some(self.name.clone())
+    }
+}
+
+/// The BSD name of the device node backing a volume, like 'disk2s1'.
+#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+pub struct DeviceName(String);
+
+impl Device for DeviceName {
+    fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+/// Errors that can occur in the macOS volume tracker.
+#[allow(missing_docs)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("failed to create DiskArbitration session")]
+    SessionCreate,
+    #[error("io error on {0}: {1}")]
+    Io(&'static str, #[source] std::io::Error),
+}
+
+/// An owned `DASessionRef`.
+struct Session(DASessionRef);
+
+impl Session {
+    fn new() -> Result<Self, Error> {
+        let session = unsafe { DASessionCreate(kCFAllocatorDefault) };
+        if session.is_null() {
+            return Err(Error::SessionCreate);
+        }
+        Ok(Self(session))
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.0) };
+    }
+}
+
+unsafe fn cf_string(s: CFStringRef) -> Option<String> {
+    let len = CFStringGetLength(s);
+    let cap = CFStringGetMaximumSizeForEncoding(len, kCFStringEncodingUTF8) + 1;
+    let mut buf = vec![0u8; usize::try_from(cap).ok()?];
+    if CFStringGetCString(s, buf.as_mut_ptr().cast(), cap, kCFStringEncodingUTF8) == 0 {
+        return None;
+    }
+    CStr::from_bytes_until_nul(&buf)
+        .ok()
+        .map(|s| s.to_string_lossy().into_owned())
+}
+
+#[allow(clippy::cast_possible_wrap)]
+unsafe fn cf_url_path(url: CFURLRef) -> Option<PathBuf> {
+    let mut buf = [0u8; MAX_PATH_LEN];
+    if CFURLGetFileSystemRepresentation(url, 1, buf.as_mut_ptr(), buf.len() as CFIndex) == 0 {
+        return None;
+    }
+    let path = CStr::from_bytes_until_nul(&buf).ok()?;
+    Some(PathBuf::from(std::ffi::OsStr::from_bytes(path.to_bytes())))
+}
+
+/// Read the volume name, BSD name and mount point of a disk, if it is mounted under '/Volumes'.
+unsafe fn describe(disk: DADiskRef) -> Option<(VolumeName, DeviceName, PathBuf)> {
+    let bsd_name = DADiskGetBSDName(disk);
+    if bsd_name.is_null() {
+        return None;
+    }
+    let bsd_name = CStr::from_ptr(bsd_name).to_string_lossy().into_owned();
+
+    let desc = DADiskCopyDescription(disk);
+    if desc.is_null() {
+        return None;
+    }
+
+    let path = CFDictionaryGetValue(desc, kDADiskDescriptionVolumePathKey.cast());
+    let mount_point = if path.is_null() {
+        None
+    } else {
+        cf_url_path(path.cast())
+    };
+    let name = CFDictionaryGetValue(desc, kDADiskDescriptionVolumeNameKey.cast());
+    let name = if name.is_null() {
+        None
+    } else {
+        cf_string(name.cast())
+    };
+    CFRelease(desc.cast());
+
+    let mount_point = mount_point.filter(|p| p.starts_with(VOLUMES_DIR))?;
+    let name = name.unwrap_or_else(|| volume_name_from_path(&mount_point));
+
+    Some((
+        VolumeName {
+            name,
+            bsd_name: bsd_name.clone(),
+        },
+        DeviceName(bsd_name),
+        mount_point,
+    ))
+}
+
+fn volume_name_from_path(mount_point: &Path) -> String {
+    mount_point
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// The mounted volumes, listed through a new DiskArbitration session each time, for a
+/// [`PollingNotifier`](crate::poll::PollingNotifier).
+pub struct DiskList;
+
+impl VolumeSource for DiskList {
+    type FileSystem = VolumeName;
+    type Device = DeviceName;
+    type Error = Error;
+
+    fn open() -> Result<Self, Self::Error> {
+        Ok(Self)
+    }
+
+    fn list(&self) -> Result<Vec<(VolumeName, DeviceName, Vec<PathBuf>)>, Self::Error> {
+        let session = Session::new()?;
+        Ok(mounted_volumes(&session)?
+            .into_iter()
+            .map(|(v, d, p)| (v, d, vec![p]))
+            .collect())
+    }
+}
+
+fn mounted_volumes(session: &Session) -> Result<Vec<(VolumeName, DeviceName, PathBuf)>, Error> {
+    let mut mounts: *mut libc::statfs = std::ptr::null_mut();
+    let count = unsafe { libc::getmntinfo(std::ptr::addr_of_mut!(mounts), libc::MNT_NOWAIT) };
+    if count <= 0 {
+        return Err(Error::Io("getmntinfo", std::io::Error::last_os_error()));
+    }
+    let mounts = unsafe { std::slice::from_raw_parts(mounts, count.unsigned_abs() as usize) };
+
+    let mut volumes = Vec::new();
+    for mount in mounts {
+        let source = unsafe { CStr::from_ptr(mount.f_mntfromname.as_ptr()) };
+        let Some(bsd_name) = source.to_bytes().strip_prefix(b"/dev/") else {
+            continue;
+        };
+        let Ok(bsd_name) = CString::new(bsd_name) else {
+            continue;
+        };
+
+        let disk =
+            unsafe { DADiskCreateFromBSDName(kCFAllocatorDefault, session.0, bsd_name.as_ptr()) };
+        if disk.is_null() {
+            continue;
+        }
+        if let Some(volume) = unsafe { describe(disk) } {
+            volumes.push(volume);
+        }
+        unsafe { CFRelease(disk) };
+    }
+
+    Ok(volumes)
+}
+
+struct Watcher {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl Watcher {
+    /// Ask the thread to stop and wait for it without blocking the async runtime.
+    ///
+    /// The watcher is only borrowed, so the thread is still joined on drop if the future is
+    /// dropped early.
+    async fn stopped(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        while !self.thread.is_finished() {
+            tokio::time::sleep(JOIN_POLL_INTERVAL).await;
+        }
+    }
+
+    fn join(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if self.thread.join().is_err() {
+            log::error!("DiskArbitration thread panicked");
+        }
+    }
+}
+
+struct WatchState<F> {
+    spawner: Arc<F>,
+    aborter: Arc<AbortHandleHolder<VolumeName>>,
+    removal: Arc<RemovalHook<VolumeName, DeviceName>>,
+    known: HashMap<VolumeName, DeviceName>,
+}
+
+impl<F> WatchState<F>
+where
+    F: Fn(VolumeName, DeviceName, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync,
+{
+    fn on_mounted(&mut self, volume: VolumeName, device: DeviceName, mount_point: PathBuf) {
+        self.aborter.gc();
+        if self.known.contains_key(&volume) {
+            return;
+        }
+
+        let _span = volume_span(&volume).entered();
+        log::info!("new volume: {:?}", volume);
+        match (self.spawner)(volume.clone(), device.clone(), vec![mount_point]) {
+            SpawnerDisposition::Spawned(handle, cleanup) => {
+                self.aborter.insert(volume.clone(), handle, cleanup);
+                self.removal.spawned(volume.clone(), device.clone());
+                self.known.insert(volume, device);
+            }
+            SpawnerDisposition::Ignore => {
+                self.known.insert(volume, device);
+            }
+            SpawnerDisposition::Skip => {}
+        }
+    }
+
+    fn on_removed(&mut self, bsd_name: &str) {
+        self.aborter.gc();
+        self.known.retain(|v, _| {
+            if v.bsd_name != bsd_name {
+                return true;
+            }
+            log::info!("volume removed: {:?}", v);
+            self.aborter.remove_abort(v);
+            self.removal.removed(v);
+            false
+        });
+    }
+
+    unsafe fn on_disk(&mut self, disk: DADiskRef) {
+        if let Some((v, d, p)) = describe(disk) {
+            self.on_mounted(v, d, p);
+        } else {
+            let bsd_name = DADiskGetBSDName(disk);
+            if !bsd_name.is_null() {
+                self.on_removed(&CStr::from_ptr(bsd_name).to_string_lossy());
+            }
+        }
+    }
+}
+
+extern "C" fn disk_appeared<F>(disk: DADiskRef, context: *mut c_void)
+where
+    F: Fn(VolumeName, DeviceName, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync,
+{
+    // SAFETY: `context` is the `WatchState` owned by the run loop thread, and callbacks are only
+    // delivered on that thread while it is running the loop.
+    let state = unsafe { &mut *context.cast::<WatchState<F>>() };
+    unsafe { state.on_disk(disk) };
+}
+
+extern "C" fn disk_description_changed<F>(disk: DADiskRef, _keys: CFArrayRef, context: *mut c_void)
+where
+    F: Fn(VolumeName, DeviceName, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync,
+{
+    // SAFETY: see `disk_appeared`.
+    let state = unsafe { &mut *context.cast::<WatchState<F>>() };
+    unsafe { state.on_disk(disk) };
+}
+
+extern "C" fn disk_disappeared<F>(disk: DADiskRef, context: *mut c_void)
+where
+    F: Fn(VolumeName, DeviceName, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync,
+{
+    // SAFETY: see `disk_appeared`.
+    let state = unsafe { &mut *context.cast::<WatchState<F>>() };
+    let bsd_name = unsafe { DADiskGetBSDName(disk) };
+    if !bsd_name.is_null() {
+        state.on_removed(&unsafe { CStr::from_ptr(bsd_name) }.to_string_lossy());
+    }
+}
+
+/// Register the callbacks on a fresh session and run the current thread's run loop until `stop` is set.
+fn run_session<F>(
+    mut state: WatchState<F>,
+    stop: &AtomicBool,
+    ready: oneshot::Sender<Result<(), Error>>,
+) where
+    F: Fn(VolumeName, DeviceName, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync,
+{
+    let session = match Session::new() {
+        Ok(session) => session,
+        Err(e) => {
+            let _ = ready.send(Err(e));
+            return;
+        }
+    };
+    let context = std::ptr::from_mut(&mut state).cast::<c_void>();
+
+    unsafe {
+        DARegisterDiskAppearedCallback(session.0, std::ptr::null(), disk_appeared::<F>, context);
+        DARegisterDiskDisappearedCallback(
+            session.0,
+            std::ptr::null(),
+            disk_disappeared::<F>,
+            context,
+        );
+        DARegisterDiskDescriptionChangedCallback(
+            session.0,
+            std::ptr::null(),
+            kDADiskDescriptionWatchVolumePath,
+            disk_description_changed::<F>,
+            context,
+        );
+        DASessionScheduleWithRunLoop(session.0, CFRunLoopGetCurrent(), kCFRunLoopDefaultMode);
+    }
+    let _ = ready.send(Ok(()));
+
+    while !stop.load(Ordering::Relaxed) {
+        let ret = unsafe { CFRunLoopRunInMode(kCFRunLoopDefaultMode, RUN_LOOP_TIMEOUT_SECS, 0) };
+        if ret == kCFRunLoopRunFinished {
+            log::error!("DiskArbitration run loop finished unexpectedly");
+            break;
+        }
+    }
+
+    unsafe {
+        DASessionUnscheduleFromRunLoop(session.0, CFRunLoopGetCurrent(), kCFRunLoopDefaultMode);
+        DAUnregisterCallback(session.0, disk_appeared::<F> as *mut c_void, context);
+        DAUnregisterCallback(session.0, disk_disappeared::<F> as *mut c_void, context);
+        DAUnregisterCallback(
+            session.0,
+            disk_description_changed::<F> as *mut c_void,
+            context,
+        );
+    }
+}
+
+/// A file system notification source for macOS backed by the `DiskArbitration` framework.
+///
+/// The callback runs on the session thread, which nothing guarantees is joined before a borrow
+/// would end, so it must be `'static`.
+pub struct DiskArbitrationNotifier<
+    'a,
+    F: Fn(VolumeName, DeviceName, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync + 'a,
+> {
+    spawner: Arc<F>,
+    aborter: Arc<AbortHandleHolder<VolumeName>>,
+    removal: Arc<RemovalHook<VolumeName, DeviceName>>,
+    watcher: Option<Watcher>,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl<F: Fn(VolumeName, DeviceName, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync + 'static>
+    NotificationSource<'static, F> for DiskArbitrationNotifier<'static, F>
+{
+    type FileSystem = VolumeName;
+    type Device = DeviceName;
+    type Error = Error;
+
+    fn new(callback: F) -> Result<Self, Self::Error> {
+        Ok(Self {
+            spawner: Arc::new(callback),
+            aborter: Arc::new(AbortHandleHolder::default()),
+            removal: Arc::new(RemovalHook::default()),
+            watcher: None,
+            _marker: PhantomData,
+        })
+    }
+
+    fn list(&self) -> Result<Vec<(Self::FileSystem, Self::Device, Vec<PathBuf>)>, Self::Error> {
+        DiskList.list()
+    }
+
+    fn list_spawn(&self) -> Result<(), Self::Error> {
+        self.aborter.clear_abort();
+        self.removal.clear();
+        let list = self.list()?;
+        for (v, d, p) in list {
+            let _span = volume_span(&v).entered();
+            if let SpawnerDisposition::Spawned(handle, cleanup) =
+                (self.spawner)(v.clone(), d.clone(), p)
+            {
+                self.aborter.insert(v.clone(), handle, cleanup);
+                self.removal.spawned(v, d);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn on_removal(&self, callback: RemovalCallback<Self::FileSystem, Self::Device>) {
+        self.removal.set(callback);
+    }
+
+    fn active_tasks(&self) -> usize {
+        self.aborter.active_count()
+    }
+
+    fn tracked_volumes(&self) -> Vec<Self::FileSystem> {
+        self.aborter.tracked_keys()
+    }
+
+    async fn start(&mut self) -> Result<(), Self::Error> {
+        if self.watcher.is_some() {
+            return Ok(());
+        }
+
+        let state = WatchState {
+            spawner: self.spawner.clone(),
+            aborter: self.aborter.clone(),
+            removal: self.removal.clone(),
+            // Volumes present now have been handled by `list_spawn`.
+            known: self.list()?.into_iter().map(|(v, d, _)| (v, d)).collect(),
+        };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+        let (ready_tx, ready_rx) = oneshot::channel();
+
+        let thread = std::thread::Builder::new()
+            .name("disk-arbitration".to_string())
+            .spawn(move || run_session(state, &stop_clone, ready_tx))
+            .map_err(|e| Error::Io("spawn watcher thread", e))?;
+
+        // Stored before waiting so the thread is still joined if this future is dropped.
+        self.watcher = Some(Watcher { stop, thread });
+
+        match ready_rx.await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => {
+                self.stop_watcher();
+                Err(e)
+            }
+            Err(_) => {
+                self.stop_watcher();
+                Err(Error::SessionCreate)
+            }
+        }
+    }
+
+    async fn pause(&mut self) -> Result<(), Self::Error> {
+        if let Some(watcher) = &self.watcher {
+            watcher.stopped().await;
+        }
+        self.stop_watcher();
+
+        Ok(())
+    }
+
+    async fn reset(&mut self) -> Result<(), Self::Error> {
+        self.pause().await?;
+        self.aborter.clear_abort();
+        self.removal.clear();
+        Ok(())
+    }
+}
+
+impl<'a, F> DiskArbitrationNotifier<'a, F>
+where
+    F: Fn(VolumeName, DeviceName, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync + 'a,
+{
+    fn stop_watcher(&mut self) {
+        if let Some(watcher) = self.watcher.take() {
+            watcher.join();
+        }
+        self.aborter.gc();
+    }
+}
+
+impl<'a, F> Drop for DiskArbitrationNotifier<'a, F>
+where
+    F: Fn(VolumeName, DeviceName, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync + 'a,
+{
+    fn drop(&mut self) {
+        self.stop_watcher();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_volume_name_from_path() {
+        assert_eq!(
+            volume_name_from_path(Path::new("/Volumes/My Backup")),
+            "My Backup"
+        );
+        assert_eq!(volume_name_from_path(Path::new("/")), "");
+    }
+}
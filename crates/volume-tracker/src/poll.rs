@@ -0,0 +1,441 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    io,
+    marker::PhantomData,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    volume_span, AbortHandleHolder, Device, FileSystem, NotificationSource, RemovalCallback,
+    RemovalHook, SpawnerDisposition,
+};
+
+/// The default time between two listings of a [`PollingNotifier`].
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const JOIN_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Lists the file systems present right now, for a [`PollingNotifier`].
+pub trait VolumeSource: Send + Sync + 'static {
+    /// The file system type, usually a volume identifier.
+    type FileSystem: FileSystem + Clone + Hash + Eq + Send + Sync;
+    /// The device identifier.
+    type Device: Device + Clone + Send + Sync;
+    /// The error type.
+    type Error: std::error::Error + 'static;
+
+    /// Open the source.
+    fn open() -> Result<Self, Self::Error>
+    where
+        Self: Sized;
+    /// List all currently present file systems and their mount paths.
+    #[allow(clippy::type_complexity)]
+    fn list(&self) -> Result<Vec<(Self::FileSystem, Self::Device, Vec<PathBuf>)>, Self::Error>;
+}
+
+#[derive(Debug, thiserror::Error)]
+/// Errors that can occur in a [`PollingNotifier`].
+#[allow(missing_docs)]
+pub enum Error<E: std::error::Error + 'static> {
+    #[error(transparent)]
+    Source(E),
+    #[error("Failed to spawn the polling thread")]
+    Spawn(#[source] io::Error),
+}
+
+/// The file systems seen by the last listing, and what to do about the changes.
+pub(crate) struct PollState<L: VolumeSource, F> {
+    source: Arc<L>,
+    spawner: Arc<F>,
+    aborter: Arc<AbortHandleHolder<L::FileSystem>>,
+    removal: Arc<RemovalHook<L::FileSystem, L::Device>>,
+    known: HashMap<L::FileSystem, L::Device>,
+}
+
+impl<L: VolumeSource, F> PollState<L, F>
+where
+    F: Fn(L::FileSystem, L::Device, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync,
+{
+    /// Start from the file systems present now, which have been handled by `list_spawn`.
+    pub(crate) fn new(
+        source: Arc<L>,
+        spawner: Arc<F>,
+        aborter: Arc<AbortHandleHolder<L::FileSystem>>,
+        removal: Arc<RemovalHook<L::FileSystem, L::Device>>,
+    ) -> Result<Self, L::Error> {
+        let known = source.list()?.into_iter().map(|(v, d, _)| (v, d)).collect();
+        Ok(Self {
+            source,
+            spawner,
+            aborter,
+            removal,
+            known,
+        })
+    }
+
+    /// List the file systems again, aborting the tasks of the removed ones and spawning tasks
+    /// for the new ones.
+    fn poll(&mut self) {
+        self.aborter.gc();
+
+        let current = match self.source.list() {
+            Ok(current) => current,
+            Err(e) => {
+                log::error!("Failed to list volumes: {}", e);
+                return;
+            }
+        };
+
+        self.known.retain(|v, _| {
+            let present = current.iter().any(|(c, _, _)| c == v);
+            if !present {
+                log::info!("volume removed: {:?}", v);
+                self.aborter.remove_abort(v);
+                self.removal.removed(v);
+            }
+            present
+        });
+
+        for (v, d, p) in current {
+            if self.known.contains_key(&v) {
+                continue;
+            }
+            // Offered once it can be resolved.
+            if let Some(e) = d.error() {
+                log::debug!("Not offering volume {:?} yet: {}", v, e);
+                continue;
+            }
+            let _span = volume_span(&v).entered();
+            log::info!("new volume: {:?}", v);
+            match (self.spawner)(v.clone(), d.clone(), p) {
+                SpawnerDisposition::Spawned(handle, cleanup) => {
+                    self.aborter.insert(v.clone(), handle, cleanup);
+                    self.removal.spawned(v.clone(), d.clone());
+                    self.known.insert(v, d);
+                }
+                SpawnerDisposition::Ignore => {
+                    self.known.insert(v, d);
+                }
+                SpawnerDisposition::Skip => {}
+            }
+        }
+    }
+}
+
+/// A thread listing the file systems every interval.
+pub(crate) struct PollThread {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl PollThread {
+    /// Start polling.
+    ///
+    /// The owners stop the thread at the latest when dropped, but nothing guarantees they are,
+    /// so `F` must be `'static`.
+    pub(crate) fn spawn<L, F>(mut state: PollState<L, F>, interval: Duration) -> io::Result<Self>
+    where
+        L: VolumeSource,
+        F: Fn(L::FileSystem, L::Device, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+
+        let run = move || {
+            let mut next = Instant::now() + interval;
+            while !stop_clone.load(Ordering::Relaxed) {
+                let now = Instant::now();
+                if now < next {
+                    // Woken up early by `stop`.
+                    std::thread::park_timeout(next - now);
+                    continue;
+                }
+                state.poll();
+                next = Instant::now() + interval;
+            }
+        };
+
+        let thread = std::thread::Builder::new()
+            .name("volume-poller".to_string())
+            .spawn(run)?;
+
+        Ok(Self { stop, thread })
+    }
+
+    fn signal(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.thread().unpark();
+    }
+
+    /// Ask the thread to stop and wait for it without blocking the async runtime.
+    pub(crate) async fn stopped(&self) {
+        self.signal();
+        while !self.thread.is_finished() {
+            tokio::time::sleep(JOIN_POLL_INTERVAL).await;
+        }
+    }
+
+    pub(crate) fn join(self) {
+        self.signal();
+        if self.thread.join().is_err() {
+            log::error!("Volume poller thread panicked");
+        }
+    }
+}
+
+/// A notification source listing the file systems every interval and diffing the listings.
+///
+/// Changes are noticed up to an interval late, so this is meant for machines where the
+/// platform notifications cannot be registered, which the platform notifiers fall back to on
+/// their own where they can.
+///
+/// The callback runs on the polling thread, so it must be `'static`.
+pub struct PollingNotifier<'a, F, L: VolumeSource>
+where
+    F: Fn(L::FileSystem, L::Device, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync + 'a,
+{
+    source: Arc<L>,
+    spawner: Arc<F>,
+    aborter: Arc<AbortHandleHolder<L::FileSystem>>,
+    removal: Arc<RemovalHook<L::FileSystem, L::Device>>,
+    interval: Duration,
+    poller: Option<PollThread>,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl<'a, F, L: VolumeSource> PollingNotifier<'a, F, L>
+where
+    F: Fn(L::FileSystem, L::Device, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync + 'a,
+{
+    /// Create a new notification source listing the file systems every `interval`.
+    pub fn with_interval(callback: F, interval: Duration) -> Result<Self, Error<L::Error>> {
+        Ok(Self {
+            source: Arc::new(L::open().map_err(Error::Source)?),
+            spawner: Arc::new(callback),
+            aborter: Arc::new(AbortHandleHolder::default()),
+            removal: Arc::new(RemovalHook::default()),
+            interval,
+            poller: None,
+            _marker: PhantomData,
+        })
+    }
+
+    fn stop_poller(&mut self) {
+        if let Some(poller) = self.poller.take() {
+            poller.join();
+        }
+        self.aborter.gc();
+    }
+}
+
+impl<F, L: VolumeSource> NotificationSource<'static, F> for PollingNotifier<'static, F, L>
+where
+    F: Fn(L::FileSystem, L::Device, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync + 'static,
+{
+    type FileSystem = L::FileSystem;
+    type Device = L::Device;
+    type Error = Error<L::Error>;
+
+    fn new(callback: F) -> Result<Self, Self::Error> {
+        Self::with_interval(callback, DEFAULT_POLL_INTERVAL)
+    }
+
+    fn list(&self) -> Result<Vec<(Self::FileSystem, Self::Device, Vec<PathBuf>)>, Self::Error> {
+        self.source.list().map_err(Error::Source)
+    }
+
+    fn list_spawn(&self) -> Result<(), Self::Error> {
+        self.aborter.clear_abort();
+        self.removal.clear();
+        let list = self.list()?;
+        for (v, d, p) in list {
+            let _span = volume_span(&v).entered();
+            if let SpawnerDisposition::Spawned(handle, cleanup) =
+                (self.spawner)(v.clone(), d.clone(), p)
+            {
+                self.aborter.insert(v.clone(), handle, cleanup);
+                self.removal.spawned(v, d);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn on_removal(&self, callback: RemovalCallback<Self::FileSystem, Self::Device>) {
+        self.removal.set(callback);
+    }
+
+    fn active_tasks(&self) -> usize {
+        self.aborter.active_count()
+    }
+
+    fn tracked_volumes(&self) -> Vec<Self::FileSystem> {
+        self.aborter.tracked_keys()
+    }
+
+    async fn start(&mut self) -> Result<(), Self::Error> {
+        if self.poller.is_some() {
+            return Ok(());
+        }
+
+        let state = PollState::new(
+            self.source.clone(),
+            self.spawner.clone(),
+            self.aborter.clone(),
+            self.removal.clone(),
+        )
+        .map_err(Error::Source)?;
+        self.poller = Some(PollThread::spawn(state, self.interval).map_err(Error::Spawn)?);
+        log::warn!(
+            "Polling for volume changes every {:?}, changes are noticed late",
+            self.interval
+        );
+
+        Ok(())
+    }
+
+    async fn pause(&mut self) -> Result<(), Self::Error> {
+        if let Some(poller) = &self.poller {
+            poller.stopped().await;
+        }
+        self.stop_poller();
+
+        Ok(())
+    }
+
+    async fn reset(&mut self) -> Result<(), Self::Error> {
+        self.pause().await?;
+        self.aborter.clear_abort();
+        self.removal.clear();
+        Ok(())
+    }
+}
+
+impl<'a, F, L: VolumeSource> Drop for PollingNotifier<'a, F, L>
+where
+    F: Fn(L::FileSystem, L::Device, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync + 'a,
+{
+    fn drop(&mut self) {
+        self.stop_poller();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fmt::Display, sync::Mutex};
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    struct Volume(&'static str);
+
+    impl Display for Volume {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+
+    impl FileSystem for Volume {
+        fn name(&self) -> &str {
+            self.0
+        }
+    }
+
+    /// A device that cannot be resolved if `true`.
+    #[derive(Debug, Clone)]
+    struct Dev(bool);
+
+    impl Device for Dev {
+        fn name(&self) -> &str {
+            "dev"
+        }
+
+        fn error(&self) -> Option<&str> {
+            self.0.then_some("unresolved")
+        }
+    }
+
+    #[derive(Default)]
+    struct Listing(Mutex<Vec<&'static str>>);
+
+    impl Listing {
+        fn set(&self, names: &[&'static str]) {
+            *self.0.lock().unwrap() = names.to_vec();
+        }
+    }
+
+    impl VolumeSource for Listing {
+        type FileSystem = Volume;
+        type Device = Dev;
+        type Error = io::Error;
+
+        fn open() -> Result<Self, Self::Error> {
+            Ok(Self::default())
+        }
+
+        fn list(&self) -> Result<Vec<(Volume, Dev, Vec<PathBuf>)>, Self::Error> {
+            Ok(self
+                .0
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|v| (Volume(v), Dev(v.starts_with("broken")), vec![]))
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_poll_state() {
+        let source = Arc::new(Listing::default());
+        source.set(&["present"]);
+        let offered = Arc::new(Mutex::new(Vec::new()));
+        let spawner = Arc::new({
+            let offered = offered.clone();
+            move |v: Volume, _: Dev, _: Vec<PathBuf>| {
+                offered.lock().unwrap().push(v.0);
+                match v.0 {
+                    "skipped" => SpawnerDisposition::Skip,
+                    _ => SpawnerDisposition::Spawned(
+                        tokio::spawn(std::future::pending::<()>()).abort_handle(),
+                        None,
+                    ),
+                }
+            }
+        });
+        let aborter = Arc::new(AbortHandleHolder::default());
+        let removal = Arc::new(RemovalHook::<Volume, Dev>::default());
+        let removed = Arc::new(Mutex::new(Vec::new()));
+        removal.set(Box::new({
+            let removed = removed.clone();
+            move |e| removed.lock().unwrap().push(e.volume.0)
+        }));
+
+        let mut state = PollState::new(source.clone(), spawner, aborter.clone(), removal).unwrap();
+        // Volumes present when polling starts are left to `list_spawn`.
+        state.poll();
+        assert!(offered.lock().unwrap().is_empty());
+
+        source.set(&["present", "new", "skipped"]);
+        state.poll();
+        assert_eq!(*offered.lock().unwrap(), ["new", "skipped"]);
+        assert_eq!(aborter.active_count(), 1);
+
+        // Skipped volumes are offered again, spawned ones are not.
+        source.set(&["skipped"]);
+        state.poll();
+        assert_eq!(*offered.lock().unwrap(), ["new", "skipped", "skipped"]);
+        assert_eq!(*removed.lock().unwrap(), ["new"]);
+        assert_eq!(aborter.active_count(), 0);
+
+        // Volumes whose device cannot be resolved are not offered until it can.
+        source.set(&["broken"]);
+        state.poll();
+        assert_eq!(offered.lock().unwrap().len(), 3);
+    }
+}
@@ -6,7 +6,8 @@ use std::{
     ops::{Deref, DerefMut},
     path::PathBuf,
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
 };
 
 #[allow(clippy::upper_case_acronyms)]
@@ -15,35 +16,70 @@ type ULONG = c_ulong;
 type USHORT = c_ushort;
 
 use array::PzzWSTRIter;
-use dashmap::DashSet;
+use dashmap::{DashMap, DashSet};
 use mount_mgr::MountMgr;
 use windows::{
-    core::PCWSTR,
+    core::{GUID, PCWSTR},
     Win32::{
-        Devices::DeviceAndDriverInstallation::{
-            CM_Get_Device_Interface_ListW, CM_Get_Device_Interface_List_SizeW,
-            CM_Register_Notification, CM_Unregister_Notification,
-            CM_GET_DEVICE_INTERFACE_LIST_PRESENT, CM_NOTIFY_ACTION,
-            CM_NOTIFY_ACTION_DEVICEINTERFACEARRIVAL, CM_NOTIFY_ACTION_DEVICEINTERFACEREMOVAL,
-            CM_NOTIFY_EVENT_DATA, CM_NOTIFY_FILTER, CM_NOTIFY_FILTER_0, CM_NOTIFY_FILTER_0_2,
-            CM_NOTIFY_FILTER_TYPE_DEVICEINTERFACE, CR_BUFFER_SMALL, CR_SUCCESS, HCMNOTIFICATION,
+        Devices::{
+            DeviceAndDriverInstallation::{
+                CM_Get_DevNode_Status, CM_Get_Device_Interface_ListW,
+                CM_Get_Device_Interface_List_SizeW, CM_Get_Device_Interface_PropertyW,
+                CM_Get_Parent, CM_Locate_DevNodeW, CM_Register_Notification,
+                CM_Request_Device_EjectW, CM_Unregister_Notification, PNP_VetoTypeUnknown,
+                CM_DEVNODE_STATUS_FLAGS, CM_GET_DEVICE_INTERFACE_LIST_PRESENT,
+                CM_LOCATE_DEVNODE_NORMAL, CM_NOTIFY_ACTION,
+                CM_NOTIFY_ACTION_DEVICEINTERFACEARRIVAL, CM_NOTIFY_ACTION_DEVICEINTERFACEREMOVAL,
+                CM_NOTIFY_EVENT_DATA, CM_NOTIFY_FILTER, CM_NOTIFY_FILTER_0, CM_NOTIFY_FILTER_0_2,
+                CM_NOTIFY_FILTER_TYPE_DEVICEINTERFACE, CM_PROB, CR_BUFFER_SMALL, CR_REMOVE_VETOED,
+                CR_SUCCESS, DN_REMOVABLE, HCMNOTIFICATION, MAX_DEVICE_ID_LEN,
+            },
+            Properties::{DEVPKEY_Device_InstanceId, DEVPROPTYPE},
+        },
+        Foundation::{
+            CloseHandle, ERROR_MORE_DATA, ERROR_SUCCESS, FVE_E_LOCKED_VOLUME, HANDLE, MAX_PATH,
         },
-        Foundation::{CloseHandle, ERROR_SUCCESS, HANDLE, MAX_PATH},
         Storage::FileSystem::{
-            CreateFileW, FILE_ATTRIBUTE_NORMAL, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_ALWAYS,
+            CreateFileW, GetDiskFreeSpaceExW, GetDriveTypeW, GetVolumeInformationW,
+            GetVolumePathNamesForVolumeNameW, FILE_ATTRIBUTE_NORMAL, FILE_SHARE_READ,
+            FILE_SHARE_WRITE, OPEN_ALWAYS,
+        },
+        System::{
+            Ioctl::{
+                GUID_DEVINTERFACE_DISK, GUID_DEVINTERFACE_VOLUME, IOCTL_STORAGE_GET_DEVICE_NUMBER,
+                STORAGE_DEVICE_NUMBER,
+            },
+            IO::DeviceIoControl,
         },
-        System::{Ioctl::GUID_DEVINTERFACE_VOLUME, IO::DeviceIoControl},
     },
 };
 use wmi::Observer;
 
-use crate::{AbortHandleHolder, Device, FileSystem, NotificationSource, SpawnerDisposition};
+use crate::{
+    poll::{PollState, PollThread, VolumeSource, DEFAULT_POLL_INTERVAL},
+    volume_span, AbortHandleHolder, Device, DriveType, FileSystem, NotificationSource,
+    RemovalCallback, RemovalHook, SpaceInfo, SpawnerDisposition,
+};
 
 pub(crate) mod array;
 pub(crate) mod mount_mgr;
 pub(crate) mod wmi;
 
 /// The root path name of a volume, like '\\?\Volume{GUID}'.
+///
+/// The identifiers of a volume last differently:
+/// - The GUID path is only stable while the system remembers the volume, the same drive can get
+///   another one in a later session or on another machine.
+/// - The [`persistent_id`](Self::persistent_id) survives remounts and drive letter changes. The
+///   Mount Manager ID also survives reformatting the partition in place, but not repartitioning
+///   the disk, the serial number fallback does not survive either.
+/// - The [`serial_number`](Self::serial_number) survives remounts and moves across machines, but
+///   changes when the volume is reformatted.
+/// - The [`label`](Self::label) can be changed by the user at any time.
+///
+/// Tasks are tracked by the GUID path, which does not change while the volume is mounted and is
+/// all that is left to go by once it is removed. With [`HcmOptions::track_by_persistent_id`]
+/// arrivals are also checked against the persistent ID of the volumes being synced.
 #[derive(Clone)]
 pub struct VolumeName {
     nonpersistent_name: String,
@@ -73,21 +109,7 @@ impl Eq for VolumeName {}
 impl VolumeName {
     /// Get the device name of the volume. Like '\\Device\HarddiskVolume1'.
     pub fn device_name(&self) -> Result<DeviceName, Error> {
-        let mut file_name = self.nonpersistent_name.encode_utf16().collect::<Vec<_>>();
-        file_name.push(0);
-
-        let handle = DropHandle(unsafe {
-            CreateFileW(
-                PCWSTR::from_raw(file_name.as_ptr()),
-                0,
-                FILE_SHARE_READ | FILE_SHARE_WRITE,
-                None,
-                OPEN_ALWAYS,
-                FILE_ATTRIBUTE_NORMAL,
-                HANDLE(std::ptr::null_mut()),
-            )
-            .map_err(|e| Error::Win32Error("CreateFileW", e))?
-        });
+        let handle = open_device(&self.nonpersistent_name.encode_utf16().collect::<Vec<_>>())?;
 
         #[repr(C)]
         #[allow(non_camel_case_types)]
@@ -119,15 +141,419 @@ impl VolumeName {
             std::slice::from_raw_parts(buf.name.as_ptr(), (buf.size / 2) as usize)
         };
 
-        Ok(DeviceName(
-            String::from_utf16(volume_name).map_err(|_| Error::DecodeUtf16Error)?,
-        ))
+        Ok(DeviceName {
+            name: String::from_utf16(volume_name).map_err(|_| Error::DecodeUtf16Error)?,
+            error: None,
+        })
     }
 
     /// Get the DOS paths of the volume. Like 'C:'.
+    ///
+    /// Folders the volume is mounted at are not DOS paths, see [`mount_paths`](Self::mount_paths).
     pub fn dos_paths(&self) -> Result<Vec<String>, Error> {
         self.device_name()?.dos_paths(&self.mount_mgr)
     }
+
+    /// Get all the paths the volume is mounted at, drive letters like 'D:' first, then folders
+    /// like 'C:\mnt\data'.
+    pub fn mount_paths(&self) -> Result<Vec<String>, Error> {
+        let mut paths = self.dos_paths()?;
+        for path in self.volume_path_names()? {
+            if !paths.iter().any(|p| p.eq_ignore_ascii_case(&path)) {
+                paths.push(path);
+            }
+        }
+        Ok(paths)
+    }
+
+    fn volume_path_names(&self) -> Result<Vec<String>, Error> {
+        let mut name = format!("{}\\", self.nonpersistent_name)
+            .encode_utf16()
+            .collect::<Vec<_>>();
+        name.push(0);
+
+        let mut buf = vec![0u16; MAX_PATH as usize];
+        for _ in 0..5 {
+            let mut len = 0u32;
+            match unsafe {
+                GetVolumePathNamesForVolumeNameW(
+                    PCWSTR::from_raw(name.as_ptr()),
+                    Some(buf.as_mut_slice()),
+                    &mut len,
+                )
+            } {
+                Ok(()) => return parse_path_names(&buf[..len as usize]),
+                Err(e) if e.code() == ERROR_MORE_DATA.into() => buf.resize(len as usize, 0),
+                Err(e) => return Err(Error::win32("GetVolumePathNamesForVolumeNameW", e)),
+            }
+        }
+        Err(Error::TooManyRetries)
+    }
+
+    /// Get an identifier of the volume that survives remounts.
+    ///
+    /// This is the unique ID the Mount Manager derives from the disk signature or GPT partition
+    /// GUID as lowercase hex, or the serial number formatted like `A1B2-C3D4` when it reports
+    /// none.
+    pub fn persistent_id(&self) -> Result<String, Error> {
+        let device = self.device_name()?;
+        match self
+            .mount_mgr
+            .query_unique_id(&device.name.encode_utf16().collect::<Vec<_>>())?
+        {
+            Some(id) => Ok(id.iter().map(|b| format!("{b:02x}")).collect()),
+            None => Ok(format_serial(self.serial_number()?)),
+        }
+    }
+
+    /// Get the null-terminated root path of the volume, like 'D:\\'.
+    fn root_path(&self) -> Result<Vec<u16>, Error> {
+        // Volumes that are not mounted anywhere are still reachable by their GUID path.
+        let root = match self.mount_paths()?.into_iter().next() {
+            Some(path) => format!("{path}\\"),
+            None => format!("{}\\", self.nonpersistent_name),
+        };
+        let mut root = root.encode_utf16().collect::<Vec<_>>();
+        root.push(0);
+        Ok(root)
+    }
+
+    fn volume_information(&self) -> Result<VolumeInformation, Error> {
+        let root = self.root_path()?;
+
+        let mut label = [0u16; MAX_PATH as usize + 1];
+        let mut serial = 0u32;
+        let mut fs_name = [0u16; MAX_PATH as usize + 1];
+        unsafe {
+            GetVolumeInformationW(
+                PCWSTR::from_raw(root.as_ptr()),
+                Some(&mut label),
+                Some(&mut serial),
+                None,
+                None,
+                Some(&mut fs_name),
+            )
+            .map_err(|e| Error::win32("GetVolumeInformationW", e))?;
+        }
+
+        Ok(VolumeInformation {
+            label: from_utf16_nul(&label)?,
+            serial,
+            fs_type: from_utf16_nul(&fs_name)?,
+        })
+    }
+
+    /// Get the label of the volume. Like `BACKUP_USB`, empty if the volume has no label.
+    pub fn label(&self) -> Result<String, Error> {
+        Ok(self.volume_information()?.label)
+    }
+
+    /// Get the serial number of the volume, which changes when the volume is reformatted.
+    pub fn serial_number(&self) -> Result<u32, Error> {
+        Ok(self.volume_information()?.serial)
+    }
+
+    /// Get the file system type of the volume. Like 'NTFS', 'exFAT' or 'FAT32'.
+    pub fn file_system_type(&self) -> Result<String, Error> {
+        Ok(self.volume_information()?.fs_type)
+    }
+
+    /// Get the kind of drive backing the volume.
+    pub fn drive_type(&self) -> Result<DriveType, Error> {
+        const DRIVE_NO_ROOT_DIR: u32 = 1;
+        const DRIVE_REMOVABLE: u32 = 2;
+        const DRIVE_FIXED: u32 = 3;
+        const DRIVE_REMOTE: u32 = 4;
+        const DRIVE_CDROM: u32 = 5;
+        const DRIVE_RAMDISK: u32 = 6;
+
+        let root = self.root_path()?;
+        Ok(
+            match unsafe { GetDriveTypeW(PCWSTR::from_raw(root.as_ptr())) } {
+                DRIVE_NO_ROOT_DIR => {
+                    return Err(Error::syscall("GetDriveTypeW", DRIVE_NO_ROOT_DIR));
+                }
+                DRIVE_REMOVABLE => DriveType::Removable,
+                DRIVE_FIXED => DriveType::Fixed,
+                DRIVE_REMOTE => DriveType::Remote,
+                DRIVE_CDROM => DriveType::CdRom,
+                DRIVE_RAMDISK => DriveType::RamDisk,
+                _ => DriveType::Unknown,
+            },
+        )
+    }
+
+    /// Check whether the volume is locked by BitLocker, its file system cannot be queried or
+    /// read until it is unlocked.
+    pub fn is_locked(&self) -> Result<bool, Error> {
+        match self.volume_information() {
+            Ok(_) => Ok(false),
+            Err(Error::Win32Error(_, e)) if e.code() == FVE_E_LOCKED_VOLUME => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the total, free and available space of the volume.
+    pub fn space(&self) -> Result<SpaceInfo, Error> {
+        let root = self.root_path()?;
+        disk_free_space(PCWSTR::from_raw(root.as_ptr()))
+            .map_err(|e| Error::win32("GetDiskFreeSpaceExW", e))
+    }
+
+    /// Safely remove the drive holding the volume, like "Eject" in the Explorer.
+    ///
+    /// This is the removable device the disk of the volume hangs off, like the USB drive, so the
+    /// other volumes on it go with it. Fails with [`Error::DeviceInUse`] while a program has
+    /// files open on it, and with [`Error::NotRemovable`] for volumes on fixed disks or spanning
+    /// several disks.
+    pub fn eject(&self) -> Result<(), Error> {
+        let devinst = removable_ancestor(self.disk_devinst()?)?;
+
+        let mut veto_type = PNP_VetoTypeUnknown;
+        let mut veto_name = [0u16; MAX_PATH as usize];
+        let ret = unsafe {
+            CM_Request_Device_EjectW(
+                devinst,
+                Some(std::ptr::from_mut(&mut veto_type)),
+                Some(&mut veto_name),
+                0,
+            )
+        };
+        // A refused removal fills in the veto, with or without CR_REMOVE_VETOED.
+        if ret == CR_REMOVE_VETOED || veto_type != PNP_VetoTypeUnknown {
+            let vetoed_by = from_utf16_nul(&veto_name)?;
+            return Err(Error::DeviceInUse(if vetoed_by.is_empty() {
+                "another program".to_string()
+            } else {
+                vetoed_by
+            }));
+        }
+        if ret != CR_SUCCESS {
+            return Err(Error::syscall("CM_Request_Device_EjectW", ret.0));
+        }
+        Ok(())
+    }
+
+    /// Get the device instance of the disk holding the volume.
+    ///
+    /// Volumes hang off the volume manager rather than their disk, so the disk is found by its
+    /// storage device number.
+    fn disk_devinst(&self) -> Result<u32, Error> {
+        let volume = device_number(&self.nonpersistent_name.encode_utf16().collect::<Vec<_>>())?;
+        for disk in PzzWSTRIter::from_slice(&device_interfaces(&GUID_DEVINTERFACE_DISK)?) {
+            match device_number(disk) {
+                Ok(number)
+                    if number.DeviceType == volume.DeviceType
+                        && number.DeviceNumber == volume.DeviceNumber =>
+                {
+                    return interface_devinst(disk);
+                }
+                Ok(_) => {}
+                Err(e) => log::debug!(
+                    "Skipping disk {} while looking for {}: {}",
+                    String::from_utf16_lossy(disk),
+                    self,
+                    e
+                ),
+            }
+        }
+        Err(Error::NotRemovable)
+    }
+}
+
+/// Open the device at the path `name`, without a null, for device I/O controls.
+fn open_device(name: &[u16]) -> Result<DropHandle, Error> {
+    let mut file_name = name.to_vec();
+    file_name.push(0);
+
+    Ok(DropHandle(unsafe {
+        CreateFileW(
+            PCWSTR::from_raw(file_name.as_ptr()),
+            0,
+            FILE_SHARE_READ | FILE_SHARE_WRITE,
+            None,
+            OPEN_ALWAYS,
+            FILE_ATTRIBUTE_NORMAL,
+            HANDLE(std::ptr::null_mut()),
+        )
+        .map_err(|e| Error::Win32Error("CreateFileW", e))?
+    }))
+}
+
+/// Get the storage device number of the device at `name`, the same for a disk and the volumes
+/// on it.
+fn device_number(name: &[u16]) -> Result<STORAGE_DEVICE_NUMBER, Error> {
+    let handle = open_device(name)?;
+    let mut number = STORAGE_DEVICE_NUMBER::default();
+    unsafe {
+        #[allow(clippy::cast_possible_truncation)]
+        DeviceIoControl(
+            *handle,
+            IOCTL_STORAGE_GET_DEVICE_NUMBER,
+            None,
+            0,
+            Some(std::ptr::from_mut(&mut number).cast()),
+            std::mem::size_of_val(&number) as u32,
+            None,
+            None,
+        )
+        .map_err(|e| Error::Win32ErrorOnIoctl("IOCTL_STORAGE_GET_DEVICE_NUMBER", e))?;
+    }
+    Ok(number)
+}
+
+/// List the present device interfaces of `class`, as a double-null-terminated list.
+fn device_interfaces(class: &windows::core::GUID) -> Result<Vec<u16>, Error> {
+    for _ in 0..5 {
+        let mut char_count = 0u32;
+        let ret = unsafe {
+            CM_Get_Device_Interface_List_SizeW(
+                std::ptr::from_mut(&mut char_count),
+                std::ptr::from_ref(class),
+                PCWSTR::null(),
+                CM_GET_DEVICE_INTERFACE_LIST_PRESENT,
+            )
+        };
+        if ret != CR_SUCCESS {
+            return Err(Error::syscall("CM_Get_Device_Interface_List_SizeW", ret.0));
+        }
+
+        let mut buffer = vec![0u16; char_count as usize];
+
+        let ret = unsafe {
+            CM_Get_Device_Interface_ListW(
+                std::ptr::from_ref(class),
+                PCWSTR::null(),
+                &mut buffer,
+                CM_GET_DEVICE_INTERFACE_LIST_PRESENT,
+            )
+        };
+        match ret {
+            CR_SUCCESS => return Ok(buffer),
+            // An interface arrived in between.
+            CR_BUFFER_SMALL => {}
+            _ => return Err(Error::syscall("CM_Get_Device_Interface_ListW", ret.0)),
+        }
+    }
+
+    Err(Error::TooManyRetries)
+}
+
+/// Get the device instance exposing the device interface `interface`, without a null.
+fn interface_devinst(interface: &[u16]) -> Result<u32, Error> {
+    let mut interface = interface.to_vec();
+    interface.push(0);
+
+    let mut id = [0u16; MAX_DEVICE_ID_LEN as usize + 1];
+    let mut property_type = DEVPROPTYPE::default();
+    #[allow(clippy::cast_possible_truncation)]
+    let mut size = std::mem::size_of_val(&id) as u32;
+    let ret = unsafe {
+        CM_Get_Device_Interface_PropertyW(
+            PCWSTR::from_raw(interface.as_ptr()),
+            &DEVPKEY_Device_InstanceId,
+            &mut property_type,
+            Some(id.as_mut_ptr().cast()),
+            &mut size,
+            0,
+        )
+    };
+    if ret != CR_SUCCESS {
+        return Err(Error::syscall("CM_Get_Device_Interface_PropertyW", ret.0));
+    }
+
+    let mut devinst = 0u32;
+    let ret = unsafe {
+        CM_Locate_DevNodeW(
+            &mut devinst,
+            PCWSTR::from_raw(id.as_ptr()),
+            CM_LOCATE_DEVNODE_NORMAL,
+        )
+    };
+    if ret != CR_SUCCESS {
+        return Err(Error::syscall("CM_Locate_DevNodeW", ret.0));
+    }
+    Ok(devinst)
+}
+
+/// Walk up from the device instance `devinst` to the first one that can be removed, like the
+/// USB mass storage device above a disk.
+fn removable_ancestor(mut devinst: u32) -> Result<u32, Error> {
+    loop {
+        let mut status = CM_DEVNODE_STATUS_FLAGS::default();
+        let mut problem = CM_PROB::default();
+        let ret = unsafe { CM_Get_DevNode_Status(&mut status, &mut problem, devinst, 0) };
+        if ret != CR_SUCCESS {
+            return Err(Error::syscall("CM_Get_DevNode_Status", ret.0));
+        }
+        if status.contains(DN_REMOVABLE) {
+            return Ok(devinst);
+        }
+
+        let mut parent = 0u32;
+        if unsafe { CM_Get_Parent(&mut parent, devinst, 0) } != CR_SUCCESS {
+            // Past the root.
+            return Err(Error::NotRemovable);
+        }
+        devinst = parent;
+    }
+}
+
+struct VolumeInformation {
+    label: String,
+    serial: u32,
+    fs_type: String,
+}
+
+/// Split the path names returned by `GetVolumePathNamesForVolumeNameW`, like 'D:' for 'D:\' and
+/// 'C:\mnt\data' for 'C:\mnt\data\', the way DOS paths are reported.
+fn parse_path_names(buf: &[u16]) -> Result<Vec<String>, Error> {
+    buf.split(|c| *c == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            String::from_utf16(name)
+                .map(|name| name.trim_end_matches('\\').to_string())
+                .map_err(|_| Error::DecodeUtf16Error)
+        })
+        .collect()
+}
+
+/// Format a volume serial number like `A1B2-C3D4`.
+fn format_serial(serial: u32) -> String {
+    format!("{:04X}-{:04X}", serial >> 16, serial & 0xFFFF)
+}
+
+fn from_utf16_nul(buf: &[u16]) -> Result<String, Error> {
+    let len = buf.iter().position(|c| *c == 0).unwrap_or(buf.len());
+    String::from_utf16(&buf[..len]).map_err(|_| Error::DecodeUtf16Error)
+}
+
+fn disk_free_space(path: PCWSTR) -> windows::core::Result<SpaceInfo> {
+    let mut available = 0u64;
+    let mut total = 0u64;
+    let mut free = 0u64;
+    unsafe {
+        GetDiskFreeSpaceExW(
+            path,
+            Some(&mut available),
+            Some(&mut total),
+            Some(&mut free),
+        )?;
+    }
+
+    Ok(SpaceInfo {
+        total,
+        free,
+        available,
+    })
+}
+
+pub(crate) fn space_of(path: &std::path::Path) -> std::io::Result<SpaceInfo> {
+    use std::os::windows::ffi::OsStrExt;
+
+    let mut path = path.as_os_str().encode_wide().collect::<Vec<_>>();
+    path.push(0);
+    disk_free_space(PCWSTR::from_raw(path.as_ptr())).map_err(std::io::Error::from)
 }
 
 impl Display for VolumeName {
@@ -140,23 +566,96 @@ impl FileSystem for VolumeName {
     fn name(&self) -> &str {
         &self.nonpersistent_name
     }
+
+    fn label(&self) -> Option<String> {
+        VolumeName::label(self)
+            .map_err(|e| log::warn!("Failed to query label of {}: {}", self, e))
+            .ok()
+            .filter(|label| !label.is_empty())
+    }
+
+    fn serial(&self) -> Option<String> {
+        self.serial_number()
+            .map(format_serial)
+            .map_err(|e| log::warn!("Failed to query serial number of {}: {}", self, e))
+            .ok()
+    }
+
+    fn persistent_id(&self) -> Option<String> {
+        VolumeName::persistent_id(self)
+            .map_err(|e| log::warn!("Failed to query persistent ID of {}: {}", self, e))
+            .ok()
+    }
+
+    fn fs_type(&self) -> Option<String> {
+        self.file_system_type()
+            .map_err(|e| log::warn!("Failed to query file system type of {}: {}", self, e))
+            .ok()
+    }
+
+    fn space(&self) -> Option<SpaceInfo> {
+        VolumeName::space(self)
+            .map_err(|e| log::warn!("Failed to query space of {}: {}", self, e))
+            .ok()
+    }
+
+    fn drive_type(&self) -> Option<DriveType> {
+        VolumeName::drive_type(self)
+            .map_err(|e| log::warn!("Failed to query drive type of {}: {}", self, e))
+            .ok()
+    }
+
+    fn locked(&self) -> bool {
+        self.is_locked()
+            .map_err(|e| log::warn!("Failed to check whether {} is locked: {}", self, e))
+            .unwrap_or(false)
+    }
+
+    fn eject(&self) -> std::io::Result<()> {
+        VolumeName::eject(self).map_err(|e| {
+            let kind = match e {
+                Error::DeviceInUse(_) => std::io::ErrorKind::ResourceBusy,
+                Error::NotRemovable => std::io::ErrorKind::Unsupported,
+                _ => std::io::ErrorKind::Other,
+            };
+            std::io::Error::new(kind, e)
+        })
+    }
 }
 
 /// The resolved device name of a volume, like '\\Device\HarddiskVolume1'.
+///
+/// A volume whose device cannot be resolved is listed with an `unknown` device carrying the
+/// reason, see [`Device::error`].
 #[derive(Debug, Clone, PartialEq, Hash, Eq)]
-pub struct DeviceName(String);
+pub struct DeviceName {
+    name: String,
+    error: Option<String>,
+}
 
 impl Device for DeviceName {
     fn name(&self) -> &str {
-        &self.0
+        &self.name
+    }
+
+    fn error(&self) -> Option<&str> {
+        self.error.as_deref()
     }
 }
 
 impl DeviceName {
+    /// The device of a volume that could not be resolved because of `error`.
+    fn unresolved(error: &Error) -> Self {
+        Self {
+            name: "unknown".to_string(),
+            error: Some(error.to_string()),
+        }
+    }
+
     /// Get the DOS paths of the device. Like 'C:'.
     pub fn dos_paths(&self, mount_mgr: &MountMgr) -> Result<Vec<String>, Error> {
         Ok(mount_mgr
-            .query_points(&self.0.encode_utf16().collect::<Vec<_>>())?
+            .query_points(&self.name.encode_utf16().collect::<Vec<_>>())?
             .into_iter()
             .filter_map(|s| find_dos_path(&s).map(std::string::ToString::to_string))
             .collect())
@@ -217,6 +716,12 @@ pub enum Error {
     Overflow,
     #[error("Allocation failed")]
     AllocFailed,
+    #[error("io error on {0}: {1}")]
+    Io(&'static str, #[source] Arc<std::io::Error>),
+    #[error("the device is in use by {0}, close the files open on it and try again")]
+    DeviceInUse(String),
+    #[error("the device cannot be ejected")]
+    NotRemovable,
 }
 
 impl Error {
@@ -247,228 +752,523 @@ impl<T> DerefMut for UnsafeSync<T> {
 unsafe impl<T> Send for UnsafeSync<T> {}
 unsafe impl<T> Sync for UnsafeSync<T> {}
 
+/// The default interval in which repeated arrival events for the same volume are collapsed.
+pub const DEFAULT_COALESCE_INTERVAL: Duration = Duration::from_millis(500);
+/// The default time to wait for a newly arrived volume to be mounted.
+pub const DEFAULT_MOUNT_WAIT: Duration = Duration::from_secs(3);
+const MOUNT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone)]
+/// Options for [`HcmNotifier`].
+pub struct HcmOptions {
+    /// Arrival events for a volume that was handed to the callback less than this long ago are dropped.
+    pub coalesce_interval: Duration,
+    /// How long to wait for a new volume to get a drive letter or folder mount point before reporting it as not mounted.
+    pub mount_wait: Duration,
+    /// How often the volumes are listed when the notifications cannot be registered, like when
+    /// a policy denies them.
+    pub poll_interval: Duration,
+    /// The device interface classes to register for, like `GUID_DEVINTERFACE_DISK`, each
+    /// reported to [`HcmNotifier::on_interface_event`].
+    ///
+    /// Only the arrivals of `GUID_DEVINTERFACE_VOLUME` reach the spawner callback, leaving it out
+    /// means new volumes are only picked up by [`NotificationSource::list_spawn`].
+    pub interface_classes: Vec<GUID>,
+    /// Ignore the arrival of a volume with the same [`VolumeName::persistent_id`] as one still
+    /// being synced, like the same drive coming back under another GUID path.
+    pub track_by_persistent_id: bool,
+}
+
+impl Default for HcmOptions {
+    fn default() -> Self {
+        Self {
+            coalesce_interval: DEFAULT_COALESCE_INTERVAL,
+            mount_wait: DEFAULT_MOUNT_WAIT,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            interface_classes: vec![GUID_DEVINTERFACE_VOLUME],
+            track_by_persistent_id: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A device interface that arrived or went away, see [`HcmNotifier::on_interface_event`].
+pub struct InterfaceEvent {
+    /// The class of the interface, one of [`HcmOptions::interface_classes`].
+    pub class: GUID,
+    /// The symbolic link name of the interface, like '\\?\Volume{GUID}' for a volume.
+    pub name: String,
+    /// Whether the interface arrived rather than went away.
+    pub arrived: bool,
+}
+
+/// A callback told about [`InterfaceEvent`]s.
+pub type InterfaceCallback = Box<dyn Fn(&InterfaceEvent) + Send + Sync>;
+
+/// Poll the mount paths of a volume until some show up or `timeout` elapses, blocking the thread.
+///
+/// The arrival notification fires before the file system is mounted, so the first queries often come back empty.
+fn wait_for_mount_paths(mp: &VolumeName, timeout: Duration) -> Vec<PathBuf> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let last_err = match mp.mount_paths() {
+            Ok(paths) if !paths.is_empty() => {
+                return paths.into_iter().map(PathBuf::from).collect();
+            }
+            Ok(_) => None,
+            Err(e) => Some(e),
+        };
+
+        if Instant::now() >= deadline {
+            if let Some(e) = last_err {
+                log::warn!("Failed to get mount paths for volume {:?}: {}", mp, e);
+            }
+            return Vec::new();
+        }
+        std::thread::sleep(MOUNT_POLL_INTERVAL);
+    }
+}
+
+/// Hands arrived volumes to the spawner once they are mounted, each from a thread of its own since
+/// the notifications come on system threads that must not be held up.
+struct Dispatcher<F> {
+    spawner: Arc<F>,
+    aborter: Arc<AbortHandleHolder<VolumeName>>,
+    removal: Arc<RemovalHook<VolumeName, DeviceName>>,
+    queue: Arc<DashSet<VolumeName>>,
+    /// Volumes a thread waits for, taken out when they are removed in the meantime.
+    waiting: Arc<DashSet<VolumeName>>,
+    mount_wait: Duration,
+    /// The volumes handed to the spawner by persistent ID, with
+    /// [`HcmOptions::track_by_persistent_id`].
+    persistent_ids: Option<DashMap<String, VolumeName>>,
+}
+
+impl<F> Dispatcher<F>
+where
+    F: Fn(VolumeName, DeviceName, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync + 'static,
+{
+    /// Start waiting for `mp` to be mounted, unless a thread already does.
+    fn spawn(self: &Arc<Self>, mp: VolumeName) {
+        if !self.waiting.insert(mp.clone()) {
+            log::debug!("already waiting for volume {:?} to be mounted", mp);
+            return;
+        }
+        let dispatcher = Arc::clone(self);
+        let name = mp.clone();
+        if let Err(e) = std::thread::Builder::new()
+            .name("volume-arrival".to_string())
+            .spawn(move || dispatcher.dispatch(mp))
+        {
+            log::error!("Failed to spawn a thread for volume {:?}: {}", name, e);
+            self.waiting.remove(&name);
+            // Tried again on the next event.
+            self.queue.insert(name);
+        }
+    }
+
+    fn dispatch(&self, mp: VolumeName) {
+        let _span = volume_span(&mp).entered();
+        let d = match mp.device_name() {
+            Ok(device) => device,
+            Err(e) => {
+                log::error!("Failed to get device name for volume {:?}: {}", mp, e);
+                self.waiting.remove(&mp);
+                return;
+            }
+        };
+
+        let mount_paths = wait_for_mount_paths(&mp, self.mount_wait);
+        if self.waiting.remove(&mp).is_none() {
+            log::debug!("volume {:?} was removed before being mounted", mp);
+            return;
+        }
+
+        let mut tracked = None;
+        if let Some(ids) = &self.persistent_ids {
+            match mp.persistent_id() {
+                Ok(id) => {
+                    let other = ids.get(&id).map(|other| other.clone());
+                    if let Some(other) =
+                        other.filter(|other| *other != mp && self.aborter.is_running(other))
+                    {
+                        log::info!(
+                            "volume {:?} is already being synced as {:?}, ignoring it",
+                            mp,
+                            other
+                        );
+                        return;
+                    }
+                    tracked = Some((ids, id));
+                }
+                Err(e) => log::warn!("Failed to get persistent ID for volume {:?}: {}", mp, e),
+            }
+        }
+
+        match (self.spawner)(mp.clone(), d.clone(), mount_paths) {
+            SpawnerDisposition::Spawned(handle, cleanup) => {
+                if let Some((ids, id)) = tracked {
+                    ids.insert(id, mp.clone());
+                }
+                self.aborter.insert(mp.clone(), handle, cleanup);
+                self.removal.spawned(mp, d);
+            }
+            SpawnerDisposition::Ignore => {}
+            // Tried again on the next event.
+            SpawnerDisposition::Skip => {
+                self.queue.insert(mp);
+            }
+        }
+    }
+}
+
+fn dispatched_within(
+    last_dispatch: &DashMap<VolumeName, Instant>,
+    mp: &VolumeName,
+    interval: Duration,
+) -> bool {
+    last_dispatch
+        .get(mp)
+        .is_some_and(|t| t.elapsed() < interval)
+}
+
+/// The volume interfaces present, listed through the Plug and Play manager, for a
+/// [`PollingNotifier`](crate::poll::PollingNotifier).
+pub struct VolumeList {
+    mount_mgr: Arc<MountMgr>,
+}
+
+impl VolumeSource for VolumeList {
+    type FileSystem = VolumeName;
+    type Device = DeviceName;
+    type Error = Error;
+
+    fn open() -> Result<Self, Self::Error> {
+        Ok(Self {
+            mount_mgr: Arc::new(MountMgr::new()?),
+        })
+    }
+
+    fn list(&self) -> Result<Vec<(VolumeName, DeviceName, Vec<PathBuf>)>, Self::Error> {
+        let buffer = device_interfaces(&GUID_DEVINTERFACE_VOLUME)?;
+        Ok(PzzWSTRIter::from_slice(&buffer)
+            .map(|s| {
+                let mp = VolumeName {
+                    nonpersistent_name: String::from_utf16_lossy(s),
+                    mount_mgr: self.mount_mgr.clone(),
+                };
+                // Still listed so it can be seen, with the reason it is unusable.
+                let device = match mp.device_name() {
+                    Ok(device) => device,
+                    Err(e) => {
+                        log::error!("Failed to get device name for volume {:?}: {}", mp, e);
+                        return (mp, DeviceName::unresolved(&e), Vec::new());
+                    }
+                };
+
+                let mount_paths = match mp.mount_paths() {
+                    Ok(paths) => paths.into_iter().map(PathBuf::from).collect(),
+                    Err(e) => {
+                        log::warn!("Failed to get mount paths for volume {:?}: {}", mp, e);
+                        Vec::new()
+                    }
+                };
+
+                (mp, device, mount_paths)
+            })
+            .collect())
+    }
+}
+
 /// A file system notification source for Windows using the Plug and Play manager.
+///
+/// Starting and stopping make blocking COM calls through [`tokio::task::block_in_place`], so
+/// they must be awaited on a multi-threaded runtime.
+///
+/// If the notifications cannot be registered, starting falls back to listing the volumes every
+/// [`HcmOptions::poll_interval`] like a [`PollingNotifier`](crate::poll::PollingNotifier).
 pub struct HcmNotifier<
     'a,
-    F: Fn(VolumeName, DeviceName, Option<PathBuf>) -> SpawnerDisposition + Send + Sync + 'a,
+    F: Fn(VolumeName, DeviceName, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync + 'a,
 > {
-    handle: Option<UnsafeSync<HCMNOTIFICATION>>,
+    /// One registration for each of `interface_classes`.
+    handles: Vec<UnsafeSync<HCMNOTIFICATION>>,
     ctx: Pin<Box<Context>>,
     spawner: Arc<F>,
     wmi: Observer<'a>,
+    poller: Option<PollThread>,
+    poll_interval: Duration,
+    interface_classes: Vec<GUID>,
 }
 
 struct Context {
     aborter: Arc<AbortHandleHolder<VolumeName>>,
+    removal: Arc<RemovalHook<VolumeName, DeviceName>>,
     new_device_queue: Arc<DashSet<VolumeName>>,
+    /// Volumes waited for to be mounted, see [`Dispatcher::waiting`].
+    waiting: Arc<DashSet<VolumeName>>,
+    last_dispatch: Arc<DashMap<VolumeName, Instant>>,
+    coalesce_interval: Duration,
     mount_mgr: Arc<MountMgr>,
+    interface_callback: RwLock<Option<InterfaceCallback>>,
     _pin: PhantomPinned,
 }
 
-impl<
-        'a,
-        F: Fn(VolumeName, DeviceName, Option<PathBuf>) -> SpawnerDisposition + Send + Sync + 'a,
-    > NotificationSource<'a, F> for HcmNotifier<'a, F>
+impl<F: Fn(VolumeName, DeviceName, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync + 'static>
+    NotificationSource<'static, F> for HcmNotifier<'static, F>
 {
     type FileSystem = VolumeName;
     type Device = DeviceName;
     type Error = Error;
 
     fn new(callback: F) -> Result<Self, Self::Error> {
-        let queue = Arc::new(DashSet::<VolumeName>::new());
-        let queue_clone = queue.clone();
-        let aborter = Arc::new(AbortHandleHolder::default());
-        let aborter_clone = aborter.clone();
-        let callback = Arc::new(callback);
-        let callback_clone = callback.clone();
+        Self::with_options(callback, HcmOptions::default())
+    }
 
-        let inner_cb = Box::new(move || {
-            log::debug!("new device callback");
-            aborter_clone.gc();
+    fn list(&self) -> Result<Vec<(Self::FileSystem, Self::Device, Vec<PathBuf>)>, Self::Error> {
+        self.volumes().list()
+    }
 
-            queue_clone.retain(|mp| {
-                let d = match mp.device_name() {
-                    Ok(device) => device,
-                    Err(e) => {
-                        log::error!("Failed to get device name for volume {:?}: {}", *mp, e);
-                        return false;
-                    }
-                };
+    fn list_spawn(&self) -> Result<(), Self::Error> {
+        self.ctx.aborter.clear_abort();
+        self.ctx.removal.clear();
+        let list = self.list()?;
+        for (mp, d, mount_paths) in list {
+            let _span = volume_span(&mp).entered();
+            if d.error().is_some() {
+                continue;
+            }
+            if let SpawnerDisposition::Spawned(handle, cleanup) =
+                (self.spawner)(mp.clone(), d.clone(), mount_paths)
+            {
+                self.ctx.aborter.insert(mp.clone(), handle, cleanup);
+                self.ctx.removal.spawned(mp, d);
+            }
+        }
 
-                let dos_paths = match mp.dos_paths() {
-                    Ok(paths) => paths.into_iter().map(PathBuf::from).next(),
-                    Err(e) => {
-                        log::warn!("Failed to get DOS paths for volume {:?}: {}", *mp, e);
-                        None
-                    }
-                };
+        Ok(())
+    }
 
-                match callback_clone(mp.clone(), d.clone(), dos_paths) {
-                    SpawnerDisposition::Spawned(handle, cleanup) => {
-                        aborter_clone.insert(mp.clone(), handle, cleanup);
-                        false
-                    }
-                    SpawnerDisposition::Ignore => false,
-                    SpawnerDisposition::Skip => true,
-                }
-            });
-        });
+    fn on_removal(&self, callback: RemovalCallback<Self::FileSystem, Self::Device>) {
+        self.ctx.removal.set(callback);
+    }
 
-        Ok(Self {
-            handle: None,
-            ctx: Box::pin(Context {
-                aborter,
-                new_device_queue: queue,
-                mount_mgr: Arc::new(MountMgr::new()?),
-                _pin: PhantomPinned,
-            }),
-            spawner: callback,
-            wmi: Observer::new(inner_cb)?,
+    fn active_tasks(&self) -> usize {
+        self.ctx.aborter.active_count()
+    }
+
+    fn tracked_volumes(&self) -> Vec<Self::FileSystem> {
+        self.ctx.aborter.tracked_keys()
+    }
+
+    async fn start(&mut self) -> Result<(), Self::Error> {
+        // Registering works on the borrowed notifier, so it cannot be moved to the blocking pool.
+        tokio::task::block_in_place(|| match self.register() {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                log::warn!("Failed to register for volume notifications: {}", e);
+                // WMI may have been registered before the Plug and Play manager failed.
+                if let Err(e) = self.unregister() {
+                    log::warn!("Failed to undo the registration: {}", e);
+                }
+                self.start_polling()
+            }
         })
     }
 
-    fn list(&self) -> Result<Vec<(Self::FileSystem, Self::Device, Option<PathBuf>)>, Self::Error> {
-        let mut attempt = 0;
+    async fn pause(&mut self) -> Result<(), Self::Error> {
+        tokio::task::block_in_place(|| self.unregister())
+    }
+
+    async fn reset(&mut self) -> Result<(), Self::Error> {
+        self.pause().await?;
+        self.ctx.aborter.clear_abort();
+        self.ctx.removal.clear();
+        Ok(())
+    }
+}
 
-        while attempt < 5 {
-            attempt += 1;
+impl<F> HcmNotifier<'static, F>
+where
+    F: Fn(VolumeName, DeviceName, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync + 'static,
+{
+    fn register(&mut self) -> Result<(), Error> {
+        self.wmi.register()?;
 
-            let mut char_count = 0u32;
-            let ret = unsafe {
-                CM_Get_Device_Interface_List_SizeW(
-                    std::ptr::from_mut(&mut char_count),
-                    std::ptr::from_ref(&GUID_DEVINTERFACE_VOLUME),
-                    PCWSTR::null(),
-                    CM_GET_DEVICE_INTERFACE_LIST_PRESENT,
-                )
+        // The registrations made before one fails are undone by `unregister`.
+        for class in &self.interface_classes {
+            let filter = CM_NOTIFY_FILTER {
+                #[allow(clippy::cast_possible_truncation)]
+                cbSize: std::mem::size_of::<CM_NOTIFY_FILTER>() as u32,
+                Flags: 0,
+                FilterType: CM_NOTIFY_FILTER_TYPE_DEVICEINTERFACE,
+                u: CM_NOTIFY_FILTER_0 {
+                    DeviceInterface: CM_NOTIFY_FILTER_0_2 { ClassGuid: *class },
+                },
+                ..Default::default()
             };
-            if ret != CR_SUCCESS {
-                return Err(Error::syscall("CM_Get_Device_Interface_List_SizeW", ret.0));
-            }
 
-            let mut buffer = vec![0u16; char_count as usize];
+            let mut hnotify = HCMNOTIFICATION::default();
 
             let ret = unsafe {
-                CM_Get_Device_Interface_ListW(
-                    std::ptr::from_ref(&GUID_DEVINTERFACE_VOLUME),
-                    PCWSTR::null(),
-                    &mut buffer,
-                    CM_GET_DEVICE_INTERFACE_LIST_PRESENT,
+                CM_Register_Notification(
+                    std::ptr::from_ref(&filter),
+                    Some(std::ptr::from_ref::<Context>(&*self.ctx).cast()),
+                    Some(notify_proc),
+                    &mut hnotify,
                 )
             };
-
             if ret != CR_SUCCESS {
-                if ret == CR_BUFFER_SMALL {
-                    continue;
-                }
-                return Err(Error::syscall("CM_Get_Device_Interface_ListW", ret.0));
+                return Err(Error::syscall("CM_Register_Notification", ret.0));
             }
 
-            return Ok(unsafe { PzzWSTRIter::new(buffer.as_ptr()) }
-                .filter_map(|s| {
-                    let mp = VolumeName {
-                        nonpersistent_name: String::from_utf16_lossy(s),
-                        mount_mgr: self.ctx.mount_mgr.clone(),
-                    };
-                    let Ok(device) = mp.device_name() else {
-                        log::error!("Failed to get device name for volume: {:?}", mp);
-                        return None;
-                    };
-
-                    let dos_paths = match mp.dos_paths() {
-                        Ok(paths) => paths.into_iter().map(PathBuf::from).next(),
-                        Err(e) => {
-                            log::warn!("Failed to get DOS paths for volume {:?}: {}", mp, e);
-                            None
-                        }
-                    };
-
-                    Some((mp, device, dos_paths))
-                })
-                .collect());
-        }
-
-        Err(Error::TooManyRetries)
-    }
-
-    fn list_spawn(&self) -> Result<(), Self::Error> {
-        self.ctx.aborter.clear_abort();
-        let list = self.list()?;
-        for (mp, d, dos_paths) in list {
-            if let SpawnerDisposition::Spawned(handle, cleanup) =
-                (self.spawner)(mp.clone(), d.clone(), dos_paths)
-            {
-                self.ctx.aborter.insert(mp, handle, cleanup);
-            }
+            self.handles.push(UnsafeSync(hnotify));
         }
 
         Ok(())
     }
 
-    fn start(&mut self) -> Result<(), Self::Error> {
-        self.wmi.register()?;
-
-        let filter = CM_NOTIFY_FILTER {
-            #[allow(clippy::cast_possible_truncation)]
-            cbSize: std::mem::size_of::<CM_NOTIFY_FILTER>() as u32,
-            Flags: 0,
-            FilterType: CM_NOTIFY_FILTER_TYPE_DEVICEINTERFACE,
-            u: CM_NOTIFY_FILTER_0 {
-                DeviceInterface: CM_NOTIFY_FILTER_0_2 {
-                    ClassGuid: GUID_DEVINTERFACE_VOLUME,
-                },
-            },
-            ..Default::default()
+    /// Replace the callback told when an interface of one of [`HcmOptions::interface_classes`]
+    /// arrives or goes away, volumes included.
+    ///
+    /// It is called on a system thread and must return quickly.
+    pub fn on_interface_event(&self, callback: InterfaceCallback) {
+        let mut guard = match self.ctx.interface_callback.write() {
+            Ok(g) => g,
+            Err(p) => p.into_inner(),
         };
+        *guard = Some(callback);
+    }
 
-        let mut hnotify = HCMNOTIFICATION::default();
-
-        let ret = unsafe {
-            CM_Register_Notification(
-                std::ptr::from_ref(&filter),
-                Some(std::ptr::from_ref::<Context>(&*self.ctx).cast()),
-                Some(notify_proc),
-                &mut hnotify,
-            )
-        };
-        if ret != CR_SUCCESS {
-            return Err(Error::syscall("CM_Register_Notification", ret.0));
+    fn start_polling(&mut self) -> Result<(), Error> {
+        if self.poller.is_some() {
+            return Ok(());
         }
 
-        self.handle = Some(UnsafeSync(hnotify));
+        let state = PollState::new(
+            Arc::new(self.volumes()),
+            self.spawner.clone(),
+            self.ctx.aborter.clone(),
+            self.ctx.removal.clone(),
+        )?;
+        let poller = PollThread::spawn(state, self.poll_interval)
+            .map_err(|e| Error::Io("spawn poller thread", Arc::new(e)))?;
+        self.poller = Some(poller);
+        log::warn!(
+            "Running in degraded mode, listing volumes every {:?} instead",
+            self.poll_interval
+        );
 
         Ok(())
     }
 
-    fn pause(&mut self) -> Result<(), Self::Error> {
-        self.wmi.unregister()?;
-        if let Some(handle) = self.handle.take() {
-            unsafe {
-                let ret = CM_Unregister_Notification(*handle);
+    fn volumes(&self) -> VolumeList {
+        VolumeList {
+            mount_mgr: self.ctx.mount_mgr.clone(),
+        }
+    }
 
-                if ret != CR_SUCCESS {
-                    return Err(Error::syscall("CM_Unregister_Notification", ret.0));
-                }
+    fn unregister(&mut self) -> Result<(), Error> {
+        if let Some(poller) = self.poller.take() {
+            poller.join();
+        }
+        self.wmi.unregister()?;
+        // The others are still unregistered when one fails.
+        let mut result = Ok(());
+        for handle in self.handles.drain(..) {
+            let ret = unsafe { CM_Unregister_Notification(*handle) };
+            if ret != CR_SUCCESS {
+                result = Err(Error::syscall("CM_Unregister_Notification", ret.0));
             }
         }
         self.ctx.aborter.gc();
 
-        Ok(())
+        result
     }
 
-    fn reset(&mut self) -> Result<(), Self::Error> {
-        self.pause()?;
-        self.ctx.aborter.clear_abort();
-        Ok(())
+    /// Create a new notification source with the given callback and options.
+    ///
+    /// The callback is called from threads of the notifier, so it must be `'static`.
+    pub fn with_options(callback: F, options: HcmOptions) -> Result<Self, Error> {
+        let queue = Arc::new(DashSet::<VolumeName>::new());
+        let queue_clone = queue.clone();
+        let aborter = Arc::new(AbortHandleHolder::default());
+        let aborter_clone = aborter.clone();
+        let removal = Arc::new(RemovalHook::default());
+        let waiting = Arc::new(DashSet::<VolumeName>::new());
+        let callback = Arc::new(callback);
+        let last_dispatch = Arc::new(DashMap::<VolumeName, Instant>::new());
+        let last_dispatch_clone = last_dispatch.clone();
+        let coalesce_interval = options.coalesce_interval;
+        let dispatcher = Arc::new(Dispatcher {
+            spawner: callback.clone(),
+            aborter: aborter.clone(),
+            removal: removal.clone(),
+            queue: queue.clone(),
+            waiting: waiting.clone(),
+            mount_wait: options.mount_wait,
+            persistent_ids: options.track_by_persistent_id.then(DashMap::new),
+        });
+
+        let inner_cb = Box::new(move || {
+            log::debug!("new device callback");
+            aborter_clone.gc();
+            last_dispatch_clone.retain(|_, t| t.elapsed() < coalesce_interval);
+
+            // Work on a snapshot so arrivals and removals are not blocked while dispatching.
+            let pending = queue_clone
+                .iter()
+                .map(|mp| mp.key().clone())
+                .collect::<Vec<_>>();
+            for mp in pending {
+                let _span = volume_span(&mp).entered();
+                // Part of the burst of events that was just handed to the callback.
+                if dispatched_within(&last_dispatch_clone, &mp, coalesce_interval) {
+                    log::debug!("coalescing repeated event for volume {:?}", mp);
+                    queue_clone.remove(&mp);
+                    continue;
+                }
+                // Spawning again would replace the handle of the task still syncing it.
+                if aborter_clone.is_running(&mp) {
+                    log::debug!("volume {:?} is already being synced", mp);
+                    queue_clone.remove(&mp);
+                    continue;
+                }
+
+                queue_clone.remove(&mp);
+                last_dispatch_clone.insert(mp.clone(), Instant::now());
+                dispatcher.spawn(mp);
+            }
+        });
+
+        Ok(Self {
+            handles: Vec::new(),
+            ctx: Box::pin(Context {
+                aborter,
+                removal,
+                new_device_queue: queue,
+                waiting,
+                last_dispatch,
+                coalesce_interval,
+                mount_mgr: Arc::new(MountMgr::new()?),
+                interface_callback: RwLock::new(None),
+                _pin: PhantomPinned,
+            }),
+            spawner: callback,
+            wmi: Observer::new(inner_cb)?,
+            poller: None,
+            poll_interval: options.poll_interval,
+            interface_classes: options.interface_classes,
+        })
     }
 }
 
 impl<'a, F> Drop for HcmNotifier<'a, F>
 where
-    F: Fn(VolumeName, DeviceName, Option<PathBuf>) -> SpawnerDisposition + Send + Sync + 'a,
+    F: Fn(VolumeName, DeviceName, Vec<PathBuf>) -> SpawnerDisposition + Send + Sync + 'a,
 {
     fn drop(&mut self) {
-        if let Err(e) = self.pause() {
+        if let Err(e) = self.unregister() {
             log::error!("Failed to unregister notification: {}", e);
         }
     }
@@ -495,6 +1295,7 @@ unsafe extern "system" fn notify_proc(
                 .cast::<CM_NOTIFY_EVENT_DATA>()
                 .as_ref()
                 .expect("invalid event data");
+            let class = data.u.DeviceInterface.ClassGuid;
             let name = data.u.DeviceInterface.SymbolicLink.as_ptr();
             let mut end_ptr = evt_data.byte_add(evt_data_size as usize).cast::<u16>();
             while end_ptr > name && (*end_ptr.sub(1)) == 0 {
@@ -502,23 +1303,52 @@ unsafe extern "system" fn notify_proc(
             }
 
             #[allow(clippy::cast_sign_loss)]
+            let name = String::from_utf16_lossy(std::slice::from_raw_parts(
+                name,
+                end_ptr.offset_from(name) as usize,
+            ));
+
+            let guard = match ctx.interface_callback.read() {
+                Ok(g) => g,
+                Err(p) => p.into_inner(),
+            };
+            if let Some(callback) = guard.as_ref() {
+                callback(&InterfaceEvent {
+                    class,
+                    name: name.clone(),
+                    arrived: action == CM_NOTIFY_ACTION_DEVICEINTERFACEARRIVAL,
+                });
+            }
+            drop(guard);
+
+            // Other classes are only for the interface callback.
+            if class != GUID_DEVINTERFACE_VOLUME {
+                log::debug!("interface event of class {:?}: {}", class, name);
+                return ERROR_SUCCESS.0;
+            }
+
             let mp = VolumeName {
-                nonpersistent_name: String::from_utf16_lossy(std::slice::from_raw_parts(
-                    name,
-                    end_ptr.offset_from(name) as usize,
-                )),
+                nonpersistent_name: name,
                 mount_mgr: ctx.mount_mgr.clone(),
             };
 
             match action {
                 CM_NOTIFY_ACTION_DEVICEINTERFACEARRIVAL => {
-                    log::info!("new device arrival: {:?}", &mp);
-                    ctx.new_device_queue.insert(mp);
+                    if dispatched_within(&ctx.last_dispatch, &mp, ctx.coalesce_interval) {
+                        log::debug!("ignoring repeated arrival: {:?}", &mp);
+                    } else {
+                        log::info!("new device arrival: {:?}", &mp);
+                        ctx.new_device_queue.insert(mp);
+                    }
                 }
                 CM_NOTIFY_ACTION_DEVICEINTERFACEREMOVAL => {
                     log::info!("device removal: {:?}", &mp);
                     ctx.new_device_queue.remove(&mp);
+                    ctx.waiting.remove(&mp);
+                    // A removal followed by a new arrival is genuine work, not a duplicate.
+                    ctx.last_dispatch.remove(&mp);
                     ctx.aborter.remove_abort(&mp);
+                    ctx.removal.removed(&mp);
                 }
                 _ => unreachable!(),
             }
@@ -527,3 +1357,31 @@ unsafe extern "system" fn notify_proc(
     }
     ERROR_SUCCESS.0
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path_names(names: &[&str]) -> Vec<u16> {
+        let mut buf = Vec::new();
+        for name in names {
+            buf.extend(name.encode_utf16());
+            buf.push(0);
+        }
+        buf.push(0);
+        buf
+    }
+
+    #[test]
+    fn test_parse_path_names() {
+        let buf = path_names(&[r"D:\", r"C:\mnt\data\"]);
+        assert_eq!(parse_path_names(&buf).unwrap(), ["D:", r"C:\mnt\data"]);
+
+        // A volume mounted only as a folder has no drive letter to go by.
+        let buf = path_names(&[r"C:\mnt\data\"]);
+        assert_eq!(parse_path_names(&buf).unwrap(), [r"C:\mnt\data"]);
+        assert_eq!(find_dos_path(r"C:\mnt\data"), None);
+
+        assert!(parse_path_names(&path_names(&[])).unwrap().is_empty());
+    }
+}
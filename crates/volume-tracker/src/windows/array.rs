@@ -1,16 +1,34 @@
-use std::marker::PhantomData;
-
+/// Iterator over the strings of a double-null-terminated UTF-16 list, like a `PZZWSTR`.
 pub struct PzzWSTRIter<'a> {
-    ptr: *const u16,
-    _marker: PhantomData<&'a u16>,
+    rest: &'a [u16],
 }
 
-impl PzzWSTRIter<'_> {
+impl<'a> PzzWSTRIter<'a> {
+    /// Iterate over the list in `buf`, stopping at the first empty string or at the end of the
+    /// slice, whichever comes first.
+    pub fn from_slice(buf: &'a [u16]) -> Self {
+        Self { rest: buf }
+    }
+
+    /// Iterate over the list at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null or point to a list terminated by an empty string, which must stay
+    /// valid and unmodified for `'a`.
+    #[allow(dead_code)]
     pub unsafe fn new(ptr: *const u16) -> Self {
-        Self {
-            ptr,
-            _marker: PhantomData,
+        if ptr.is_null() {
+            return Self::from_slice(&[]);
+        }
+
+        // Measure up to the null ending the terminating empty string.
+        let mut len = 0;
+        while *ptr.add(len) != 0 || (len > 0 && *ptr.add(len - 1) != 0) {
+            len += 1;
         }
+
+        Self::from_slice(std::slice::from_raw_parts(ptr, len + 1))
     }
 }
 
@@ -18,26 +36,65 @@ impl<'a> Iterator for PzzWSTRIter<'a> {
     type Item = &'a [u16];
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.ptr.is_null() {
+        let end = self
+            .rest
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(self.rest.len());
+        if end == 0 {
+            self.rest = &[];
             return None;
         }
 
-        unsafe {
-            if (*self.ptr) == 0 {
-                return None;
-            }
+        let (s, rest) = self.rest.split_at(end);
+        self.rest = rest.get(1..).unwrap_or_default();
 
-            let mut end_ptr = self.ptr;
-            while (*end_ptr) != 0 {
-                end_ptr = end_ptr.add(1);
-            }
+        Some(s)
+    }
+}
 
-            #[allow(clippy::cast_sign_loss)]
-            let slice =
-                std::slice::from_raw_parts(self.ptr, end_ptr.offset_from(self.ptr) as usize);
-            self.ptr = end_ptr.add(1);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            Some(slice)
-        }
+    fn list(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    fn collect(iter: PzzWSTRIter) -> Vec<String> {
+        iter.map(String::from_utf16_lossy).collect()
+    }
+
+    #[test]
+    fn test_pzzwstr_iter() {
+        let buf = list("C:\\\0D:\\\0\0");
+        assert_eq!(collect(PzzWSTRIter::from_slice(&buf)), ["C:\\", "D:\\"]);
+        assert_eq!(
+            collect(unsafe { PzzWSTRIter::new(buf.as_ptr()) }),
+            ["C:\\", "D:\\"]
+        );
+
+        // Nothing past the terminating empty string is read.
+        let buf = list("C:\\\0\0D:\\\0\0");
+        assert_eq!(collect(PzzWSTRIter::from_slice(&buf)), ["C:\\"]);
+        assert_eq!(collect(unsafe { PzzWSTRIter::new(buf.as_ptr()) }), ["C:\\"]);
+    }
+
+    #[test]
+    fn test_pzzwstr_iter_unterminated() {
+        let buf = list("C:\\\0D:\\");
+        assert_eq!(collect(PzzWSTRIter::from_slice(&buf)), ["C:\\", "D:\\"]);
+
+        let buf = list("C:\\\0D:\\\0");
+        assert_eq!(collect(PzzWSTRIter::from_slice(&buf)), ["C:\\", "D:\\"]);
+    }
+
+    #[test]
+    fn test_pzzwstr_iter_empty() {
+        assert!(collect(PzzWSTRIter::from_slice(&[])).is_empty());
+        assert!(collect(PzzWSTRIter::from_slice(&[0])).is_empty());
+        assert!(collect(PzzWSTRIter::from_slice(&[0, 0])).is_empty());
+        assert!(collect(unsafe { PzzWSTRIter::new(list("\0").as_ptr()) }).is_empty());
+        assert!(collect(unsafe { PzzWSTRIter::new(std::ptr::null()) }).is_empty());
     }
 }
@@ -37,6 +37,46 @@ struct MOUNTMGR_MOUNT_POINTS {
 
 const IOCTL_MOUNTMGR_QUERY_POINTS: u32 = 0x006D0008;
 
+/// Build the input of `IOCTL_MOUNTMGR_QUERY_POINTS` asking for the mount points of
+/// `volume_name`, a `MOUNTMGR_MOUNT_POINT` followed by the name.
+fn query_input(volume_name: &[u16]) -> Result<AlignedBuffer, Error> {
+    let mut buf = AlignedBuffer::new(
+        std::mem::size_of::<MOUNTMGR_MOUNT_POINT>() + std::mem::size_of_val(volume_name),
+        std::mem::align_of::<MOUNTMGR_MOUNT_POINT>(),
+    )
+    .ok_or(Error::AllocFailed)?;
+
+    let input = MOUNTMGR_MOUNT_POINT {
+        device_name_length: std::mem::size_of_val(volume_name)
+            .try_into()
+            .map_err(|_| Error::Overflow)?,
+        ..Default::default()
+    };
+
+    unsafe {
+        let input_ptr = buf.write_aligned(&input, 1).ok_or(Error::Overflow)?;
+
+        let volume_name_ptr = buf
+            .write_aligned(volume_name.as_ptr(), volume_name.len())
+            .ok_or(Error::Overflow)?;
+
+        (*input_ptr).device_name_offset = volume_name_ptr
+            .byte_offset_from(input_ptr)
+            .try_into()
+            .map_err(|_| Error::Overflow)?;
+    }
+
+    Ok(buf)
+}
+
+/// A mount point reported by the mount manager.
+struct MountPoint {
+    /// The symbolic link of the mount point, like `\DosDevices\D:`, if it has one.
+    symbolic_link_name: Option<String>,
+    /// The unique ID of the volume behind the mount point, shared by all its mount points.
+    unique_id: Vec<u8>,
+}
+
 pub struct MountMgr {
     handle: DropHandle,
 }
@@ -62,34 +102,28 @@ impl MountMgr {
     }
 
     pub fn query_points(&self, volume_name: &[u16]) -> Result<Vec<String>, Error> {
-        let mut names = Vec::new();
-
-        unsafe {
-            let mut attempt = 0;
-            let mut buf = AlignedBuffer::new(
-                std::mem::size_of::<MOUNTMGR_MOUNT_POINT>() + volume_name.len() * 2,
-                std::mem::align_of::<MOUNTMGR_MOUNT_POINT>(),
-            )
-            .ok_or(Error::AllocFailed)?;
-
-            #[allow(clippy::cast_possible_truncation)]
-            let input = MOUNTMGR_MOUNT_POINT {
-                device_name_length: (volume_name.len() * 2)
-                    .try_into()
-                    .map_err(|_| Error::Overflow)?,
-                ..Default::default()
-            };
+        Ok(self
+            .query(volume_name)?
+            .into_iter()
+            .filter_map(|point| point.symbolic_link_name)
+            .collect())
+    }
 
-            let input_ptr = buf.write_aligned(&input, 1).ok_or(Error::Overflow)?;
+    /// Get the unique ID the mount manager assigned to the volume, if it reports one.
+    pub fn query_unique_id(&self, volume_name: &[u16]) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self
+            .query(volume_name)?
+            .into_iter()
+            .map(|point| point.unique_id)
+            .find(|id| !id.is_empty()))
+    }
 
-            let volume_name_ptr = buf
-                .write_aligned(std::ptr::from_ref(&volume_name), volume_name.len())
-                .ok_or(Error::Overflow)?;
+    fn query(&self, volume_name: &[u16]) -> Result<Vec<MountPoint>, Error> {
+        let mut points = Vec::new();
 
-            (*input_ptr).device_name_offset = volume_name_ptr
-                .byte_offset_from(input_ptr)
-                .try_into()
-                .map_err(|_| Error::Overflow)?;
+        unsafe {
+            let mut attempt = 0;
+            let buf = query_input(volume_name)?;
 
             let mut out_buf_size = std::mem::size_of::<MOUNTMGR_MOUNT_POINTS>() + MAX_PATH as usize;
 
@@ -136,18 +170,30 @@ impl MountMgr {
                         .cast::<MOUNTMGR_MOUNT_POINT>()
                         .as_ref()
                         .expect("point is null");
-                    if point.symbolic_link_name_offset == 0 {
-                        continue;
-                    }
-                    #[allow(clippy::cast_ptr_alignment)]
-                    let name = std::slice::from_raw_parts(
-                        out_buf
-                            .as_ptr()
-                            .add(point.symbolic_link_name_offset as usize)
-                            .cast::<u16>(),
-                        point.symbolic_link_name_length as usize / 2,
-                    );
-                    names.push(String::from_utf16_lossy(name));
+                    let symbolic_link_name = (point.symbolic_link_name_offset != 0).then(|| {
+                        #[allow(clippy::cast_ptr_alignment)]
+                        let name = std::slice::from_raw_parts(
+                            out_buf
+                                .as_ptr()
+                                .add(point.symbolic_link_name_offset as usize)
+                                .cast::<u16>(),
+                            point.symbolic_link_name_length as usize / 2,
+                        );
+                        String::from_utf16_lossy(name)
+                    });
+                    let unique_id = if point.unique_id_offset == 0 {
+                        Vec::new()
+                    } else {
+                        std::slice::from_raw_parts(
+                            out_buf.as_ptr().add(point.unique_id_offset as usize),
+                            point.unique_id_length as usize,
+                        )
+                        .to_vec()
+                    };
+                    points.push(MountPoint {
+                        symbolic_link_name,
+                        unique_id,
+                    });
                 }
 
                 break;
@@ -158,6 +204,42 @@ impl MountMgr {
             }
         }
 
-        Ok(names)
+        Ok(points)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(clippy::cast_ptr_alignment)]
+    fn test_query_input() {
+        let name = r"\\?\Volume{01234567-89ab-cdef-0123-456789abcdef}"
+            .encode_utf16()
+            .collect::<Vec<_>>();
+        let buf = query_input(&name).unwrap();
+
+        let input = unsafe { &*buf.as_ptr().cast::<MOUNTMGR_MOUNT_POINT>() };
+        assert_eq!(input.device_name_length as usize, name.len() * 2);
+        assert_eq!(
+            input.device_name_offset as usize,
+            std::mem::size_of::<MOUNTMGR_MOUNT_POINT>()
+        );
+
+        // The name itself is copied, not the slice pointing to it.
+        let written = unsafe {
+            std::slice::from_raw_parts(
+                buf.as_ptr()
+                    .add(input.device_name_offset as usize)
+                    .cast::<u16>(),
+                name.len(),
+            )
+        };
+        assert_eq!(written, &name[..]);
+        assert_eq!(
+            String::from_utf16_lossy(written),
+            r"\\?\Volume{01234567-89ab-cdef-0123-456789abcdef}"
+        );
     }
 }